@@ -0,0 +1,70 @@
+// Throughput of PackStream encoding and decoding. `#[bench]` needs a nightly compiler, so this
+// runs with `harness = false`: `cargo bench --bench packstream`.
+
+extern crate neo4j_rust_driver as neo4j;
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use neo4j::Value;
+use neo4j::v1::packstream::{encode, decode};
+
+const ITEMS: usize = 1000;
+const MIN_DURATION: Duration = Duration::from_millis(500);
+
+// Run `f` repeatedly for at least `MIN_DURATION` and print the throughput over `bytes` bytes.
+fn bench<F: FnMut()>(name: &str, bytes: usize, mut f: F) {
+    let started = Instant::now();
+    let mut iterations = 0u32;
+    while started.elapsed() < MIN_DURATION {
+        f();
+        iterations += 1;
+    }
+
+    let elapsed = started.elapsed();
+    let per_iteration = elapsed / iterations;
+    let throughput = (bytes as f64 * iterations as f64) / elapsed.as_secs_f64() / 1_000_000.0;
+    println!("{:<24} {:>10.2?}/iter {:>10.1} MB/s", name, per_iteration, throughput);
+}
+
+fn main() {
+    let tiny_ints: Vec<i64> = (0..ITEMS as i64).map(|i| i % 100).collect();
+    let ints: Vec<i64> = (0..ITEMS as i64).map(|i| i * 1_000_003).collect();
+    let strings: Vec<String> = (0..ITEMS).map(|i| format!("string number {}", i)).collect();
+    let maps: Vec<BTreeMap<String, i64>> = (0..ITEMS as i64)
+        .map(|i| {
+            let mut map = BTreeMap::new();
+            map.insert("id".to_owned(), i);
+            map.insert("age".to_owned(), i % 100);
+            map
+        })
+        .collect();
+
+    let data = encode(&tiny_ints).unwrap();
+    bench("encode tiny ints", data.len(), || { encode(&tiny_ints).unwrap(); });
+    bench("decode tiny ints", data.len(), || {
+        let _: Vec<i64> = decode(&mut Cursor::new(&data)).unwrap();
+    });
+
+    let data = encode(&ints).unwrap();
+    bench("encode ints", data.len(), || { encode(&ints).unwrap(); });
+    bench("decode ints", data.len(), || {
+        let _: Vec<i64> = decode(&mut Cursor::new(&data)).unwrap();
+    });
+
+    let data = encode(&strings).unwrap();
+    bench("encode strings", data.len(), || { encode(&strings).unwrap(); });
+    bench("decode strings", data.len(), || {
+        let _: Vec<String> = decode(&mut Cursor::new(&data)).unwrap();
+    });
+
+    let data = encode(&maps).unwrap();
+    bench("encode maps", data.len(), || { encode(&maps).unwrap(); });
+    bench("decode maps", data.len(), || {
+        let _: Vec<BTreeMap<String, i64>> = decode(&mut Cursor::new(&data)).unwrap();
+    });
+    bench("decode maps as values", data.len(), || {
+        Value::from_reader(&mut Cursor::new(&data)).unwrap();
+    });
+}
@@ -0,0 +1,57 @@
+extern crate neo4j_rust_driver as neo4j;
+extern crate env_logger;
+
+use std::thread;
+use std::time::Duration;
+
+use neo4j::v1::{Driver, ErrorKind, GraphError, GraphResult, Session};
+
+const MAX_ATTEMPTS: u32 = 5;
+
+// Errors worth retrying: the server may be restarting or the pool busy for a moment.
+fn is_retryable(error: &GraphError) -> bool {
+    matches!(error.kind(), ErrorKind::ConnectionError | ErrorKind::TransientError)
+}
+
+fn with_retries<T, F: FnMut(&mut Session) -> GraphResult<T>>(driver: &Driver, mut work: F) -> GraphResult<T> {
+    let mut attempt = 1;
+    loop {
+        let result = driver.try_session(Duration::from_secs(5))
+            .and_then(|mut session| work(&mut session));
+
+        match result {
+            Err(ref e) if attempt < MAX_ATTEMPTS && is_retryable(e) => {
+                println!("Attempt {} failed: {}, retrying", attempt, e);
+                thread::sleep(Duration::from_millis(100 * 2u64.pow(attempt)));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let driver = match Driver::new("bolt://localhost:7687") {
+        Ok(driver) => driver,
+        Err(e) => {
+            println!("Invalid uri: {}", e);
+            return
+        }
+    };
+
+    let result = with_retries(&driver, |session| {
+        session.init("MyClient/1.0")?;
+        session.run("return 1 as one")?;
+        let mut records = Vec::new();
+        session.pull_all(&mut records)?;
+        Ok(records)
+    });
+
+    match result {
+        Ok(records) => println!("{:?}", records),
+        Err(GraphError::Neo4j(ref e)) => println!("Query failed: {}", e),
+        Err(e) => println!("Gave up after {} attempts: {}", MAX_ATTEMPTS, e),
+    }
+}
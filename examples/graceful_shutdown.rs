@@ -0,0 +1,40 @@
+extern crate neo4j_rust_driver as neo4j;
+extern crate env_logger;
+extern crate libc;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as *const () as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_interrupt as *const () as libc::sighandler_t);
+    }
+
+    let driver = neo4j::v1::Driver::new("bolt://localhost:7687").unwrap();
+
+    let mut session = driver.session().unwrap();
+    println!("{:?}", session.init("MyClient/1.0").unwrap());
+
+    println!("Running, press Ctrl-C to stop");
+    while !INTERRUPTED.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    drop(session);
+
+    if driver.shutdown_with_timeout(Duration::from_secs(10)) {
+        println!("All connections released, bye");
+    } else {
+        println!("Forced connections closed after timeout");
+    }
+}
@@ -0,0 +1,29 @@
+extern crate neo4j_rust_driver as neo4j;
+extern crate env_logger;
+
+use neo4j::{AuthToken, GraphResult, Statement};
+
+// Values are sent alongside the statement instead of being spliced into it, so they never need
+// escaping and the server can reuse the query plan.
+fn run() -> GraphResult<()> {
+    let driver = neo4j::driver("bolt://localhost:7687", AuthToken::basic("neo4j", "neo4j"))?;
+    let mut session = driver.session()?;
+
+    let statement = Statement::new("CREATE (p:Person {name: {name}, age: {age}}) RETURN p.name")
+        .param("name", "Alice")
+        .param("age", 33);
+
+    for record in session.run(statement)? {
+        println!("Created {:?}", record?.get(0));
+    }
+
+    Ok(())
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    if let Err(e) = run() {
+        println!("Error: {}", e);
+    }
+}
@@ -0,0 +1,27 @@
+extern crate neo4j_rust_driver as neo4j;
+extern crate env_logger;
+
+use neo4j::{AuthToken, GraphResult};
+
+// Records are read from the connection one at a time while iterating, so memory use stays
+// bounded however large the result is.
+fn run() -> GraphResult<()> {
+    let driver = neo4j::driver("bolt://localhost:7687", AuthToken::basic("neo4j", "neo4j"))?;
+    let mut session = driver.session()?;
+
+    let mut sum = 0;
+    for record in session.run("UNWIND range(1, 1000000) AS n RETURN n")? {
+        sum += record?.get(0).and_then(|v| v.as_integer()).unwrap_or(0);
+    }
+
+    println!("Sum: {}", sum);
+    Ok(())
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    if let Err(e) = run() {
+        println!("Error: {}", e);
+    }
+}
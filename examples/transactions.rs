@@ -0,0 +1,43 @@
+extern crate neo4j_rust_driver as neo4j;
+extern crate env_logger;
+
+use neo4j::{AuthToken, GraphResult};
+
+fn run() -> GraphResult<()> {
+    let driver = neo4j::driver("bolt://localhost:7687", AuthToken::basic("neo4j", "neo4j"))?;
+    let mut session = driver.session()?;
+
+    // committed: both nodes are created
+    let mut tx = session.begin_transaction()?;
+    tx.query("CREATE (:Example {name: 'committed'})")?.consume()?;
+    tx.query("CREATE (:Example {name: 'also committed'})")?.consume()?;
+    tx.commit()?;
+
+    // rolled back explicitly
+    let mut tx = session.begin_transaction()?;
+    tx.query("CREATE (:Example {name: 'rolled back'})")?.consume()?;
+    tx.rollback()?;
+
+    // rolled back when dropped without committing
+    {
+        let mut tx = session.begin_transaction()?;
+        tx.query("CREATE (:Example {name: 'dropped'})")?.consume()?;
+    }
+
+    let mut cursor = session.query("MATCH (e:Example) RETURN e.name")?;
+    while let Some(record) = cursor.fetch()? {
+        println!("{:?}", record.get(0));
+    }
+    drop(cursor);
+
+    session.query("MATCH (e:Example) DELETE e")?.consume()?;
+    Ok(())
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    if let Err(e) = run() {
+        println!("Error: {}", e);
+    }
+}
@@ -0,0 +1,19 @@
+// Feeds arbitrary bytes to the PackStream readers, which must fail with an error instead of
+// panicking or allocating for sizes the input does not hold: `cargo fuzz run from_reader`.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate neo4j_rust_driver as neo4j;
+
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use neo4j::Value;
+use neo4j::v1::packstream::decode;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Value::from_reader(&mut Cursor::new(data));
+    let _ = decode::<Vec<String>, _>(&mut Cursor::new(data));
+    let _ = decode::<HashMap<String, Vec<i64>>, _>(&mut Cursor::new(data));
+});
@@ -0,0 +1,158 @@
+/// Build a `Value` from a JSON-like literal: `null`, lists in `[...]`, maps in `{"key": ...}` and
+/// any expression convertible into a `Value`.
+///
+/// ```
+/// # #[macro_use] extern crate neo4j_rust_driver;
+/// # fn main() {
+/// let name = "Alice";
+/// let value = value!([1, -2.5, null, {"name": name, "tags": ["a", "b"]}]);
+/// # let _ = value;
+/// # }
+/// ```
+#[macro_export]
+macro_rules! value {
+    // the items of a list, as the tokens of the item being read and the items read so far
+    (@list [$($done:expr,)*] []) => {
+        vec![$($done,)*]
+    };
+    (@list [$($done:expr,)*] [$($item:tt)+]) => {
+        vec![$($done,)* $crate::value!($($item)+)]
+    };
+    (@list [$($done:expr,)*] [$($item:tt)*] , $($rest:tt)*) => {
+        $crate::value!(@list [$($done,)* $crate::value!($($item)*),] [] $($rest)*)
+    };
+    (@list [$($done:expr,)*] [$($item:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::value!(@list [$($done,)*] [$($item)* $next] $($rest)*)
+    };
+
+    // the entries of a map, inserted into `$map` one at a time
+    (@map $map:ident) => {};
+    (@map $map:ident $key:tt : $($rest:tt)*) => {
+        $crate::value!(@entry $map $key [] $($rest)*);
+    };
+    (@entry $map:ident $key:tt [$($value:tt)+]) => {
+        $map.insert(::std::string::String::from($key), $crate::value!($($value)+));
+    };
+    (@entry $map:ident $key:tt [$($value:tt)+] , $($rest:tt)*) => {
+        $map.insert(::std::string::String::from($key), $crate::value!($($value)+));
+        $crate::value!(@map $map $($rest)*);
+    };
+    (@entry $map:ident $key:tt [$($value:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::value!(@entry $map $key [$($value)* $next] $($rest)*);
+    };
+
+    (null) => {
+        $crate::Value::Null
+    };
+    ([$($items:tt)*]) => {
+        $crate::Value::List($crate::value!(@list [] [] $($items)*))
+    };
+    ({$($entries:tt)*}) => {{
+        #[allow(unused_mut)]
+        let mut map = $crate::v1::packstream::value::Map::new();
+        $crate::value!(@map map $($entries)*);
+        $crate::Value::Map(map)
+    }};
+    ($other:expr) => {
+        $crate::Value::from($other)
+    };
+}
+
+/// Build the parameters of a statement, a `Map` whose values are written like in `value!`.
+///
+/// It can be imported on its own, without `value!`:
+///
+/// ```
+/// # extern crate neo4j_rust_driver;
+/// use neo4j_rust_driver::params;
+/// # fn main() {
+/// let params = params!{"name" => "Alice", "age" => 42, "tags" => ["a", "b"]};
+/// assert_eq!(3, params.len());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! params {
+    (@entry $map:ident) => {};
+    (@entry $map:ident $key:tt => $($rest:tt)*) => {
+        $crate::params!(@value $map $key [] $($rest)*);
+    };
+    (@value $map:ident $key:tt [$($value:tt)+]) => {
+        $map.insert(::std::string::String::from($key), $crate::value!($($value)+));
+    };
+    (@value $map:ident $key:tt [$($value:tt)+] , $($rest:tt)*) => {
+        $map.insert(::std::string::String::from($key), $crate::value!($($value)+));
+        $crate::params!(@entry $map $($rest)*);
+    };
+    (@value $map:ident $key:tt [$($value:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::params!(@value $map $key [$($value)* $next] $($rest)*);
+    };
+
+    ($($entries:tt)*) => {{
+        #[allow(unused_mut)]
+        let mut params = $crate::v1::packstream::value::Map::new();
+        $crate::params!(@entry params $($entries)*);
+        params
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use v1::packstream::value::{Value, Map};
+
+    #[test]
+    fn value_scalars() {
+        assert_eq!(Value::Null, value!(null));
+        assert_eq!(Value::Boolean(true), value!(true));
+        assert_eq!(Value::Integer(-1), value!(-1));
+        assert_eq!(Value::Float(2.5), value!(2.5));
+        assert_eq!(Value::from("a"), value!("a"));
+
+        let n = 3;
+        assert_eq!(Value::Integer(4), value!(n + 1));
+    }
+
+    #[test]
+    fn value_lists() {
+        assert_eq!(Value::List(vec![]), value!([]));
+        assert_eq!(Value::from(vec![1, 2]), value!([1, 2,]));
+        assert_eq!(Value::List(vec![Value::Integer(-1), Value::Null, Value::from(vec![true])]),
+                   value!([-1, null, [true]]));
+    }
+
+    #[test]
+    fn value_maps() {
+        assert_eq!(Value::Map(Map::new()), value!({}));
+
+        let name = "Alice";
+        let expected = Value::map()
+            .entry("name", name)
+            .entry("age", 33)
+            .entry("address", Value::map().entry("city", "Lisbon").build())
+            .entry("tags", vec!["a", "b"])
+            .build();
+
+        let value = value!({
+            "name": name,
+            "age": 30 + 3,
+            "address": {"city": "Lisbon"},
+            "tags": ["a", "b"],
+        });
+        assert_eq!(expected, value);
+
+        assert_eq!(Value::List(vec![Value::map().entry("k", Value::Null).build()]), value!([{"k": null}]));
+    }
+
+    #[test]
+    fn params() {
+        let expected = Value::map()
+            .entry("name", "Alice")
+            .entry("age", -42)
+            .entry("friends", Value::list().item(Value::map().entry("name", "Bob").build()).build())
+            .build();
+
+        let params = params!{"name" => "Alice", "age" => -42, "friends" => [{"name": "Bob"}]};
+        assert_eq!(expected, Value::Map(params));
+
+        assert!(params!{}.is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+// `#[derive(RustcEncodable, RustcDecodable)]` is no longer provided by the compiler, so tests
+// declare their plain structs through these macros, which expand to the same calls the derive
+// used to generate.
+
+macro_rules! encodable_struct {
+    ($(#[$attr:meta])* struct $name:ident { $($field:ident : $ty:ty),* $(,)* }) => {
+        $(#[$attr])*
+        struct $name { $($field: $ty),* }
+
+        encodable_struct!(@impl $name { $($field),* });
+    };
+    (@impl $name:ident { $($field:ident),* }) => {
+        impl ::rustc_serialize::Encodable for $name {
+            fn encode<S: ::rustc_serialize::Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+                let len = [$(stringify!($field)),*].len();
+                e.emit_struct(stringify!($name), len, |e| {
+                    let mut idx = 0;
+                    $(
+                        e.emit_struct_field(stringify!($field), idx, |e| self.$field.encode(e))?;
+                        idx += 1;
+                    )*
+                    let _ = idx;
+                    Ok(())
+                })
+            }
+        }
+    }
+}
+
+macro_rules! decodable_struct {
+    ($(#[$attr:meta])* struct $name:ident { $($field:ident : $ty:ty),* $(,)* }) => {
+        $(#[$attr])*
+        struct $name { $($field: $ty),* }
+
+        decodable_struct!(@impl $name { $($field),* });
+    };
+    (@impl $name:ident { $($field:ident),* }) => {
+        impl ::rustc_serialize::Decodable for $name {
+            fn decode<D: ::rustc_serialize::Decoder>(d: &mut D) -> Result<Self, D::Error> {
+                let len = [$(stringify!($field)),*].len();
+                d.read_struct(stringify!($name), len, |d| {
+                    Ok($name {
+                        $($field: d.read_struct_field(
+                            stringify!($field), 0, ::rustc_serialize::Decodable::decode
+                        )?),*
+                    })
+                })
+            }
+        }
+    }
+}
+
+// For a struct that is both encoded and decoded, e.g. to check a round trip.
+macro_rules! codable_struct {
+    ($(#[$attr:meta])* struct $name:ident { $($field:ident : $ty:ty),* $(,)* }) => {
+        $(#[$attr])*
+        struct $name { $($field: $ty),* }
+
+        encodable_struct!(@impl $name { $($field),* });
+        decodable_struct!(@impl $name { $($field),* });
+    }
+}
@@ -0,0 +1,224 @@
+// An in-memory transport for unit testing code built on `Connection` or `Session`, without a
+// server.
+
+use std::io::prelude::*;
+use std::io::{self, Cursor};
+
+use v1::{Connection, Session};
+use v1::packstream::encode;
+use v1::packstream::value::{Value, Map, List, StructureSignature};
+
+/// A stream replaying scripted server replies and recording the requests written to it.
+///
+/// Replies are read in the order they are scripted, regardless of the requests sent, so the
+/// script must follow the exchange the code under test performs:
+///
+/// ```
+/// use neo4j_rust_driver::Value;
+/// use neo4j_rust_driver::testing::MockStream;
+///
+/// let mut conn = MockStream::new()
+///     .success(Value::map().entry("fields", vec!["n"]).build())
+///     .record(vec![Value::Integer(1)])
+///     .success(Value::map().build())
+///     .connection();
+///
+/// let records: Vec<_> = conn.query("RETURN 1 AS n").unwrap().into_iter().collect();
+/// assert_eq!(1, records.len());
+/// assert_eq!("RETURN 1 AS n", conn.socket().statements()[0].0);
+/// ```
+#[derive(Debug, Default)]
+pub struct MockStream {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl MockStream {
+    pub fn new() -> Self {
+        MockStream::default()
+    }
+
+    /// Reply with SUCCESS and `metadata`, e.g. `{fields: [...]}` for a RUN.
+    pub fn success(self, metadata: Value) -> Self {
+        self.reply(StructureSignature::Success, metadata)
+    }
+
+    /// Reply with a RECORD holding `fields`.
+    pub fn record(self, fields: List) -> Self {
+        self.reply(StructureSignature::Record, Value::List(fields))
+    }
+
+    /// Reply with a FAILURE, e.g. `Neo.ClientError.Statement.SyntaxError`.
+    pub fn failure(self, code: &str, message: &str) -> Self {
+        self.reply(StructureSignature::Failure, Value::map().entry("code", code).entry("message", message).build())
+    }
+
+    /// Reply with IGNORED, as the server does for requests sent after a failure.
+    pub fn ignored(self) -> Self {
+        self.reply(StructureSignature::Ignored, Value::map().build())
+    }
+
+    /// Append `bytes` to the replies as they are, e.g. a truncated message.
+    pub fn raw(mut self, bytes: &[u8]) -> Self {
+        self.input.get_mut().extend_from_slice(bytes);
+        self
+    }
+
+    /// A connection using this stream, as after the handshake and before INIT.
+    pub fn connection(self) -> Connection<MockStream> {
+        Connection::new(self)
+    }
+
+    /// A session over a connection using this stream, taken as initialized, so the script
+    /// starts with the replies to the first statement run.
+    pub fn session(self) -> Session<MockStream> {
+        Session::over(self.connection())
+    }
+
+    /// Requests written so far, as `Value::Structure(signature, fields)`. A partially written
+    /// request at the end is left out.
+    pub fn requests(&self) -> Vec<Value> {
+        let mut requests = Vec::new();
+        let mut message = Vec::new();
+        let mut data = &self.output[..];
+
+        while data.len() >= 2 {
+            let size = ((data[0] as usize) << 8) | data[1] as usize;
+            if data.len() < 2 + size {
+                break
+            }
+
+            if size == 0 {
+                if let Ok(request) = Value::from_reader(&mut &message[..]) {
+                    requests.push(request);
+                }
+                message.clear();
+            } else {
+                message.extend_from_slice(&data[2..2 + size]);
+            }
+            data = &data[2 + size..];
+        }
+
+        requests
+    }
+
+    /// Bytes written so far, as they are, e.g. to check the handshake.
+    pub fn written(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Statements and parameters of the RUN requests written so far.
+    pub fn statements(&self) -> Vec<(String, Map)> {
+        self.requests().into_iter()
+            .filter_map(|request| match request {
+                Value::Structure(StructureSignature::Run, mut fields) if fields.len() == 2 => {
+                    let params = fields.pop().and_then(|p| p.as_map().cloned()).unwrap_or_default();
+                    let text = fields.pop().and_then(|t| t.as_string().map(str::to_owned))?;
+                    Some((text, params))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether every scripted reply has been read.
+    pub fn is_exhausted(&self) -> bool {
+        self.input.position() as usize >= self.input.get_ref().len()
+    }
+
+    fn reply(mut self, signature: StructureSignature, field: Value) -> Self {
+        let data = encode(&Value::Structure(signature, vec![field])).expect("encoding a reply");
+        let input = self.input.get_mut();
+        for chunk in data.chunks(0xFFFF) {
+            input.extend_from_slice(&[(chunk.len() >> 8) as u8, chunk.len() as u8]);
+            input.extend_from_slice(chunk);
+        }
+        input.extend_from_slice(&[0x00, 0x00]);
+        self
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MockStream;
+    use v1::{ErrorKind, GraphError};
+    use v1::packstream::value::{Value, StructureSignature};
+
+    #[test]
+    fn scripted_query() {
+        let mut conn = MockStream::new()
+            .success(Value::map().entry("server", "Neo4j/3.4.0").build())
+            .success(Value::map().entry("fields", vec!["name"]).build())
+            .record(vec![Value::from("Alice")])
+            .record(vec![Value::from("Bob")])
+            .success(Value::map().build())
+            .connection();
+
+        conn.init("MyClient/1.0").unwrap();
+        let names: Vec<_> = conn.query_with_params("MATCH (p:Person) WHERE p.age > {age} RETURN p.name AS name",
+                                                   Value::map().entry("age", 30).build().as_map().cloned().unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|record| record.unwrap().get(0).and_then(Value::as_string).unwrap().to_owned())
+            .collect();
+        assert_eq!(vec!["Alice", "Bob"], names);
+
+        let socket = conn.socket();
+        assert!(socket.is_exhausted());
+
+        let signatures: Vec<_> = socket.requests().iter().map(|r| r.as_struct().unwrap().0).collect();
+        assert_eq!(vec![StructureSignature::Init, StructureSignature::Run, StructureSignature::PullAll], signatures);
+
+        let statements = socket.statements();
+        assert_eq!(1, statements.len());
+        assert_eq!("MATCH (p:Person) WHERE p.age > {age} RETURN p.name AS name", statements[0].0);
+        assert_eq!(Some(&Value::Integer(30)), statements[0].1.get("age"));
+    }
+
+    #[test]
+    fn scripted_session() {
+        let mut session = MockStream::new()
+            .success(Value::map().entry("fields", vec!["n"]).build())
+            .record(vec![Value::from(1)])
+            .success(Value::map().build())
+            .session();
+
+        let records = session.execute("RETURN 1 AS n").unwrap();
+        assert_eq!(Some(&Value::from(1)), records[0].get_by_key("n"));
+        assert_eq!("RETURN 1 AS n", session.socket().statements()[0].0);
+        assert!(session.socket().is_exhausted());
+    }
+
+    #[test]
+    fn scripted_failure() {
+        let mut conn = MockStream::new()
+            .failure("Neo.ClientError.Statement.SyntaxError", "Invalid input")
+            .ignored()
+            .success(Value::map().build())
+            .connection();
+
+        match conn.query("RETRUN 1") {
+            Err(GraphError::Neo4j(ref e)) => assert_eq!(ErrorKind::ClientError, e.kind()),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("expected failure"),
+        }
+
+        assert_eq!(StructureSignature::AckFailure, conn.socket().requests().last().unwrap().as_struct().unwrap().0);
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+use super::connection::REDACTED;
+use super::packstream::value::{Value, Map};
+
+/// Credentials sent to the server when a connection is initialized.
+///
+/// The password is hidden when the token is formatted with `{:?}`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum AuthToken {
+    None,
+    Basic { user: String, password: String },
+}
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuthToken::None => f.write_str("None"),
+            AuthToken::Basic { ref user, .. } => {
+                f.debug_struct("Basic").field("user", user).field("password", &REDACTED).finish()
+            }
+        }
+    }
+}
+
+impl AuthToken {
+    pub fn basic<U: Into<String>, P: Into<String>>(user: U, password: P) -> Self {
+        AuthToken::Basic { user: user.into(), password: password.into() }
+    }
+
+    /// The auth token map of the INIT message.
+    pub fn to_map(&self) -> Map {
+        let mut map = Map::new();
+        match *self {
+            AuthToken::None => {
+                map.insert("scheme".to_owned(), Value::from("none"));
+            }
+            AuthToken::Basic { ref user, ref password } => {
+                map.insert("scheme".to_owned(), Value::from("basic"));
+                map.insert("principal".to_owned(), Value::from(user.as_str()));
+                map.insert("credentials".to_owned(), Value::from(password.as_str()));
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn none_token() {
+        let map = AuthToken::None.to_map();
+        assert_eq!(1, map.len());
+        assert_eq!(Some(&Value::from("none")), map.get("scheme"));
+    }
+
+    #[test]
+    fn basic_token() {
+        let map = AuthToken::basic("neo4j", "secret").to_map();
+        assert_eq!(Some(&Value::from("basic")), map.get("scheme"));
+        assert_eq!(Some(&Value::from("neo4j")), map.get("principal"));
+        assert_eq!(Some(&Value::from("secret")), map.get("credentials"));
+    }
+
+    #[test]
+    fn debug_hides_password() {
+        let debug = format!("{:?}", AuthToken::basic("neo4j", "secret"));
+        assert_eq!("Basic { user: \"neo4j\", password: \"******\" }", debug);
+        assert!(!format!("{:#?}", Some(AuthToken::basic("neo4j", "secret"))).contains("secret"));
+        assert_eq!("None", format!("{:?}", AuthToken::None));
+    }
+}
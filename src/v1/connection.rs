@@ -1,74 +1,1779 @@
-use std::collections::BTreeMap;
-use std::io::Cursor;
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::prelude::*;
+use std::io::{self, Cursor};
 use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use rustc_serialize::Encodable;
+use log::LogLevel;
 
-use super::transport::ChunkedStream;
-use super::protocol::client::{Init, Run, PullAll};
-use super::protocol::server::Message;
-use super::packstream::{encode, decode};
+use super::error::{GraphError, GraphResult, Neo4jError, ProtocolViolation};
+use super::metrics::ConnectionMetrics;
+use super::result::{self, ResultCursor};
+use super::transport::{ChunkedStream, FlushPolicy};
+use super::protocol::client::{Init, Run, DiscardAll, PullAll, AckFailure, Reset, Hello, Goodbye, Begin, Commit, Rollback};
+use super::protocol::client::{Pull, Discard, ALL_RECORDS};
+use super::protocol::server::ServerMessage;
+use super::packstream::encode;
+use super::auth::AuthToken;
+use super::packstream::value::{Value, List, Map, Registry, StructureSignature};
+use super::statement::validate_params;
 
-pub struct Connection {
-    transport: ChunkedStream,
+// Log targets of the messages sent and received, at trace level.
+const SEND_TARGET: &str = "bolt::send";
+const RECV_TARGET: &str = "bolt::recv";
+// Log target of the lifecycle of queries (RUN, first record and end of the results), at debug
+// level, with key=value fields as the slow query warning.
+const QUERY_TARGET: &str = "bolt::query";
+
+// Shown instead of credentials and parameter values, see `describe`.
+pub(crate) const REDACTED: &str = "******";
+
+const TRANSPORT_TAKEN: &str = "transport taken from the connection";
+
+/// First protocol version whose RUN carries a map of extra fields, e.g. the access mode.
+pub const RUN_EXTRA_VERSION: u32 = 3;
+
+/// First protocol version initializing with HELLO instead of INIT, and clearing a failure
+/// with RESET as it has no ACK_FAILURE.
+pub const HELLO_VERSION: u32 = 3;
+
+/// First protocol version with BEGIN, COMMIT and ROLLBACK messages, instead of running them as
+/// statements.
+pub const TRANSACTION_MESSAGES_VERSION: u32 = 3;
+
+/// First protocol version pulling records in batches with PULL and DISCARD, which replace
+/// PULL_ALL and DISCARD_ALL.
+pub const PULL_N_VERSION: u32 = 4;
+
+/// Records requested by each PULL, see `Connection::set_fetch_size`.
+pub const DEFAULT_FETCH_SIZE: i64 = 1000;
+
+/// The state of the server side of a connection, as far as the requests sent and the replies
+/// received tell. Requests the state does not allow fail with `GraphError::InvalidState`.
+///
+/// The state moves as requests are sent, assuming they succeed, e.g. to `Streaming` once RUN is
+/// sent, and to `Failed` when a FAILURE is received. A new connection is `Ready` for INIT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Waiting for RUN, or for INIT on a new connection.
+    Ready,
+    /// A statement was run whose result is waiting for PULL_ALL or DISCARD_ALL.
+    Streaming,
+    /// The server ignores every request until ACK_FAILURE or RESET.
+    Failed,
+    /// A transport or initialization failure left the connection unusable.
+    Defunct,
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ConnectionState::Ready => "READY",
+            ConnectionState::Streaming => "STREAMING",
+            ConnectionState::Failed => "FAILED",
+            ConnectionState::Defunct => "DEFUNCT",
+        };
+        f.write_str(name)
+    }
+}
+
+// A query being timed from RUN to the message ending its results.
+struct QueryTimer {
+    statement: String,
+    // identifies the statement in the logs without its text, see `statement_hash`
+    hash: u64,
+    started: Instant,
+    records: usize,
+    discarded: bool,
+}
+
+impl QueryTimer {
+    fn new(statement: &str) -> Self {
+        QueryTimer {
+            statement: statement.to_owned(),
+            hash: statement_hash(statement),
+            started: Instant::now(),
+            records: 0,
+            discarded: false,
+        }
+    }
 }
 
-impl Connection {
-    pub fn new(socket: TcpStream) -> Self {
+/// A Bolt connection over `S`, a `TcpStream` unless connected through `::handshake`.
+pub struct Connection<S: Read + Write = TcpStream> {
+    // only taken by `into_transport`, which consumes the connection
+    transport: Option<ChunkedStream<S>>,
+    version: u32,
+    state: ConnectionState,
+    // signatures of the requests sent whose summary (SUCCESS, FAILURE or IGNORED) has not been
+    // received yet, oldest first
+    requests: VecDeque<StructureSignature>,
+    slow_query_threshold: Option<Duration>,
+    query: Option<QueryTimer>,
+    created: Instant,
+    // `server` and `connection_id` from the reply to INIT
+    server_agent: Option<String>,
+    connection_id: Option<String>,
+    redact_parameters: bool,
+    fetch_size: i64,
+    // when waiting for a reply times out, and the timeout it was computed from, see
+    // `set_deadline`
+    deadline: Option<(Instant, Duration)>,
+    // bounds each read of the socket by the time left before the deadline
+    set_read_timeout: Option<SetReadTimeout<S>>,
+}
+
+// Sets the read timeout of a socket, as `TcpStream::set_read_timeout`.
+type SetReadTimeout<S> = fn(&S, Option<Duration>) -> io::Result<()>;
+
+/// A connection over a plain TCP socket, as opened by `::connect`.
+pub type TcpConnection = Connection<TcpStream>;
+
+impl<S: Read + Write> Connection<S> {
+    pub fn new(socket: S) -> Self {
         Connection {
-            transport: ChunkedStream::new(socket),
+            transport: Some(ChunkedStream::new(socket)),
+            version: super::VERSION,
+            state: ConnectionState::Ready,
+            requests: VecDeque::new(),
+            slow_query_threshold: None,
+            query: None,
+            created: Instant::now(),
+            server_agent: None,
+            connection_id: None,
+            redact_parameters: true,
+            fetch_size: DEFAULT_FETCH_SIZE,
+            deadline: None,
+            set_read_timeout: None,
         }
     }
 
-    pub fn init(&mut self, user_agent: &str) -> Message<BTreeMap<String, ()>> {
-        let message = Init::new(user_agent);
-        let data = encode(&message).unwrap();
+    /// Record `version` as the protocol version agreed in the handshake.
+    pub(crate) fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// The protocol version agreed with the server.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Log queries taking longer than `threshold` to complete at warn level.
+    pub fn set_slow_query_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_query_threshold = threshold;
+    }
+
+    /// Whether messages logged to the `bolt::send` target hide the credentials sent with INIT
+    /// and the parameter values sent with RUN, which is the default.
+    pub fn set_redact_parameters(&mut self, redact: bool) {
+        self.redact_parameters = redact;
+    }
+
+    /// When the requests sent are written to the socket, see `FlushPolicy`. Requests are always
+    /// written before waiting for a reply.
+    pub fn set_flush_policy(&mut self, flush_policy: FlushPolicy) {
+        self.transport().set_flush_policy(flush_policy);
+    }
+
+    /// Request the records of a result `fetch_size` at a time, `DEFAULT_FETCH_SIZE` by default,
+    /// so the server does not stream more than the client reads. A size of `-1` requests all
+    /// of them at once.
+    ///
+    /// Only applies from `PULL_N_VERSION`, older versions always stream all the records.
+    pub fn set_fetch_size(&mut self, fetch_size: i64) {
+        self.fetch_size = if fetch_size > 0 { fetch_size } else { ALL_RECORDS };
+    }
+
+    pub fn fetch_size(&self) -> i64 {
+        self.fetch_size
+    }
+
+    /// Whether the connection was closed or a transport failure left it unusable.
+    pub fn is_defunct(&self) -> bool {
+        self.state == ConnectionState::Defunct
+    }
 
-        self.transport.write(&data).unwrap();
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
 
-        self.transport.flush(true).unwrap();
-        self.transport.send().unwrap();
+    /// Time since the connection was opened.
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
 
-        let data = self.transport.receive().unwrap();
+    /// Check the server still answers by sending a RESET, which also discards any pending
+    /// results. A connection failing the check should not be used again.
+    pub fn is_alive(&mut self) -> bool {
+        !self.is_defunct() && self.reset().is_ok()
+    }
 
-        println!("[{}]", data.iter().fold(String::new(), |mut acc, i| { acc.push_str(&format!("{:02X} ", i)); acc }));
+    /// What went through the connection since it was opened, e.g. to export to a monitoring
+    /// system. Requests are counted once queued, bytes once they are sent.
+    pub fn metrics(&self) -> &ConnectionMetrics {
+        self.transport.as_ref().expect(TRANSPORT_TAKEN).metrics()
+    }
+
+    /// Number of requests sent whose reply has not been received yet.
+    pub fn pending(&self) -> usize {
+        self.requests.len()
+    }
 
-        let mut cur = Cursor::new(data);
-        let msg: Message<BTreeMap<String, ()>> = decode(&mut cur).unwrap();
-        msg
+    pub(crate) fn into_transport(mut self) -> ChunkedStream<S> {
+        self.state = ConnectionState::Defunct;
+        self.transport.take().expect(TRANSPORT_TAKEN)
     }
 
-    pub fn run(&mut self, query: &str) -> Message<BTreeMap<String, Vec<String>>> {
-        let message = Run::new(query);
-        let data = encode(&message).unwrap();
+    /// Tell the server the connection is being closed with GOODBYE, from `HELLO_VERSION`. Older
+    /// versions have no such message, the server just sees the socket shut down once the
+    /// connection is dropped. The connection is defunct afterwards, replies still pending are
+    /// not received.
+    ///
+    /// Dropping the connection closes it too, this is only needed to know whether GOODBYE
+    /// could be sent.
+    pub fn close(&mut self) -> GraphResult<()> {
+        if self.is_defunct() {
+            return Ok(())
+        }
 
-        self.transport.write(&data).unwrap();
+        let result = if self.version >= HELLO_VERSION {
+            self.queue_checked(&Goodbye, false).and_then(|_| self.flush())
+        } else {
+            Ok(())
+        };
 
-        self.transport.flush(true).unwrap();
-        self.transport.send().unwrap();
+        self.state = ConnectionState::Defunct;
+        self.requests.clear();
+        result
+    }
 
-        let data = self.transport.receive().unwrap();
+    fn transport(&mut self) -> &mut ChunkedStream<S> {
+        self.transport.as_mut().expect(TRANSPORT_TAKEN)
+    }
 
-        println!("[ {}]", data.iter().fold(String::new(), |mut acc, i| { acc.push_str(&format!("{:02X} ", i)); acc }));
+    // Messages are fully encoded before reaching the transport, so an encoding failure leaves
+    // the connection untouched. Whether the message leaves now depends on the flush policy.
+    fn send<T: Encodable>(&mut self, message: &T) -> GraphResult<()> {
+        self.queue(message)?;
+        self.send_due()
+    }
+
+    fn send_due(&mut self) -> GraphResult<()> {
+        match self.transport().send_due() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(self.transport_error(e)),
+        }
+    }
 
-        let mut cur = Cursor::new(data);
-        let msg: Message<BTreeMap<String, Vec<String>>> = decode(&mut cur).unwrap();
-        msg
+    /// Add `message` to the requests sent by the next `flush` or `sync`, so several requests
+    /// (e.g. RUN and PULL_ALL) take a single round trip.
+    ///
+    /// Fails with `GraphError::InvalidState` if the state of the connection does not allow the
+    /// request, e.g. RUN while the result of the previous one has not been pulled.
+    pub fn queue<T: Encodable>(&mut self, message: &T) -> GraphResult<()> {
+        self.queue_checked(message, true)
     }
 
-    pub fn pull_all(&mut self) -> Message<Vec<u32>> {
-        let message = PullAll;
-        let data = encode(&message).unwrap();
+    fn queue_checked<T: Encodable>(&mut self, message: &T, check: bool) -> GraphResult<()> {
+        if self.is_defunct() {
+            return Err(GraphError::ConnectionDefunct)
+        }
+
+        let data = encode(message)?;
+        let signature = StructureSignature::from(data.get(1).cloned().unwrap_or(0));
+        let next = match self.next_state(signature) {
+            Some(state) => state,
+            None if check => return Err(GraphError::InvalidState { state: self.state, request: signature }),
+            None => self.state,
+        };
+
+        if log_enabled!(target: SEND_TARGET, LogLevel::Trace) {
+            trace!(target: SEND_TARGET, "C: {}", describe(&data, self.redact_parameters));
+        }
+
+        let result = self.transport().write(&data).and_then(|_| self.transport().flush(true));
+        if let Err(e) = result {
+            return Err(self.transport_error(e))
+        }
+
+        self.state = next;
+        self.transport().metrics_mut().message_sent(signature);
+        // GOODBYE gets no reply
+        if signature != StructureSignature::Goodbye {
+            self.requests.push_back(signature);
+        }
+        Ok(())
+    }
+
+    // The state once the server accepts `request`, or `None` if the current state does not
+    // allow it. Messages the driver does not model leave the state as it is.
+    fn next_state(&self, request: StructureSignature) -> Option<ConnectionState> {
+        use self::ConnectionState::*;
+
+        let (allowed, next) = match request {
+            StructureSignature::Init => (self.state == Ready, Ready),
+            StructureSignature::Run => (self.state == Ready, Streaming),
+            StructureSignature::PullAll | StructureSignature::DiscardAll => (self.state == Streaming, Ready),
+            StructureSignature::AckFailure => (self.state == Failed, Ready),
+            StructureSignature::Reset => (true, Ready),
+            StructureSignature::Begin | StructureSignature::Commit | StructureSignature::Rollback => {
+                (self.state == Ready, Ready)
+            }
+            _ => (true, self.state),
+        };
+
+        if allowed { Some(next) } else { None }
+    }
+
+    /// Send the queued requests.
+    pub fn flush(&mut self) -> GraphResult<()> {
+        if self.is_defunct() {
+            return Err(GraphError::ConnectionDefunct)
+        }
+
+        match self.transport().send() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(self.transport_error(e)),
+        }
+    }
+
+    /// Send the queued requests and receive the replies to every request sent, in order, as
+    /// the kind and field of each message (records included).
+    ///
+    /// If any request failed, the failure is acknowledged once all the replies are received
+    /// and the first one is returned.
+    pub fn sync(&mut self) -> GraphResult<Vec<(ServerMessage, Value)>> {
+        self.flush()?;
+
+        let mut replies = Vec::new();
+        let mut failure = None;
+        while !self.requests.is_empty() {
+            let data = self.receive_raw()?;
+            let reply = (ServerMessage::peek(data), result::field(data)?);
+
+            if reply.0 == ServerMessage::Failure && failure.is_none() {
+                let metadata = reply.1.as_map().cloned().unwrap_or_default();
+                failure = Some(Neo4jError::from_metadata(&metadata));
+            }
+            replies.push(reply);
+        }
+
+        if let Some(failure) = failure {
+            self.ack_failure();
+            return Err(failure.into())
+        }
+
+        Ok(replies)
+    }
+
+    // A transport failure discards whatever was buffered, as the server may have received
+    // part of it, and marks the connection as defunct.
+    fn transport_error(&mut self, e: io::Error) -> GraphError {
+        warn!("Marking connection as defunct after transport error: {}", e);
+        self.transport().clear();
+        self.state = ConnectionState::Defunct;
+        GraphError::Io(e)
+    }
+
+    // Any failure to read a whole message leaves the stream at an unknown position, so the
+    // connection is marked as defunct.
+    pub(crate) fn receive_raw(&mut self) -> GraphResult<&[u8]> {
+        if self.is_defunct() {
+            return Err(GraphError::ConnectionDefunct)
+        }
+
+        let timeout = self.wait_for_reply()?;
+
+        if !self.transport().raw().is_empty() {
+            self.flush()?;
+        }
+
+        let data = match self.transport.as_mut().expect(TRANSPORT_TAKEN).receive_message() {
+            Ok(data) => data,
+            // the reply may still arrive, so the connection is left for a RESET to clear
+            Err(GraphError::Io(ref e)) if timeout.is_some() && is_timeout(e) => {
+                return Err(GraphError::Timeout(timeout.unwrap()))
+            }
+            Err(e) => {
+                warn!("Marking connection as defunct after receive error: {}", e);
+                self.state = ConnectionState::Defunct;
+                return Err(e)
+            }
+        };
+
+        if log_enabled!(target: RECV_TARGET, LogLevel::Trace) {
+            trace!(target: RECV_TARGET, "S: {}", describe(data, false));
+        }
+
+        // A failure leaves the server ignoring requests until one sent after it clears it.
+        match ServerMessage::peek(data) {
+            ServerMessage::Record => {}
+            kind => {
+                self.requests.pop_front();
+                let cleared = self.requests.iter()
+                    .any(|&r| r == StructureSignature::AckFailure || r == StructureSignature::Reset);
+                if kind == ServerMessage::Failure && !cleared {
+                    self.state = ConnectionState::Failed;
+                }
+            }
+        }
+
+        Ok(data)
+    }
+
+    // Fail with `GraphError::Timeout` once the deadline has passed, otherwise bound the next
+    // read by the time left, returning the timeout if there is a deadline.
+    fn wait_for_reply(&mut self) -> GraphResult<Option<Duration>> {
+        let (deadline, timeout) = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Ok(None),
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(GraphError::Timeout(timeout))
+        }
+
+        if let Some(set_read_timeout) = self.set_read_timeout {
+            set_read_timeout(self.socket(), Some(remaining))?;
+        }
+        Ok(Some(timeout))
+    }
+
+    /// Fail with `GraphError::Timeout` when waiting for a reply past `timeout` from `started`,
+    /// until the query ends.
+    pub(crate) fn set_deadline(&mut self, started: Instant, timeout: Duration) {
+        self.deadline = Some((started + timeout, timeout));
+    }
+
+    /// Stop timing out the replies, see `set_deadline`.
+    pub(crate) fn clear_deadline(&mut self) {
+        if self.deadline.take().is_none() {
+            return
+        }
+
+        if let Some(set_read_timeout) = self.set_read_timeout {
+            if let Err(e) = set_read_timeout(self.socket(), None) {
+                warn!("Failed to clear the read timeout of the socket: {}", e);
+            }
+        }
+    }
+
+    // Receive the reply to a request, returning its metadata if it is a SUCCESS. A FAILURE is
+    // acknowledged before being returned.
+    fn receive_success(&mut self) -> GraphResult<Map> {
+        let data = self.receive_raw()?;
+        let result = success_metadata(data);
+        if let Err(GraphError::Neo4j(_)) = result {
+            self.ack_failure();
+        }
+        result
+    }
+
+    // Receive the replies to all the requests sent, returning the metadata of the last one.
+    fn receive_last(&mut self) -> GraphResult<Map> {
+        while self.requests.len() > 1 {
+            self.receive_raw()?;
+        }
+
+        let data = self.receive_raw()?;
+        success_metadata(data)
+    }
+
+    /// Acknowledge a FAILURE received from the server, which ignores any request until then.
+    /// The IGNORED replies to requests sent after the failure are discarded.
+    ///
+    /// The failure itself is what gets reported to the caller, so if acknowledging it fails the
+    /// error is only logged and the connection is marked as defunct. Nothing is sent if a RESET
+    /// sent after the failure already cleared it.
+    pub(crate) fn ack_failure(&mut self) {
+        if self.state != ConnectionState::Failed {
+            return
+        }
+
+        let result = if self.version >= HELLO_VERSION {
+            self.send(&Reset)
+        } else {
+            self.send(&AckFailure)
+        };
+        let result = result.and_then(|_| self.receive_last());
+        if let Err(e) = result {
+            warn!("Failed to acknowledge failure: {}", e);
+            self.state = ConnectionState::Defunct;
+        }
+    }
+
+    /// Send a message the driver does not model, e.g. from a newer protocol version, as its
+    /// structure signature and fields. Its replies are read with `fetch_raw`.
+    ///
+    /// The message is counted as a request awaiting a summary, like the ones the driver sends.
+    /// Its signature moves the state of the connection as it would for a modelled message, but
+    /// the state is not checked first.
+    pub fn send_raw(&mut self, signature: u8, fields: Vec<Value>) -> GraphResult<()> {
+        self.queue_checked(&Value::Structure(signature.into(), fields), false)?;
+        self.send_due()
+    }
+
+    /// Receive the next message as its structure signature and fields, which are left as sent
+    /// (nodes, for example, are `Value::Structure`s). A FAILURE is returned like any other
+    /// message, so it is up to the caller to acknowledge it.
+    pub fn fetch_raw(&mut self) -> GraphResult<(u8, Vec<Value>)> {
+        let data = self.receive_raw()?;
+        match Registry::empty().read_value(&mut Cursor::new(data))? {
+            Value::Structure(signature, fields) => Ok((signature.byte(), fields)),
+            other => Err(ProtocolViolation::InvalidMessage(format!("{:?}", other)).into()),
+        }
+    }
+
+    /// Receive the next message as a whole, e.g. `Value::Structure(StructureSignature::Success,
+    /// [metadata])`, to inspect any reply without decoding it into a message type. Unlike
+    /// `fetch_raw`, nodes, relationships and paths in it are decoded into their `Value`
+    /// variants, and a message that is not a structure is returned as read.
+    ///
+    /// A FAILURE is returned like any other message, so it is up to the caller to acknowledge
+    /// it.
+    pub fn fetch_value(&mut self) -> GraphResult<Value> {
+        let data = self.receive_raw()?;
+        Ok(Registry::default().read_value(&mut Cursor::new(data))?)
+    }
+
+    /// See `ChunkedStream::set_max_message_size`.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.transport().set_max_message_size(max_message_size);
+    }
+
+    pub fn socket(&self) -> &S {
+        self.transport.as_ref().expect(TRANSPORT_TAKEN).socket()
+    }
+
+    /// Initialize the connection without authentication, returning the metadata of the
+    /// server's reply.
+    pub fn init(&mut self, user_agent: &str) -> GraphResult<Map> {
+        self.authenticate(user_agent, &AuthToken::None)
+    }
+
+    /// Initialize the connection with `auth`, returning the metadata of the server's reply.
+    ///
+    /// The server closes the connection when initialization fails, so the connection is
+    /// defunct after a failure.
+    ///
+    /// From Bolt v3 (see `HELLO_VERSION`) HELLO is sent instead of INIT.
+    pub fn authenticate(&mut self, user_agent: &str, auth: &AuthToken) -> GraphResult<Map> {
+        if self.version >= HELLO_VERSION {
+            self.send(&Hello::new(user_agent).with_auth(auth))?;
+        } else {
+            self.send(&Init::new(user_agent).with_auth(auth))?;
+        }
+
+        let data = self.receive_raw()?;
+        let result = success_metadata(data);
+        match result {
+            Ok(ref metadata) => {
+                let field = |name| metadata.get(name).and_then(Value::as_string).map(|v| v.to_owned());
+                self.server_agent = field("server");
+                self.connection_id = field("connection_id");
+            }
+            Err(_) => self.state = ConnectionState::Defunct,
+        }
+        result
+    }
+
+    /// The agent of the server, e.g. `Neo4j/3.4.0`, once initialized.
+    pub fn server_agent(&self) -> Option<&str> {
+        self.server_agent.as_deref()
+    }
+
+    /// The `(major, minor, patch)` version of the server, read from its agent.
+    pub fn server_version(&self) -> Option<(u32, u32, u32)> {
+        self.server_agent().and_then(parse_server_version)
+    }
+
+    /// The id the server gave the connection, e.g. `bolt-42`, once initialized.
+    pub fn connection_id(&self) -> Option<&str> {
+        self.connection_id.as_deref()
+    }
+
+    /// Return the connection to a clean state, discarding the replies to requests sent before
+    /// (e.g. records still being streamed) and clearing a failure.
+    pub fn reset(&mut self) -> GraphResult<()> {
+        self.send(&Reset)?;
+
+        // replies to earlier requests come first, the last summary is the one for RESET
+        self.receive_last().map(|_| ())
+    }
+
+    /// Send RUN, returning the metadata of the server's reply, which includes the `fields` of
+    /// the result.
+    pub fn run(&mut self, query: &str) -> GraphResult<Map> {
+        self.run_with_params(query, Map::new())
+    }
+
+    /// Like `run`, with `params` bound to the `{name}` placeholders of `query`.
+    pub fn run_with_params(&mut self, query: &str, params: Map) -> GraphResult<Map> {
+        self.run_with_extra(query, params, Map::new())
+    }
+
+    // Like `run_with_params`, sending `extra` with RUN if the protocol version supports it.
+    pub(crate) fn run_with_extra(&mut self, query: &str, params: Map, extra: Map) -> GraphResult<Map> {
+        let params_count = params.len();
+        let message = self.run_message(query, params, extra)?;
+        self.start_query(query, params_count);
+        self.send(&message)?;
+
+        let result = self.receive_success();
+        if let Err(ref e) = result {
+            self.end_query(Some(e.to_string()));
+        }
+        result
+    }
+
+    /// Run `statement` and return a cursor over its records.
+    pub fn query(&mut self, statement: &str) -> GraphResult<ResultCursor<'_, S>> {
+        self.query_with_params(statement, Map::new())
+    }
+
+    /// Like `query`, with `params` bound to the placeholders of `statement`.
+    ///
+    /// RUN and PULL_ALL are sent together. If RUN fails the server ignores PULL_ALL, whose reply
+    /// is discarded when the failure is acknowledged.
+    pub fn query_with_params(&mut self, statement: &str, params: Map) -> GraphResult<ResultCursor<'_, S>> {
+        self.query_with_extra(statement, params, Map::new())
+    }
+
+    // Like `query_with_params`, sending `extra` with RUN if the protocol version supports it.
+    pub(crate) fn query_with_extra(&mut self, statement: &str, params: Map, extra: Map)
+        -> GraphResult<ResultCursor<'_, S>> {
+
+        let params_count = params.len();
+        let message = self.run_message(statement, params, extra)?;
+        self.start_query(statement, params_count);
+        self.queue(&message)?;
+        if self.version >= PULL_N_VERSION {
+            let fetch_size = self.fetch_size;
+            self.send(&Pull::new(fetch_size))?;
+        } else {
+            self.send(&PullAll)?;
+        }
+        let metadata = match self.receive_success() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                self.end_query(Some(e.to_string()));
+                if let GraphError::Timeout(_) = e {
+                    if let Err(e) = self.reset() {
+                        warn!("Failed to stop query after timeout: {}", e);
+                    }
+                }
+                return Err(e)
+            }
+        };
+
+        Ok(ResultCursor::new(self, metadata))
+    }
+
+    // Older versions have no room for `extra`, which is only advisory, so it is left out.
+    // Parameters the server would reject fail here, before anything is sent.
+    fn run_message(&self, statement: &str, params: Map, extra: Map) -> GraphResult<Run> {
+        validate_params(&params, self.version)?;
+        let message = Run::new(statement).with_params(params);
+        if self.version >= RUN_EXTRA_VERSION && !extra.is_empty() {
+            Ok(message.with_extra(extra))
+        } else {
+            Ok(message)
+        }
+    }
+
+    /// Pull the records of the statement sent by `run` into `records`, each as its list of
+    /// fields, returning the metadata of the server's reply ending them. A FAILURE is
+    /// acknowledged before being returned.
+    pub fn pull_all(&mut self, records: &mut Vec<List>) -> GraphResult<Map> {
+        if self.version >= PULL_N_VERSION {
+            self.send(&Pull::new(ALL_RECORDS))?;
+        } else {
+            self.send(&PullAll)?;
+        }
+
+        records.clear();
+        let result = self.receive_records(records);
+        self.end_query(result.as_ref().err().map(ToString::to_string));
+        result
+    }
+
+    // Receive records into `records` until the reply ending them, see `receive_success`.
+    fn receive_records(&mut self, records: &mut Vec<List>) -> GraphResult<Map> {
+        let result = loop {
+            let data = self.receive_raw()?;
+            if ServerMessage::peek(data) != ServerMessage::Record {
+                break success_metadata(data)
+            }
+
+            if let Value::List(values) = result::field(data)? {
+                records.push(values);
+            }
+            self.query_record();
+        };
+
+        if let Err(GraphError::Neo4j(_)) = result {
+            self.ack_failure();
+        }
+        result
+    }
+
+    /// Discard the records of the statement sent by `run` without transferring them, returning
+    /// the metadata of the server's reply.
+    pub fn discard_all(&mut self) -> GraphResult<Map> {
+        if self.version >= PULL_N_VERSION {
+            self.send(&Discard::new(ALL_RECORDS))?;
+        } else {
+            self.send(&DiscardAll)?;
+        }
+
+        if let Some(ref mut query) = self.query {
+            query.discarded = true;
+        }
+        let result = self.receive_success();
+        self.end_query(result.as_ref().err().map(ToString::to_string));
+        result
+    }
+
+    /// Send BEGIN with the transaction options in `extra`, returning the metadata of the
+    /// server's reply. Needs Bolt v3, see `TRANSACTION_MESSAGES_VERSION`.
+    pub fn begin(&mut self, extra: Map) -> GraphResult<Map> {
+        self.check_version("BEGIN", TRANSACTION_MESSAGES_VERSION)?;
+        self.send(&Begin::new().with_extra(extra))?;
+        self.receive_success()
+    }
+
+    /// Send COMMIT, returning the metadata of the server's reply, e.g. the `bookmark`. Needs
+    /// Bolt v3.
+    pub fn commit(&mut self) -> GraphResult<Map> {
+        self.check_version("COMMIT", TRANSACTION_MESSAGES_VERSION)?;
+        self.send(&Commit)?;
+        self.receive_success()
+    }
+
+    /// Send ROLLBACK, returning the metadata of the server's reply. Needs Bolt v3.
+    pub fn rollback(&mut self) -> GraphResult<Map> {
+        self.check_version("ROLLBACK", TRANSACTION_MESSAGES_VERSION)?;
+        self.send(&Rollback)?;
+        self.receive_success()
+    }
+
+    fn check_version(&self, feature: &'static str, since: u32) -> GraphResult<()> {
+        if self.version < since {
+            return Err(GraphError::Unsupported { feature, version: self.version })
+        }
+        Ok(())
+    }
+
+    // Request the next batch of records of a result whose last batch ended with `has_more`,
+    // or discard all of them. The server is still streaming the result.
+    pub(crate) fn request_more(&mut self, qid: Option<i64>, discard: bool) -> GraphResult<()> {
+        self.state = ConnectionState::Streaming;
+        match (discard, qid) {
+            (false, Some(qid)) => self.send(&Pull::new(self.fetch_size).with_qid(qid)),
+            (false, None) => self.send(&Pull::new(self.fetch_size)),
+            (true, Some(qid)) => self.send(&Discard::new(ALL_RECORDS).with_qid(qid)),
+            (true, None) => self.send(&Discard::new(ALL_RECORDS)),
+        }
+    }
+
+    // Start timing `statement`, sent with `params_count` parameters, if it is logged when slow
+    // or its lifecycle is logged at all.
+    fn start_query(&mut self, statement: &str, params_count: usize) {
+        let logged = log_enabled!(target: QUERY_TARGET, LogLevel::Debug);
+        if !logged && self.slow_query_threshold.is_none() {
+            self.query = None;
+            return
+        }
+
+        let query = QueryTimer::new(statement);
+        debug!(target: QUERY_TARGET, "query started: statement_hash={:016x} params={}",
+               query.hash, params_count);
+        self.query = Some(query);
+    }
+
+    // Count a record of the current query, logging when the first one arrives.
+    pub(crate) fn query_record(&mut self) {
+        if let Some(ref mut query) = self.query {
+            query.records += 1;
+            if query.records == 1 {
+                debug!(target: QUERY_TARGET, "query first record: statement_hash={:016x} after_ms={}",
+                       query.hash, query.started.elapsed().as_millis());
+            }
+        }
+    }
+
+    // End the current query, having failed with `error` if any, logging how it went and
+    // warning if it was slow.
+    pub(crate) fn end_query(&mut self, error: Option<String>) {
+        self.clear_deadline();
+        if let Some((query, elapsed)) = self.finish_query(error) {
+            let records = if query.discarded { "discarded".to_owned() } else { query.records.to_string() };
+            warn!("slow query: duration_ms={} records={} statement={:?}",
+                  elapsed.as_millis(), records, query.statement);
+        }
+    }
+
+    // Stop timing the current query, returning it if it took longer than the threshold.
+    fn finish_query(&mut self, error: Option<String>) -> Option<(QueryTimer, Duration)> {
+        let query = self.query.take()?;
+        let elapsed = query.started.elapsed();
+        match error {
+            None => debug!(target: QUERY_TARGET, "query completed: statement_hash={:016x} records={} duration_ms={}",
+                           query.hash, query.records, elapsed.as_millis()),
+            Some(e) => debug!(target: QUERY_TARGET, "query failed: statement_hash={:016x} records={} duration_ms={} error={:?}",
+                              query.hash, query.records, elapsed.as_millis(), e),
+        }
+
+        match self.slow_query_threshold {
+            Some(threshold) if elapsed > threshold => Some((query, elapsed)),
+            _ => None,
+        }
+    }
+}
+
+impl Connection<TcpStream> {
+    // Bound the reads of the socket by the time left before the deadline, so a server that
+    // stops replying does not block the client, see `set_deadline`.
+    pub(crate) fn with_read_timeouts(mut self) -> Self {
+        self.set_read_timeout = Some(TcpStream::set_read_timeout);
+        self
+    }
+}
+
+// How a read interrupted by the read timeout of the socket fails, depending on the platform.
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+impl<S: Read + Write> Drop for Connection<S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            debug!("Failed to close connection gracefully: {}", e);
+        }
+    }
+}
+
+// Read the version in an agent like `Neo4j/3.4.0`, ignoring a suffix such as `-beta01` and
+// taking missing components as 0.
+fn parse_server_version(agent: &str) -> Option<(u32, u32, u32)> {
+    let version = agent.split('/').nth(1)?;
+    let mut parts = version.split('.').map(|part| {
+        let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+        digits.parse::<u32>().ok()
+    });
+
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    let patch = parts.next().unwrap_or(Some(0)).unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+fn success_metadata(data: &[u8]) -> GraphResult<Map> {
+    let metadata = result::field(data)?.as_map().cloned().unwrap_or_default();
+    match ServerMessage::peek(data) {
+        ServerMessage::Success => Ok(metadata),
+        ServerMessage::Failure => Err(GraphError::Neo4j(Neo4jError::from_metadata(&metadata))),
+        kind => Err(GraphError::UnexpectedMessage(kind)),
+    }
+}
+
+// Identifies a statement in the `bolt::query` logs without its text, which may hold literal
+// values. Equal statements hash equally within a process, not across builds of the driver.
+fn statement_hash(statement: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    statement.hash(&mut hasher);
+    hasher.finish()
+}
+
+// A message as logged, with the INIT credentials and RUN parameter values hidden if `redact`.
+// Structures are shown as sent, not as the typed values they may be read into.
+fn describe(data: &[u8], redact: bool) -> String {
+    let mut message = match Registry::empty().read_value(&mut Cursor::new(data)) {
+        Ok(message) => message,
+        Err(_) => return data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "),
+    };
 
-        self.transport.write(&data).unwrap();
+    if redact {
+        if let Value::Structure(signature, ref mut fields) = message {
+            match signature {
+                // INIT has the auth token as its second field, HELLO merges it into its only one
+                StructureSignature::Init => if let Some(&mut Value::Map(ref mut auth)) = fields.last_mut() {
+                    if let Some(credentials) = auth.get_mut("credentials") {
+                        *credentials = REDACTED.into();
+                    }
+                }
+                StructureSignature::Run => if let Some(&mut Value::Map(ref mut params)) = fields.get_mut(1) {
+                    for value in params.values_mut() {
+                        *value = REDACTED.into();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    format!("{:?}", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::net::{TcpListener, TcpStream, Shutdown};
+    use rustc_serialize::{Encodable, Encoder};
+    use std::time::Duration;
+    use super::{Connection, ConnectionState, QueryTimer, describe, statement_hash};
+    use ::v1::error::{GraphError, ProtocolViolation};
+    use ::v1::protocol::server::ServerMessage;
+    use ::v1::packstream::serialize::EncoderError;
+    use ::v1::protocol::client::{AckFailure, PullAll, Reset, Run};
+    use ::v1::packstream::encode;
+    use ::v1::packstream::value::{Value, Map, StructureSignature};
+    use ::v1::testing::{self, Step};
+
+    struct TooLarge;
+
+    impl Encodable for TooLarge {
+        fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+            e.emit_struct("__STRUCTURE__\x01", 70_000, |_| Ok(()))
+        }
+    }
+
+    fn connection() -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (Connection::new(socket), server)
+    }
+
+    #[test]
+    fn encode_error_leaves_connection_usable() {
+        let (mut conn, mut server) = connection();
+
+        match conn.send(&TooLarge) {
+            Err(GraphError::Encode(EncoderError::InvalidStructureLength)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert_eq!(0, conn.transport().pending());
+        assert!(!conn.is_defunct());
+
+        conn.send(&Reset).unwrap();
+        assert_eq!(0, conn.transport().pending());
+
+        let mut buf = [0u8; 6];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!([0x00, 0x02, 0xB0, 0x0F, 0x00, 0x00], buf);
+    }
+
+    #[test]
+    fn transport_error_marks_connection_defunct() {
+        let (mut conn, _server) = connection();
+        conn.socket().shutdown(Shutdown::Write).unwrap();
+
+        match conn.send(&Reset) {
+            Err(GraphError::Io(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert_eq!(0, conn.transport().pending());
+        assert!(conn.is_defunct());
+
+        match conn.send(&Reset) {
+            Err(GraphError::ConnectionDefunct) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn message_too_large_marks_connection_defunct() {
+        let (mut conn, mut server) = connection();
+        conn.set_max_message_size(2);
+        server.write_all(&[0x00, 0x03, 0xB1, 0x70, 0xA0, 0x00, 0x00]).unwrap();
+
+        match conn.receive_raw() {
+            Err(GraphError::Protocol(ProtocolViolation::MessageTooLarge { limit: 2 })) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert!(conn.is_defunct());
+    }
+
+    #[test]
+    fn finish_query_above_threshold() {
+        let (mut conn, _server) = connection();
+        conn.set_slow_query_threshold(Some(Duration::from_millis(0)));
+        conn.query = Some(QueryTimer { records: 2, ..QueryTimer::new("RETURN 1") });
+
+        let (query, _) = conn.finish_query(None).unwrap();
+        assert_eq!("RETURN 1", query.statement);
+        assert_eq!(2, query.records);
+        assert!(conn.query.is_none());
+    }
+
+    #[test]
+    fn query_lifecycle_is_logged() {
+        use ::testing::MockStream;
+        use v1::testing::{capture_logs, fields};
+
+        let mut conn = MockStream::new()
+            .success(fields(&["x"]))
+            .record(vec![Value::from(1)])
+            .record(vec![Value::from(2)])
+            .success(Value::map().build())
+            .failure("Neo.ClientError.Statement.SyntaxError", "bad")
+            .connection();
+
+        let logs = capture_logs(|| {
+            let params = Value::map().entry("x", 1).build().as_map().cloned().unwrap();
+            conn.query_with_params("UNWIND [1, 2] AS x RETURN x", params).unwrap().consume().unwrap();
+            assert!(conn.query("RETURN").is_err());
+        });
+        let logs: Vec<&str> = logs.iter().map(String::as_str).filter(|l| l.starts_with("bolt::query")).collect();
+
+        let hash = format!("statement_hash={:016x}", statement_hash("UNWIND [1, 2] AS x RETURN x"));
+        assert_eq!(5, logs.len(), "{:?}", logs);
+        assert_eq!(format!("bolt::query: query started: {} params=1", hash), logs[0]);
+        assert!(logs[1].starts_with(&format!("bolt::query: query first record: {} after_ms=", hash)));
+        assert!(logs[2].starts_with(&format!("bolt::query: query completed: {} records=2 duration_ms=", hash)));
+        assert!(logs[3].starts_with("bolt::query: query started: "));
+        assert!(logs[4].contains(" records=0 duration_ms="));
+        assert!(logs[4].contains(" error=\"Neo.ClientError.Statement.SyntaxError: bad"), "{}", logs[4]);
+    }
+
+    #[test]
+    fn statement_hash_identifies_statement() {
+        assert_eq!(statement_hash("RETURN 1"), statement_hash("RETURN 1"));
+        assert!(statement_hash("RETURN 1") != statement_hash("RETURN 2"));
+    }
+
+    #[test]
+    fn finish_query_below_threshold() {
+        let (mut conn, _server) = connection();
+        conn.set_slow_query_threshold(Some(Duration::from_secs(3600)));
+        conn.query = Some(QueryTimer::new("RETURN 1"));
+
+        assert!(conn.finish_query(None).is_none());
+        assert!(conn.query.is_none());
+    }
+
+    #[test]
+    fn init_failure_is_returned() {
+        let (mut conn, mut server) = connection();
+        testing::failure(&mut server, "Neo.ClientError.Security.Unauthorized", "Invalid credentials");
+
+        match conn.init("MyClient/1.0") {
+            Err(GraphError::Neo4j(ref e)) => assert_eq!("Neo.ClientError.Security.Unauthorized", e.code),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(conn.is_defunct());
+    }
+
+    #[test]
+    fn init_stores_server_metadata() {
+        let (mut conn, mut server) = connection();
+        assert_eq!(None, conn.server_agent());
+
+        testing::reply(&mut server, testing::SUCCESS, Value::map()
+            .entry("server", "Neo4j/3.4.0")
+            .entry("connection_id", "bolt-42")
+            .build());
+
+        conn.init("MyClient/1.0").unwrap();
+        assert_eq!(Some("Neo4j/3.4.0"), conn.server_agent());
+        assert_eq!(Some((3, 4, 0)), conn.server_version());
+        assert_eq!(Some("bolt-42"), conn.connection_id());
+    }
+
+    #[test]
+    fn parse_server_versions() {
+        assert_eq!(Some((3, 5, 0)), super::parse_server_version("Neo4j/3.5"));
+        assert_eq!(Some((4, 0, 0)), super::parse_server_version("Neo4j/4.0.0-beta01"));
+        assert_eq!(Some((3, 4, 12)), super::parse_server_version("Neo4j/3.4.12"));
+        assert_eq!(None, super::parse_server_version("Neo4j"));
+        assert_eq!(None, super::parse_server_version("Neo4j/dev"));
+    }
+
+    #[test]
+    fn protocol_version() {
+        let (conn, _server) = connection();
+        assert_eq!(::v1::VERSION, conn.version());
+        assert_eq!(2, conn.with_version(2).version());
+    }
+
+    #[test]
+    fn run_returns_metadata() {
+        let (mut conn, mut server) = connection();
+        testing::reply(&mut server, testing::SUCCESS, testing::fields(&["n"]));
+
+        let metadata = conn.run("RETURN 1 AS n").unwrap();
+        assert_eq!(Some(&Value::list().item("n").build()), metadata.get("fields"));
+    }
+
+    #[test]
+    fn run_sends_params() {
+        let (mut conn, mut server) = connection();
+        testing::reply(&mut server, testing::SUCCESS, testing::fields(&["n"]));
+
+        let mut params = Map::new();
+        params.insert("n".to_owned(), Value::from(1));
+        conn.run_with_params("RETURN {n} AS n", params).unwrap();
+
+        let expected = encode(&Run::new("RETURN {n} AS n").with_param("n", 1).unwrap()).unwrap();
+        assert_eq!(Some(expected), testing::read_message(&mut server));
+    }
+
+    #[test]
+    fn invalid_params_are_not_sent() {
+        let (mut conn, mut server) = connection();
+        testing::reply(&mut server, testing::SUCCESS, testing::fields(&["n"]));
+
+        let mut params = Map::new();
+        params.insert("d".to_owned(), Value::Structure(StructureSignature::Unknown(0x45), vec![]));
+        match conn.run_with_params("RETURN {d}", params) {
+            Err(GraphError::InvalidParameter(ref e)) if e.path == "d" => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(ConnectionState::Ready, conn.state());
+
+        conn.run("RETURN 1 AS n").unwrap();
+        let expected = encode(&Run::new("RETURN 1 AS n")).unwrap();
+        assert_eq!(Some(expected), testing::read_message(&mut server));
+    }
+
+    #[test]
+    fn metrics_count_messages() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Record(Value::list().item(1).build()),
+            Step::Success(Value::map().build()),
+        ]);
+
+        conn.query("RETURN 1 AS n").unwrap().into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+        let metrics = conn.metrics();
+        assert_eq!(Some(&1), metrics.messages_sent.get("RUN"));
+        assert_eq!(Some(&1), metrics.messages_sent.get("PULL_ALL"));
+        assert_eq!(Some(&2), metrics.messages_received.get("SUCCESS"));
+        assert_eq!(Some(&1), metrics.messages_received.get("RECORD"));
+        assert_eq!((2, 3), (metrics.total_messages_sent(), metrics.total_messages_received()));
+        assert_eq!(1, metrics.round_trips);
+        assert!(metrics.bytes_sent > 0 && metrics.bytes_received > 0);
+    }
+
+    #[test]
+    fn discard_all_returns_summary() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Success(Value::map().entry("type", "r").build()),
+        ]);
+
+        conn.run("UNWIND range(1, 1000) AS n RETURN n").unwrap();
+        let metadata = conn.discard_all().unwrap();
+
+        assert_eq!(Some(&Value::from("r")), metadata.get("type"));
+        assert_eq!(Some(0x10), testing::try_expect(&mut server));
+        assert_eq!(Some(0x2F), testing::try_expect(&mut server));
+        assert_eq!(0, conn.pending());
+    }
+
+    #[test]
+    fn pull_all_returns_records_and_metadata() {
+        let (mut conn, mut server) = connection();
+        testing::reply(&mut server, testing::SUCCESS, testing::fields(&["n"]));
+        testing::reply(&mut server, testing::RECORD, Value::list().item(1).build());
+        testing::reply(&mut server, testing::RECORD, Value::list().item(2).build());
+        testing::reply(&mut server, testing::SUCCESS, Value::map().entry("type", "r").build());
+
+        conn.run("UNWIND [1, 2] AS n RETURN n").unwrap();
+        let mut records = vec![vec![Value::Null]];
+        let metadata = conn.pull_all(&mut records).unwrap();
+
+        assert_eq!(vec![vec![Value::from(1)], vec![Value::from(2)]], records);
+        assert_eq!(Some(&Value::from("r")), metadata.get("type"));
+        assert_eq!(ConnectionState::Ready, conn.state());
+        assert_eq!(0, conn.pending());
+    }
+
+    #[test]
+    fn pull_all_failure_is_acknowledged() {
+        let (mut conn, mut server) = connection();
+        testing::reply(&mut server, testing::SUCCESS, testing::fields(&["n"]));
+        testing::failure(&mut server, "Neo.ClientError.Statement.ArithmeticError", "/ by zero");
+        testing::success(&mut server);
+
+        conn.run("RETURN 1 / 0 AS n").unwrap();
+        match conn.pull_all(&mut Vec::new()) {
+            Err(GraphError::Neo4j(ref e)) => assert_eq!("Neo.ClientError.Statement.ArithmeticError", e.code),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(ConnectionState::Ready, conn.state());
+        assert_eq!(0, conn.pending());
+    }
+
+    #[test]
+    fn run_while_streaming_is_invalid() {
+        let (mut conn, mut server) = connection();
+        testing::reply(&mut server, testing::SUCCESS, testing::fields(&["n"]));
+
+        conn.run("RETURN 1 AS n").unwrap();
+        assert_eq!(ConnectionState::Streaming, conn.state());
+
+        match conn.run("RETURN 2 AS n") {
+            Err(GraphError::InvalidState { state: ConnectionState::Streaming, request: StructureSignature::Run }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(0, conn.pending());
+        assert!(!conn.is_defunct());
+    }
+
+    #[test]
+    fn pull_without_run_is_invalid() {
+        let (mut conn, _server) = connection();
+
+        match conn.discard_all() {
+            Err(GraphError::InvalidState { state: ConnectionState::Ready, request: StructureSignature::DiscardAll }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match conn.send(&AckFailure) {
+            Err(GraphError::InvalidState { state: ConnectionState::Ready, request: StructureSignature::AckFailure }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(0, conn.pending());
+    }
+
+    #[test]
+    fn state_follows_requests_and_replies() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Failure("Neo.ClientError.Statement.SyntaxError"),
+            Step::Ignored,
+            Step::Success(Value::map().build()),
+            Step::Success(testing::fields(&["n"])),
+            Step::Success(Value::map().build()),
+        ]);
+
+        conn.queue(&Run::new("RETRUN 1")).unwrap();
+        conn.queue(&PullAll).unwrap();
+        assert_eq!(ConnectionState::Ready, conn.state());
+
+        assert!(conn.receive_raw().is_ok());
+        assert_eq!(ConnectionState::Failed, conn.state());
+        match conn.send(&Run::new("RETURN 1")) {
+            Err(GraphError::InvalidState { state: ConnectionState::Failed, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        conn.ack_failure();
+        assert_eq!(ConnectionState::Ready, conn.state());
+        assert_eq!(0, conn.pending());
+
+        conn.run("RETURN 1 AS n").unwrap();
+        conn.discard_all().unwrap();
+        assert_eq!(ConnectionState::Ready, conn.state());
+    }
+
+    #[test]
+    fn failure_before_queued_reset_is_cleared() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Failure("Neo.ClientError.Statement.SyntaxError"),
+            Step::Success(Value::map().build()),
+        ]);
+
+        conn.queue(&Run::new("RETRUN 1")).unwrap();
+        conn.queue(&Reset).unwrap();
+
+        assert!(conn.sync().is_err());
+        assert_eq!(ConnectionState::Ready, conn.state());
+        assert_eq!(Some(0x10), testing::try_expect(&mut server));
+        assert_eq!(Some(0x0F), testing::try_expect(&mut server));
+        assert!(!conn.is_defunct());
+    }
+
+    #[test]
+    fn closed_socket_is_io_error() {
+        let (mut conn, server) = connection();
+        drop(server);
+
+        match conn.init("MyClient/1.0") {
+            Err(GraphError::Io(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(conn.is_defunct());
+    }
+
+    #[test]
+    fn reset_while_streaming() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Record(Value::list().item(1).build()),
+            Step::Record(Value::list().item(2).build()),
+            Step::Success(Value::map().build()),
+            Step::Success(Value::map().build()),
+            Step::Success(testing::fields(&["n"])),
+        ]);
+
+        conn.run("UNWIND [1, 2] AS n RETURN n").unwrap();
+        conn.send(&PullAll).unwrap();
+        assert_eq!(1, conn.pending());
+
+        conn.reset().unwrap();
+        assert_eq!(0, conn.pending());
+        assert!(conn.run("RETURN 1 AS n").is_ok());
+    }
+
+    #[test]
+    fn reset_after_failure() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Failure("Neo.ClientError.Statement.SyntaxError"),
+            Step::Success(Value::map().build()),
+            Step::Success(Value::map().build()),
+        ]);
+
+        assert!(conn.run("RETRUN 1").is_err());
+        conn.reset().unwrap();
+        assert!(!conn.is_defunct());
+    }
+
+    #[test]
+    fn reset_failure_is_returned() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![Step::Failure("Neo.DatabaseError.General.UnknownError")]);
+
+        match conn.reset() {
+            Err(GraphError::Neo4j(ref e)) => assert!(e.is_database_error()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn alive_connection_answers_reset() {
+        let (mut conn, mut server) = connection();
+        testing::success(&mut server);
+
+        assert!(conn.is_alive());
+        assert_eq!(Some(0x0F), testing::try_expect(&mut server));
+    }
+
+    #[test]
+    fn closed_connection_is_not_alive() {
+        let (mut conn, server) = connection();
+        drop(server);
+
+        assert!(!conn.is_alive());
+        assert!(conn.is_defunct());
+        assert!(!conn.is_alive());
+    }
+
+    #[test]
+    fn failure_is_acknowledged() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Failure("Neo.ClientError.Statement.SyntaxError"),
+            Step::Success(Value::map().build()),
+        ]);
+
+        assert!(conn.run("RETRUN 1").is_err());
+        assert_eq!(Some(0x10), testing::try_expect(&mut server));
+        assert_eq!(Some(0x0E), testing::try_expect(&mut server));
+        assert_eq!(0, conn.pending());
+        assert!(!conn.is_defunct());
+    }
+
+    #[test]
+    fn ignored_replies_are_drained() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Failure("Neo.ClientError.Statement.SyntaxError"),
+            Step::Ignored,
+            Step::Success(Value::map().build()),
+            Step::Success(testing::fields(&["n"])),
+        ]);
+
+        // PULL_ALL pipelined after the failing RUN is ignored
+        conn.send(&Run::new("RETRUN 1")).unwrap();
+        conn.send(&PullAll).unwrap();
+        assert!(conn.receive_success().is_err());
+
+        assert_eq!(0, conn.pending());
+        assert!(conn.run("RETURN 1 AS n").is_ok());
+    }
+
+    #[test]
+    fn failed_acknowledgement_makes_defunct() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Failure("Neo.ClientError.Statement.SyntaxError"),
+            Step::Close,
+        ]);
+
+        match conn.run("RETRUN 1") {
+            Err(GraphError::Neo4j(ref e)) => assert!(e.is_client_error()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(conn.is_defunct());
+    }
+
+    #[test]
+    fn queued_requests_are_sent_on_sync() {
+        let (mut conn, mut server) = connection();
+        conn.queue(&Run::new("RETURN 1 AS n")).unwrap();
+        conn.queue(&PullAll).unwrap();
+        assert_eq!(2, conn.pending());
+
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Record(Value::list().item(1).build()),
+            Step::Success(Value::map().build()),
+        ]);
+
+        let replies = conn.sync().unwrap();
+        let kinds: Vec<_> = replies.iter().map(|r| r.0.clone()).collect();
+        assert_eq!(vec![ServerMessage::Success, ServerMessage::Record, ServerMessage::Success], kinds);
+        assert_eq!(Value::list().item(1).build(), replies[1].1);
+        assert_eq!(0, conn.pending());
+
+        assert_eq!(Some(0x10), testing::try_expect(&mut server));
+        assert_eq!(Some(0x3F), testing::try_expect(&mut server));
+    }
+
+    #[test]
+    fn sync_returns_first_failure() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Failure("Neo.ClientError.Statement.SyntaxError"),
+            Step::Ignored,
+            Step::Success(Value::map().build()),
+        ]);
+
+        conn.queue(&Run::new("RETRUN 1")).unwrap();
+        conn.queue(&PullAll).unwrap();
+
+        match conn.sync() {
+            Err(GraphError::Neo4j(ref e)) => assert!(e.is_client_error()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(0, conn.pending());
+        assert!(!conn.is_defunct());
+    }
+
+    #[test]
+    fn query_sends_run_and_pull_all_together() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Success(Value::map().build()),
+        ]);
+
+        conn.query("RETURN 1 AS n").unwrap().consume().unwrap();
+
+        assert_eq!(Some(0x10), testing::try_expect(&mut server));
+        assert_eq!(Some(0x3F), testing::try_expect(&mut server));
+    }
+
+    // Scripted exchanges checking the connection state and the error of every step.
+
+    #[test]
+    fn scripted_successful_queries() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Record(Value::list().item(1).build()),
+            Step::Success(Value::map().build()),
+            Step::Success(testing::fields(&[])),
+            Step::Success(Value::map().build()),
+        ]);
+
+        assert_eq!(1, conn.query("RETURN 1 AS n").unwrap().fetch().unwrap().unwrap().values().len());
+        assert!(!conn.is_defunct());
+
+        assert!(conn.query("CREATE ()").unwrap().fetch().unwrap().is_none());
+        assert!(!conn.is_defunct());
+    }
+
+    #[test]
+    fn scripted_failed_run_keeps_connection() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Failure("Neo.ClientError.Statement.SyntaxError"),
+            Step::Ignored,
+            Step::Success(Value::map().build()),
+            Step::Success(testing::fields(&["n"])),
+            Step::Success(Value::map().build()),
+        ]);
+
+        match conn.query("RETRUN 1") {
+            Err(GraphError::Neo4j(ref e)) => assert!(e.is_client_error()),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("expected failure"),
+        }
+        assert!(!conn.is_defunct());
+
+        assert!(conn.query("RETURN 1 AS n").unwrap().fetch().unwrap().is_none());
+    }
+
+    #[test]
+    fn scripted_failure_while_streaming() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Record(Value::list().item(1).build()),
+            Step::Failure("Neo.DatabaseError.General.UnknownError"),
+            Step::Success(Value::map().build()),
+        ]);
+
+        let mut cursor = conn.query("UNWIND [1, 0] AS n RETURN 1 / n").unwrap();
+        assert!(cursor.fetch().unwrap().is_some());
+        match cursor.fetch() {
+            Err(GraphError::Neo4j(ref e)) => assert!(e.is_database_error()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(cursor.fetch().unwrap().is_none());
+        drop(cursor);
+
+        assert!(!conn.is_defunct());
+    }
+
+    #[test]
+    fn scripted_server_closes_mid_stream() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Record(Value::list().item(1).build()),
+            Step::Close,
+        ]);
+
+        {
+            let mut cursor = conn.query("UNWIND range(1, 10) AS n RETURN n").unwrap();
+            assert!(cursor.fetch().unwrap().is_some());
+            match cursor.fetch() {
+                Err(GraphError::Io(_)) => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+        assert!(conn.is_defunct());
+
+        let result = conn.query("RETURN 1");
+        match result {
+            Err(GraphError::ConnectionDefunct) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("expected defunct connection"),
+        }
+    }
+
+    #[test]
+    fn scripted_truncated_message() {
+        let (mut conn, mut server) = connection();
+        testing::play(&mut server, vec![
+            Step::Raw(vec![0x00, 0x10, 0xB1, 0x70]),
+            Step::Close,
+        ]);
+
+        match conn.query("RETURN 1") {
+            Err(GraphError::Io(_)) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("expected io error"),
+        }
+        assert!(conn.is_defunct());
+    }
+
+    #[test]
+    fn describe_redacts_credentials_and_parameters() {
+        use ::v1::auth::AuthToken;
+        use ::v1::protocol::client::{Hello, Init};
+
+        let init = encode(&Init::new("MyClient/1.0").with_auth(&AuthToken::basic("neo4j", "secret"))).unwrap();
+        let logged = describe(&init, true);
+        assert!(logged.contains("neo4j"));
+        assert!(!logged.contains("secret"));
+        assert!(describe(&init, false).contains("secret"));
+
+        let hello = encode(&Hello::new("MyClient/1.0").with_auth(&AuthToken::basic("neo4j", "secret"))).unwrap();
+        assert!(!describe(&hello, true).contains("secret"));
+
+        let run = Run::new("MATCH (u:User {password: {password}}) RETURN u").with_param("password", "hunter2").unwrap();
+        let run = encode(&run).unwrap();
+        let logged = describe(&run, true);
+        assert!(logged.contains("MATCH (u:User"));
+        assert!(logged.contains("password"));
+        assert!(!logged.contains("hunter2"));
+
+        assert_eq!("B1 70", describe(&[0xB1, 0x70], true));
+    }
+
+    #[test]
+    fn extra_is_sent_from_run_extra_version() {
+        use ::testing::MockStream;
+
+        let mut extra = Map::new();
+        extra.insert("mode".to_owned(), Value::from("r"));
+
+        for &(version, fields) in &[(1, 2), (super::RUN_EXTRA_VERSION, 3)] {
+            let mut conn = MockStream::new()
+                .success(Value::map().build())
+                .success(Value::map().build())
+                .connection()
+                .with_version(version);
+
+            conn.query_with_extra("RETURN 1", Map::new(), extra.clone()).unwrap().consume().unwrap();
+
+            let requests = conn.socket().requests();
+            assert_eq!(Some((StructureSignature::Run, fields)), requests[0].as_struct().map(|(s, f)| (s, f.len())));
+        }
+    }
+
+    #[test]
+    fn bolt_v3_messages() {
+        use ::testing::MockStream;
+        use ::v1::auth::AuthToken;
+
+        let mut conn = MockStream::new()
+            .success(Value::map().entry("server", "Neo4j/3.5.0").build())
+            .failure("Neo.ClientError.Statement.SyntaxError", "Invalid input")
+            .success(Value::map().build())
+            .connection()
+            .with_version(super::HELLO_VERSION);
+
+        conn.authenticate("MyClient/1.0", &AuthToken::basic("neo4j", "secret")).unwrap();
+        assert!(conn.begin(Map::new()).is_err());
+
+        let requests = conn.socket().requests();
+        let (signature, fields) = requests[0].as_struct().unwrap();
+        assert_eq!(StructureSignature::Init, signature);
+        assert_eq!(1, fields.len());
+        assert_eq!(Some(&Value::from("MyClient/1.0")), fields[0].as_map().and_then(|m| m.get("user_agent")));
+        assert_eq!(Some(StructureSignature::Begin), requests[1].as_struct().map(|(s, _)| s));
+        // a failure is cleared with RESET, as there is no ACK_FAILURE
+        assert_eq!(Some(StructureSignature::Reset), requests[2].as_struct().map(|(s, _)| s));
+        assert_eq!(ConnectionState::Ready, conn.state());
+    }
+
+    #[test]
+    fn close_sends_goodbye_from_bolt_v3() {
+        use ::testing::MockStream;
+
+        for &(version, ref sent) in &[(1, vec![]), (super::HELLO_VERSION, vec![StructureSignature::Goodbye])] {
+            let mut conn = MockStream::new().connection().with_version(version);
+
+            conn.close().unwrap();
+            assert!(conn.is_defunct());
+            assert_eq!(0, conn.pending());
+            let requests: Vec<_> = conn.socket().requests().iter().filter_map(|r| r.as_struct().map(|(s, _)| s)).collect();
+            assert_eq!(*sent, requests);
+
+            // closing again sends nothing more
+            conn.close().unwrap();
+            assert_eq!(sent.len(), conn.socket().requests().len());
+        }
+    }
+
+    #[test]
+    fn dropped_connection_says_goodbye() {
+        let (conn, mut server) = connection();
+        drop(conn.with_version(super::HELLO_VERSION));
+
+        let mut data = Vec::new();
+        server.read_to_end(&mut data).unwrap();
+        assert_eq!(vec![0x00, 0x02, 0xB0, 0x02, 0x00, 0x00], data);
+    }
+
+    #[test]
+    fn transaction_messages_need_bolt_v3() {
+        let (mut conn, _server) = connection();
+
+        match conn.commit() {
+            Err(GraphError::Unsupported { feature: "COMMIT", version: 1 }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(0, conn.pending());
+    }
+
+    #[test]
+    fn raw_messages() {
+        use ::testing::MockStream;
+        use v1::error::ProtocolViolation;
+
+        let node = Value::Structure(StructureSignature::Node, vec![Value::from(1), Value::List(vec![]), Value::map().build()]);
+        let mut conn = MockStream::new()
+            .record(vec![node.clone()])
+            .success(Value::map().entry("type", "r").build())
+            .raw(&[0x00, 0x01, 0x2A, 0x00, 0x00])
+            .connection();
+
+        conn.send_raw(0x54, vec![Value::from("x"), Value::Null]).unwrap();
+        assert_eq!(vec![Value::Structure(StructureSignature::Unknown(0x54), vec![Value::from("x"), Value::Null])], conn.socket().requests());
+        assert_eq!(1, conn.pending());
+
+        assert_eq!((0x71, vec![Value::List(vec![node])]), conn.fetch_raw().unwrap());
+        assert_eq!((0x70, vec![Value::map().entry("type", "r").build()]), conn.fetch_raw().unwrap());
+        assert_eq!(0, conn.pending());
+
+        match conn.fetch_raw() {
+            Err(GraphError::Protocol(ProtocolViolation::InvalidMessage(ref found))) => assert_eq!("Integer(42)", found),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fetch_value_decodes_whole_message() {
+        use ::testing::MockStream;
+        use v1::graph::Node;
+
+        let node = Value::Structure(StructureSignature::Node, vec![Value::from(1), Value::List(vec![]), Value::map().build()]);
+        let mut conn = MockStream::new()
+            .record(vec![node])
+            .failure("Neo.ClientError.Statement.SyntaxError", "bad")
+            .raw(&[0x00, 0x01, 0x2A, 0x00, 0x00])
+            .connection();
+        conn.send_raw(0x2F, vec![]).unwrap();
+
+        match conn.fetch_value().unwrap() {
+            Value::Structure(StructureSignature::Record, ref fields) => match fields[..] {
+                [Value::List(ref values)] => match values[..] {
+                    [Value::Node(Node { id: Some(1), .. })] => {}
+                    ref other => panic!("unexpected values: {:?}", other),
+                },
+                ref other => panic!("unexpected fields: {:?}", other),
+            },
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        match conn.fetch_value().unwrap() {
+            Value::Structure(StructureSignature::Failure, fields) => {
+                assert_eq!(Value::from("Neo.ClientError.Statement.SyntaxError"), fields[0].as_map().unwrap()["code"]);
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert_eq!(ConnectionState::Failed, conn.state());
+        assert_eq!(Value::Integer(42), conn.fetch_value().unwrap());
+    }
 
-        self.transport.flush(true).unwrap();
-        self.transport.send().unwrap();
+    #[test]
+    fn requests_wait_for_reply_with_per_sync_policy() {
+        use ::testing::MockStream;
+        use v1::transport::FlushPolicy;
 
-        let data = self.transport.receive().unwrap();
+        let mut conn = MockStream::new()
+            .success(Value::map().build())
+            .success(Value::map().build())
+            .connection();
+        conn.set_flush_policy(FlushPolicy::PerSync);
 
-        println!("[ {}]", data.iter().fold(String::new(), |mut acc, i| { acc.push_str(&format!("{:02X} ", i)); acc }));
+        conn.send_raw(0x2F, vec![]).unwrap();
+        conn.send_raw(0x3F, vec![]).unwrap();
+        assert!(conn.socket().requests().is_empty());
 
-        let mut cur = Cursor::new(data);
-        let msg: Message<Vec<u32>> = decode(&mut cur).unwrap();
-        msg
+        assert_eq!(0x70, conn.fetch_raw().unwrap().0);
+        assert_eq!(vec![Value::Structure(StructureSignature::DiscardAll, vec![]), Value::Structure(StructureSignature::PullAll, vec![])], conn.socket().requests());
+        assert_eq!(0x70, conn.fetch_raw().unwrap().0);
     }
 }
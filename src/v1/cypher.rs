@@ -0,0 +1,98 @@
+// Escaping of the parts of a statement that cannot be passed as parameters, e.g. labels,
+// relationship types and property names. Values should always be sent as parameters instead.
+
+use std::fmt::Write;
+
+/// Quote `name` with backticks so it is read as a single identifier, whatever it contains.
+///
+/// Backticks in `name` are doubled, e.g. ``a`b`` becomes ```a``b```.
+pub fn escape_identifier(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len() + 2);
+    escaped.push('`');
+    for c in name.chars() {
+        if c == '`' {
+            escaped.push('`');
+        }
+        escaped.push(c);
+    }
+    escaped.push('`');
+    escaped
+}
+
+/// Quote `text` as a Cypher string literal, escaping quotes, backslashes and control
+/// characters.
+pub fn escape_string_literal(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('\'');
+    for c in text.chars() {
+        match c {
+            '\'' => escaped.push_str("\\'"),
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            c if c.is_control() => { let _ = write!(escaped, "\\u{:04X}", c as u32); }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('\'');
+    escaped
+}
+
+/// Each of `labels` quoted with `escape_identifier` and prefixed with a colon, to be appended
+/// to a node variable as in `format!("MATCH (n{}) RETURN n", labels(&names))`.
+pub fn labels<I>(labels: I) -> String
+    where I: IntoIterator, I::Item: AsRef<str> {
+
+    let mut expression = String::new();
+    for label in labels {
+        expression.push(':');
+        expression.push_str(&escape_identifier(label.as_ref()));
+    }
+    expression
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifier_is_quoted() {
+        assert_eq!("`name`", escape_identifier("name"));
+        assert_eq!("`first name`", escape_identifier("first name"));
+        assert_eq!("``", escape_identifier(""));
+    }
+
+    #[test]
+    fn backticks_in_identifier_are_doubled() {
+        assert_eq!("`a``b`", escape_identifier("a`b"));
+        assert_eq!("`Person``) DETACH DELETE (n`", escape_identifier("Person`) DETACH DELETE (n"));
+    }
+
+    #[test]
+    fn string_literal_is_quoted() {
+        assert_eq!("'Alice'", escape_string_literal("Alice"));
+        assert_eq!("''", escape_string_literal(""));
+        assert_eq!("'caf\u{E9}'", escape_string_literal("caf\u{E9}"));
+    }
+
+    #[test]
+    fn string_literal_escapes() {
+        assert_eq!(r"'it\'s'", escape_string_literal("it's"));
+        assert_eq!(r#"'\"quoted\"'"#, escape_string_literal("\"quoted\""));
+        assert_eq!(r"'back\\slash'", escape_string_literal(r"back\slash"));
+        assert_eq!(r"'a\nb\tc\r'", escape_string_literal("a\nb\tc\r"));
+        assert_eq!(r"'\u0000\u001B'", escape_string_literal("\u{0}\u{1B}"));
+    }
+
+    #[test]
+    fn label_expression() {
+        assert_eq!(":`Person`", labels(&["Person"]));
+        assert_eq!(":`Person`:`Admin`", labels(vec!["Person".to_owned(), "Admin".to_owned()]));
+        assert_eq!(":`A``B`", labels(&["A`B"]));
+        assert_eq!("", labels(Vec::<String>::new()));
+    }
+}
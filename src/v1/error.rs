@@ -0,0 +1,381 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use super::connection::ConnectionState;
+use super::net::ConnectError;
+use super::packstream::deserialize::DecoderError;
+use super::packstream::serialize::EncoderError;
+use super::packstream::value::{Map, StructureSignature};
+use super::protocol::handshake::HandshakeError;
+use super::protocol::server::ServerMessage;
+use super::statement::InvalidParameter;
+use super::uri::UriError;
+
+#[derive(Debug)]
+pub enum GraphError {
+    Io(io::Error),
+    /// None of the addresses of the server accepted the connection.
+    Connect(ConnectError),
+    Uri(UriError),
+    Handshake(HandshakeError),
+    DriverClosed,
+    Encode(EncoderError),
+    Decode(DecoderError),
+    ConnectionDefunct,
+    AcquisitionTimeout(Duration),
+    Protocol(ProtocolViolation),
+    Neo4j(Neo4jError),
+    UnexpectedMessage(ServerMessage),
+    TransactionClosed,
+    /// No cluster member could serve the request, see `RoutingDriver`.
+    ServiceUnavailable(String),
+    /// `feature` is not available with the protocol `version` agreed with the server.
+    Unsupported { feature: &'static str, version: u32 },
+    /// `request` cannot be sent while the connection is in `state`, e.g. RUN while streaming.
+    InvalidState { state: ConnectionState, request: StructureSignature },
+    /// The connection cannot be switched to non-blocking mode while replies to the given number
+    /// of requests have yet to be received.
+    PendingReplies(usize),
+    /// A parameter the server does not accept, found before sending the statement.
+    InvalidParameter(InvalidParameter),
+    /// The statement did not complete within its timeout, see `Statement::with_timeout`. The
+    /// connection was reset to stop the query, which may succeed if run again.
+    Timeout(Duration),
+}
+
+/// Broad kind of a `GraphError`, to decide how to handle it without matching every variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The request or the use of the driver was invalid, retrying it will fail again.
+    ClientError,
+    /// The request failed but may succeed if retried, e.g. after a deadlock.
+    TransientError,
+    /// The server failed to process a valid request.
+    DatabaseError,
+    /// The server could not be reached or the connection was lost.
+    ConnectionError,
+    /// The server sent something the driver could not understand.
+    ProtocolError,
+}
+
+impl GraphError {
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            GraphError::Uri(_)
+            | GraphError::DriverClosed
+            | GraphError::Encode(_)
+            | GraphError::TransactionClosed
+            | GraphError::Unsupported { .. }
+            | GraphError::InvalidState { .. }
+            | GraphError::PendingReplies(_)
+            | GraphError::InvalidParameter(_) => ErrorKind::ClientError,
+            GraphError::Timeout(_) => ErrorKind::TransientError,
+            GraphError::Io(_)
+            | GraphError::Connect(_)
+            | GraphError::Handshake(_)
+            | GraphError::ConnectionDefunct
+            | GraphError::AcquisitionTimeout(_)
+            | GraphError::ServiceUnavailable(_) => ErrorKind::ConnectionError,
+            GraphError::Decode(_)
+            | GraphError::Protocol(_)
+            | GraphError::UnexpectedMessage(_) => ErrorKind::ProtocolError,
+            GraphError::Neo4j(ref e) => e.kind(),
+        }
+    }
+}
+
+pub const STATUS_CODES_URL: &str = "https://neo4j.com/docs/status-codes/current/errors/all-errors/";
+
+/// An error reported by the server in a FAILURE message.
+///
+/// Codes have the form `Neo.<classification>.<category>.<title>`,
+/// e.g. `Neo.ClientError.Statement.SyntaxError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neo4jError {
+    pub code: String,
+    pub message: String,
+}
+
+impl Neo4jError {
+    pub fn new<C: Into<String>, M: Into<String>>(code: C, message: M) -> Self {
+        Neo4jError { code: code.into(), message: message.into() }
+    }
+
+    /// Read the error from the metadata of a FAILURE message.
+    pub fn from_metadata(metadata: &Map) -> Self {
+        let field = |name| metadata.get(name).and_then(|v| v.as_string()).unwrap_or("");
+        Neo4jError::new(field("code"), field("message"))
+    }
+
+    pub fn classification(&self) -> Option<&str> {
+        self.code.split('.').nth(1)
+    }
+
+    pub fn category(&self) -> Option<&str> {
+        self.code.split('.').nth(2)
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.code.split('.').nth(3)
+    }
+
+    /// The request was invalid and should not be retried without changes.
+    pub fn is_client_error(&self) -> bool {
+        self.classification() == Some("ClientError")
+    }
+
+    /// The request failed but may succeed if retried.
+    pub fn is_transient(&self) -> bool {
+        self.classification() == Some("TransientError")
+    }
+
+    /// The server failed to process a valid request.
+    pub fn is_database_error(&self) -> bool {
+        self.classification() == Some("DatabaseError")
+    }
+
+    /// The kind of the error according to its classification. Codes with an unknown
+    /// classification are reported as database errors.
+    pub fn kind(&self) -> ErrorKind {
+        match self.classification() {
+            Some("ClientError") => ErrorKind::ClientError,
+            Some("TransientError") => ErrorKind::TransientError,
+            _ => ErrorKind::DatabaseError,
+        }
+    }
+
+    /// Link to the description of the code in the status code reference.
+    pub fn docs_url(&self) -> String {
+        format!("{}#_{}", STATUS_CODES_URL, self.code.to_lowercase().replace('.', "_"))
+    }
+}
+
+impl Error for Neo4jError {}
+
+impl fmt::Display for Neo4jError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} (see {})", self.code, self.message, self.docs_url())
+    }
+}
+
+/// The server sent something the driver refuses to process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolViolation {
+    MessageTooLarge { limit: usize },
+    /// The reply to the routing procedure could not be read as a routing table.
+    InvalidRoutingTable(String),
+    /// A message that is not a structure, shown as the value read instead.
+    InvalidMessage(String),
+}
+
+impl fmt::Display for ProtocolViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtocolViolation::MessageTooLarge { limit } => {
+                write!(f, "Received message is larger than the limit of {} bytes", limit)
+            }
+            ProtocolViolation::InvalidRoutingTable(ref reason) => {
+                write!(f, "Invalid routing table: {}", reason)
+            }
+            ProtocolViolation::InvalidMessage(ref value) => {
+                write!(f, "Expected a message structure, found {}", value)
+            }
+        }
+    }
+}
+
+impl Error for ProtocolViolation {}
+
+impl Error for GraphError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            GraphError::Io(ref e) => Some(e),
+            GraphError::Connect(ref e) => Some(e),
+            GraphError::Uri(ref e) => Some(e),
+            GraphError::Handshake(ref e) => Some(e),
+            GraphError::Encode(ref e) => Some(e),
+            GraphError::Decode(ref e) => Some(e),
+            GraphError::Protocol(ref e) => Some(e),
+            GraphError::Neo4j(ref e) => Some(e),
+            GraphError::InvalidParameter(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GraphError::Io(ref e) => write!(f, "{}", e),
+            GraphError::Connect(ref e) => write!(f, "{}", e),
+            GraphError::Uri(ref e) => write!(f, "{}", e),
+            GraphError::Handshake(ref e) => write!(f, "{}", e),
+            GraphError::DriverClosed => write!(f, "The driver has been shut down"),
+            GraphError::Encode(ref e) => write!(f, "{}", e),
+            GraphError::Decode(ref e) => write!(f, "{}", e),
+            GraphError::ConnectionDefunct => write!(f, "The connection is defunct after a previous transport error"),
+            GraphError::AcquisitionTimeout(ref timeout) => {
+                write!(f, "No connection became available within {:?}", timeout)
+            }
+            GraphError::Protocol(ref e) => write!(f, "{}", e),
+            GraphError::Neo4j(ref e) => write!(f, "{}", e),
+            GraphError::TransactionClosed => write!(f, "The transaction has already been committed or rolled back"),
+            GraphError::UnexpectedMessage(ref kind) => write!(f, "Unexpected {:?} message from the server", kind),
+            GraphError::ServiceUnavailable(ref reason) => write!(f, "Service unavailable: {}", reason),
+            GraphError::Unsupported { feature, version } => {
+                write!(f, "{} is not supported by protocol version {}", feature, version)
+            }
+            GraphError::InvalidState { state, request } => {
+                write!(f, "Cannot send {} while the connection is {}", request, state)?;
+                match state {
+                    ConnectionState::Streaming => write!(f, ", pull or discard the result of the previous RUN first"),
+                    ConnectionState::Failed => write!(f, ", acknowledge the failure or reset the connection first"),
+                    _ => Ok(()),
+                }
+            }
+            GraphError::PendingReplies(count) => {
+                write!(f, "Cannot switch to non-blocking mode with {} replies still to receive", count)
+            }
+            GraphError::InvalidParameter(ref e) => write!(f, "{}", e),
+            GraphError::Timeout(ref timeout) => write!(f, "The query did not complete within {:?}", timeout),
+        }
+    }
+}
+
+impl From<io::Error> for GraphError {
+    fn from(error: io::Error) -> Self {
+        GraphError::Io(error)
+    }
+}
+
+impl From<ConnectError> for GraphError {
+    fn from(error: ConnectError) -> Self {
+        GraphError::Connect(error)
+    }
+}
+
+impl From<EncoderError> for GraphError {
+    fn from(error: EncoderError) -> Self {
+        GraphError::Encode(error)
+    }
+}
+
+impl From<DecoderError> for GraphError {
+    fn from(error: DecoderError) -> Self {
+        GraphError::Decode(error)
+    }
+}
+
+impl From<HandshakeError> for GraphError {
+    fn from(error: HandshakeError) -> Self {
+        GraphError::Handshake(error)
+    }
+}
+
+impl From<ProtocolViolation> for GraphError {
+    fn from(error: ProtocolViolation) -> Self {
+        GraphError::Protocol(error)
+    }
+}
+
+impl From<Neo4jError> for GraphError {
+    fn from(error: Neo4jError) -> Self {
+        GraphError::Neo4j(error)
+    }
+}
+
+impl From<InvalidParameter> for GraphError {
+    fn from(error: InvalidParameter) -> Self {
+        GraphError::InvalidParameter(error)
+    }
+}
+
+impl From<UriError> for GraphError {
+    fn from(error: UriError) -> Self {
+        GraphError::Uri(error)
+    }
+}
+
+pub type GraphResult<T> = Result<T, GraphError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_error_code() {
+        let error = Neo4jError::new("Neo.ClientError.Statement.SyntaxError", "Invalid input");
+
+        assert_eq!(Some("ClientError"), error.classification());
+        assert_eq!(Some("Statement"), error.category());
+        assert_eq!(Some("SyntaxError"), error.title());
+        assert!(error.is_client_error());
+        assert!(!error.is_transient());
+        assert!(!error.is_database_error());
+    }
+
+    #[test]
+    fn classify_transient_and_database_errors() {
+        assert!(Neo4jError::new("Neo.TransientError.Transaction.DeadlockDetected", "").is_transient());
+        assert!(Neo4jError::new("Neo.DatabaseError.General.UnknownError", "").is_database_error());
+    }
+
+    #[test]
+    fn classify_malformed_code() {
+        let error = Neo4jError::new("Oops", "");
+
+        assert_eq!(None, error.classification());
+        assert!(!error.is_client_error() && !error.is_transient() && !error.is_database_error());
+    }
+
+    #[test]
+    fn neo4j_error_kind() {
+        let error = GraphError::from(Neo4jError::new("Neo.ClientError.Statement.SyntaxError", ""));
+        assert_eq!(ErrorKind::ClientError, error.kind());
+
+        let error = GraphError::from(Neo4jError::new("Neo.TransientError.Transaction.DeadlockDetected", ""));
+        assert_eq!(ErrorKind::TransientError, error.kind());
+
+        assert_eq!(ErrorKind::DatabaseError, Neo4jError::new("Oops", "").kind());
+    }
+
+    #[test]
+    fn driver_error_kind() {
+        let error = GraphError::from(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        assert_eq!(ErrorKind::ConnectionError, error.kind());
+
+        assert_eq!(ErrorKind::ConnectionError, GraphError::ConnectionDefunct.kind());
+        assert_eq!(ErrorKind::ClientError, GraphError::TransactionClosed.kind());
+        assert_eq!(ErrorKind::TransientError, GraphError::Timeout(Duration::from_secs(1)).kind());
+        assert_eq!(ErrorKind::ConnectionError, GraphError::ServiceUnavailable("no writers".to_owned()).kind());
+        assert_eq!(ErrorKind::ProtocolError,
+                   GraphError::from(ProtocolViolation::MessageTooLarge { limit: 1 }).kind());
+    }
+
+    #[test]
+    fn source_chain_reaches_io_error() {
+        let io_error = io::Error::new(io::ErrorKind::UnexpectedEof, "eof");
+        let error = GraphError::from(DecoderError::from(io_error));
+
+        let decode = error.source().unwrap();
+        assert!(decode.is::<DecoderError>());
+        let io = decode.source().unwrap();
+        assert_eq!("eof", io.to_string());
+        assert!(io.source().is_none());
+
+        let error = GraphError::from(EncoderError::from(io::Error::new(io::ErrorKind::WriteZero, "zero")));
+        assert_eq!("zero", error.source().unwrap().source().unwrap().to_string());
+
+        assert!(GraphError::TransactionClosed.source().is_none());
+    }
+
+    #[test]
+    fn docs_url_points_to_code() {
+        let error = Neo4jError::new("Neo.ClientError.Statement.SyntaxError", "Invalid input");
+
+        assert_eq!(format!("{}#_neo_clienterror_statement_syntaxerror", STATUS_CODES_URL),
+                   error.docs_url());
+    }
+}
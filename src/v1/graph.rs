@@ -0,0 +1,416 @@
+use rustc_serialize::Decodable;
+
+use super::error::GraphResult;
+use super::packstream::value::{self, Value, Map, StructureSignature};
+
+/// A node returned by the server.
+///
+/// Older servers identify nodes only by the numeric `id`, newer ones add a string `element_id`
+/// and may eventually stop sending the numeric one, so both are optional.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Node {
+    pub id: Option<i64>,
+    pub element_id: Option<String>,
+    pub labels: Vec<String>,
+    pub properties: Map,
+}
+
+impl Node {
+    /// Read a node from a `Node` structure with either 3 (`id`, `labels`, `properties`) or 4
+    /// (plus `element_id`) fields, or from a `Value::Node`.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        if let Value::Node(ref node) = *value {
+            return Some(node.clone())
+        }
+
+        let fields = match value.as_struct() {
+            Some((signature, fields)) if signature == StructureSignature::Node
+                && (fields.len() == 3 || fields.len() == 4) => fields,
+            _ => return None,
+        };
+
+        Some(Node {
+            id: optional(&fields[0], Value::as_integer)?,
+            labels: strings(&fields[1])?,
+            properties: fields[2].as_map()?.clone(),
+            element_id: element_id(fields.get(3))?,
+        })
+    }
+
+    /// The `Node` structure this node is read from.
+    pub fn to_structure(&self) -> Value {
+        let mut fields = vec![
+            self.id.into(), strings_value(&self.labels), Value::Map(self.properties.clone()),
+        ];
+        if let Some(ref element_id) = self.element_id {
+            fields.push(element_id.as_str().into());
+        }
+        Value::Structure(StructureSignature::Node, fields)
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels.iter().any(|l| l == label)
+    }
+
+    /// Decode the properties into a `T` whose fields are named after them, see
+    /// `Record::to`.
+    pub fn properties_as<T: Decodable>(&self) -> GraphResult<T> {
+        properties_as(&self.properties)
+    }
+}
+
+/// A relationship returned by the server, identified like `Node`.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Relationship {
+    pub id: Option<i64>,
+    pub element_id: Option<String>,
+    pub start_id: Option<i64>,
+    pub start_element_id: Option<String>,
+    pub end_id: Option<i64>,
+    pub end_element_id: Option<String>,
+    pub rel_type: String,
+    pub properties: Map,
+}
+
+impl Relationship {
+    /// Read a relationship from a `Relationship` structure with either 5 (`id`, `start_id`,
+    /// `end_id`, `type`, `properties`) or 8 (plus `element_id`, `start_element_id` and
+    /// `end_element_id`) fields, or from a `Value::Relationship`.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        if let Value::Relationship(ref rel) = *value {
+            return Some(rel.clone())
+        }
+
+        let fields = match value.as_struct() {
+            Some((signature, fields)) if signature == StructureSignature::Relationship
+                && (fields.len() == 5 || fields.len() == 8) => fields,
+            _ => return None,
+        };
+
+        Some(Relationship {
+            id: optional(&fields[0], Value::as_integer)?,
+            start_id: optional(&fields[1], Value::as_integer)?,
+            end_id: optional(&fields[2], Value::as_integer)?,
+            rel_type: fields[3].as_string()?.to_owned(),
+            properties: fields[4].as_map()?.clone(),
+            element_id: element_id(fields.get(5))?,
+            start_element_id: element_id(fields.get(6))?,
+            end_element_id: element_id(fields.get(7))?,
+        })
+    }
+
+    /// The `Relationship` structure this relationship is read from.
+    pub fn to_structure(&self) -> Value {
+        let mut fields = vec![
+            self.id.into(), self.start_id.into(), self.end_id.into(),
+            self.rel_type.as_str().into(), Value::Map(self.properties.clone()),
+        ];
+        if self.element_id.is_some() || self.start_element_id.is_some() || self.end_element_id.is_some() {
+            fields.push(self.element_id.clone().into());
+            fields.push(self.start_element_id.clone().into());
+            fields.push(self.end_element_id.clone().into());
+        }
+        Value::Structure(StructureSignature::Relationship, fields)
+    }
+
+    /// Decode the properties into a `T` whose fields are named after them, see
+    /// `Record::to`.
+    pub fn properties_as<T: Decodable>(&self) -> GraphResult<T> {
+        properties_as(&self.properties)
+    }
+}
+
+/// A relationship within a `Path`, whose nodes are given by the position in the path.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct UnboundRelationship {
+    pub id: Option<i64>,
+    pub element_id: Option<String>,
+    pub rel_type: String,
+    pub properties: Map,
+}
+
+impl UnboundRelationship {
+    /// Read a relationship from an `UnboundRelationship` structure with either 3 (`id`, `type`,
+    /// `properties`) or 4 (plus `element_id`) fields.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let fields = match value.as_struct() {
+            Some((signature, fields)) if signature == StructureSignature::UnboundRelationship
+                && (fields.len() == 3 || fields.len() == 4) => fields,
+            _ => return None,
+        };
+
+        Some(UnboundRelationship {
+            id: optional(&fields[0], Value::as_integer)?,
+            rel_type: fields[1].as_string()?.to_owned(),
+            properties: fields[2].as_map()?.clone(),
+            element_id: element_id(fields.get(3))?,
+        })
+    }
+
+    pub fn to_structure(&self) -> Value {
+        let mut fields = vec![
+            self.id.into(), self.rel_type.as_str().into(), Value::Map(self.properties.clone()),
+        ];
+        if let Some(ref element_id) = self.element_id {
+            fields.push(element_id.as_str().into());
+        }
+        Value::Structure(StructureSignature::UnboundRelationship, fields)
+    }
+
+    /// See `Relationship::properties_as`.
+    pub fn properties_as<T: Decodable>(&self) -> GraphResult<T> {
+        properties_as(&self.properties)
+    }
+}
+
+/// A path returned by the server, as sent: its distinct nodes and relationships, and the
+/// sequence of alternating relationship and node indices walking it from the first node.
+///
+/// A relationship index `i` is 1-based and negative when the relationship is traversed against
+/// its direction.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Path {
+    pub nodes: Vec<Node>,
+    pub relationships: Vec<UnboundRelationship>,
+    pub sequence: Vec<i64>,
+}
+
+impl Path {
+    /// Read a path from a `Path` structure, or from a `Value::Path`.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        if let Value::Path(ref path) = *value {
+            return Some(path.clone())
+        }
+
+        let fields = match value.as_struct() {
+            Some((signature, fields)) if signature == StructureSignature::Path
+                && fields.len() == 3 => fields,
+            _ => return None,
+        };
+
+        Some(Path {
+            nodes: fields[0].as_list()?.iter().map(Node::from_value).collect::<Option<_>>()?,
+            relationships: fields[1].as_list()?.iter()
+                .map(UnboundRelationship::from_value)
+                .collect::<Option<_>>()?,
+            sequence: fields[2].as_list()?.iter().map(Value::as_integer).collect::<Option<_>>()?,
+        })
+    }
+
+    pub fn to_structure(&self) -> Value {
+        Value::Structure(StructureSignature::Path, vec![
+            Value::List(self.nodes.iter().map(Node::to_structure).collect()),
+            Value::List(self.relationships.iter().map(UnboundRelationship::to_structure).collect()),
+            self.sequence.clone().into(),
+        ])
+    }
+
+    /// The first node of the path.
+    pub fn start(&self) -> Option<&Node> {
+        self.nodes.first()
+    }
+
+    /// The number of relationships traversed.
+    pub fn len(&self) -> usize {
+        self.sequence.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequence.is_empty()
+    }
+}
+
+fn properties_as<T: Decodable>(properties: &Map) -> GraphResult<T> {
+    Ok(value::from_value(Value::Map(properties.clone()))?)
+}
+
+// `Some(None)` for a null field, `None` if the field has the wrong type.
+fn optional<'a, T, F: Fn(&'a Value) -> Option<T>>(value: &'a Value, f: F) -> Option<Option<T>> {
+    if value.is_null() {
+        Some(None)
+    } else {
+        f(value).map(Some)
+    }
+}
+
+fn element_id(value: Option<&Value>) -> Option<Option<String>> {
+    match value {
+        Some(value) => optional(value, |v| v.as_string().map(|s| s.to_owned())),
+        None => Some(None),
+    }
+}
+
+fn strings_value(strings: &[String]) -> Value {
+    Value::List(strings.iter().map(|s| s.as_str().into()).collect())
+}
+
+fn strings(value: &Value) -> Option<Vec<String>> {
+    value.as_list()?.iter().map(|v| v.as_string().map(|s| s.to_owned())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::v1::packstream::value::{Value, Map};
+
+    fn properties() -> Map {
+        let mut map = Map::new();
+        map.insert("name".to_owned(), Value::from("Alice"));
+        map
+    }
+
+    #[test]
+    fn node_with_numeric_id() {
+        let value = Value::Structure(StructureSignature::Node, vec![
+            Value::from(1), Value::List(vec![Value::from("Person")]), Value::Map(properties()),
+        ]);
+
+        let expected = Node {
+            id: Some(1),
+            element_id: None,
+            labels: vec!["Person".to_owned()],
+            properties: properties(),
+        };
+
+        assert_eq!(Some(expected), Node::from_value(&value));
+    }
+
+    #[test]
+    fn node_with_element_id() {
+        let value = Value::Structure(StructureSignature::Node, vec![
+            Value::Null, Value::List(vec![]), Value::Map(Map::new()), Value::from("4:abc:1"),
+        ]);
+
+        let node = Node::from_value(&value).unwrap();
+        assert_eq!(None, node.id);
+        assert_eq!(Some("4:abc:1".to_owned()), node.element_id);
+    }
+
+    #[test]
+    fn node_from_invalid_value() {
+        assert_eq!(None, Node::from_value(&Value::from(1)));
+        assert_eq!(None, Node::from_value(&Value::Structure(StructureSignature::Node, vec![Value::from(1)])));
+        assert_eq!(None, Node::from_value(&Value::Structure(StructureSignature::Node, vec![
+            Value::from("1"), Value::List(vec![]), Value::Map(Map::new()),
+        ])));
+    }
+
+    #[test]
+    fn relationship_with_numeric_ids() {
+        let value = Value::Structure(StructureSignature::Relationship, vec![
+            Value::from(3), Value::from(1), Value::from(2), Value::from("KNOWS"), Value::Map(properties()),
+        ]);
+
+        let expected = Relationship {
+            id: Some(3),
+            element_id: None,
+            start_id: Some(1),
+            start_element_id: None,
+            end_id: Some(2),
+            end_element_id: None,
+            rel_type: "KNOWS".to_owned(),
+            properties: properties(),
+        };
+
+        assert_eq!(Some(expected), Relationship::from_value(&value));
+    }
+
+    #[test]
+    fn relationship_with_element_ids() {
+        let value = Value::Structure(StructureSignature::Relationship, vec![
+            Value::from(3), Value::from(1), Value::from(2), Value::from("KNOWS"), Value::Map(Map::new()),
+            Value::from("5:abc:3"), Value::from("4:abc:1"), Value::from("4:abc:2"),
+        ]);
+
+        let rel = Relationship::from_value(&value).unwrap();
+        assert_eq!(Some(3), rel.id);
+        assert_eq!(Some("5:abc:3".to_owned()), rel.element_id);
+        assert_eq!(Some("4:abc:1".to_owned()), rel.start_element_id);
+        assert_eq!(Some("4:abc:2".to_owned()), rel.end_element_id);
+    }
+
+    #[test]
+    fn relationship_from_node_should_fail() {
+        let value = Value::Structure(StructureSignature::Node, vec![
+            Value::from(1), Value::List(vec![]), Value::Map(Map::new()),
+        ]);
+
+        assert_eq!(None, Relationship::from_value(&value));
+    }
+
+    #[test]
+    fn graph_types_to_structure() {
+        let node = Value::Structure(StructureSignature::Node, vec![
+            Value::from(1), Value::List(vec![Value::from("Person")]), Value::Map(properties()),
+        ]);
+        assert_eq!(node, Node::from_value(&node).unwrap().to_structure());
+
+        let rel = Value::Structure(StructureSignature::Relationship, vec![
+            Value::from(3), Value::from(1), Value::from(2), Value::from("KNOWS"), Value::Map(Map::new()),
+            Value::from("5:abc:3"), Value::from("4:abc:1"), Value::from("4:abc:2"),
+        ]);
+        assert_eq!(rel, Relationship::from_value(&rel).unwrap().to_structure());
+    }
+
+    #[test]
+    fn node_labels() {
+        let node = Node { id: Some(1), element_id: None, labels: vec!["Person".to_owned()], properties: Map::new() };
+
+        assert_eq!(&["Person".to_owned()], node.labels());
+        assert!(node.has_label("Person"));
+        assert!(!node.has_label("person"));
+    }
+
+    #[test]
+    fn properties_into_struct() {
+        decodable_struct! {
+            #[derive(Debug, PartialEq)]
+            struct Person { name: String, age: Option<i64> }
+        }
+
+        let node = Node { id: Some(1), element_id: None, labels: vec![], properties: properties() };
+        assert_eq!(Person { name: "Alice".to_owned(), age: None }, node.properties_as().unwrap());
+
+        let mut rel = Relationship::from_value(&Value::Structure(StructureSignature::Relationship, vec![
+            Value::from(3), Value::from(1), Value::from(2), Value::from("KNOWS"), Value::Map(properties()),
+        ])).unwrap();
+        rel.properties.insert("age".to_owned(), Value::from(33));
+        assert_eq!(Person { name: "Alice".to_owned(), age: Some(33) }, rel.properties_as().unwrap());
+
+        rel.properties.remove("name");
+        assert!(rel.properties_as::<Person>().is_err());
+    }
+
+    #[test]
+    fn path_of_two_nodes() {
+        let alice = Value::Structure(StructureSignature::Node, vec![
+            Value::from(1), Value::List(vec![]), Value::Map(properties()),
+        ]);
+        let bob = Value::Node(Node {
+            id: Some(2),
+            element_id: None,
+            labels: vec![],
+            properties: Map::new(),
+        });
+        let knows = Value::Structure(StructureSignature::UnboundRelationship, vec![
+            Value::from(3), Value::from("KNOWS"), Value::Map(Map::new()),
+        ]);
+        let value = Value::Structure(StructureSignature::Path, vec![
+            Value::List(vec![alice, bob]), Value::List(vec![knows]), Value::from(vec![1, 1]),
+        ]);
+
+        let path = Path::from_value(&value).unwrap();
+        assert_eq!(Some(1), path.start().and_then(|n| n.id));
+        assert_eq!(Some(2), path.nodes[1].id);
+        assert_eq!("KNOWS", path.relationships[0].rel_type);
+        assert_eq!(1, path.len());
+        assert!(!path.is_empty());
+
+        assert_eq!(Some(path.clone()), Path::from_value(&path.to_structure()));
+        assert_eq!(None, Path::from_value(&Value::Structure(StructureSignature::Path, vec![])));
+    }
+}
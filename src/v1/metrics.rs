@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::uri::Address;
+
+/// Counters of what went through a connection since it was opened, see `Connection::metrics`.
+///
+/// Bytes and chunks are counted as they cross the socket, chunk headers and end markers
+/// included. Messages are counted by the name of their signature, e.g. `RUN` or `RECORD`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionMetrics {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Chunks carrying data, leaving out end markers and keep-alive NOOPs.
+    pub chunks_sent: u64,
+    pub chunks_received: u64,
+    pub messages_sent: BTreeMap<String, u64>,
+    pub messages_received: BTreeMap<String, u64>,
+    /// Times a reply was awaited after sending requests, so pipelined requests count once.
+    pub round_trips: u64,
+    /// Time spent blocked writing to and reading from the socket.
+    pub write_time: Duration,
+    pub read_time: Duration,
+}
+
+impl ConnectionMetrics {
+    pub fn total_messages_sent(&self) -> u64 {
+        self.messages_sent.values().sum()
+    }
+
+    pub fn total_messages_received(&self) -> u64 {
+        self.messages_received.values().sum()
+    }
+
+    pub(crate) fn message_sent<T: ToString>(&mut self, name: T) {
+        *self.messages_sent.entry(name.to_string()).or_insert(0) += 1;
+    }
+
+    pub(crate) fn message_received<T: ToString>(&mut self, name: T) {
+        *self.messages_received.entry(name.to_string()).or_insert(0) += 1;
+    }
+}
+
+/// A snapshot of the connection pool of a `Driver`, see `Driver::pool_status`.
+///
+/// `created` and `closed` count the connections opened and closed since the driver was
+/// created, so `created - closed` is the number of connections open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStatus {
+    pub idle: usize,
+    pub in_use: usize,
+    pub created: usize,
+    pub closed: usize,
+}
+
+/// Notified of the events of the connection pool of a `Driver`, e.g. to record how long
+/// sessions wait for a connection. Set with `Driver::with_pool_listener`.
+///
+/// Methods are called from the thread acquiring or releasing the connection, outside the lock
+/// of the pool, so they should return quickly.
+pub trait PoolListener: Send + Sync {
+    /// A connection was handed out after `waited`, which includes opening it if it is new.
+    fn acquired(&self, _address: &Address, _waited: Duration) {}
+
+    /// A connection handed out was given back, to be kept idle or closed.
+    fn released(&self, _address: &Address) {}
+
+    /// No connection became available before the deadline of `Driver::try_session` or
+    /// `Driver::try_session_now`.
+    fn timed_out(&self, _address: &Address, _waited: Duration) {}
+}
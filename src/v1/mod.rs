@@ -1,6 +1,43 @@
+/// Bolt protocol version implemented by this module.
+pub const VERSION: u32 = 1;
+
 pub mod transport;
+pub mod auth;
 pub mod connection;
+pub mod cypher;
 pub mod packstream;
 pub mod protocol;
+pub mod uri;
+pub mod driver;
+pub mod error;
+pub mod summary;
+pub mod session;
+pub mod statement;
+pub mod graph;
+pub mod metrics;
+pub mod net;
+pub mod result;
+pub mod transaction;
+pub mod routing;
+pub mod nonblocking;
+pub mod repl;
+
+#[cfg(test)]
+pub(crate) mod testing;
 
-pub use self::connection::Connection;
+pub use self::auth::AuthToken;
+pub use self::connection::{Connection, ConnectionState, TcpConnection};
+pub use self::driver::{Driver, DriverConnection};
+pub use self::error::{ErrorKind, GraphError, GraphResult, Neo4jError, ProtocolViolation};
+pub use self::graph::{Node, Relationship, UnboundRelationship, Path};
+pub use self::metrics::{ConnectionMetrics, PoolListener, PoolStatus};
+pub use self::net::{AddressOrder, ConnectError};
+pub use self::nonblocking::{AsyncConnection, RecordStream};
+pub use self::result::{Record, ResultCursor, StatementResult};
+pub use self::routing::{AccessMode, RoutingDriver};
+pub use self::session::{Session, SessionConfig};
+pub use self::statement::{InvalidParameter, Statement};
+pub use self::summary::{QueryType, ResultSummary, SummaryCounters};
+pub use self::transaction::{Transaction, TransactionConfig, TransactionState};
+pub use self::transport::{FlushPolicy, TcpChunkedStream};
+pub use self::uri::UriError;
@@ -0,0 +1,143 @@
+// Opening the TCP socket of a connection, trying each address a host resolves to.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+
+use super::error::GraphResult;
+
+/// The order in which the addresses of a host are tried by `connect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressOrder {
+    /// As the resolver returns them, which usually follows the preferences of the system.
+    #[default]
+    AsResolved,
+    /// IPv6 and IPv4 addresses alternately, starting with IPv6, as Happy Eyeballs (RFC 8305)
+    /// sorts them, so a broken IPv6 route costs a single failed attempt. Attempts are made one
+    /// after the other, not raced.
+    PreferIpv6,
+}
+
+/// Every address of `host` failed, or it resolved to none.
+#[derive(Debug)]
+pub struct ConnectError {
+    pub host: String,
+    pub port: u16,
+    /// Each address tried and why it failed, in order.
+    pub attempts: Vec<(SocketAddr, io::Error)>,
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not connect to {}:{}", self.host, self.port)?;
+        if self.attempts.is_empty() {
+            return write!(f, ", no address resolved")
+        }
+
+        for (i, (address, error)) in self.attempts.iter().enumerate() {
+            let sep = if i == 0 { ", tried" } else { "," };
+            write!(f, "{} {} ({})", sep, address, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConnectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.attempts.last().map(|(_, e)| e as &(dyn Error + 'static))
+    }
+}
+
+/// Resolve `host`, a name or an IP address (IPv6 ones without brackets), to its addresses in
+/// `order`.
+pub fn resolve(host: &str, port: u16, order: AddressOrder) -> io::Result<Vec<SocketAddr>> {
+    let addresses = (host, port).to_socket_addrs()?.collect();
+    Ok(sort(addresses, order))
+}
+
+/// Connect to the first address of `host` accepting the connection, see `resolve`. Fails with
+/// `GraphError::Io` if the host cannot be resolved, and `GraphError::Connect` if no address
+/// accepts the connection.
+pub fn connect(host: &str, port: u16, order: AddressOrder) -> GraphResult<TcpStream> {
+    let mut attempts = Vec::new();
+    for address in resolve(host, port, order)? {
+        match TcpStream::connect(address) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                debug!("Failed to connect to {}: {}", address, e);
+                attempts.push((address, e));
+            }
+        }
+    }
+
+    Err(ConnectError { host: host.to_owned(), port, attempts }.into())
+}
+
+fn sort(addresses: Vec<SocketAddr>, order: AddressOrder) -> Vec<SocketAddr> {
+    match order {
+        AddressOrder::AsResolved => addresses,
+        AddressOrder::PreferIpv6 => {
+            let (v6, v4): (Vec<_>, Vec<_>) = addresses.into_iter().partition(SocketAddr::is_ipv6);
+            let mut v6 = v6.into_iter();
+            let mut v4 = v4.into_iter();
+            let mut sorted = Vec::new();
+            loop {
+                match (v6.next(), v4.next()) {
+                    (None, None) => return sorted,
+                    (a, b) => sorted.extend(a.into_iter().chain(b)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+    use super::*;
+    use ::v1::error::GraphError;
+
+    fn addresses(list: &[&str]) -> Vec<SocketAddr> {
+        list.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn prefer_ipv6_interleaves_families() {
+        let resolved = addresses(&["10.0.0.1:7687", "10.0.0.2:7687", "[::1]:7687", "10.0.0.3:7687", "[::2]:7687"]);
+
+        assert_eq!(resolved, sort(resolved.clone(), AddressOrder::AsResolved));
+        assert_eq!(addresses(&["[::1]:7687", "10.0.0.1:7687", "[::2]:7687", "10.0.0.2:7687", "10.0.0.3:7687"]),
+                   sort(resolved, AddressOrder::PreferIpv6));
+    }
+
+    #[test]
+    fn resolve_ip_literals() {
+        assert_eq!(addresses(&["[::1]:7687"]), resolve("::1", 7687, AddressOrder::AsResolved).unwrap());
+        assert_eq!(addresses(&["127.0.0.1:7687"]), resolve("127.0.0.1", 7687, AddressOrder::PreferIpv6).unwrap());
+    }
+
+    #[test]
+    fn connect_to_listening_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let stream = connect("127.0.0.1", port, AddressOrder::default()).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), stream.peer_addr().unwrap());
+    }
+
+    #[test]
+    fn failed_attempts_are_listed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let error = match connect("127.0.0.1", port, AddressOrder::default()) {
+            Err(GraphError::Connect(error)) => error,
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(1, error.attempts.len());
+        assert!(error.to_string().starts_with(&format!("Could not connect to 127.0.0.1:{}, tried 127.0.0.1:{} (", port, port)));
+        assert!(error.source().is_some());
+    }
+}
@@ -0,0 +1,497 @@
+// Non-blocking mode for connections driven by an event loop: requests are buffered and sent
+// as the socket accepts them, replies are assembled from whatever the socket has available,
+// and every call returns `Poll::NotReady` instead of waiting.
+
+use std::io::prelude::*;
+use std::io;
+use std::net::TcpStream;
+use std::rc::Rc;
+use rustc_serialize::Encodable;
+
+use super::Connection;
+use super::connection::RUN_EXTRA_VERSION;
+use super::error::{GraphError, GraphResult, Neo4jError, ProtocolViolation};
+use super::packstream::encode;
+use super::packstream::value::{Value, Map};
+use super::protocol::client::{Run, PullAll, AckFailure};
+use super::protocol::server::ServerMessage;
+use super::statement::validate_params;
+use super::result::{self, Record};
+use super::transport::ChunkedStream;
+
+/// Outcome of an operation that may have to wait for the socket.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Poll<T> {
+    Ready(T),
+    /// The socket is not ready, try again once the event loop reports it readable or writable.
+    NotReady,
+}
+
+pub struct AsyncConnection {
+    transport: ChunkedStream,
+    // chunked requests not accepted by the socket yet
+    output: Vec<u8>,
+    // bytes received that do not make a whole message yet
+    input: Vec<u8>,
+    // the last message taken out of the input, its allocation reused for the next one
+    message: Vec<u8>,
+    // requests sent whose summary has not been received yet
+    pending: usize,
+    defunct: bool,
+    // the protocol version agreed in the handshake, below `RUN_EXTRA_VERSION`
+    version: u32,
+}
+
+impl AsyncConnection {
+    /// Switch an initialized `connection` to non-blocking mode. Fails if the connection is
+    /// defunct, or with `GraphError::PendingReplies` if it still has replies to receive.
+    ///
+    /// Only the RUN, PULL_ALL and ACK_FAILURE messages of Bolt v1 and v2 are sent, so a
+    /// connection from `RUN_EXTRA_VERSION` fails with `GraphError::Unsupported`.
+    pub fn new(connection: Connection) -> GraphResult<Self> {
+        if connection.is_defunct() {
+            return Err(GraphError::ConnectionDefunct)
+        }
+        if connection.pending() > 0 {
+            return Err(GraphError::PendingReplies(connection.pending()))
+        }
+
+        let version = connection.version();
+        if version >= RUN_EXTRA_VERSION {
+            return Err(GraphError::Unsupported { feature: "Non-blocking mode", version })
+        }
+
+        let transport = connection.into_transport();
+        transport.socket().set_nonblocking(true)?;
+
+        Ok(AsyncConnection {
+            transport,
+            output: Vec::new(),
+            input: Vec::new(),
+            message: Vec::new(),
+            pending: 0,
+            defunct: false,
+            version,
+        })
+    }
+
+    /// The socket to register with the event loop.
+    pub fn socket(&self) -> &TcpStream {
+        self.transport.socket()
+    }
+
+    pub fn is_defunct(&self) -> bool {
+        self.defunct
+    }
+
+    /// Queue RUN and PULL_ALL for `statement`, returning a stream polling for its records.
+    /// Replies to a previous query not read to the end are discarded first.
+    pub fn run(&mut self, statement: &str, params: Map) -> GraphResult<RecordStream<'_>> {
+        validate_params(&params, self.version)?;
+        let skip = self.pending;
+        self.queue(&Run::new(statement).with_params(params))?;
+        self.queue(&PullAll)?;
+
+        Ok(RecordStream { connection: self, skip, keys: None, done: false })
+    }
+
+    /// Send as much of the queued requests as the socket accepts, ready once all are sent.
+    pub fn poll_flush(&mut self) -> GraphResult<Poll<()>> {
+        while !self.output.is_empty() {
+            let result = self.transport.socket().write(&self.output);
+            match result {
+                Ok(0) => return Err(self.transport_error(io::ErrorKind::WriteZero.into())),
+                Ok(n) => { self.output.drain(..n); }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Poll::NotReady),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.transport_error(e)),
+            }
+        }
+
+        Ok(Poll::Ready(()))
+    }
+
+    fn queue<T: Encodable>(&mut self, message: &T) -> GraphResult<()> {
+        if self.defunct {
+            return Err(GraphError::ConnectionDefunct)
+        }
+
+        let data = encode(message)?;
+        self.transport.write(&data)?;
+        self.transport.flush(true)?;
+        self.output.extend_from_slice(self.transport.raw());
+        self.transport.clear();
+        self.pending += 1;
+        Ok(())
+    }
+
+    // Flush, then read what the socket has available and take the first whole message out of
+    // the input, if any.
+    fn poll_message(&mut self) -> GraphResult<Poll<&[u8]>> {
+        if self.defunct {
+            return Err(GraphError::ConnectionDefunct)
+        }
+
+        self.poll_flush()?;
+
+        if self.take_message()? {
+            return Ok(Poll::Ready(&self.message))
+        }
+
+        let mut buf = [0u8; 8192];
+        loop {
+            let result = self.transport.socket().read(&mut buf);
+            match result {
+                Ok(0) => return Err(self.transport_error(io::ErrorKind::UnexpectedEof.into())),
+                Ok(n) => {
+                    self.input.extend_from_slice(&buf[..n]);
+                    if self.take_message()? {
+                        return Ok(Poll::Ready(&self.message))
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Poll::NotReady),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.transport_error(e)),
+            }
+        }
+    }
+
+    // Assemble the chunks of the first message in the input into `message`, if it was entirely
+    // received.
+    fn take_message(&mut self) -> GraphResult<bool> {
+        self.message.clear();
+        let mut position = 0;
+
+        loop {
+            if self.input.len() < position + 2 {
+                return Ok(false)
+            }
+
+            let size = ((self.input[position] as usize) << 8) | self.input[position + 1] as usize;
+            position += 2;
+            if size == 0 {
+                // an empty chunk before a message is a keep-alive NOOP
+                if self.message.is_empty() { continue }
+                break
+            }
+
+            if self.message.len() + size > self.transport.max_message_size() {
+                self.defunct = true;
+                let limit = self.transport.max_message_size();
+                return Err(ProtocolViolation::MessageTooLarge { limit }.into())
+            }
+
+            if self.input.len() < position + size {
+                return Ok(false)
+            }
+
+            self.message.extend_from_slice(&self.input[position..position + size]);
+            position += size;
+        }
+
+        self.input.drain(..position);
+
+        if ServerMessage::peek(&self.message) != ServerMessage::Record {
+            self.pending = self.pending.saturating_sub(1);
+        }
+
+        Ok(true)
+    }
+
+    fn transport_error(&mut self, e: io::Error) -> GraphError {
+        warn!("Marking connection as defunct after transport error: {}", e);
+        self.output.clear();
+        self.defunct = true;
+        GraphError::Io(e)
+    }
+}
+
+/// The records of a query run with `AsyncConnection::run`, read as they become available.
+///
+/// Dropping the stream before the end leaves the remaining replies to be discarded by the
+/// next query.
+pub struct RecordStream<'a> {
+    connection: &'a mut AsyncConnection,
+    // summaries to discard before the replies to this query, left by previous queries
+    skip: usize,
+    keys: Option<Rc<Vec<String>>>,
+    done: bool,
+}
+
+impl<'a> RecordStream<'a> {
+    /// The keys of the records, once the reply to RUN has been received.
+    pub fn keys(&self) -> Option<&[String]> {
+        self.keys.as_ref().map(|keys| keys.as_slice())
+    }
+
+    /// Poll for the next record, ready with `None` once all of them have been read.
+    ///
+    /// A FAILURE ends the stream; it is acknowledged with the next request sent.
+    pub fn poll(&mut self) -> GraphResult<Poll<Option<Record>>> {
+        if self.done {
+            return Ok(Poll::Ready(None))
+        }
+
+        loop {
+            let data = match self.connection.poll_message()? {
+                Poll::Ready(data) => data,
+                Poll::NotReady => return Ok(Poll::NotReady),
+            };
+
+            let kind = ServerMessage::peek(data);
+            if self.skip > 0 {
+                if kind != ServerMessage::Record {
+                    self.skip -= 1;
+                }
+                continue
+            }
+
+            let field = result::field(data)?;
+            match (kind, self.keys.clone()) {
+                (ServerMessage::Success, None) => {
+                    self.keys = Some(Rc::new(keys(&field)));
+                }
+                (ServerMessage::Record, Some(keys)) => {
+                    let values = match field {
+                        Value::List(values) => values,
+                        _ => Vec::new(),
+                    };
+                    return Ok(Poll::Ready(Some(Record::new(keys, values))))
+                }
+                (ServerMessage::Success, Some(_)) => {
+                    self.done = true;
+                    return Ok(Poll::Ready(None))
+                }
+                (ServerMessage::Failure, _) => {
+                    // the IGNORED replies and the reply to ACK_FAILURE are left pending, for
+                    // the next query to discard
+                    self.done = true;
+                    self.connection.queue(&AckFailure)?;
+
+                    let metadata = field.as_map().cloned().unwrap_or_default();
+                    return Err(GraphError::Neo4j(Neo4jError::from_metadata(&metadata)))
+                }
+                (kind, _) => {
+                    self.done = true;
+                    self.connection.defunct = true;
+                    return Err(GraphError::UnexpectedMessage(kind))
+                }
+            }
+        }
+    }
+}
+
+fn keys(metadata: &Value) -> Vec<String> {
+    match metadata.as_map().and_then(|m| m.get("fields")) {
+        Some(Value::List(fields)) => {
+            fields.iter().filter_map(|f| f.as_string()).map(|f| f.to_owned()).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+    use super::*;
+    use ::v1::testing::{self, Step};
+    use ::v1::packstream::value::StructureSignature;
+
+    const ACK_FAILURE: u8 = 0x0E;
+    const RUN: u8 = 0x10;
+    const PULL_ALL: u8 = 0x3F;
+
+    fn async_connection() -> (AsyncConnection, TcpStream) {
+        let (conn, server) = testing::connection();
+        (AsyncConnection::new(conn).unwrap(), server)
+    }
+
+    // Poll until ready, as an event loop would when the socket becomes readable.
+    fn wait<T, F: FnMut() -> GraphResult<Poll<T>>>(mut f: F) -> GraphResult<T> {
+        loop {
+            match f()? {
+                Poll::Ready(value) => return Ok(value),
+                Poll::NotReady => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+
+    #[test]
+    fn poll_is_not_ready_without_replies() {
+        let (mut conn, mut server) = async_connection();
+        let mut stream = conn.run("RETURN 1", Map::new()).unwrap();
+
+        assert_eq!(Poll::NotReady, stream.poll().unwrap());
+        assert_eq!(RUN, testing::expect(&mut server));
+        assert_eq!(PULL_ALL, testing::expect(&mut server));
+    }
+
+    #[test]
+    fn poll_records() {
+        let (mut conn, mut server) = async_connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Record(Value::list().item(1).build()),
+            Step::Record(Value::list().item(2).build()),
+            Step::Success(Value::map().build()),
+        ]);
+
+        let mut stream = conn.run("UNWIND [1, 2] AS n RETURN n", Map::new()).unwrap();
+        let first = wait(|| stream.poll()).unwrap().unwrap();
+        assert_eq!(Some(&["n".to_owned()][..]), stream.keys());
+        assert_eq!(Some(&Value::Integer(1)), first.get_by_key("n"));
+
+        let second = wait(|| stream.poll()).unwrap().unwrap();
+        assert_eq!(Some(&Value::Integer(2)), second.get(0));
+        assert_eq!(None, wait(|| stream.poll()).unwrap());
+        assert_eq!(0, conn.pending);
+    }
+
+    #[test]
+    fn message_buffer_is_reused() {
+        let (mut conn, mut server) = async_connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["s"])),
+            Step::Record(Value::list().item("a long string to grow the buffer first").build()),
+            Step::Record(Value::list().item("short").build()),
+            Step::Success(Value::map().build()),
+        ]);
+
+        let mut stream = conn.run("UNWIND ... AS s RETURN s", Map::new()).unwrap();
+        wait(|| stream.poll()).unwrap().unwrap();
+        let capacity = stream.connection.message.capacity();
+
+        assert_eq!(Some(&Value::from("short")), wait(|| stream.poll()).unwrap().unwrap().get(0));
+        assert_eq!(capacity, stream.connection.message.capacity());
+    }
+
+    #[test]
+    fn noop_chunks_are_skipped() {
+        let (mut conn, mut server) = async_connection();
+        let mut stream = conn.run("RETURN 1", Map::new()).unwrap();
+
+        server.write_all(&[0x00, 0x00]).unwrap();
+        assert_eq!(Poll::NotReady, stream.poll().unwrap());
+
+        server.write_all(&[0x00, 0x00, 0x00, 0x03, 0xB1, 0x70, 0xA0, 0x00, 0x00, 0x00, 0x00]).unwrap();
+        testing::success(&mut server);
+        assert_eq!(None, wait(|| stream.poll()).unwrap());
+        assert_eq!(0, stream.connection.pending);
+    }
+
+    #[test]
+    fn message_split_across_reads() {
+        let (mut conn, mut server) = async_connection();
+        let mut stream = conn.run("RETURN 1", Map::new()).unwrap();
+
+        server.write_all(&[0x00, 0x03, 0xB1, 0x70]).unwrap();
+        assert_eq!(Poll::NotReady, stream.poll().unwrap());
+
+        server.write_all(&[0xA0, 0x00, 0x00]).unwrap();
+        testing::success(&mut server);
+        assert_eq!(None, wait(|| stream.poll()).unwrap());
+    }
+
+    #[test]
+    fn failure_is_acknowledged_with_next_query() {
+        let (mut conn, mut server) = async_connection();
+        testing::play(&mut server, vec![Step::Failure("Neo.ClientError.Statement.SyntaxError"), Step::Ignored]);
+
+        match wait(|| conn.run("RETURN", Map::new()).unwrap().poll()) {
+            Err(GraphError::Neo4j(e)) => assert_eq!("Neo.ClientError.Statement.SyntaxError", e.code),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        testing::play(&mut server, vec![
+            Step::Success(Value::map().build()),
+            Step::Success(testing::fields(&["n"])),
+            Step::Success(Value::map().build()),
+        ]);
+
+        let mut stream = conn.run("RETURN 1 AS n", Map::new()).unwrap();
+        assert_eq!(None, wait(|| stream.poll()).unwrap());
+        assert_eq!(Some(&["n".to_owned()][..]), stream.keys());
+
+        let sent: Vec<u8> = (0..5).map(|_| testing::expect(&mut server)).collect();
+        assert_eq!(vec![RUN, PULL_ALL, ACK_FAILURE, RUN, PULL_ALL], sent);
+    }
+
+    #[test]
+    fn unfinished_query_is_discarded() {
+        let (mut conn, mut server) = async_connection();
+        testing::play(&mut server, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Record(Value::list().item(1).build()),
+            Step::Success(Value::map().build()),
+            Step::Success(testing::fields(&["m"])),
+            Step::Success(Value::map().build()),
+        ]);
+
+        drop(conn.run("RETURN 1 AS n", Map::new()).unwrap());
+
+        let mut stream = conn.run("RETURN 2 AS m", Map::new()).unwrap();
+        assert_eq!(None, wait(|| stream.poll()).unwrap());
+        assert_eq!(Some(&["m".to_owned()][..]), stream.keys());
+    }
+
+    #[test]
+    fn closed_socket_makes_defunct() {
+        let (mut conn, server) = async_connection();
+        drop(server);
+
+        assert!(wait(|| conn.run("RETURN 1", Map::new()).unwrap().poll()).is_err());
+        assert!(conn.is_defunct());
+    }
+
+    #[test]
+    fn defunct_connection_is_rejected() {
+        let (mut conn, server) = testing::connection();
+        drop(server);
+        assert!(conn.reset().is_err());
+
+        assert!(AsyncConnection::new(conn).is_err());
+    }
+
+    #[test]
+    fn structure_params_follow_connection_version() {
+        let duration = Value::Structure(StructureSignature::Unknown(0x45), vec![1.into(), 0.into(), 0.into(), 0.into()]);
+        let mut params = Map::new();
+        params.insert("d".to_owned(), duration);
+
+        let (mut conn, _server) = async_connection();
+        match conn.run("RETURN {d}", params.clone()) {
+            Err(GraphError::InvalidParameter(_)) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        let (conn, mut server) = testing::connection();
+        let mut conn = AsyncConnection::new(conn.with_version(2)).unwrap();
+        let mut stream = conn.run("RETURN {d}", params).unwrap();
+        assert_eq!(Poll::NotReady, stream.poll().unwrap());
+        assert_eq!(RUN, testing::expect(&mut server));
+    }
+
+    #[test]
+    fn bolt_v3_connection_is_rejected() {
+        let (conn, _server) = testing::connection();
+
+        match AsyncConnection::new(conn.with_version(3)) {
+            Err(GraphError::Unsupported { version: 3, .. }) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn connection_with_pending_replies_is_rejected() {
+        let (mut conn, _server) = testing::connection();
+        conn.queue(&Run::new("RETURN 1")).unwrap();
+        conn.queue(&PullAll).unwrap();
+
+        match AsyncConnection::new(conn) {
+            Err(GraphError::PendingReplies(2)) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+}
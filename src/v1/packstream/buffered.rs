@@ -0,0 +1,111 @@
+use std::io::prelude::*;
+use std::io::BufReader;
+use rustc_serialize::Decodable;
+
+use super::deserialize::{decode, DecodeResult};
+use super::value::{Value, Registry};
+
+/// Capacity of the buffer of a `BufferedDecoder` created with `new`.
+pub const DEFAULT_BUFFER_CAPACITY: usize = 8 * 1024;
+
+/// Decodes consecutive values from an unbuffered source, e.g. a `TcpStream`, which the decoders
+/// would otherwise read a byte at a time.
+///
+/// The source is read ahead of the value being decoded, so it should not be read directly while
+/// the decoder is in use.
+pub struct BufferedDecoder<R> {
+    reader: BufReader<R>,
+    registry: Registry,
+}
+
+impl<R: Read> BufferedDecoder<R> {
+    pub fn new(source: R) -> Self {
+        BufferedDecoder::with_capacity(DEFAULT_BUFFER_CAPACITY, source)
+    }
+
+    /// A decoder reading at most `capacity` bytes from `source` at a time.
+    pub fn with_capacity(capacity: usize, source: R) -> Self {
+        BufferedDecoder {
+            reader: BufReader::with_capacity(capacity, source),
+            registry: Registry::default(),
+        }
+    }
+
+    /// Decode structures read by `read_value` using `registry`.
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    pub fn decode<T: Decodable>(&mut self) -> DecodeResult<T> {
+        decode(&mut self.reader)
+    }
+
+    /// Read the next value, like `Value::from_reader`.
+    pub fn read_value(&mut self) -> DecodeResult<Value> {
+        self.registry.read_value(&mut self.reader)
+    }
+
+    /// Bytes read from the source but not decoded yet.
+    pub fn buffer(&self) -> &[u8] {
+        self.reader.buffer()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.reader.get_ref()
+    }
+
+    /// The source, dropping the bytes read ahead (see `buffer`).
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read};
+    use super::BufferedDecoder;
+    use v1::packstream::encode;
+    use v1::packstream::value::Value;
+
+    // Counts the calls to `read`, which would be system calls on a socket.
+    struct CountingReader<R> {
+        inner: R,
+        reads: usize,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            self.inner.read(buf)
+        }
+    }
+
+    #[test]
+    fn decode_consecutive_values() {
+        let mut data = encode(&(0..100).collect::<Vec<i64>>()).unwrap();
+        data.extend(encode(&"second").unwrap());
+        data.extend(encode(&vec![1, 2, 3]).unwrap());
+
+        let mut decoder = BufferedDecoder::new(CountingReader { inner: Cursor::new(data), reads: 0 });
+        let first: Vec<i64> = decoder.decode().unwrap();
+        assert_eq!(100, first.len());
+        assert_eq!("second", decoder.decode::<String>().unwrap());
+        assert_eq!(Value::from(vec![1, 2, 3]), decoder.read_value().unwrap());
+        assert!(decoder.buffer().is_empty());
+
+        assert!(decoder.get_ref().reads <= 3);
+    }
+
+    #[test]
+    fn small_capacity() {
+        let data = encode(&vec!["a"; 100]).unwrap();
+        let len = data.len();
+
+        let mut decoder = BufferedDecoder::with_capacity(16, CountingReader { inner: Cursor::new(data), reads: 0 });
+        assert_eq!(vec!["a"; 100], decoder.decode::<Vec<String>>().unwrap());
+
+        let reads = decoder.into_inner().reads;
+        assert!(reads >= len / 16 && reads <= len / 16 + 2, "{} reads", reads);
+    }
+}
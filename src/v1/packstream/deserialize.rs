@@ -1,4 +1,5 @@
-use std::convert::From;
+use std::cmp;
+use std::convert::{From, TryFrom};
 use std::error::Error;
 use std::fmt;
 use std::io::prelude::*;
@@ -8,14 +9,158 @@ use rustc_serialize::{Decodable, Decoder};
 use byteorder::{ReadBytesExt, BigEndian};
 
 use super::marker as m;
+use super::serialize::encode;
+use super::value::{Value, Map, Registry};
 
+/// Decode a `T` from `source`, which is read a few bytes at a time: wrap an unbuffered source,
+/// e.g. a `TcpStream`, in a `BufferedDecoder`.
 pub fn decode<T: Decodable, R: Read>(source: &mut R) -> DecodeResult<T> {
     let mut decoder = PackstreamDecoder::new(source);
     Decodable::decode(&mut decoder)
 }
 
+/// Like `decode`, failing with `DecoderError::SizeLimitExceeded` on values larger than `limits`.
+pub fn decode_with_limits<T: Decodable, R: Read>(source: &mut R, limits: DecodeLimits) -> DecodeResult<T> {
+    let mut decoder = PackstreamDecoder::new(source).with_limits(limits);
+    Decodable::decode(&mut decoder)
+}
+
+/// Like `decode`, converting integers out of range of the type they are decoded into as
+/// `overflow` says.
+pub fn decode_with_integer_overflow<T: Decodable, R: Read>(source: &mut R, overflow: IntegerOverflow) -> DecodeResult<T> {
+    let mut decoder = PackstreamDecoder::new(source).with_integer_overflow(overflow);
+    Decodable::decode(&mut decoder)
+}
+
 pub type DecodeResult<T> = Result<T, DecoderError>;
 
+/// The largest values accepted when decoding, checked against the size declared before a
+/// value so a corrupt or malicious length is rejected before anything is allocated for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Bytes of a string or byte array.
+    pub max_string_size: usize,
+    /// Items of a list.
+    pub max_list_size: usize,
+    /// Entries of a map.
+    pub max_map_size: usize,
+    /// Fields of a structure.
+    pub max_struct_size: usize,
+}
+
+impl DecodeLimits {
+    /// No limit besides the sizes the markers can declare.
+    pub fn unlimited() -> Self {
+        DecodeLimits {
+            max_string_size: usize::MAX,
+            max_list_size: usize::MAX,
+            max_map_size: usize::MAX,
+            max_struct_size: usize::MAX,
+        }
+    }
+
+    pub(crate) fn check(limit: usize, kind: &'static str, size: usize) -> DecodeResult<usize> {
+        if size > limit {
+            return Err(DecErr::SizeLimitExceeded { kind, size, limit })
+        }
+        Ok(size)
+    }
+}
+
+/// What to do with a key repeated in a map, which a well-behaved server never sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKeys {
+    /// Fail with `DecoderError::DuplicateKey`.
+    Error,
+    KeepFirst,
+    /// Keep the value read last, the default.
+    #[default]
+    KeepLast,
+}
+
+/// What to do with an integer too large or too small for the type it is decoded into, e.g. 300
+/// into a `u8`, which can only happen when decoding into a Rust type, not into a `Value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegerOverflow {
+    /// Fail with `DecoderError::IntegerOutOfRange`, the default.
+    #[default]
+    Error,
+    /// Clamp to the smallest or largest value of the type, so 300 becomes 255 in a `u8`.
+    Saturate,
+    /// Keep the lowest bits, as `as` does, so 300 becomes 44 in a `u8`.
+    Wrap,
+}
+
+impl IntegerOverflow {
+    pub(crate) fn narrow<T: Narrow>(self, value: i64, target: &'static str) -> DecodeResult<T> {
+        match self {
+            IntegerOverflow::Error => T::checked(value).ok_or(DecErr::IntegerOutOfRange { value, target }),
+            IntegerOverflow::Saturate => Ok(T::saturating(value)),
+            IntegerOverflow::Wrap => Ok(T::wrapping(value)),
+        }
+    }
+}
+
+// The conversions of an `i64` read from the stream into a narrower or unsigned integer.
+pub(crate) trait Narrow: Sized {
+    fn checked(value: i64) -> Option<Self>;
+    fn saturating(value: i64) -> Self;
+    fn wrapping(value: i64) -> Self;
+}
+
+macro_rules! impl_narrow {
+    ($($ty:ident),*) => {$(
+        impl Narrow for $ty {
+            fn checked(value: i64) -> Option<Self> {
+                $ty::try_from(value).ok()
+            }
+
+            fn saturating(value: i64) -> Self {
+                (value as i128).clamp($ty::MIN as i128, $ty::MAX as i128) as $ty
+            }
+
+            fn wrapping(value: i64) -> Self {
+                value as $ty
+            }
+        }
+    )*}
+}
+
+impl_narrow!(u8, u16, u32, u64, usize, i8, i16, i32, isize);
+
+/// How values are read by `Registry::read_value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DecodeOptions {
+    pub limits: DecodeLimits,
+    pub duplicate_keys: DuplicateKeys,
+}
+
+/// As large as a default `ChunkedStream` message for strings, and a million items for
+/// collections, which are allocated up front by `Vec` and `HashMap` when decoded.
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_string_size: 64 * 1024 * 1024,
+            max_list_size: 1024 * 1024,
+            max_map_size: 1024 * 1024,
+            max_struct_size: 256,
+        }
+    }
+}
+
+// Read the `size` bytes following a length prefix, however many calls to `read` it takes. The
+// buffer grows with the data received, so a bogus prefix cannot allocate gigabytes up front.
+pub(crate) fn read_bytes<R: Read>(reader: &mut R, size: usize) -> DecodeResult<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(cmp::min(size, 4096));
+    Read::take(reader, size as u64).read_to_end(&mut bytes)?;
+
+    if bytes.len() < size {
+        return Err(DecErr::UnexpectedEOF)
+    }
+
+    Ok(bytes)
+}
+
 fn is_tiny_int_pos(b: u8) -> bool { b >> 7 == 0x00 }
 fn is_tiny_int_neg(b: u8) -> bool { b >> 4 == m::TINY_INT_NEG_NIBBLE >> 4 }
 fn is_tiny_int(b: u8) -> bool { is_tiny_int_pos(b) || is_tiny_int_neg(b) }
@@ -51,6 +196,10 @@ fn is_string(b: u8) -> bool {
         || b == m::STRING_16 || b == m::STRING_32
 }
 
+fn is_bytes(b: u8) -> bool {
+    b == m::BYTES_8 || b == m::BYTES_16 || b == m::BYTES_32
+}
+
 fn is_map(b: u8) -> bool {
     is_tiny_map(b) || b == m::MAP_8
         || b == m::MAP_16 || b == m::MAP_32
@@ -75,6 +224,9 @@ pub fn which(byte: u8) -> Option<&'static str> {
         m::STRING_8 => Some("STRING_8"),
         m::STRING_16 => Some("STRING_16"),
         m::STRING_32 => Some("STRING_32"),
+        m::BYTES_8 => Some("BYTES_8"),
+        m::BYTES_16 => Some("BYTES_16"),
+        m::BYTES_32 => Some("BYTES_32"),
         _ if is_tiny_list(byte) => Some("TINY_LIST"),
         m::LIST_8 => Some("LIST_8"),
         m::LIST_16 => Some("LIST_16"),
@@ -96,16 +248,25 @@ pub enum DecoderError {
     UnexpectedMarker(String, String),
     UnexpectedInput(String, String),
     UnknownVariant(String),
-    WrongField(String, String),
+    MissingField(String),
     InvalidUTF8,
     ApplicationError(String),
     UnexpectedEOF,
+    SizeLimitExceeded { kind: &'static str, size: usize, limit: usize },
+    DuplicateKey(String),
+    /// An integer did not fit the type it was decoded into, named by `target`.
+    IntegerOutOfRange { value: i64, target: &'static str },
 }
 
 use self::DecoderError as DecErr;
 
 impl Error for DecoderError {
-    fn description(&self) -> &str { "decoder error" }
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            DecErr::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for DecoderError {
@@ -114,8 +275,13 @@ impl fmt::Display for DecoderError {
             DecErr::UnexpectedMarker(ref exp, ref got) | DecErr::UnexpectedInput(ref exp, ref got) => {
                 write!(f, "Expected '{}', Found '{}'", exp, got)
             }
-            DecErr::WrongField(ref exp, ref got) => {
-                write!(f, "Expected field '{}', Found '{}'", exp, got)
+            DecErr::MissingField(ref name) => write!(f, "Missing field '{}'", name),
+            DecErr::DuplicateKey(ref key) => write!(f, "Duplicate map key '{}'", key),
+            DecErr::SizeLimitExceeded { kind, size, limit } => {
+                write!(f, "{} of size {} exceeds the limit of {}", kind, size, limit)
+            }
+            DecErr::IntegerOutOfRange { value, target } => {
+                write!(f, "Integer {} is out of range for {}", value, target)
             }
             _ => fmt::Debug::fmt(&self, f)
         }
@@ -145,6 +311,16 @@ macro_rules! wrong_marker {
     }
 }
 
+// Read any integer and narrow it to `$ty` as `integer_overflow` says.
+macro_rules! read_integer {
+    ($name:ident, $ty:ident) => {
+        fn $name(&mut self) -> Result<$ty, Self::Error> {
+            let value = self.read_i64()?;
+            self.integer_overflow.narrow(value, stringify!($ty))
+        }
+    }
+}
+
 macro_rules! wrong_input {
     ($expected:expr, $got:expr) => {
         Err(DecErr::UnexpectedInput($expected, $got))
@@ -152,58 +328,125 @@ macro_rules! wrong_input {
 }
 
 enum StructKind {
-    Regular,
+    // a map with `remaining` entries still unread and the entries read ahead of their field
+    Regular { remaining: usize, buffered: Map },
     Structure,
 }
 
+// Reads the bytes of the entries read ahead of their field before the ones of `inner`.
+struct Source<'a, R: Read + 'a> {
+    // in reverse order, so the next byte is popped from the end
+    replay: Vec<u8>,
+    inner: &'a mut R,
+}
+
+impl<'a, R: Read> Source<'a, R> {
+    fn push_front(&mut self, bytes: &[u8]) {
+        self.replay.extend(bytes.iter().rev());
+    }
+}
+
+impl<'a, R: Read> Read for Source<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.replay.is_empty() {
+            return self.inner.read(buf)
+        }
+
+        let len = cmp::min(buf.len(), self.replay.len());
+        for byte in buf.iter_mut().take(len) {
+            *byte = self.replay.pop().unwrap();
+        }
+        Ok(len)
+    }
+}
+
+// Where the value of a field of a map read as a struct comes from.
+enum FieldValue {
+    Next,
+    Buffered(Value),
+    Missing,
+}
+
 pub struct PackstreamDecoder<'a, R: Read + 'a> {
-    reader: &'a mut R,
-    struct_stack: Vec<StructKind>
+    reader: Source<'a, R>,
+    struct_stack: Vec<StructKind>,
+    // inside a byte array read as a sequence, whose elements are raw bytes
+    bytes: bool,
+    limits: DecodeLimits,
+    integer_overflow: IntegerOverflow,
 }
 
 impl<'a, R: Read> PackstreamDecoder<'a, R> {
     pub fn new(reader: &'a mut R) -> Self {
         PackstreamDecoder {
-            reader: reader,
+            reader: Source { replay: Vec::new(), inner: reader },
             struct_stack: Vec::new(),
+            bytes: false,
+            limits: DecodeLimits::default(),
+            integer_overflow: IntegerOverflow::default(),
         }
     }
 
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_integer_overflow(mut self, integer_overflow: IntegerOverflow) -> Self {
+        self.integer_overflow = integer_overflow;
+        self
+    }
+
+    // A reader of values skipped or buffered by `read_struct`, under the same limits.
+    fn registry(&self) -> Registry {
+        Registry::empty().with_limits(self.limits)
+    }
+
+    // Find the value of the field `f_name` of the map being read as a struct, buffering the
+    // entries of the fields read ahead of it.
+    fn next_field_value(&mut self, f_name: &str) -> DecodeResult<Option<FieldValue>> {
+        let remaining = match self.struct_stack.last_mut() {
+            Some(&mut StructKind::Regular { ref mut buffered, remaining }) => {
+                if let Some(value) = buffered.remove(f_name) {
+                    return Ok(Some(FieldValue::Buffered(value)))
+                }
+                remaining
+            }
+            _ => return Ok(None),
+        };
+
+        for _ in 0..remaining {
+            let key = self.read_str()?;
+            let value = if key == f_name { None } else { Some(self.registry().read_value(&mut self.reader)?) };
+
+            if let Some(&mut StructKind::Regular { ref mut buffered, ref mut remaining }) = self.struct_stack.last_mut() {
+                *remaining -= 1;
+                match value {
+                    None => return Ok(Some(FieldValue::Next)),
+                    Some(value) => { buffered.insert(key, value); }
+                }
+            }
+        }
+
+        Ok(Some(FieldValue::Missing))
+    }
+
     fn read_string_data(&mut self, marker: u8) -> Result<String, DecoderError> {
         let size;
         if is_tiny_string(marker) {
             size = (marker & 0b0000_1111) as usize;
         } else if marker == m::STRING_8 {
-            size = try!(self.reader.read_u8()) as usize;
+            size = self.reader.read_u8()? as usize;
         } else if marker == m::STRING_16 {
-            size = try!(self.reader.read_u16::<BigEndian>()) as usize;
+            size = self.reader.read_u16::<BigEndian>()? as usize;
         } else if marker == m::STRING_32 {
-            size = try!(self.reader.read_u32::<BigEndian>()) as usize;
+            size = self.reader.read_u32::<BigEndian>()? as usize;
         } else {
             return wrong_marker!("STRING".to_owned(), marker)
         }
 
-        let mut store;
-        if size < 4096 {
-            store = vec![0u8; size];
-            try!(self.reader.read(&mut store));
-        } else {
-            store = Vec::with_capacity(size);
-            let mut buf = [0u8; 4096];
-
-            let loops = (size as f32 / 4096.0).floor() as usize;
-            for _ in 0..loops {
-                let bytes = try!(self.reader.read(&mut buf));
-                store.extend(buf[0..bytes].iter());
-            }
-
-            if size % 4096 > 0 {
-                let mut buf = vec![0u8; size % 4096];
-                try!(self.reader.read(&mut buf));
-                store.append(&mut buf);
-            }
-        }
-
+        let size = DecodeLimits::check(self.limits.max_string_size, "STRING", size)?;
+        let store = read_bytes(&mut self.reader, size)?;
         String::from_utf8(store).map_err(From::from)
     }
 }
@@ -213,7 +456,7 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
 
     // Primitive types:
     fn read_nil(&mut self) -> Result<(), Self::Error> {
-        let marker = try!(self.reader.read_u8());
+        let marker = self.reader.read_u8()?;
         if marker != m::NULL {
             wrong_marker!("NULL".to_owned(), marker)
         } else {
@@ -221,68 +464,24 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
         }
     }
 
-    #[cfg(target_pointer_width = "32")]
-    fn read_usize(&mut self) -> Result<usize, Self::Error> {
-        self.read_u32().map(|v| v as usize)
-    }
-
-    #[cfg(target_pointer_width = "64")]
-    fn read_usize(&mut self) -> Result<usize, Self::Error> {
-        self.read_u64().map(|v| v as usize)
-    }
-
-    fn read_u64(&mut self) -> Result<u64, Self::Error> {
-        let value = try!(self.read_i64());
-
-        if value < 0 {
-            return wrong_input!("+INT_64".to_owned(), "-INTEGER".to_owned())
-        }
-
-        Ok(value as u64)
-    }
-
-    fn read_u32(&mut self) -> Result<u32, Self::Error> {
-        let value = try!(self.read_i32());
-
-        if value < 0 {
-            return wrong_input!("+INT_32".to_owned(), "-INTEGER".to_owned())
-        }
-
-        Ok(value as u32)
-    }
-
-    fn read_u16(&mut self) -> Result<u16, Self::Error> {
-        let value = try!(self.read_i16());
-
-        if value < 0 {
-            return wrong_input!("+INT_16".to_owned(), "-INTEGER".to_owned())
-        }
-
-        Ok(value as u16)
-    }
+    read_integer!(read_usize, usize);
+    read_integer!(read_u64, u64);
+    read_integer!(read_u32, u32);
+    read_integer!(read_u16, u16);
 
     fn read_u8(&mut self) -> Result<u8, Self::Error> {
-        let value = try!(self.read_i8());
-
-        if value < 0 {
-            return wrong_input!("+INT_8".to_owned(), "-INTEGER".to_owned())
+        if self.bytes {
+            return self.reader.read_u8().map_err(From::from)
         }
 
-        Ok(value as u8)
+        let value = self.read_i64()?;
+        self.integer_overflow.narrow(value, "u8")
     }
 
-    #[cfg(target_pointer_width = "32")]
-    fn read_isize(&mut self) -> Result<isize, Self::Error> {
-        self.read_i32().map(|v| v as isize)
-    }
-
-    #[cfg(target_pointer_width = "64")]
-    fn read_isize(&mut self) -> Result<isize, Self::Error> {
-        self.read_i64().map(|v| v as isize)
-    }
+    read_integer!(read_isize, isize);
 
     fn read_i64(&mut self) -> Result<i64, Self::Error> {
-        let marker = try!(self.reader.read_u8());
+        let marker = self.reader.read_u8()?;
         if !is_int64_or_lesser(marker) {
             return wrong_marker!("INT_64".to_owned(), marker)
         }
@@ -291,84 +490,28 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
         if is_tiny_int(marker) {
             value = read_tiny_int(marker) as i64;
         } else if marker == m::INT_8 {
-            let value_read = try!(self.reader.read_i8());
+            let value_read = self.reader.read_i8()?;
             value = value_read as i64;
         } else if marker == m::INT_16 {
-            let value_read = try!(self.reader.read_i16::<BigEndian>());
+            let value_read = self.reader.read_i16::<BigEndian>()?;
             value = value_read as i64;
         } else if marker == m::INT_32 {
-            let value_read = try!(self.reader.read_i32::<BigEndian>());
-            value = value_read as i64;
-        } else {
-            let value_read = try!(self.reader.read_i64::<BigEndian>());
+            let value_read = self.reader.read_i32::<BigEndian>()?;
             value = value_read as i64;
-        }
-
-        Ok(value)
-    }
-
-    fn read_i32(&mut self) -> Result<i32, Self::Error> {
-        let marker = try!(self.reader.read_u8());
-        if !is_int32_or_lesser(marker) {
-            return wrong_marker!("INT_32".to_owned(), marker)
-        }
-
-        let value: i32;
-        if is_tiny_int(marker) {
-            value = read_tiny_int(marker) as i32;
-        } else if marker == m::INT_8 {
-            let value_read = try!(self.reader.read_i8());
-            value = value_read as i32;
-        } else if marker == m::INT_16 {
-            let value_read = try!(self.reader.read_i16::<BigEndian>());
-            value = value_read as i32;
-        } else {
-            let value_read = try!(self.reader.read_i32::<BigEndian>());
-            value = value_read as i32;
-        }
-
-        Ok(value)
-    }
-
-    fn read_i16(&mut self) -> Result<i16, Self::Error> {
-        let marker = try!(self.reader.read_u8());
-        if !is_int16_or_lesser(marker) {
-            return wrong_marker!("INT_16".to_owned(), marker)
-        }
-
-        let value: i16;
-        if is_tiny_int(marker) {
-            value = read_tiny_int(marker) as i16;
-        } else if marker == m::INT_8 {
-            let value_read = try!(self.reader.read_i8());
-            value = value_read as i16
         } else {
-            let value_read = try!(self.reader.read_i16::<BigEndian>());
-            value = value_read as i16
+            let value_read = self.reader.read_i64::<BigEndian>()?;
+            value = value_read;
         }
 
         Ok(value)
     }
 
-    fn read_i8(&mut self) -> Result<i8, Self::Error> {
-        let marker = try!(self.reader.read_u8());
-        if !is_int8_or_lesser(marker) {
-            return wrong_marker!("INT_8".to_owned(), marker)
-        }
-
-        let value: i8;
-        if is_tiny_int(marker) {
-            value = read_tiny_int(marker);
-        } else  {
-            let value_read = try!(self.reader.read_i8());
-            value = value_read
-        }
-
-        Ok(value)
-    }
+    read_integer!(read_i32, i32);
+    read_integer!(read_i16, i16);
+    read_integer!(read_i8, i8);
 
     fn read_bool(&mut self) -> Result<bool, Self::Error> {
-        let marker = try!(self.reader.read_u8());
+        let marker = self.reader.read_u8()?;
         match marker {
             m::TRUE => Ok(true),
             m::FALSE => Ok(false),
@@ -377,7 +520,7 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
     }
 
     fn read_f64(&mut self) -> Result<f64, Self::Error> {
-        let marker = try!(self.reader.read_u8());
+        let marker = self.reader.read_u8()?;
         if marker != m::FLOAT {
             return wrong_marker!("FLOAT".to_owned(), marker)
         }
@@ -390,7 +533,7 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
     }
 
     fn read_char(&mut self) -> Result<char, Self::Error> {
-        let value = try!(self.read_str());
+        let value = self.read_str()?;
 
         if value.len() > 1 { return wrong_input!("CHAR".to_owned(), "STRING".to_owned()) }
 
@@ -400,7 +543,7 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
     }
 
     fn read_str(&mut self) -> Result<String, Self::Error> {
-        let marker = try!(self.reader.read_u8());
+        let marker = self.reader.read_u8()?;
 
         if !is_string(marker) {
             return wrong_marker!("STRING".to_owned(), marker)
@@ -420,17 +563,17 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
                                -> Result<T, Self::Error>
         where F: FnMut(&mut Self, usize) -> Result<T, Self::Error> {
 
-        let marker = try!(self.reader.read_u8());
+        let marker = self.reader.read_u8()?;
         let name: String;
         if is_string(marker) {
-            name = try!(self.read_string_data(marker));
+            name = self.read_string_data(marker)?;
         } else if is_tiny_map(marker) {
             let size = 1;
             if size != marker & 0b0000_1111 {
                 return wrong_input!("Map(1)".to_owned(), format!("Map({})", marker & 0b0000_1111))
             }
-            name = try!(self.read_str());
-            try!(self.read_seq(|_, _| Ok(())));
+            name = self.read_str()?;
+            self.read_seq(|_, _| Ok(()))?;
         } else {
             return wrong_marker!("ENUM_VARIANT".to_owned(), marker)
         }
@@ -471,7 +614,7 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
                          -> Result<T, Self::Error>
         where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
 
-        let marker = try!(self.reader.read_u8());
+        let marker = self.reader.read_u8()?;
 
         let struct_kind: StructKind;
         let size: usize;
@@ -479,21 +622,24 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
             if is_tiny_map(marker) {
                 size = (marker & 0b0000_1111) as usize;
             } else if marker == m::MAP_8 {
-                size = try!(self.reader.read_u8()) as usize;
+                size = self.reader.read_u8()? as usize;
             } else if marker == m::MAP_16 {
-                size = try!(self.reader.read_u16::<BigEndian>()) as usize;
+                size = self.reader.read_u16::<BigEndian>()? as usize;
             } else {
-                size = try!(self.reader.read_u32::<BigEndian>()) as usize;
+                size = self.reader.read_u32::<BigEndian>()? as usize;
             }
 
-            struct_kind = StructKind::Regular;
+            DecodeLimits::check(self.limits.max_map_size, "MAP", size)?;
+
+            // the keys of a map are not known until read, so any number of them is accepted
+            struct_kind = StructKind::Regular { remaining: size, buffered: Map::new() };
         } else if is_structure(marker) {
             if is_tiny_structure(marker) {
                 size = (marker & 0b0000_1111) as usize + 1;
             } else if marker == m::STRUCT_8 {
-                size = try!(self.reader.read_u8()) as usize + 1;
+                size = self.reader.read_u8()? as usize + 1;
             } else {
-                size = try!(self.reader.read_u16::<BigEndian>()) as usize + 1;
+                size = self.reader.read_u16::<BigEndian>()? as usize + 1;
             }
 
             struct_kind = StructKind::Structure;
@@ -501,13 +647,25 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
             return wrong_marker!("MAP or STRUCTURE".to_owned(), marker)
         }
 
-        if size != len {
-            return wrong_input!(format!("{} ({} fields)", s_name, len), format!("? ({} fields)", size))
+        if let StructKind::Structure = struct_kind {
+            // `size` counts the signature, read as the first field
+            DecodeLimits::check(self.limits.max_struct_size, "STRUCTURE", size - 1)?;
+            if size != len {
+                return wrong_input!(format!("{} ({} fields)", s_name, len), format!("? ({} fields)", size))
+            }
         }
 
         self.struct_stack.push(struct_kind);
         let result = f(self);
-        self.struct_stack.pop();
+        let struct_kind = self.struct_stack.pop();
+
+        // skip the entries of fields the struct does not have
+        if let (Ok(_), Some(StructKind::Regular { remaining, .. })) = (&result, struct_kind) {
+            for _ in 0..remaining {
+                self.read_str()?;
+                self.registry().read_value(&mut self.reader)?;
+            }
+        }
         result
     }
 
@@ -518,18 +676,20 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
                                -> Result<T, Self::Error>
         where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
 
-        match self.struct_stack.last() {
-            Some(&StructKind::Regular) => {
-                let prop = try!(self.read_str());
-                if prop != f_name {
-                    return Err(DecErr::WrongField(prop, f_name.to_owned()))
-                }
+        match self.next_field_value(f_name)? {
+            Some(FieldValue::Next) => f(self),
+            Some(FieldValue::Buffered(value)) => {
+                let bytes = encode(&value).map_err(|e| DecErr::ApplicationError(e.to_string()))?;
+                self.reader.push_front(&bytes);
+                f(self)
             }
-            Some(&StructKind::Structure) => {},
-            _ => {}
+            // a missing field is read as null, which only an `Option` accepts
+            Some(FieldValue::Missing) => {
+                self.reader.push_front(&[m::NULL]);
+                f(self).map_err(|_| DecErr::MissingField(f_name.to_owned()))
+            }
+            None => f(self),
         }
-
-        f(self)
     }
 
     fn read_tuple<T, F>(&mut self, len: usize, f: F) -> Result<T, Self::Error>
@@ -551,11 +711,16 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
         self.read_seq_elt(a_idx, f)
     }
 
+    // newtype structs (e.g. `struct NodeId(i64)`) are read from the value they wrap
     fn read_tuple_struct<T, F>(&mut self, _: &str, len: usize, f: F)
                                -> Result<T, Self::Error>
         where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
 
-        self.read_tuple(len, f)
+        if len == 1 {
+            f(self)
+        } else {
+            self.read_tuple(len, f)
+        }
     }
 
     fn read_tuple_struct_arg<T, F>(&mut self, a_idx: usize, f: F)
@@ -569,29 +734,49 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
     fn read_option<T, F>(&mut self, mut f: F) -> Result<T, Self::Error>
         where F: FnMut(&mut Self, bool) -> Result<T, Self::Error> {
 
-        let marker = try!(self.reader.read_u8());
-        if marker == m::NULL { f(self, false) }
-        else { f(self, true) }
+        let marker = self.reader.read_u8()?;
+        if marker == m::NULL {
+            f(self, false)
+        } else {
+            // the marker belongs to the value
+            self.reader.push_front(&[marker]);
+            f(self, true)
+        }
     }
 
     fn read_seq<T, F>(&mut self, f: F) -> Result<T, Self::Error>
         where F: FnOnce(&mut Self, usize) -> Result<T, Self::Error> {
 
-        let marker = try!(self.reader.read_u8());
+        let marker = self.reader.read_u8()?;
 
         let size: usize;
         if is_tiny_list(marker) {
             size = (marker & 0b0000_1111) as usize;
         } else if marker == m::LIST_8 {
-            size = try!(self.reader.read_u8()) as usize;
+            size = self.reader.read_u8()? as usize;
         } else if marker == m::LIST_16 {
-            size = try!(self.reader.read_u16::<BigEndian>()) as usize;
+            size = self.reader.read_u16::<BigEndian>()? as usize;
         } else if marker == m::LIST_32 {
-            size = try!(self.reader.read_u32::<BigEndian>()) as usize;
+            size = self.reader.read_u32::<BigEndian>()? as usize;
+        } else if is_bytes(marker) {
+            // a byte array is read as a sequence of `u8`, e.g. into a `Vec<u8>`
+            size = match marker {
+                m::BYTES_8 => self.reader.read_u8()? as usize,
+                m::BYTES_16 => self.reader.read_u16::<BigEndian>()? as usize,
+                _ => self.reader.read_u32::<BigEndian>()? as usize,
+            };
+            DecodeLimits::check(self.limits.max_string_size, "BYTES", size)?;
+
+            let outer = self.bytes;
+            self.bytes = true;
+            let result = f(self, size);
+            self.bytes = outer;
+            return result
         } else {
             return wrong_marker!("LIST".to_owned(), marker)
         }
 
+        DecodeLimits::check(self.limits.max_list_size, "LIST", size)?;
         f(self, size)
     }
 
@@ -604,21 +789,22 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
     fn read_map<T, F>(&mut self, f: F) -> Result<T, Self::Error>
         where F: FnOnce(&mut Self, usize) -> Result<T, Self::Error> {
 
-        let marker = try!(self.reader.read_u8());
+        let marker = self.reader.read_u8()?;
 
         let size: usize;
         if is_tiny_map(marker) {
             size = (marker & 0b0000_1111) as usize;
         } else if marker == m::MAP_8 {
-            size = try!(self.reader.read_u8()) as usize;
+            size = self.reader.read_u8()? as usize;
         } else if marker == m::MAP_16 {
-            size = try!(self.reader.read_u16::<BigEndian>()) as usize;
+            size = self.reader.read_u16::<BigEndian>()? as usize;
         } else if marker == m::MAP_32 {
-            size = try!(self.reader.read_u32::<BigEndian>()) as usize;
+            size = self.reader.read_u32::<BigEndian>()? as usize;
         } else {
             return wrong_marker!("MAP".to_owned(), marker)
         }
 
+        DecodeLimits::check(self.limits.max_map_size, "MAP", size)?;
         f(self, size)
     }
 
@@ -644,11 +830,40 @@ impl<'a, R: Read> Decoder for PackstreamDecoder<'a, R> {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, HashMap};
     use std::string::String;
     use std::io::Cursor;
-    use super::decode;
+    use super::{decode, decode_with_limits, decode_with_integer_overflow, DecodeLimits, DecoderError, IntegerOverflow};
+    use ::v1::packstream::encode;
+    use ::v1::packstream::value::{Value, Registry};
     use ::v1::packstream::marker as m;
+    use ::v1::testing::ShortReads;
+
+    #[test]
+    fn deserialize_bytes() {
+        let mut input = Cursor::new(vec![m::BYTES_8, 0x03, 0x01, 0x02, 0xFF]);
+        let result: ::v1::packstream::Bytes = decode(&mut input).unwrap();
+
+        assert_eq!(vec![0x01, 0x02, 0xFF], result.0);
+    }
+
+    #[test]
+    fn deserialize_bytes_into_vec() {
+        let mut input = Cursor::new(vec![m::BYTES_16, 0x00, 0x02, 0x80, 0x81]);
+        let result: Vec<u8> = decode(&mut input).unwrap();
+
+        assert_eq!(vec![0x80, 0x81], result);
+    }
+
+    #[test]
+    fn deserialize_list_after_bytes() {
+        let mut input = Cursor::new(vec![m::TINY_LIST_NIBBLE + 0x02,
+                                         m::BYTES_8, 0x01, 0xFF,
+                                         m::TINY_LIST_NIBBLE + 0x01, 0x01]);
+        let result: (Vec<u8>, Vec<u8>) = decode(&mut input).unwrap();
+
+        assert_eq!((vec![0xFF], vec![0x01]), result);
+    }
 
     #[test]
     fn deserialize_nil() {
@@ -660,15 +875,22 @@ mod tests {
         assert_eq!(None, result);
     }
 
+    #[test]
+    fn deserialize_option() {
+        let mut input = Cursor::new(vec![m::TINY_LIST_NIBBLE + 0x02, 0x01, m::NULL]);
+        let result: Vec<Option<i64>> = decode(&mut input).unwrap();
+        assert_eq!(vec![Some(1), None], result);
+    }
+
     #[test]
     fn deserialize_bool() {
         let mut input = Cursor::new(vec![0xC3]);
         let result: bool = decode(&mut input).unwrap();
-        assert_eq!(true, result);
+        assert!(result);
 
         let mut input = Cursor::new(vec![0xC2]);
         let result: bool = decode(&mut input).unwrap();
-        assert_eq!(false, result);
+        assert!(!result);
     }
 
     // Integer 64
@@ -701,21 +923,21 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedInput(\"+INT_64\", \"-INTEGER\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: -1, target: \"u64\" }")]
     fn negative_int_into_u64_should_panic() {
         let mut input = Cursor::new(vec![0xFF]);
         let _: u64 = decode(&mut input).unwrap();
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedMarker(\"INT_32\", \"INT_64\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: 9223372036854775807, target: \"u32\" }")]
     fn positive_int64_into_smaller_should_fail() {
         let mut input = Cursor::new(vec![m::INT_64, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
         let _: u32 = decode(&mut input).unwrap();
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedMarker(\"INT_32\", \"INT_64\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: -9223372036854775808, target: \"i32\" }")]
     fn negative_int64_into_smaller_should_fail() {
         let mut input = Cursor::new(vec![m::INT_64, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
         let _: i32 = decode(&mut input).unwrap();
@@ -751,21 +973,21 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedInput(\"+INT_32\", \"-INTEGER\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: -1, target: \"u32\" }")]
     fn negative_int_into_u32_should_panic() {
         let mut input = Cursor::new(vec![0xFF]);
         let _: u32 = decode(&mut input).unwrap();
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedMarker(\"INT_16\", \"INT_32\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: 2147483647, target: \"u16\" }")]
     fn positive_int32_into_smaller_should_fail() {
         let mut input = Cursor::new(vec![m::INT_32, 0x7F, 0xFF, 0xFF, 0xFF]);
         let _: u16 = decode(&mut input).unwrap();
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedMarker(\"INT_16\", \"INT_32\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: -2147483648, target: \"i16\" }")]
     fn negative_int32_into_smaller_should_fail() {
         let mut input = Cursor::new(vec![m::INT_32, 0x80, 0x00, 0x00, 0x00]);
         let _: i16 = decode(&mut input).unwrap();
@@ -801,21 +1023,21 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedInput(\"+INT_16\", \"-INTEGER\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: -1, target: \"u16\" }")]
     fn negative_int_into_u16_should_panic() {
         let mut input = Cursor::new(vec![0xFF]);
         let _: u16 = decode(&mut input).unwrap();
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedMarker(\"INT_8\", \"INT_16\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: 32767, target: \"u8\" }")]
     fn positive_int16_into_smaller_should_fail() {
         let mut input = Cursor::new(vec![m::INT_16, 0x7F, 0xFF]);
         let _: u8 = decode(&mut input).unwrap();
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedMarker(\"INT_8\", \"INT_16\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: -32768, target: \"i8\" }")]
     fn negative_int16_into_smaller_should_fail() {
         let mut input = Cursor::new(vec![m::INT_16, 0x80, 0x00]);
         let _: i8 = decode(&mut input).unwrap();
@@ -841,19 +1063,109 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedInput(\"+INT_8\", \"-INTEGER\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: -128, target: \"u8\" }")]
     fn negative_int_into_u8_should_panic() {
         let mut input = Cursor::new(vec![m::INT_8, 0x80]);
         let _: u8 = decode(&mut input).unwrap();
     }
 
     #[test]
-    #[should_panic(expected = "UnexpectedInput(\"+INT_8\", \"-INTEGER\")")]
+    #[should_panic(expected = "IntegerOutOfRange { value: -16, target: \"u8\" }")]
     fn negative_small_int_into_u8_should_panic() {
         let mut input = Cursor::new(vec![0xF0]);
         let _: u8 = decode(&mut input).unwrap();
     }
 
+    // Decode the values around the bounds of `$ty` in every mode, from the smallest marker
+    // holding each of them.
+    macro_rules! check_integer_bounds {
+        ($ty:ident) => {{
+            let bounds = [$ty::MIN as i128 - 1, $ty::MIN as i128, $ty::MIN as i128 + 1,
+                          -1, 0, 1, $ty::MAX as i128 - 1, $ty::MAX as i128, $ty::MAX as i128 + 1];
+
+            for &bound in bounds.iter().filter(|&&b| b >= i64::MIN as i128 && b <= i64::MAX as i128) {
+                let value = bound as i64;
+                let input = encode(&value).unwrap();
+                let fits = bound >= $ty::MIN as i128 && bound <= $ty::MAX as i128;
+
+                let result: Result<$ty, _> = decode_with_integer_overflow(&mut Cursor::new(&input), IntegerOverflow::Error);
+                match result {
+                    Ok(v) => assert!(fits && v as i128 == bound, "{} into {} gave {}", value, stringify!($ty), v),
+                    Err(DecoderError::IntegerOutOfRange { value: v, target }) => {
+                        assert!(!fits, "{} into {} failed", value, stringify!($ty));
+                        assert_eq!((value, stringify!($ty)), (v, target));
+                    }
+                    Err(e) => panic!("{} into {}: {:?}", value, stringify!($ty), e),
+                }
+
+                let saturated = bound.clamp($ty::MIN as i128, $ty::MAX as i128) as $ty;
+                let result: $ty = decode_with_integer_overflow(&mut Cursor::new(&input), IntegerOverflow::Saturate).unwrap();
+                assert_eq!(saturated, result, "{} saturated into {}", value, stringify!($ty));
+
+                let result: $ty = decode_with_integer_overflow(&mut Cursor::new(&input), IntegerOverflow::Wrap).unwrap();
+                assert_eq!(value as $ty, result, "{} wrapped into {}", value, stringify!($ty));
+            }
+        }}
+    }
+
+    #[test]
+    fn integer_bounds_unsigned() {
+        check_integer_bounds!(u8);
+        check_integer_bounds!(u16);
+        check_integer_bounds!(u32);
+        check_integer_bounds!(u64);
+        check_integer_bounds!(usize);
+    }
+
+    #[test]
+    fn integer_bounds_signed() {
+        check_integer_bounds!(i8);
+        check_integer_bounds!(i16);
+        check_integer_bounds!(i32);
+        check_integer_bounds!(i64);
+        check_integer_bounds!(isize);
+    }
+
+    #[test]
+    fn every_u8_round_trips() {
+        for v in 0..=u8::MAX {
+            let input = encode(&v).unwrap();
+            let result: u8 = decode(&mut Cursor::new(&input)).unwrap();
+            assert_eq!(v, result);
+
+            let value = Registry::empty().read_value(&mut Cursor::new(&input)).unwrap();
+            assert_eq!(Value::Integer(v as i64), value);
+        }
+
+        let all: Vec<u8> = (0..=u8::MAX).collect();
+        let result: Vec<u8> = decode(&mut Cursor::new(encode(&all).unwrap())).unwrap();
+        assert_eq!(all, result);
+    }
+
+    #[test]
+    fn integer_from_wider_marker_that_fits() {
+        // a server may send small values in a wider encoding than needed
+        let mut input = Cursor::new(vec![m::INT_64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC8]);
+        let result: u8 = decode(&mut input).unwrap();
+        assert_eq!(200, result);
+
+        let mut input = Cursor::new(vec![m::INT_32, 0xFF, 0xFF, 0x80, 0x00]);
+        let result: i16 = decode(&mut input).unwrap();
+        assert_eq!(i16::MIN, result);
+    }
+
+    #[test]
+    fn integer_out_of_range_in_collections() {
+        let input = encode(&vec![1i64, 300]).unwrap();
+        let result: Vec<u8> = decode_with_integer_overflow(&mut Cursor::new(&input), IntegerOverflow::Saturate).unwrap();
+        assert_eq!(vec![1, 255], result);
+
+        match decode::<Vec<u8>, _>(&mut Cursor::new(&input)) {
+            Err(e @ DecoderError::IntegerOutOfRange { .. }) => assert_eq!("Integer 300 is out of range for u8", e.to_string()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
     #[test]
     fn deserialize_float_positive() {
         let mut input = Cursor::new(vec![m::FLOAT, 0x3F, 0xF1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9A]);
@@ -868,6 +1180,57 @@ mod tests {
         assert_eq!(-1.1, result);
     }
 
+    #[test]
+    fn deserialize_string_from_short_reads() {
+        let text = "A".repeat(10_000);
+        let mut input = vec![m::STRING_16, 0x27, 0x10];
+        input.extend(text.as_bytes());
+
+        let result: String = decode(&mut ShortReads::new(Cursor::new(input), 7)).unwrap();
+        assert_eq!(text, result);
+    }
+
+    #[test]
+    fn deserialize_truncated_string_should_fail() {
+        let mut input = Cursor::new(vec![m::STRING_16, 0x27, 0x10, b'A']);
+        match decode::<String, _>(&mut input) {
+            Err(DecoderError::UnexpectedEOF) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn declared_size_beyond_limit_is_rejected_before_allocating() {
+        // `Vec` and `HashMap` reserve the declared size up front
+        let mut input = Cursor::new(vec![m::LIST_32, 0xFF, 0xFF, 0xFF, 0xFF]);
+        match decode::<Vec<i64>, _>(&mut input) {
+            Err(DecoderError::SizeLimitExceeded { kind: "LIST", size: 0xFFFF_FFFF, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut input = Cursor::new(vec![m::MAP_32, 0xFF, 0xFF, 0xFF, 0xFF]);
+        match decode::<HashMap<String, i64>, _>(&mut input) {
+            Err(DecoderError::SizeLimitExceeded { kind: "MAP", .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_with_custom_limits() {
+        let limits = DecodeLimits { max_string_size: 3, ..DecodeLimits::default() };
+
+        let result: String = decode_with_limits(&mut Cursor::new(vec![0x83, b'a', b'b', b'c']), limits).unwrap();
+        assert_eq!("abc", result);
+
+        match decode_with_limits::<String, _>(&mut Cursor::new(vec![0x84, b'a', b'b', b'c', b'd']), limits) {
+            Err(e @ DecoderError::SizeLimitExceeded { .. }) => {
+                assert_eq!("STRING of size 4 exceeds the limit of 3", e.to_string())
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(decode_with_limits::<String, _>(&mut Cursor::new(vec![0x84, b'a', b'b', b'c', b'd']), DecodeLimits::unlimited()).is_ok());
+    }
+
     #[test]
     fn deserialize_string32() {
         let size = 70_000;
@@ -928,7 +1291,7 @@ mod tests {
 
     #[test]
     fn deserialize_char() {
-        for c in b'A'..b'Z' {
+        for c in b'A'..=b'Z' {
             let mut input = Cursor::new(vec![0x81, c]);
             let result: char = decode(&mut input).unwrap();
 
@@ -1086,6 +1449,27 @@ mod tests {
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn deserialize_newtype_struct() {
+        use rustc_serialize::{Decodable, Decoder};
+
+        #[derive(Debug, PartialEq)]
+        struct NodeId(i64);
+
+        impl Decodable for NodeId {
+            fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+                d.read_tuple_struct("NodeId", 1, |d| d.read_tuple_struct_arg(0, Decodable::decode).map(NodeId))
+            }
+        }
+
+        let mut input = Cursor::new(vec![m::TINY_LIST_NIBBLE + 2, 0x01, m::INT_16, 0x7D, 0x00]);
+
+        let result: Vec<NodeId> = decode(&mut input).unwrap();
+        let expected = vec![NodeId(1), NodeId(32_000)];
+
+        assert_eq!(expected, result);
+    }
+
     #[test]
     fn deserialize_map32() {
         let size = 70_000;
@@ -1299,41 +1683,43 @@ mod tests {
 
     #[test]
     fn deserialize_struct16() {
-        #[derive(RustcDecodable, Debug, PartialEq)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            A001: u16, A002: u16, A003: u16, A004: u16, A005: u16, A006: u16, A007: u16, A008: u16,
-            A009: u16, A010: u16, A011: u16, A012: u16, A013: u16, A014: u16, A015: u16, A016: u16,
-            A017: u16, A018: u16, A019: u16, A020: u16, A021: u16, A022: u16, A023: u16, A024: u16,
-            A025: u16, A026: u16, A027: u16, A028: u16, A029: u16, A030: u16, A031: u16, A032: u16,
-            A033: u16, A034: u16, A035: u16, A036: u16, A037: u16, A038: u16, A039: u16, A040: u16,
-            A041: u16, A042: u16, A043: u16, A044: u16, A045: u16, A046: u16, A047: u16, A048: u16,
-            A049: u16, A050: u16, A051: u16, A052: u16, A053: u16, A054: u16, A055: u16, A056: u16,
-            A057: u16, A058: u16, A059: u16, A060: u16, A061: u16, A062: u16, A063: u16, A064: u16,
-            A065: u16, A066: u16, A067: u16, A068: u16, A069: u16, A070: u16, A071: u16, A072: u16,
-            A073: u16, A074: u16, A075: u16, A076: u16, A077: u16, A078: u16, A079: u16, A080: u16,
-            A081: u16, A082: u16, A083: u16, A084: u16, A085: u16, A086: u16, A087: u16, A088: u16,
-            A089: u16, A090: u16, A091: u16, A092: u16, A093: u16, A094: u16, A095: u16, A096: u16,
-            A097: u16, A098: u16, A099: u16, A100: u16, A101: u16, A102: u16, A103: u16, A104: u16,
-            A105: u16, A106: u16, A107: u16, A108: u16, A109: u16, A110: u16, A111: u16, A112: u16,
-            A113: u16, A114: u16, A115: u16, A116: u16, A117: u16, A118: u16, A119: u16, A120: u16,
-            A121: u16, A122: u16, A123: u16, A124: u16, A125: u16, A126: u16, A127: u16, A128: u16,
-            A129: u16, A130: u16, A131: u16, A132: u16, A133: u16, A134: u16, A135: u16, A136: u16,
-            A137: u16, A138: u16, A139: u16, A140: u16, A141: u16, A142: u16, A143: u16, A144: u16,
-            A145: u16, A146: u16, A147: u16, A148: u16, A149: u16, A150: u16, A151: u16, A152: u16,
-            A153: u16, A154: u16, A155: u16, A156: u16, A157: u16, A158: u16, A159: u16, A160: u16,
-            A161: u16, A162: u16, A163: u16, A164: u16, A165: u16, A166: u16, A167: u16, A168: u16,
-            A169: u16, A170: u16, A171: u16, A172: u16, A173: u16, A174: u16, A175: u16, A176: u16,
-            A177: u16, A178: u16, A179: u16, A180: u16, A181: u16, A182: u16, A183: u16, A184: u16,
-            A185: u16, A186: u16, A187: u16, A188: u16, A189: u16, A190: u16, A191: u16, A192: u16,
-            A193: u16, A194: u16, A195: u16, A196: u16, A197: u16, A198: u16, A199: u16, A200: u16,
-            A201: u16, A202: u16, A203: u16, A204: u16, A205: u16, A206: u16, A207: u16, A208: u16,
-            A209: u16, A210: u16, A211: u16, A212: u16, A213: u16, A214: u16, A215: u16, A216: u16,
-            A217: u16, A218: u16, A219: u16, A220: u16, A221: u16, A222: u16, A223: u16, A224: u16,
-            A225: u16, A226: u16, A227: u16, A228: u16, A229: u16, A230: u16, A231: u16, A232: u16,
-            A233: u16, A234: u16, A235: u16, A236: u16, A237: u16, A238: u16, A239: u16, A240: u16,
-            A241: u16, A242: u16, A243: u16, A244: u16, A245: u16, A246: u16, A247: u16, A248: u16,
-            A249: u16, A250: u16, A251: u16, A252: u16, A253: u16, A254: u16, A255: u16, A256: u16,
+        decodable_struct! {
+            #[derive(Debug, PartialEq)]
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                A001: u16, A002: u16, A003: u16, A004: u16, A005: u16, A006: u16, A007: u16, A008: u16,
+                A009: u16, A010: u16, A011: u16, A012: u16, A013: u16, A014: u16, A015: u16, A016: u16,
+                A017: u16, A018: u16, A019: u16, A020: u16, A021: u16, A022: u16, A023: u16, A024: u16,
+                A025: u16, A026: u16, A027: u16, A028: u16, A029: u16, A030: u16, A031: u16, A032: u16,
+                A033: u16, A034: u16, A035: u16, A036: u16, A037: u16, A038: u16, A039: u16, A040: u16,
+                A041: u16, A042: u16, A043: u16, A044: u16, A045: u16, A046: u16, A047: u16, A048: u16,
+                A049: u16, A050: u16, A051: u16, A052: u16, A053: u16, A054: u16, A055: u16, A056: u16,
+                A057: u16, A058: u16, A059: u16, A060: u16, A061: u16, A062: u16, A063: u16, A064: u16,
+                A065: u16, A066: u16, A067: u16, A068: u16, A069: u16, A070: u16, A071: u16, A072: u16,
+                A073: u16, A074: u16, A075: u16, A076: u16, A077: u16, A078: u16, A079: u16, A080: u16,
+                A081: u16, A082: u16, A083: u16, A084: u16, A085: u16, A086: u16, A087: u16, A088: u16,
+                A089: u16, A090: u16, A091: u16, A092: u16, A093: u16, A094: u16, A095: u16, A096: u16,
+                A097: u16, A098: u16, A099: u16, A100: u16, A101: u16, A102: u16, A103: u16, A104: u16,
+                A105: u16, A106: u16, A107: u16, A108: u16, A109: u16, A110: u16, A111: u16, A112: u16,
+                A113: u16, A114: u16, A115: u16, A116: u16, A117: u16, A118: u16, A119: u16, A120: u16,
+                A121: u16, A122: u16, A123: u16, A124: u16, A125: u16, A126: u16, A127: u16, A128: u16,
+                A129: u16, A130: u16, A131: u16, A132: u16, A133: u16, A134: u16, A135: u16, A136: u16,
+                A137: u16, A138: u16, A139: u16, A140: u16, A141: u16, A142: u16, A143: u16, A144: u16,
+                A145: u16, A146: u16, A147: u16, A148: u16, A149: u16, A150: u16, A151: u16, A152: u16,
+                A153: u16, A154: u16, A155: u16, A156: u16, A157: u16, A158: u16, A159: u16, A160: u16,
+                A161: u16, A162: u16, A163: u16, A164: u16, A165: u16, A166: u16, A167: u16, A168: u16,
+                A169: u16, A170: u16, A171: u16, A172: u16, A173: u16, A174: u16, A175: u16, A176: u16,
+                A177: u16, A178: u16, A179: u16, A180: u16, A181: u16, A182: u16, A183: u16, A184: u16,
+                A185: u16, A186: u16, A187: u16, A188: u16, A189: u16, A190: u16, A191: u16, A192: u16,
+                A193: u16, A194: u16, A195: u16, A196: u16, A197: u16, A198: u16, A199: u16, A200: u16,
+                A201: u16, A202: u16, A203: u16, A204: u16, A205: u16, A206: u16, A207: u16, A208: u16,
+                A209: u16, A210: u16, A211: u16, A212: u16, A213: u16, A214: u16, A215: u16, A216: u16,
+                A217: u16, A218: u16, A219: u16, A220: u16, A221: u16, A222: u16, A223: u16, A224: u16,
+                A225: u16, A226: u16, A227: u16, A228: u16, A229: u16, A230: u16, A231: u16, A232: u16,
+                A233: u16, A234: u16, A235: u16, A236: u16, A237: u16, A238: u16, A239: u16, A240: u16,
+                A241: u16, A242: u16, A243: u16, A244: u16, A245: u16, A246: u16, A247: u16, A248: u16,
+                A249: u16, A250: u16, A251: u16, A252: u16, A253: u16, A254: u16, A255: u16, A256: u16,
+            }
         }
 
         let mut input = Cursor::new(vec![m::MAP_16, 0x01, 0x00,
@@ -1413,13 +1799,15 @@ mod tests {
 
     #[test]
     fn deserialize_struct8() {
-        #[derive(RustcDecodable, Debug, PartialEq)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            A: u16, B: u16, C: u16, D: u16,
-            E: u16, F: u16, G: u16, H: u16,
-            I: u16, J: u16, K: u16, L: u16,
-            M: u16, N: u16, O: u16, P: u16,
+        decodable_struct! {
+            #[derive(Debug, PartialEq)]
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                A: u16, B: u16, C: u16, D: u16,
+                E: u16, F: u16, G: u16, H: u16,
+                I: u16, J: u16, K: u16, L: u16,
+                M: u16, N: u16, O: u16, P: u16,
+            }
         }
 
         let mut input = Cursor::new(vec![m::MAP_8, 0x10,
@@ -1443,12 +1831,14 @@ mod tests {
 
     #[test]
     fn deserialize_tiny_struct() {
-        #[derive(RustcDecodable, Debug, PartialEq)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            A: u32,
-            B: f64,
-            C: String,
+        decodable_struct! {
+            #[derive(Debug, PartialEq)]
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                A: u32,
+                B: f64,
+                C: String,
+            }
         }
 
         let mut input = Cursor::new(vec![m::TINY_MAP_NIBBLE + 0x03,
@@ -1468,15 +1858,107 @@ mod tests {
         assert_eq!(expected, result);
     }
 
+    decodable_struct! {
+        #[derive(Debug, PartialEq)]
+        struct Person {
+            name: String,
+            age: i64,
+            nickname: Option<String>,
+            address: Address,
+        }
+    }
+
+    decodable_struct! {
+        #[derive(Debug, PartialEq)]
+        struct Address {
+            street: String,
+            city: String,
+        }
+    }
+
+    fn alice() -> Person {
+        Person {
+            name: "Alice".to_owned(),
+            age: 33,
+            nickname: None,
+            address: Address { street: "Rua Augusta".to_owned(), city: "Lisbon".to_owned() },
+        }
+    }
+
+    #[test]
+    fn deserialize_struct_with_keys_in_any_order() {
+        // the entries of a `Value::Map` are encoded sorted by key, not in declaration order
+        let value = Value::map()
+            .entry("name", "Alice")
+            .entry("age", 33)
+            .entry("address", Value::map().entry("street", "Rua Augusta").entry("city", "Lisbon").build())
+            .build();
+        let mut input = Cursor::new(encode(&value).unwrap());
+
+        assert_eq!(alice(), decode(&mut input).unwrap());
+        assert_eq!(input.get_ref().len() as u64, input.position());
+    }
+
+    #[test]
+    fn deserialize_struct_skips_unknown_fields() {
+        let value = Value::map()
+            .entry("name", "Alice")
+            .entry("age", 33)
+            .entry("email", "alice@example.com")
+            .entry("address", Value::map()
+                .entry("street", "Rua Augusta")
+                .entry("city", "Lisbon")
+                .entry("zip", Value::list().item(1100).item(53).build())
+                .build())
+            .build();
+        let mut data = encode(&value).unwrap();
+        data.extend(encode(&"next").unwrap());
+        let mut input = Cursor::new(data);
+
+        assert_eq!(alice(), decode(&mut input).unwrap());
+        assert_eq!("next", decode::<String, _>(&mut input).unwrap());
+    }
+
+    #[test]
+    fn deserialize_struct_with_missing_field() {
+        let value = Value::map()
+            .entry("name", "Alice")
+            .entry("address", Value::map().entry("street", "Rua Augusta").entry("city", "Lisbon").build())
+            .build();
+        let mut input = Cursor::new(encode(&value).unwrap());
+
+        match decode::<Person, _>(&mut input) {
+            Err(DecoderError::MissingField(ref name)) if name == "age" => {}
+            other => panic!("expected a missing field, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deserialize_struct_with_missing_nested_field() {
+        let value = Value::map()
+            .entry("name", "Alice")
+            .entry("age", 33)
+            .entry("address", Value::map().entry("street", "Rua Augusta").build())
+            .build();
+        let mut input = Cursor::new(encode(&value).unwrap());
+
+        match decode::<Person, _>(&mut input) {
+            Err(DecoderError::MissingField(ref name)) if name == "city" => {}
+            other => panic!("expected a missing field, got {:?}", other),
+        }
+    }
+
     #[test]
     fn deserialize_tiny_structure() {
-        #[derive(RustcDecodable, Debug, PartialEq)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            signature: u8,
-            A: u32,
-            B: f64,
-            C: String,
+        decodable_struct! {
+            #[derive(Debug, PartialEq)]
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                signature: u8,
+                A: u32,
+                B: f64,
+                C: String,
+            }
         }
 
         let mut input = Cursor::new(vec![m::TINY_STRUCT_NIBBLE + 0x03, 0x22,
@@ -1501,14 +1983,16 @@ mod tests {
     fn deserialize_structure8() {
         let size = 16;
 
-        #[derive(RustcDecodable, Debug, PartialEq)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            signature: u8,
-            A: u16, B: u16, C: u16, D: u16,
-            E: u16, F: u16, G: u16, H: u16,
-            I: u16, J: u16, K: u16, L: u16,
-            M: u16, N: u16, O: u16, P: u16,
+        decodable_struct! {
+            #[derive(Debug, PartialEq)]
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                signature: u8,
+                A: u16, B: u16, C: u16, D: u16,
+                E: u16, F: u16, G: u16, H: u16,
+                I: u16, J: u16, K: u16, L: u16,
+                M: u16, N: u16, O: u16, P: u16,
+            }
         }
 
         let mut input = Cursor::new((0..size).fold(
@@ -1533,42 +2017,44 @@ mod tests {
     fn deserialize_structure16() {
         let size = 256;
 
-        #[derive(RustcDecodable, Debug, PartialEq)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            signature: u8,
-            A001: u16, A002: u16, A003: u16, A004: u16, A005: u16, A006: u16, A007: u16, A008: u16,
-            A009: u16, A010: u16, A011: u16, A012: u16, A013: u16, A014: u16, A015: u16, A016: u16,
-            A017: u16, A018: u16, A019: u16, A020: u16, A021: u16, A022: u16, A023: u16, A024: u16,
-            A025: u16, A026: u16, A027: u16, A028: u16, A029: u16, A030: u16, A031: u16, A032: u16,
-            A033: u16, A034: u16, A035: u16, A036: u16, A037: u16, A038: u16, A039: u16, A040: u16,
-            A041: u16, A042: u16, A043: u16, A044: u16, A045: u16, A046: u16, A047: u16, A048: u16,
-            A049: u16, A050: u16, A051: u16, A052: u16, A053: u16, A054: u16, A055: u16, A056: u16,
-            A057: u16, A058: u16, A059: u16, A060: u16, A061: u16, A062: u16, A063: u16, A064: u16,
-            A065: u16, A066: u16, A067: u16, A068: u16, A069: u16, A070: u16, A071: u16, A072: u16,
-            A073: u16, A074: u16, A075: u16, A076: u16, A077: u16, A078: u16, A079: u16, A080: u16,
-            A081: u16, A082: u16, A083: u16, A084: u16, A085: u16, A086: u16, A087: u16, A088: u16,
-            A089: u16, A090: u16, A091: u16, A092: u16, A093: u16, A094: u16, A095: u16, A096: u16,
-            A097: u16, A098: u16, A099: u16, A100: u16, A101: u16, A102: u16, A103: u16, A104: u16,
-            A105: u16, A106: u16, A107: u16, A108: u16, A109: u16, A110: u16, A111: u16, A112: u16,
-            A113: u16, A114: u16, A115: u16, A116: u16, A117: u16, A118: u16, A119: u16, A120: u16,
-            A121: u16, A122: u16, A123: u16, A124: u16, A125: u16, A126: u16, A127: u16, A128: u16,
-            A129: u16, A130: u16, A131: u16, A132: u16, A133: u16, A134: u16, A135: u16, A136: u16,
-            A137: u16, A138: u16, A139: u16, A140: u16, A141: u16, A142: u16, A143: u16, A144: u16,
-            A145: u16, A146: u16, A147: u16, A148: u16, A149: u16, A150: u16, A151: u16, A152: u16,
-            A153: u16, A154: u16, A155: u16, A156: u16, A157: u16, A158: u16, A159: u16, A160: u16,
-            A161: u16, A162: u16, A163: u16, A164: u16, A165: u16, A166: u16, A167: u16, A168: u16,
-            A169: u16, A170: u16, A171: u16, A172: u16, A173: u16, A174: u16, A175: u16, A176: u16,
-            A177: u16, A178: u16, A179: u16, A180: u16, A181: u16, A182: u16, A183: u16, A184: u16,
-            A185: u16, A186: u16, A187: u16, A188: u16, A189: u16, A190: u16, A191: u16, A192: u16,
-            A193: u16, A194: u16, A195: u16, A196: u16, A197: u16, A198: u16, A199: u16, A200: u16,
-            A201: u16, A202: u16, A203: u16, A204: u16, A205: u16, A206: u16, A207: u16, A208: u16,
-            A209: u16, A210: u16, A211: u16, A212: u16, A213: u16, A214: u16, A215: u16, A216: u16,
-            A217: u16, A218: u16, A219: u16, A220: u16, A221: u16, A222: u16, A223: u16, A224: u16,
-            A225: u16, A226: u16, A227: u16, A228: u16, A229: u16, A230: u16, A231: u16, A232: u16,
-            A233: u16, A234: u16, A235: u16, A236: u16, A237: u16, A238: u16, A239: u16, A240: u16,
-            A241: u16, A242: u16, A243: u16, A244: u16, A245: u16, A246: u16, A247: u16, A248: u16,
-            A249: u16, A250: u16, A251: u16, A252: u16, A253: u16, A254: u16, A255: u16, A256: u16,
+        decodable_struct! {
+            #[derive(Debug, PartialEq)]
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                signature: u8,
+                A001: u16, A002: u16, A003: u16, A004: u16, A005: u16, A006: u16, A007: u16, A008: u16,
+                A009: u16, A010: u16, A011: u16, A012: u16, A013: u16, A014: u16, A015: u16, A016: u16,
+                A017: u16, A018: u16, A019: u16, A020: u16, A021: u16, A022: u16, A023: u16, A024: u16,
+                A025: u16, A026: u16, A027: u16, A028: u16, A029: u16, A030: u16, A031: u16, A032: u16,
+                A033: u16, A034: u16, A035: u16, A036: u16, A037: u16, A038: u16, A039: u16, A040: u16,
+                A041: u16, A042: u16, A043: u16, A044: u16, A045: u16, A046: u16, A047: u16, A048: u16,
+                A049: u16, A050: u16, A051: u16, A052: u16, A053: u16, A054: u16, A055: u16, A056: u16,
+                A057: u16, A058: u16, A059: u16, A060: u16, A061: u16, A062: u16, A063: u16, A064: u16,
+                A065: u16, A066: u16, A067: u16, A068: u16, A069: u16, A070: u16, A071: u16, A072: u16,
+                A073: u16, A074: u16, A075: u16, A076: u16, A077: u16, A078: u16, A079: u16, A080: u16,
+                A081: u16, A082: u16, A083: u16, A084: u16, A085: u16, A086: u16, A087: u16, A088: u16,
+                A089: u16, A090: u16, A091: u16, A092: u16, A093: u16, A094: u16, A095: u16, A096: u16,
+                A097: u16, A098: u16, A099: u16, A100: u16, A101: u16, A102: u16, A103: u16, A104: u16,
+                A105: u16, A106: u16, A107: u16, A108: u16, A109: u16, A110: u16, A111: u16, A112: u16,
+                A113: u16, A114: u16, A115: u16, A116: u16, A117: u16, A118: u16, A119: u16, A120: u16,
+                A121: u16, A122: u16, A123: u16, A124: u16, A125: u16, A126: u16, A127: u16, A128: u16,
+                A129: u16, A130: u16, A131: u16, A132: u16, A133: u16, A134: u16, A135: u16, A136: u16,
+                A137: u16, A138: u16, A139: u16, A140: u16, A141: u16, A142: u16, A143: u16, A144: u16,
+                A145: u16, A146: u16, A147: u16, A148: u16, A149: u16, A150: u16, A151: u16, A152: u16,
+                A153: u16, A154: u16, A155: u16, A156: u16, A157: u16, A158: u16, A159: u16, A160: u16,
+                A161: u16, A162: u16, A163: u16, A164: u16, A165: u16, A166: u16, A167: u16, A168: u16,
+                A169: u16, A170: u16, A171: u16, A172: u16, A173: u16, A174: u16, A175: u16, A176: u16,
+                A177: u16, A178: u16, A179: u16, A180: u16, A181: u16, A182: u16, A183: u16, A184: u16,
+                A185: u16, A186: u16, A187: u16, A188: u16, A189: u16, A190: u16, A191: u16, A192: u16,
+                A193: u16, A194: u16, A195: u16, A196: u16, A197: u16, A198: u16, A199: u16, A200: u16,
+                A201: u16, A202: u16, A203: u16, A204: u16, A205: u16, A206: u16, A207: u16, A208: u16,
+                A209: u16, A210: u16, A211: u16, A212: u16, A213: u16, A214: u16, A215: u16, A216: u16,
+                A217: u16, A218: u16, A219: u16, A220: u16, A221: u16, A222: u16, A223: u16, A224: u16,
+                A225: u16, A226: u16, A227: u16, A228: u16, A229: u16, A230: u16, A231: u16, A232: u16,
+                A233: u16, A234: u16, A235: u16, A236: u16, A237: u16, A238: u16, A239: u16, A240: u16,
+                A241: u16, A242: u16, A243: u16, A244: u16, A245: u16, A246: u16, A247: u16, A248: u16,
+                A249: u16, A250: u16, A251: u16, A252: u16, A253: u16, A254: u16, A255: u16, A256: u16,
+            }
         }
 
         let mut input = Cursor::new((0..size).fold(
@@ -1619,11 +2105,22 @@ mod tests {
 
     #[test]
     fn deserialize_enum() {
-        #[derive(RustcDecodable, Debug, PartialEq)]
+        use rustc_serialize::{Decodable, Decoder};
+
+        #[derive(Debug, PartialEq)]
         enum MyEnum {
             A, B,
         }
 
+        impl Decodable for MyEnum {
+            fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+                d.read_enum("MyEnum", |d| d.read_enum_variant(&["A", "B"], |_, idx| match idx {
+                    0 => Ok(MyEnum::A),
+                    _ => Ok(MyEnum::B),
+                }))
+            }
+        }
+
         let mut input = Cursor::new(vec![0x81, 0x41]);
 
         let expected = MyEnum::A;
@@ -1634,11 +2131,24 @@ mod tests {
 
     #[test]
     fn deserialize_enum_tuple_variant() {
-        #[derive(RustcDecodable, Debug, PartialEq)]
+        use rustc_serialize::{Decodable, Decoder};
+
+        #[derive(Debug, PartialEq)]
         enum MyEnum {
             A(u16, u16), B(f32, f32),
         }
 
+        impl Decodable for MyEnum {
+            fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+                d.read_enum("MyEnum", |d| d.read_enum_variant(&["A", "B"], |d, idx| match idx {
+                    0 => Ok(MyEnum::A(d.read_enum_variant_arg(0, Decodable::decode)?,
+                                      d.read_enum_variant_arg(1, Decodable::decode)?)),
+                    _ => Ok(MyEnum::B(d.read_enum_variant_arg(0, Decodable::decode)?,
+                                      d.read_enum_variant_arg(1, Decodable::decode)?)),
+                }))
+            }
+        }
+
         let mut input = Cursor::new(vec![m::TINY_MAP_NIBBLE + 0x01,
                                          0x81, 0x41,
                                          0x92, 0x01, 0x02]);
@@ -1652,11 +2162,24 @@ mod tests {
     #[test]
     #[should_panic(expected = "UnexpectedInput(\"Map(1)\", \"Map(2)\")")]
     fn enum_tuple_variant_with_wrong_map_size_should_fail() {
-        #[derive(RustcDecodable, Debug, PartialEq)]
+        use rustc_serialize::{Decodable, Decoder};
+
+        #[derive(Debug, PartialEq)]
         enum MyEnum {
             A(u16, u16), B(f32, f32),
         }
 
+        impl Decodable for MyEnum {
+            fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+                d.read_enum("MyEnum", |d| d.read_enum_variant(&["A", "B"], |d, idx| match idx {
+                    0 => Ok(MyEnum::A(d.read_enum_variant_arg(0, Decodable::decode)?,
+                                      d.read_enum_variant_arg(1, Decodable::decode)?)),
+                    _ => Ok(MyEnum::B(d.read_enum_variant_arg(0, Decodable::decode)?,
+                                      d.read_enum_variant_arg(1, Decodable::decode)?)),
+                }))
+            }
+        }
+
         let mut input = Cursor::new(vec![m::TINY_MAP_NIBBLE + 0x02,
                                          0x81, 0x41,
                                          0x92, 0x01, 0x02]);
@@ -0,0 +1,240 @@
+use std::fmt;
+use byteorder::{ByteOrder, BigEndian};
+
+use super::marker as m;
+use super::deserialize::which;
+
+/// A single PackStream token found by `inspect`.
+///
+/// `length` covers the marker and everything stored inline with it (sizes, signature, scalar
+/// value or string bytes), but not the items of a list, map or structure, which are reported
+/// as the tokens that follow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenDescription {
+    pub offset: usize,
+    pub length: usize,
+    pub marker: &'static str,
+    pub detail: String,
+}
+
+impl fmt::Display for TokenDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:06X} +{:<4} {}", self.offset, self.length, self.marker)?;
+        if !self.detail.is_empty() {
+            write!(f, " {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Split a PackStream byte slice into human readable tokens without building values.
+///
+/// Scanning stops at the first unknown marker or truncated token, which is reported as
+/// `UNKNOWN` or `TRUNCATED`.
+pub fn inspect(bytes: &[u8]) -> Vec<TokenDescription> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        match read_token(&bytes[offset..]) {
+            Ok((marker, length, detail)) => {
+                tokens.push(TokenDescription { offset, length, marker, detail });
+                offset += length;
+            }
+            Err(marker) => {
+                tokens.push(TokenDescription {
+                    offset,
+                    length: bytes.len() - offset,
+                    marker,
+                    detail: format!("0x{:02X}", bytes[offset]),
+                });
+                break
+            }
+        }
+    }
+
+    tokens
+}
+
+type TokenResult = Result<(&'static str, usize, String), &'static str>;
+
+fn read_token(bytes: &[u8]) -> TokenResult {
+    let marker = bytes[0];
+    let name = match which(marker) {
+        Some(name) => name,
+        None => return Err("UNKNOWN"),
+    };
+
+    match marker {
+        m::NULL | m::TRUE | m::FALSE => Ok((name, 1, String::new())),
+        0x00..=0x7F | 0xF0..=0xFF => Ok((name, 1, format!("{}", marker as i8))),
+        m::INT_8 => scalar(bytes, name, 1, |b| format!("{}", b[0] as i8)),
+        m::INT_16 => scalar(bytes, name, 2, |b| format!("{}", BigEndian::read_i16(b))),
+        m::INT_32 => scalar(bytes, name, 4, |b| format!("{}", BigEndian::read_i32(b))),
+        m::INT_64 => scalar(bytes, name, 8, |b| format!("{}", BigEndian::read_i64(b))),
+        m::FLOAT => scalar(bytes, name, 8, |b| format!("{}", BigEndian::read_f64(b))),
+        0x80..=0x8F => string(bytes, name, 0, (marker & 0x0F) as usize),
+        m::STRING_8 | m::STRING_16 | m::STRING_32 => {
+            let (width, size) = size(bytes, marker - m::STRING_8)?;
+            string(bytes, name, width, size)
+        }
+        m::BYTES_8 | m::BYTES_16 | m::BYTES_32 => {
+            let (width, size) = size(bytes, marker - m::BYTES_8)?;
+            if bytes.len() < 1 + width + size {
+                return Err("TRUNCATED")
+            }
+            Ok((name, 1 + width + size, format!("size={}", size)))
+        }
+        0x90..=0xAF => Ok((name, 1, format!("size={}", marker & 0x0F))),
+        m::LIST_8 | m::LIST_16 | m::LIST_32 => {
+            let (width, size) = size(bytes, marker - m::LIST_8)?;
+            Ok((name, 1 + width, format!("size={}", size)))
+        }
+        m::MAP_8 | m::MAP_16 | m::MAP_32 => {
+            let (width, size) = size(bytes, marker - m::MAP_8)?;
+            Ok((name, 1 + width, format!("size={}", size)))
+        }
+        0xB0..=0xBF => {
+            let signature = *bytes.get(1).ok_or("TRUNCATED")?;
+            Ok((name, 2, format!("size={} signature=0x{:02X}", marker & 0x0F, signature)))
+        }
+        m::STRUCT_8 | m::STRUCT_16 => {
+            let (width, size) = size(bytes, marker - m::STRUCT_8)?;
+            let signature = *bytes.get(1 + width).ok_or("TRUNCATED")?;
+            Ok((name, 2 + width, format!("size={} signature=0x{:02X}", size, signature)))
+        }
+        _ => Err("UNKNOWN"),
+    }
+}
+
+fn scalar<F: Fn(&[u8]) -> String>(bytes: &[u8], name: &'static str, width: usize, f: F) -> TokenResult {
+    if bytes.len() < 1 + width {
+        return Err("TRUNCATED")
+    }
+
+    Ok((name, 1 + width, f(&bytes[1..1 + width])))
+}
+
+// `kind` is the distance from the 8 bit marker of the family: 0 for 8, 1 for 16 and 2 for 32 bit sizes.
+fn size(bytes: &[u8], kind: u8) -> Result<(usize, usize), &'static str> {
+    let width = 1 << kind;
+    if bytes.len() < 1 + width {
+        return Err("TRUNCATED")
+    }
+
+    let size = match width {
+        1 => bytes[1] as usize,
+        2 => BigEndian::read_u16(&bytes[1..3]) as usize,
+        _ => BigEndian::read_u32(&bytes[1..5]) as usize,
+    };
+
+    Ok((width, size))
+}
+
+fn string(bytes: &[u8], name: &'static str, width: usize, size: usize) -> TokenResult {
+    let start = 1 + width;
+    if bytes.len() < start + size {
+        return Err("TRUNCATED")
+    }
+
+    let detail = match ::std::str::from_utf8(&bytes[start..start + size]) {
+        Ok(s) => format!("{:?}", s),
+        Err(_) => format!("invalid utf-8 ({} bytes)", size),
+    };
+
+    Ok((name, start + size, detail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::v1::packstream::marker as m;
+
+    #[test]
+    fn inspect_bytes() {
+        let input = vec![m::BYTES_8, 0x02, 0x00, 0xFF, 0x01];
+
+        assert_eq!(vec![(0, 4, "BYTES_8", "size=2".to_owned()),
+                        (4, 1, "TINY_INT", "1".to_owned())], describe(&input));
+    }
+
+    #[test]
+    fn inspect_truncated_bytes() {
+        let input = vec![m::BYTES_8, 0x03, 0x00];
+
+        assert_eq!(vec![(0, 3, "TRUNCATED", "0xCC".to_owned())], describe(&input));
+    }
+
+    fn describe(bytes: &[u8]) -> Vec<(usize, usize, &'static str, String)> {
+        inspect(bytes).into_iter().map(|t| (t.offset, t.length, t.marker, t.detail)).collect()
+    }
+
+    #[test]
+    fn inspect_scalars() {
+        let input = vec![m::NULL, m::TRUE, 0x2A, 0xF0, m::INT_16, 0x7D, 0x00,
+                         m::FLOAT, 0x3F, 0xF1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9A];
+
+        assert_eq!(vec![(0, 1, "NULL", "".to_owned()),
+                        (1, 1, "TRUE", "".to_owned()),
+                        (2, 1, "TINY_INT", "42".to_owned()),
+                        (3, 1, "TINY_INT", "-16".to_owned()),
+                        (4, 3, "INT_16", "32000".to_owned()),
+                        (7, 9, "FLOAT", "1.1".to_owned())],
+                   describe(&input));
+    }
+
+    #[test]
+    fn inspect_string() {
+        let input = vec![0x83, 0x61, 0x62, 0x63, m::STRING_8, 0x01, 0x64];
+
+        assert_eq!(vec![(0, 4, "TINY_STRING", "\"abc\"".to_owned()),
+                        (4, 3, "STRING_8", "\"d\"".to_owned())],
+                   describe(&input));
+    }
+
+    #[test]
+    fn inspect_containers() {
+        // SUCCESS {fields: [1]}
+        let input = vec![0xB1, 0x70, 0xA1, 0x86, 0x66, 0x69, 0x65, 0x6C, 0x64, 0x73, 0x91, 0x01];
+
+        assert_eq!(vec![(0, 2, "TINY_STRUCT", "size=1 signature=0x70".to_owned()),
+                        (2, 1, "TINY_MAP", "size=1".to_owned()),
+                        (3, 7, "TINY_STRING", "\"fields\"".to_owned()),
+                        (10, 1, "TINY_LIST", "size=1".to_owned()),
+                        (11, 1, "TINY_INT", "1".to_owned())],
+                   describe(&input));
+    }
+
+    #[test]
+    fn inspect_large_sizes() {
+        let input = vec![m::LIST_16, 0x01, 0x00, m::MAP_32, 0x00, 0x01, 0x00, 0x00,
+                         m::STRUCT_8, 0x10, 0x22];
+
+        assert_eq!(vec![(0, 3, "LIST_16", "size=256".to_owned()),
+                        (3, 5, "MAP_32", "size=65536".to_owned()),
+                        (8, 3, "STRUCT_8", "size=16 signature=0x22".to_owned())],
+                   describe(&input));
+    }
+
+    #[test]
+    fn inspect_truncated() {
+        let input = vec![0x01, m::INT_32, 0x00, 0x01];
+
+        assert_eq!(vec![(0, 1, "TINY_INT", "1".to_owned()),
+                        (1, 3, "TRUNCATED", "0xCA".to_owned())],
+                   describe(&input));
+    }
+
+    #[test]
+    fn inspect_unknown_marker() {
+        let input = vec![0xC4, 0x01];
+
+        assert_eq!(vec![(0, 2, "UNKNOWN", "0xC4".to_owned())], describe(&input));
+    }
+
+    #[test]
+    fn display_token() {
+        let token = &inspect(&[0x83, 0x61, 0x62, 0x63])[0];
+        assert_eq!("000000 +4    TINY_STRING \"abc\"", format!("{}", token));
+    }
+}
@@ -47,6 +47,15 @@ pub const USE_STRING_8: usize = 255;
 pub const USE_STRING_16: usize = 65_535;
 pub const USE_STRING_32: usize = 4_294_967_295;
 
+// Bytes
+marker! { BYTES_8 = 0xCC }
+marker! { BYTES_16 = 0xCD }
+marker! { BYTES_32 = 0xCE }
+
+pub const USE_BYTES_8: usize = 255;
+pub const USE_BYTES_16: usize = 65_535;
+pub const USE_BYTES_32: usize = 4_294_967_295;
+
 // List
 pub const TINY_LIST_NIBBLE: u8 = 0b1001_0000;
 
@@ -2,9 +2,17 @@ pub mod marker;
 pub mod serialize;
 pub mod deserialize;
 pub mod value;
+pub mod inspect;
+pub mod buffered;
 
-pub use self::serialize::encode;
-pub use self::deserialize::decode;
-pub use self::value::Value;
+pub use self::serialize::{encode, encode_skipping_none};
+pub use self::deserialize::{decode, decode_with_limits, decode_with_integer_overflow, DecodeLimits, DecodeOptions,
+                            DuplicateKeys, IntegerOverflow};
+pub use self::value::{Value, Bytes, StructureSignature};
+pub use self::inspect::{inspect, TokenDescription};
+pub use self::buffered::BufferedDecoder;
 
-const STRUCTURE_PREFIX: &'static str = "__STRUCTURE__";
+const STRUCTURE_PREFIX: &str = "__STRUCTURE__";
+
+// Name of the newtype struct whose `Vec<u8>` is written as a byte array instead of a list.
+const BYTES_NAME: &str = "__BYTES__";
@@ -1,35 +1,62 @@
 use std::error::Error;
 use std::fmt;
 use std::io::prelude::*;
-use std::io::{self, Cursor};
+use std::io;
 use rustc_serialize::{Encodable, Encoder};
 use byteorder::{WriteBytesExt, BigEndian};
 
 use super::marker as m;
-use super::STRUCTURE_PREFIX;
+use super::{STRUCTURE_PREFIX, BYTES_NAME};
 
 pub fn encode<T: Encodable>(object: &T) -> EncodeResult<Vec<u8>> {
-    let mut buf = Cursor::new(Vec::new());
+    let mut buf = Vec::new();
     {
         let mut encoder = PackstreamEncoder::new(&mut buf);
-        try!(object.encode(&mut encoder));
+        object.encode(&mut encoder)?;
     }
-    Ok(buf.into_inner())
+    Ok(buf)
+}
+
+/// Like `encode`, leaving out the struct fields written as null, e.g. an `Option` that is `None`.
+/// The decoders read a missing field as null, so the struct decodes back the same.
+pub fn encode_skipping_none<T: Encodable>(object: &T) -> EncodeResult<Vec<u8>> {
+    let mut buf = Vec::new();
+    {
+        let mut encoder = PackstreamEncoder::new(&mut buf);
+        encoder.skip_none = true;
+        object.encode(&mut encoder)?;
+    }
+    Ok(buf)
 }
 
 #[derive(Debug)]
 pub enum EncoderError {
     IoError(io::Error),
     InvalidStructureLength,
+    InvalidBytesLength,
+    /// An unsigned integer above `i64::MAX`, which PackStream integers cannot hold.
+    ValueOutOfRange(u64),
 }
 
 impl Error for EncoderError {
-    fn description(&self) -> &str { "encoder error" }
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            EncoderError::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for EncoderError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(&self, f)
+        match *self {
+            EncoderError::IoError(ref e) => write!(f, "Failed to write encoded value: {}", e),
+            EncoderError::InvalidStructureLength => write!(f, "Structure has more than {} fields", m::USE_STRUCT_16),
+            EncoderError::InvalidBytesLength => write!(f, "Byte array is longer than {} bytes", m::USE_BYTES_32),
+            EncoderError::ValueOutOfRange(v) => {
+                write!(f, "Integer {} is out of range, PackStream integers are at most {}", v, i64::MAX)
+            }
+        }
     }
 }
 
@@ -41,24 +68,97 @@ impl From<io::Error> for EncoderError {
 
 pub type EncodeResult<T> = Result<T, EncoderError>;
 
-struct PackstreamEncoder<'a, W: Write + 'a> {
-    writer: &'a mut W,
+// Elements written to each sequence or map being encoded, innermost last, so the header of one
+// of unknown length can be inserted once all of its elements have been written.
+struct PackstreamEncoder<'a> {
+    writer: &'a mut Vec<u8>,
+    seq_elements: Vec<usize>,
+    // inside a `Bytes`, whose sequence is written as a byte array
+    bytes: bool,
+    // leave out the struct fields written as null
+    skip_none: bool,
 }
 
-impl<'a, W: Write> PackstreamEncoder<'a, W> {
-    pub fn new(writer: &'a mut W) -> Self {
+impl<'a> PackstreamEncoder<'a> {
+    pub fn new(writer: &'a mut Vec<u8>) -> Self {
         PackstreamEncoder {
-            writer: writer,
+            writer,
+            seq_elements: Vec::new(),
+            bytes: false,
+            skip_none: false,
         }
     }
+
+    // Write the value wrapped by a newtype struct in its place. It goes through
+    // `emit_tuple_struct_arg`, and so do the bytes of a `Bytes`, which are not elements of an
+    // enclosing sequence of unknown length.
+    fn emit_wrapped<F>(&mut self, f: F) -> EncodeResult<()>
+        where F: FnOnce(&mut Self) -> EncodeResult<()> {
+
+        self.seq_elements.push(0);
+        let result = f(self);
+        self.seq_elements.pop();
+        result
+    }
+}
+
+fn write_list_header<W: Write>(writer: &mut W, len: usize) -> io::Result<()> {
+    if len <= m::USE_TINY_LIST {
+        writer.write_u8(m::TINY_LIST_NIBBLE | len as u8)?;
+    } else if len <= m::USE_LIST_8 {
+        writer.write_u8(m::LIST_8)?;
+        writer.write_u8(len as u8)?;
+    } else if len <= m::USE_LIST_16 {
+        writer.write_u8(m::LIST_16)?;
+        writer.write_u16::<BigEndian>(len as u16)?;
+    } else if len <= m::USE_LIST_32 {
+        writer.write_u8(m::LIST_32)?;
+        writer.write_u32::<BigEndian>(len as u32)?;
+    }
+
+    Ok(())
+}
+
+fn write_map_header<W: Write>(writer: &mut W, len: usize) -> io::Result<()> {
+    if len <= m::USE_TINY_MAP {
+        writer.write_u8(m::TINY_MAP_NIBBLE | len as u8)?;
+    } else if len <= m::USE_MAP_8 {
+        writer.write_u8(m::MAP_8)?;
+        writer.write_u8(len as u8)?;
+    } else if len <= m::USE_MAP_16 {
+        writer.write_u8(m::MAP_16)?;
+        writer.write_u16::<BigEndian>(len as u16)?;
+    } else if len <= m::USE_MAP_32 {
+        writer.write_u8(m::MAP_32)?;
+        writer.write_u32::<BigEndian>(len as u32)?;
+    }
+
+    Ok(())
 }
 
-impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
+fn write_bytes_header<W: Write>(writer: &mut W, len: usize) -> EncodeResult<()> {
+    if len <= m::USE_BYTES_8 {
+        writer.write_u8(m::BYTES_8)?;
+        writer.write_u8(len as u8)?;
+    } else if len <= m::USE_BYTES_16 {
+        writer.write_u8(m::BYTES_16)?;
+        writer.write_u16::<BigEndian>(len as u16)?;
+    } else if len <= m::USE_BYTES_32 {
+        writer.write_u8(m::BYTES_32)?;
+        writer.write_u32::<BigEndian>(len as u32)?;
+    } else {
+        return Err(EncoderError::InvalidBytesLength)
+    }
+
+    Ok(())
+}
+
+impl<'a> Encoder for PackstreamEncoder<'a> {
     type Error = EncoderError;
 
     // Primitive types:
     fn emit_nil(&mut self) -> Result<(), Self::Error> {
-        try!(self.writer.write_u8(m::NULL));
+        self.writer.write_u8(m::NULL)?;
         Ok(())
     }
 
@@ -67,17 +167,20 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
     }
 
     fn emit_u64(&mut self, v: u64) -> Result<(), Self::Error> {
-        if v >= m::RANGE_POS_INT_64.0 as u64 && v <= m::RANGE_POS_INT_64.1 as u64 {
-            try!(self.writer.write_u8(m::INT_64));
-            try!(self.writer.write_u64::<BigEndian>(v));
-        } else if v >= m::RANGE_POS_INT_32.0 as u64 && v <= m::RANGE_POS_INT_32.1 as u64 {
-            try!(self.writer.write_u8(m::INT_32));
-            try!(self.writer.write_u32::<BigEndian>(v as u32));
-        } else if v >= m::RANGE_POS_INT_16.0 as u64 && v <= m::RANGE_POS_INT_16.1 as u64 {
-            try!(self.writer.write_u8(m::INT_16));
-            try!(self.writer.write_u16::<BigEndian>(v as u16));
-        } else if v <= m::RANGE_TINY_INT.1 as u64 {
-            try!(self.writer.write_u8(v as u8));
+        // smallest encodings first, as most integers are small
+        if v <= m::RANGE_TINY_INT.1 as u64 {
+            self.writer.write_u8(v as u8)?;
+        } else if v <= m::RANGE_POS_INT_16.1 as u64 {
+            self.writer.write_u8(m::INT_16)?;
+            self.writer.write_u16::<BigEndian>(v as u16)?;
+        } else if v <= m::RANGE_POS_INT_32.1 as u64 {
+            self.writer.write_u8(m::INT_32)?;
+            self.writer.write_u32::<BigEndian>(v as u32)?;
+        } else if v <= m::RANGE_POS_INT_64.1 as u64 {
+            self.writer.write_u8(m::INT_64)?;
+            self.writer.write_u64::<BigEndian>(v)?;
+        } else {
+            return Err(EncoderError::ValueOutOfRange(v))
         }
 
         Ok(())
@@ -92,6 +195,12 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
     }
 
     fn emit_u8(&mut self, v: u8) -> Result<(), Self::Error> {
+        if self.bytes {
+            self.writer.write_u8(v)?;
+            return Ok(())
+        }
+
+        // INT_8 is signed, so 128..=255 take an INT_16, which `read_u8` narrows back
         self.emit_u64(v as u64)
     }
 
@@ -100,26 +209,21 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
     }
 
     fn emit_i64(&mut self, v: i64) -> Result<(), Self::Error> {
-        if (v >= m::RANGE_POS_INT_64.0 && v <= m::RANGE_POS_INT_64.1)
-            || (v >= m::RANGE_NEG_INT_64.0 && v <= m::RANGE_NEG_INT_64.1)
-        {
-            try!(self.writer.write_u8(m::INT_64));
-            try!(self.writer.write_i64::<BigEndian>(v));
-        } else if (v >= m::RANGE_POS_INT_32.0 && v <= m::RANGE_POS_INT_32.1)
-            || (v >= m::RANGE_NEG_INT_32.0 && v <= m::RANGE_NEG_INT_32.1)
-        {
-            try!(self.writer.write_u8(m::INT_32));
-            try!(self.writer.write_i32::<BigEndian>(v as i32));
-        } else if (v >= m::RANGE_POS_INT_16.0 && v <= m::RANGE_POS_INT_16.1)
-            || (v >= m::RANGE_NEG_INT_16.0 && v <= m::RANGE_NEG_INT_16.1)
-        {
-            try!(self.writer.write_u8(m::INT_16));
-            try!(self.writer.write_i16::<BigEndian>(v as i16));
-        } else if v >= m::RANGE_TINY_INT.0 && v <= m::RANGE_TINY_INT.1  {
-            try!(self.writer.write_i8(v as i8));
+        // smallest encodings first, as most integers are small
+        if v >= m::RANGE_TINY_INT.0 && v <= m::RANGE_TINY_INT.1 {
+            self.writer.write_i8(v as i8)?;
         } else if v >= m::RANGE_NEG_INT_8.0 && v <= m::RANGE_NEG_INT_8.1 {
-            try!(self.writer.write_u8(m::INT_8));
-            try!(self.writer.write_i8(v as i8));
+            self.writer.write_u8(m::INT_8)?;
+            self.writer.write_i8(v as i8)?;
+        } else if v >= m::RANGE_NEG_INT_16.0 && v <= m::RANGE_POS_INT_16.1 {
+            self.writer.write_u8(m::INT_16)?;
+            self.writer.write_i16::<BigEndian>(v as i16)?;
+        } else if v >= m::RANGE_NEG_INT_32.0 && v <= m::RANGE_POS_INT_32.1 {
+            self.writer.write_u8(m::INT_32)?;
+            self.writer.write_i32::<BigEndian>(v as i32)?;
+        } else {
+            self.writer.write_u8(m::INT_64)?;
+            self.writer.write_i64::<BigEndian>(v)?;
         }
 
         Ok(())
@@ -139,17 +243,17 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
 
     fn emit_bool(&mut self, v: bool) -> Result<(), Self::Error> {
         if v {
-            try!(self.writer.write_u8(m::TRUE));
+            self.writer.write_u8(m::TRUE)?;
         } else {
-            try!(self.writer.write_u8(m::FALSE));
+            self.writer.write_u8(m::FALSE)?;
         }
 
         Ok(())
     }
 
     fn emit_f64(&mut self, v: f64) -> Result<(), Self::Error> {
-        try!(self.writer.write_u8(m::FLOAT));
-        try!(self.writer.write_f64::<BigEndian>(v));
+        self.writer.write_u8(m::FLOAT)?;
+        self.writer.write_f64::<BigEndian>(v)?;
 
         Ok(())
     }
@@ -159,8 +263,8 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
     }
 
     fn emit_char(&mut self, v: char) -> Result<(), Self::Error> {
-        try!(self.writer.write_u8(m::TINY_STRING_NIBBLE | 0x01));
-        try!(self.writer.write_u8(v as u8));
+        self.writer.write_u8(m::TINY_STRING_NIBBLE | 0x01)?;
+        self.writer.write_u8(v as u8)?;
 
         Ok(())
     }
@@ -170,19 +274,19 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
         let size = bytes.len();
 
         if size <= m::USE_TINY_STRING {
-            try!(self.writer.write_u8(m::TINY_STRING_NIBBLE | size as u8));
+            self.writer.write_u8(m::TINY_STRING_NIBBLE | size as u8)?;
         } else if size <= m::USE_STRING_8 {
-            try!(self.writer.write_u8(m::STRING_8));
-            try!(self.writer.write_u8(size as u8));
+            self.writer.write_u8(m::STRING_8)?;
+            self.writer.write_u8(size as u8)?;
         } else if size <= m::USE_STRING_16 {
-            try!(self.writer.write_u8(m::STRING_16));
-            try!(self.writer.write_u16::<BigEndian>(size as u16));
+            self.writer.write_u8(m::STRING_16)?;
+            self.writer.write_u16::<BigEndian>(size as u16)?;
         } else if size <= m::USE_STRING_32 {
-            try!(self.writer.write_u8(m::STRING_32));
-            try!(self.writer.write_u32::<BigEndian>(size as u32));
+            self.writer.write_u8(m::STRING_32)?;
+            self.writer.write_u32::<BigEndian>(size as u32)?;
         }
 
-        try!(self.writer.write_all(bytes));
+        self.writer.write_all(bytes)?;
 
         Ok(())
     }
@@ -203,8 +307,8 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
         if len == 0 {
             self.emit_str(v_name)
         } else {
-            try!(self.writer.write_u8(m::TINY_MAP_NIBBLE | 0x01));
-            try!(self.emit_str(v_name));
+            self.writer.write_u8(m::TINY_MAP_NIBBLE | 0x01)?;
+            self.emit_str(v_name)?;
             self.emit_seq(len, f)
         }
     }
@@ -235,7 +339,7 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
                                          f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-        try!(self.emit_str(f_name));
+        self.emit_str(f_name)?;
         self.emit_map_elt_val(f_idx, f)
     }
 
@@ -249,32 +353,46 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
             let signature = *name.as_bytes().last().unwrap();
 
             if len <= m::USE_TINY_STRUCT {
-                try!(self.writer.write_u8(m::TINY_STRUCT_NIBBLE | len as u8));
-                try!(self.writer.write_u8(signature));
+                self.writer.write_u8(m::TINY_STRUCT_NIBBLE | len as u8)?;
+                self.writer.write_u8(signature)?;
             } else if len <= m::USE_STRUCT_8 {
-                try!(self.writer.write_u8(m::STRUCT_8));
-                try!(self.writer.write_u8(signature));
-                try!(self.writer.write_u8(len as u8));
+                self.writer.write_u8(m::STRUCT_8)?;
+                self.writer.write_u8(signature)?;
+                self.writer.write_u8(len as u8)?;
             } else if len <= m::USE_STRUCT_16 {
-                try!(self.writer.write_u8(m::STRUCT_16));
-                try!(self.writer.write_u8(signature));
-                try!(self.writer.write_u16::<BigEndian>(len as u16));
+                self.writer.write_u8(m::STRUCT_16)?;
+                self.writer.write_u8(signature)?;
+                self.writer.write_u16::<BigEndian>(len as u16)?;
             } else {
                 return Err(EncoderError::InvalidStructureLength)
             }
 
             f(self)
+        } else if self.skip_none {
+            // the fields left out are only known once written, so they are counted
+            self.emit_map(0, f)
         } else {
             self.emit_map(len, f)
         }
     }
 
-    fn emit_struct_field<F>(&mut self, f_name: &str, _: usize, f: F)
+    fn emit_struct_field<F>(&mut self, f_name: &str, f_idx: usize, f: F)
                             -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-        try!(self.emit_str(f_name));
-        f(self)
+        let start = self.writer.len();
+        self.emit_map_elt_key(f_idx, |e| e.emit_str(f_name))?;
+        let value_start = self.writer.len();
+        f(self)?;
+
+        if self.skip_none && self.writer[value_start..] == [m::NULL] {
+            self.writer.truncate(start);
+            if let Some(count) = self.seq_elements.last_mut() {
+                *count -= 1;
+            }
+        }
+
+        Ok(())
     }
 
     fn emit_tuple<F>(&mut self, len: usize, f: F) -> Result<(), Self::Error>
@@ -289,11 +407,22 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
         self.emit_seq_elt(idx, f)
     }
 
-    fn emit_tuple_struct<F>(&mut self, _: &str, len: usize, f: F)
+    // newtype structs (e.g. `struct NodeId(i64)`) are written as the value they wrap
+    fn emit_tuple_struct<F>(&mut self, name: &str, len: usize, f: F)
                             -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-        self.emit_seq(len, f)
+        if name == BYTES_NAME {
+            let outer = self.bytes;
+            self.bytes = true;
+            let result = self.emit_wrapped(f);
+            self.bytes = outer;
+            result
+        } else if len == 1 {
+            self.emit_wrapped(f)
+        } else {
+            self.emit_seq(len, f)
+        }
     }
     fn emit_tuple_struct_arg<F>(&mut self, f_idx: usize, f: F)
                                 -> Result<(), Self::Error>
@@ -319,51 +448,73 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
         f(self)
     }
 
+    // A length of 0 is taken as unknown (e.g. a filtered iterator), so the elements are counted
+    // and the header is inserted before them afterwards.
     fn emit_seq<F>(&mut self, len: usize, f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-        if len <= m::USE_TINY_LIST as usize {
-            try!(self.writer.write_u8(m::TINY_LIST_NIBBLE | len as u8));
-        } else if len <= m::USE_LIST_8 as usize {
-            try!(self.writer.write_u8(m::LIST_8));
-            try!(self.writer.write_u8(len as u8));
-        } else if len <= m::USE_LIST_16 as usize {
-            try!(self.writer.write_u8(m::LIST_16));
-            try!(self.writer.write_u16::<BigEndian>(len as u16));
-        } else if len <= m::USE_LIST_32 as usize {
-            try!(self.writer.write_u8(m::LIST_32));
-            try!(self.writer.write_u32::<BigEndian>(len as u32));
+        if self.bytes {
+            write_bytes_header(self.writer, len)?;
+            return f(self)
         }
 
-        f(self)
+        let start = self.writer.len();
+        if len > 0 {
+            write_list_header(self.writer, len)?;
+        }
+
+        self.seq_elements.push(0);
+        let result = f(self);
+        let count = self.seq_elements.pop().unwrap_or(0);
+        result?;
+
+        if len == 0 {
+            let mut header = Vec::new();
+            write_list_header(&mut header, count)?;
+            self.writer.splice(start..start, header);
+        }
+
+        Ok(())
     }
     fn emit_seq_elt<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
+        if let Some(count) = self.seq_elements.last_mut() {
+            *count += 1;
+        }
+
         f(self)
     }
 
+    // Like `emit_seq`, a length of 0 is taken as unknown and the entries are counted.
     fn emit_map<F>(&mut self, len: usize, f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-            if len <= m::USE_TINY_MAP as usize {
-                try!(self.writer.write_u8(m::TINY_MAP_NIBBLE | len as u8));
-            } else if len <= m::USE_MAP_8 as usize {
-                try!(self.writer.write_u8(m::MAP_8));
-                try!(self.writer.write_u8(len as u8));
-            } else if len <= m::USE_MAP_16 as usize {
-                try!(self.writer.write_u8(m::MAP_16));
-                try!(self.writer.write_u16::<BigEndian>(len as u16));
-            } else if len <= m::USE_MAP_32 as usize {
-                try!(self.writer.write_u8(m::MAP_32));
-                try!(self.writer.write_u32::<BigEndian>(len as u32));
-            }
+        let start = self.writer.len();
+        if len > 0 {
+            write_map_header(self.writer, len)?;
+        }
 
-            f(self)
+        self.seq_elements.push(0);
+        let result = f(self);
+        let count = self.seq_elements.pop().unwrap_or(0);
+        result?;
+
+        if len == 0 {
+            let mut header = Vec::new();
+            write_map_header(&mut header, count)?;
+            self.writer.splice(start..start, header);
+        }
+
+        Ok(())
     }
     fn emit_map_elt_key<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
+        if let Some(count) = self.seq_elements.last_mut() {
+            *count += 1;
+        }
+
         f(self)
     }
     fn emit_map_elt_val<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
@@ -377,8 +528,73 @@ impl<'a, W: Write> Encoder for PackstreamEncoder<'a, W> {
 mod tests {
     use std::collections::BTreeMap;
     use std::string::String;
-    use super::encode;
+    use super::{encode, encode_skipping_none, EncoderError};
     use ::v1::packstream::marker as m;
+    use ::v1::packstream::value::Value;
+    use ::v1::testing::{Evens, EvenNames};
+
+    #[test]
+    fn serialize_u64_above_i64_max_should_fail() {
+        let result = encode(&(m::RANGE_POS_INT_64.1 as u64)).unwrap();
+        let expected = vec![m::INT_64, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert_eq!(expected, result);
+
+        match encode(&(m::RANGE_POS_INT_64.1 as u64 + 1)) {
+            Err(EncoderError::ValueOutOfRange(v)) => assert_eq!(9_223_372_036_854_775_808, v),
+            other => panic!("expected out of range error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn serialize_bytes() {
+        let input = ::v1::packstream::Bytes(vec![0x01, 0x02, 0xFF]);
+
+        let result = encode(&input).unwrap();
+        let expected = vec![m::BYTES_8, 0x03, 0x01, 0x02, 0xFF];
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn serialize_bytes_16() {
+        let input = ::v1::packstream::Bytes(vec![0x01; 256]);
+
+        let result = encode(&input).unwrap();
+
+        assert_eq!(vec![m::BYTES_16, 0x01, 0x00], &result[..3]);
+        assert_eq!(3 + 256, result.len());
+    }
+
+    // byte arrays in a sequence of unknown length
+    struct BytesList(Vec<::v1::packstream::Bytes>);
+
+    impl ::rustc_serialize::Encodable for BytesList {
+        fn encode<S: ::rustc_serialize::Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+            e.emit_seq(0, |e| {
+                for (i, v) in self.0.iter().enumerate() {
+                    e.emit_seq_elt(i, |e| v.encode(e))?;
+                }
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn serialize_bytes_in_list_of_unknown_length() {
+        let input = BytesList(vec![::v1::packstream::Bytes(vec![0x01, 0x02, 0x03])]);
+
+        let result = encode(&input).unwrap();
+        assert_eq!(vec![m::TINY_LIST_NIBBLE + 0x01, m::BYTES_8, 0x03, 0x01, 0x02, 0x03], result);
+
+        let expected = Value::List(vec![Value::Bytes(vec![0x01, 0x02, 0x03])]);
+        assert_eq!(expected, Value::from_reader(&mut &result[..]).unwrap());
+    }
+
+    #[test]
+    fn serialize_plain_vec_u8_as_list() {
+        let result = encode(&vec![0x01u8, 0x02]).unwrap();
+        assert_eq!(vec![m::TINY_LIST_NIBBLE + 0x02, 0x01, 0x02], result);
+    }
 
     #[test]
     fn serialize_nil() {
@@ -465,6 +681,14 @@ mod tests {
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn serialize_u8_above_tiny_int() {
+        assert_eq!(vec![0x7F], encode(&127u8).unwrap());
+        assert_eq!(vec![m::INT_16, 0x00, 0x80], encode(&128u8).unwrap());
+        assert_eq!(vec![m::INT_16, 0x00, 0xC8], encode(&200u8).unwrap());
+        assert_eq!(vec![m::INT_16, 0x00, 0xFF], encode(&255u8).unwrap());
+    }
+
     #[test]
     fn serialize_float_positive() {
         let result = encode(&1.1).unwrap();
@@ -539,7 +763,7 @@ mod tests {
 
     #[test]
     fn serialize_char() {
-        for c in b'A'..b'Z' {
+        for c in b'A'..=b'Z' {
             let result: Vec<u8> = encode(&(c as char)).unwrap();
             let expected = vec![0x81, c];
 
@@ -682,6 +906,106 @@ mod tests {
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn serialize_list_of_unknown_length() {
+        let result = encode(&Evens((1..41).collect())).unwrap();
+
+        let mut expected = vec![m::LIST_8, 20];
+        expected.extend((1..21).map(|i| i * 2));
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn serialize_nested_lists_of_unknown_length() {
+        let input = vec![Evens(vec![1, 2, 3, 4]), Evens(vec![]), Evens(vec![1])];
+
+        let result = encode(&input).unwrap();
+        let expected = vec![m::TINY_LIST_NIBBLE + 3,
+                            m::TINY_LIST_NIBBLE + 2, 0x02, 0x04,
+                            m::TINY_LIST_NIBBLE,
+                            m::TINY_LIST_NIBBLE];
+
+        assert_eq!(expected, result);
+
+        let input = (Evens(vec![2, 3]), vec![1, 2]);
+        assert_eq!(vec![m::TINY_LIST_NIBBLE + 2,
+                        m::TINY_LIST_NIBBLE + 1, 0x02,
+                        m::TINY_LIST_NIBBLE + 2, 0x01, 0x02],
+                   encode(&input).unwrap());
+    }
+
+    #[test]
+    fn serialize_map_of_unknown_length() {
+        let result = encode(&vec![EvenNames(vec![1, 2, 3, 4]), EvenNames(vec![])]).unwrap();
+        let expected = vec![m::TINY_LIST_NIBBLE + 2,
+                            m::TINY_MAP_NIBBLE + 2,
+                            m::TINY_STRING_NIBBLE + 2, 0x6E, 0x32, 0x02,
+                            m::TINY_STRING_NIBBLE + 2, 0x6E, 0x34, 0x04,
+                            m::TINY_MAP_NIBBLE];
+        assert_eq!(expected, result);
+
+        let result = encode(&EvenNames((1..41).collect())).unwrap();
+        assert_eq!(&[m::MAP_8, 20], &result[..2]);
+    }
+
+    // a map of unknown length whose values are lists of unknown length
+    struct EvensByName(Vec<(&'static str, Vec<i64>)>);
+
+    impl ::rustc_serialize::Encodable for EvensByName {
+        fn encode<S: ::rustc_serialize::Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+            e.emit_map(0, |e| {
+                for (i, &(name, ref values)) in self.0.iter().enumerate() {
+                    e.emit_map_elt_key(i, |e| name.encode(e))?;
+                    e.emit_map_elt_val(i, |e| Evens(values.clone()).encode(e))?;
+                }
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn serialize_nested_maps_and_lists_of_unknown_length() {
+        let input = EvensByName(vec![("a", vec![1, 2, 4]), ("b", vec![]), ("c", (1..41).collect())]);
+
+        let mut expected = vec![m::TINY_MAP_NIBBLE + 3,
+                                m::TINY_STRING_NIBBLE + 1, 0x61, m::TINY_LIST_NIBBLE + 2, 0x02, 0x04,
+                                m::TINY_STRING_NIBBLE + 1, 0x62, m::TINY_LIST_NIBBLE,
+                                m::TINY_STRING_NIBBLE + 1, 0x63, m::LIST_8, 20];
+        expected.extend((1..21).map(|i| i * 2));
+
+        assert_eq!(expected, encode(&input).unwrap());
+    }
+
+    #[test]
+    fn serialize_hash_map() {
+        use std::collections::HashMap;
+
+        let mut input = HashMap::new();
+        input.insert("A".to_owned(), 1);
+
+        let expected = vec![m::TINY_MAP_NIBBLE + 1, m::TINY_STRING_NIBBLE + 1, 0x41, 0x01];
+        assert_eq!(expected, encode(&input).unwrap());
+    }
+
+    #[test]
+    fn serialize_newtype_struct() {
+        use rustc_serialize::{Encodable, Encoder};
+
+        struct NodeId(i64);
+
+        impl Encodable for NodeId {
+            fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+                e.emit_tuple_struct("NodeId", 1, |e| e.emit_tuple_struct_arg(0, |e| self.0.encode(e)))
+            }
+        }
+
+        let result = encode(&vec![NodeId(1), NodeId(32_000)]).unwrap();
+        let expected = vec![m::TINY_LIST_NIBBLE + 2, 0x01, m::INT_16, 0x7D, 0x00];
+
+        assert_eq!(expected, result);
+    }
+
     #[test]
     fn serialize_map32() {
         let size = 70_000;
@@ -871,41 +1195,42 @@ mod tests {
 
     #[test]
     fn serialize_struct16() {
-        #[derive(RustcEncodable)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            A001: u16, A002: u16, A003: u16, A004: u16, A005: u16, A006: u16, A007: u16, A008: u16,
-            A009: u16, A010: u16, A011: u16, A012: u16, A013: u16, A014: u16, A015: u16, A016: u16,
-            A017: u16, A018: u16, A019: u16, A020: u16, A021: u16, A022: u16, A023: u16, A024: u16,
-            A025: u16, A026: u16, A027: u16, A028: u16, A029: u16, A030: u16, A031: u16, A032: u16,
-            A033: u16, A034: u16, A035: u16, A036: u16, A037: u16, A038: u16, A039: u16, A040: u16,
-            A041: u16, A042: u16, A043: u16, A044: u16, A045: u16, A046: u16, A047: u16, A048: u16,
-            A049: u16, A050: u16, A051: u16, A052: u16, A053: u16, A054: u16, A055: u16, A056: u16,
-            A057: u16, A058: u16, A059: u16, A060: u16, A061: u16, A062: u16, A063: u16, A064: u16,
-            A065: u16, A066: u16, A067: u16, A068: u16, A069: u16, A070: u16, A071: u16, A072: u16,
-            A073: u16, A074: u16, A075: u16, A076: u16, A077: u16, A078: u16, A079: u16, A080: u16,
-            A081: u16, A082: u16, A083: u16, A084: u16, A085: u16, A086: u16, A087: u16, A088: u16,
-            A089: u16, A090: u16, A091: u16, A092: u16, A093: u16, A094: u16, A095: u16, A096: u16,
-            A097: u16, A098: u16, A099: u16, A100: u16, A101: u16, A102: u16, A103: u16, A104: u16,
-            A105: u16, A106: u16, A107: u16, A108: u16, A109: u16, A110: u16, A111: u16, A112: u16,
-            A113: u16, A114: u16, A115: u16, A116: u16, A117: u16, A118: u16, A119: u16, A120: u16,
-            A121: u16, A122: u16, A123: u16, A124: u16, A125: u16, A126: u16, A127: u16, A128: u16,
-            A129: u16, A130: u16, A131: u16, A132: u16, A133: u16, A134: u16, A135: u16, A136: u16,
-            A137: u16, A138: u16, A139: u16, A140: u16, A141: u16, A142: u16, A143: u16, A144: u16,
-            A145: u16, A146: u16, A147: u16, A148: u16, A149: u16, A150: u16, A151: u16, A152: u16,
-            A153: u16, A154: u16, A155: u16, A156: u16, A157: u16, A158: u16, A159: u16, A160: u16,
-            A161: u16, A162: u16, A163: u16, A164: u16, A165: u16, A166: u16, A167: u16, A168: u16,
-            A169: u16, A170: u16, A171: u16, A172: u16, A173: u16, A174: u16, A175: u16, A176: u16,
-            A177: u16, A178: u16, A179: u16, A180: u16, A181: u16, A182: u16, A183: u16, A184: u16,
-            A185: u16, A186: u16, A187: u16, A188: u16, A189: u16, A190: u16, A191: u16, A192: u16,
-            A193: u16, A194: u16, A195: u16, A196: u16, A197: u16, A198: u16, A199: u16, A200: u16,
-            A201: u16, A202: u16, A203: u16, A204: u16, A205: u16, A206: u16, A207: u16, A208: u16,
-            A209: u16, A210: u16, A211: u16, A212: u16, A213: u16, A214: u16, A215: u16, A216: u16,
-            A217: u16, A218: u16, A219: u16, A220: u16, A221: u16, A222: u16, A223: u16, A224: u16,
-            A225: u16, A226: u16, A227: u16, A228: u16, A229: u16, A230: u16, A231: u16, A232: u16,
-            A233: u16, A234: u16, A235: u16, A236: u16, A237: u16, A238: u16, A239: u16, A240: u16,
-            A241: u16, A242: u16, A243: u16, A244: u16, A245: u16, A246: u16, A247: u16, A248: u16,
-            A249: u16, A250: u16, A251: u16, A252: u16, A253: u16, A254: u16, A255: u16, A256: u16,
+        encodable_struct! {
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                A001: u16, A002: u16, A003: u16, A004: u16, A005: u16, A006: u16, A007: u16, A008: u16,
+                A009: u16, A010: u16, A011: u16, A012: u16, A013: u16, A014: u16, A015: u16, A016: u16,
+                A017: u16, A018: u16, A019: u16, A020: u16, A021: u16, A022: u16, A023: u16, A024: u16,
+                A025: u16, A026: u16, A027: u16, A028: u16, A029: u16, A030: u16, A031: u16, A032: u16,
+                A033: u16, A034: u16, A035: u16, A036: u16, A037: u16, A038: u16, A039: u16, A040: u16,
+                A041: u16, A042: u16, A043: u16, A044: u16, A045: u16, A046: u16, A047: u16, A048: u16,
+                A049: u16, A050: u16, A051: u16, A052: u16, A053: u16, A054: u16, A055: u16, A056: u16,
+                A057: u16, A058: u16, A059: u16, A060: u16, A061: u16, A062: u16, A063: u16, A064: u16,
+                A065: u16, A066: u16, A067: u16, A068: u16, A069: u16, A070: u16, A071: u16, A072: u16,
+                A073: u16, A074: u16, A075: u16, A076: u16, A077: u16, A078: u16, A079: u16, A080: u16,
+                A081: u16, A082: u16, A083: u16, A084: u16, A085: u16, A086: u16, A087: u16, A088: u16,
+                A089: u16, A090: u16, A091: u16, A092: u16, A093: u16, A094: u16, A095: u16, A096: u16,
+                A097: u16, A098: u16, A099: u16, A100: u16, A101: u16, A102: u16, A103: u16, A104: u16,
+                A105: u16, A106: u16, A107: u16, A108: u16, A109: u16, A110: u16, A111: u16, A112: u16,
+                A113: u16, A114: u16, A115: u16, A116: u16, A117: u16, A118: u16, A119: u16, A120: u16,
+                A121: u16, A122: u16, A123: u16, A124: u16, A125: u16, A126: u16, A127: u16, A128: u16,
+                A129: u16, A130: u16, A131: u16, A132: u16, A133: u16, A134: u16, A135: u16, A136: u16,
+                A137: u16, A138: u16, A139: u16, A140: u16, A141: u16, A142: u16, A143: u16, A144: u16,
+                A145: u16, A146: u16, A147: u16, A148: u16, A149: u16, A150: u16, A151: u16, A152: u16,
+                A153: u16, A154: u16, A155: u16, A156: u16, A157: u16, A158: u16, A159: u16, A160: u16,
+                A161: u16, A162: u16, A163: u16, A164: u16, A165: u16, A166: u16, A167: u16, A168: u16,
+                A169: u16, A170: u16, A171: u16, A172: u16, A173: u16, A174: u16, A175: u16, A176: u16,
+                A177: u16, A178: u16, A179: u16, A180: u16, A181: u16, A182: u16, A183: u16, A184: u16,
+                A185: u16, A186: u16, A187: u16, A188: u16, A189: u16, A190: u16, A191: u16, A192: u16,
+                A193: u16, A194: u16, A195: u16, A196: u16, A197: u16, A198: u16, A199: u16, A200: u16,
+                A201: u16, A202: u16, A203: u16, A204: u16, A205: u16, A206: u16, A207: u16, A208: u16,
+                A209: u16, A210: u16, A211: u16, A212: u16, A213: u16, A214: u16, A215: u16, A216: u16,
+                A217: u16, A218: u16, A219: u16, A220: u16, A221: u16, A222: u16, A223: u16, A224: u16,
+                A225: u16, A226: u16, A227: u16, A228: u16, A229: u16, A230: u16, A231: u16, A232: u16,
+                A233: u16, A234: u16, A235: u16, A236: u16, A237: u16, A238: u16, A239: u16, A240: u16,
+                A241: u16, A242: u16, A243: u16, A244: u16, A245: u16, A246: u16, A247: u16, A248: u16,
+                A249: u16, A250: u16, A251: u16, A252: u16, A253: u16, A254: u16, A255: u16, A256: u16,
+            }
         }
 
         let input = MyStruct {
@@ -987,13 +1312,14 @@ mod tests {
     fn serialize_struct8() {
         let size = 16;
 
-        #[derive(RustcEncodable)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            A: u16, B: u16, C: u16, D: u16,
-            E: u16, F: u16, G: u16, H: u16,
-            I: u16, J: u16, K: u16, L: u16,
-            M: u16, N: u16, O: u16, P: u16,
+        encodable_struct! {
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                A: u16, B: u16, C: u16, D: u16,
+                E: u16, F: u16, G: u16, H: u16,
+                I: u16, J: u16, K: u16, L: u16,
+                M: u16, N: u16, O: u16, P: u16,
+            }
         }
 
         let input = MyStruct {
@@ -1017,12 +1343,13 @@ mod tests {
     fn serialize_tiny_struct() {
         let size = 3;
 
-        #[derive(RustcEncodable)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            A: u32,
-            B: f64,
-            C: &'static str,
+        encodable_struct! {
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                A: u32,
+                B: f64,
+                C: &'static str,
+            }
         }
 
         let input = MyStruct {
@@ -1042,11 +1369,20 @@ mod tests {
 
     #[test]
     fn serialize_enum() {
-        #[derive(RustcEncodable)]
+        use rustc_serialize::{Encodable, Encoder};
+
         enum MyEnum {
             A,
         }
 
+        impl Encodable for MyEnum {
+            fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+                e.emit_enum("MyEnum", |e| match *self {
+                    MyEnum::A => e.emit_enum_variant("A", 0, 0, |_| Ok(())),
+                })
+            }
+        }
+
         let input = MyEnum::A;
 
         let result = encode(&input).unwrap();
@@ -1057,11 +1393,23 @@ mod tests {
 
     #[test]
     fn serialize_enum_tuple_variant() {
-        #[derive(RustcEncodable)]
+        use rustc_serialize::{Encodable, Encoder};
+
         enum MyEnum {
             A(u16, u16),
         }
 
+        impl Encodable for MyEnum {
+            fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+                e.emit_enum("MyEnum", |e| match *self {
+                    MyEnum::A(a, b) => e.emit_enum_variant("A", 0, 2, |e| {
+                        e.emit_enum_variant_arg(0, |e| a.encode(e))?;
+                        e.emit_enum_variant_arg(1, |e| b.encode(e))
+                    }),
+                })
+            }
+        }
+
         let input = MyEnum::A(1, 2);
 
         let result = encode(&input).unwrap();
@@ -1091,4 +1439,60 @@ mod tests {
     //
     //     assert_eq!(expected, result);
     // }
+
+    codable_struct! {
+        #[derive(Debug, PartialEq)]
+        struct Person {
+            name: String,
+            nickname: Option<String>,
+            manager: Option<Box<Person>>,
+        }
+    }
+
+    #[test]
+    fn serialize_skipping_none_fields() {
+        let input = Person {
+            name: "Alice".to_owned(),
+            nickname: None,
+            manager: Some(Box::new(Person { name: "Bob".to_owned(), nickname: Some("B".to_owned()), manager: None })),
+        };
+
+        let expected = vec![m::TINY_MAP_NIBBLE + 0x02,
+            0x84, 0x6E, 0x61, 0x6D, 0x65, 0x85, 0x41, 0x6C, 0x69, 0x63, 0x65,
+            0x87, 0x6D, 0x61, 0x6E, 0x61, 0x67, 0x65, 0x72, m::TINY_MAP_NIBBLE + 0x02,
+                0x84, 0x6E, 0x61, 0x6D, 0x65, 0x83, 0x42, 0x6F, 0x62,
+                0x88, 0x6E, 0x69, 0x63, 0x6B, 0x6E, 0x61, 0x6D, 0x65, 0x81, 0x42,
+        ];
+
+        let result = encode_skipping_none(&input).unwrap();
+        assert_eq!(expected, result);
+        assert_eq!(input, ::v1::packstream::decode(&mut ::std::io::Cursor::new(result)).unwrap());
+    }
+
+    encodable_struct! {
+        struct Attachment {
+            b: ::v1::packstream::Bytes,
+            o: Option<i64>,
+        }
+    }
+
+    #[test]
+    fn serialize_skipping_none_fields_with_bytes() {
+        let input = Attachment { b: ::v1::packstream::Bytes(vec![0x01, 0x02, 0x03]), o: None };
+
+        let result = encode_skipping_none(&input).unwrap();
+        assert_eq!(vec![m::TINY_MAP_NIBBLE + 0x01, 0x81, 0x62, m::BYTES_8, 0x03, 0x01, 0x02, 0x03], result);
+
+        let expected = Value::map().entry("b", Value::Bytes(vec![0x01, 0x02, 0x03])).build();
+        assert_eq!(expected, Value::from_reader(&mut &result[..]).unwrap());
+    }
+
+    #[test]
+    fn serialize_none_fields_by_default() {
+        let input = Person { name: "Alice".to_owned(), nickname: None, manager: None };
+        let result = encode(&input).unwrap();
+
+        assert_eq!(m::TINY_MAP_NIBBLE + 0x03, result[0]);
+        assert_eq!(input, ::v1::packstream::decode(&mut ::std::io::Cursor::new(result)).unwrap());
+    }
 }
@@ -1,9 +1,12 @@
 use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
 use std::io::prelude::*;
 use byteorder::{ReadBytesExt, BigEndian};
 
 use super::Value;
-use super::super::deserialize::{DecoderError, DecodeResult};
+use super::registry::{self, StructureDecoder};
+use super::StructureSignature;
+use super::super::deserialize::{DecoderError, DecodeLimits, DecodeOptions, DecodeResult, DuplicateKeys, read_bytes};
 use super::super::marker as m;
 
 pub fn from_reader<'a, R: Read + 'a>(reader: &mut R) -> DecodeResult<Value> {
@@ -18,6 +21,7 @@ enum ParserEvent {
     Integer(i64),
     Float(f64),
     String(usize),
+    Bytes(usize),
     List(usize),
     Map(usize),
     Struct(u8, usize),
@@ -30,30 +34,43 @@ type ParserEventResult = DecodeResult<ParserEvent>;
 pub struct Builder<'a, R: Read + 'a> {
     reader: &'a mut R,
     stack: Vec<Value>,
+    decoders: &'a [(StructureSignature, StructureDecoder)],
+    options: DecodeOptions,
 }
 
 impl<'a, R: Read + 'a> Builder<'a, R> {
     pub fn new(reader: &'a mut R) -> Self {
+        Builder::with_decoders(reader, registry::GRAPH)
+    }
+
+    pub fn with_decoders(reader: &'a mut R, decoders: &'a [(StructureSignature, StructureDecoder)]) -> Self {
         Builder {
-            reader: reader,
+            reader,
             stack: Vec::new(),
+            decoders,
+            options: DecodeOptions::default(),
         }
     }
 
+    pub fn with_options(mut self, options: DecodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
     pub fn build(&mut self) -> DecodeResult<Value> {
-        try!(self.parse());
+        self.parse()?;
         Ok(self.stack.pop().unwrap_or(Value::Null))
     }
 
     pub fn parse(&mut self) -> DecodeResult<()> {
         let mut buf = [0u8; 1];
-        let bytes_read = try!(self.reader.read(&mut buf));
+        let bytes_read = self.reader.read(&mut buf)?;
 
         if bytes_read == 0 {
             return Ok(())
         }
 
-        match self.read_next(buf[0]) {
+        match self.read_next(buf[0]).and_then(|e| self.check_size(e)) {
             Ok(e) => match e {
                 ev::Null => self.stack.push(Value::Null),
                 ev::True => self.stack.push(Value::Boolean(true)),
@@ -61,15 +78,19 @@ impl<'a, R: Read + 'a> Builder<'a, R> {
                 ev::Integer(v) => self.stack.push(Value::Integer(v)),
                 ev::Float(v) => self.stack.push(Value::Float(v)),
                 ev::String(size) => {
-                    let value = try!(self.read_string(size));
+                    let value = self.read_string(size)?;
                     self.stack.push(Value::String(value));
                 },
+                ev::Bytes(size) => {
+                    let value = read_bytes(self.reader, size)?;
+                    self.stack.push(Value::Bytes(value));
+                },
                 ev::List(size) => {
                     let values = {
                         let mut values = vec![];
                         for _ in 0..size {
                             // println!("{}", i);
-                            try!(self.parse());
+                            self.parse()?;
                             match self.stack.pop() {
                                 Some(v) => values.push(v),
                                 _ => return Err(DecoderError::UnexpectedEOF)
@@ -87,7 +108,7 @@ impl<'a, R: Read + 'a> Builder<'a, R> {
                         let mut values: BTreeMap<String, Value> = BTreeMap::new();
 
                         for i in 1..(size + 1) {
-                            try!(self.parse());
+                            self.parse()?;
                             match self.stack.pop() {
                                 Some(Value::String(ref k)) if i % 2 != 0 => cur_key = k.to_owned(),
                                 Some(ref v) if i % 2 != 0 => return Err(DecoderError::UnexpectedInput(
@@ -99,7 +120,7 @@ impl<'a, R: Read + 'a> Builder<'a, R> {
                                             "Map key".to_owned(), "None".to_owned()
                                         ))
                                     }
-                                    values.insert(cur_key.clone(), v);
+                                    self.insert_entry(&mut values, &cur_key, v)?;
                                 },
                                 _ => return Err(DecoderError::UnexpectedEOF),
                             }
@@ -113,7 +134,7 @@ impl<'a, R: Read + 'a> Builder<'a, R> {
                     let values = {
                         let mut values = vec![];
                         for _ in 0..size {
-                            try!(self.parse());
+                            self.parse()?;
                             match self.stack.pop() {
                                 Some(v) => values.push(v),
                                 _ => return Err(DecoderError::UnexpectedEOF)
@@ -122,10 +143,10 @@ impl<'a, R: Read + 'a> Builder<'a, R> {
                         values
 
                     };
-                    self.stack.push(Value::Structure(s, values));
+                    self.stack.push(registry::decode(self.decoders, s, values));
                 },
             },
-            Err(e) => return Err(From::from(e))
+            Err(e) => return Err(e)
         };
 
         Ok(())
@@ -136,48 +157,74 @@ impl<'a, R: Read + 'a> Builder<'a, R> {
             m::NULL => Ok(ev::Null),
             m::TRUE => Ok(ev::True),
             m::FALSE => Ok(ev::False),
-            v @ 0x00...0x7F => Ok(ev::Integer(v as i64)),
-            v @ 0xF0...0xFF => Ok(ev::Integer(((v | 0b1111_0000) as i8) as i64)),
+            v @ 0x00..=0x7F => Ok(ev::Integer(v as i64)),
+            v @ 0xF0..=0xFF => Ok(ev::Integer(((v | 0b1111_0000) as i8) as i64)),
             m::INT_8 => self.read_int(8),
             m::INT_16 => self.read_int(16),
             m::INT_32 => self.read_int(32),
             m::INT_64 => self.read_int(64),
             m::FLOAT => self.reader.read_f64::<BigEndian>().map(
-                |v| ev::Float(v)).map_err(From::from),
-            v @ 0x80...0x8F => Ok(ev::String((v & 0b0000_1111) as usize)),
-            m::STRING_8 => self.read_len(8).map(|v| ev::String(v)),
-            m::STRING_16 => self.read_len(16).map(|v| ev::String(v)),
-            m::STRING_32 => self.read_len(32).map(|v| ev::String(v)),
-            v @ 0x90...0x9F => Ok(ev::List((v & 0b0000_1111) as usize)),
-            m::LIST_8 => self.read_len(8).map(|v| ev::List(v)),
-            m::LIST_16 => self.read_len(16).map(|v| ev::List(v)),
-            m::LIST_32 => self.read_len(32).map(|v| ev::List(v)),
-            v @ 0xA0...0xAF => Ok(ev::Map((v & 0b0000_1111) as usize)),
-            m::MAP_8 => self.read_len(8).map(|v| ev::Map(v)),
-            m::MAP_16 => self.read_len(16).map(|v| ev::Map(v)),
-            m::MAP_32 => self.read_len(32).map(|v| ev::Map(v)),
-            v @ 0xB0...0xBF => self.reader.read_u8().map(
+                ev::Float).map_err(From::from),
+            v @ 0x80..=0x8F => Ok(ev::String((v & 0b0000_1111) as usize)),
+            m::STRING_8 => self.read_len(8).map(ev::String),
+            m::STRING_16 => self.read_len(16).map(ev::String),
+            m::STRING_32 => self.read_len(32).map(ev::String),
+            m::BYTES_8 => self.read_len(8).map(ev::Bytes),
+            m::BYTES_16 => self.read_len(16).map(ev::Bytes),
+            m::BYTES_32 => self.read_len(32).map(ev::Bytes),
+            v @ 0x90..=0x9F => Ok(ev::List((v & 0b0000_1111) as usize)),
+            m::LIST_8 => self.read_len(8).map(ev::List),
+            m::LIST_16 => self.read_len(16).map(ev::List),
+            m::LIST_32 => self.read_len(32).map(ev::List),
+            v @ 0xA0..=0xAF => Ok(ev::Map((v & 0b0000_1111) as usize)),
+            m::MAP_8 => self.read_len(8).map(ev::Map),
+            m::MAP_16 => self.read_len(16).map(ev::Map),
+            m::MAP_32 => self.read_len(32).map(ev::Map),
+            v @ 0xB0..=0xBF => self.reader.read_u8().map(
                 |s| ev::Struct(s, (v & 0b0000_1111) as usize)).map_err(From::from),
-            m::STRUCT_8 => self.read_len(8)
-                .map_err(From::from)
-                .and_then(|size| self.reader.read_u8()
+            m::STRUCT_8 => self.read_len(8).and_then(|size| self.reader.read_u8()
                     .map(|sig| ev::Struct(sig, size))
                     .map_err(From::from)),
-            m::STRUCT_16 => self.read_len(16)
-                .map_err(From::from)
-                .and_then(|size| self.reader.read_u8()
+            m::STRUCT_16 => self.read_len(16).and_then(|size| self.reader.read_u8()
                     .map(|sig| ev::Struct(sig, size))
                     .map_err(From::from)),
             _ => unreachable!()
         }
     }
 
+    fn insert_entry(&self, map: &mut BTreeMap<String, Value>, key: &str, value: Value) -> DecodeResult<()> {
+        match map.entry(key.to_owned()) {
+            Entry::Vacant(entry) => { entry.insert(value); }
+            Entry::Occupied(mut entry) => {
+                debug!("Duplicate map key '{}' read, applying {:?}", key, self.options.duplicate_keys);
+                match self.options.duplicate_keys {
+                    DuplicateKeys::Error => return Err(DecoderError::DuplicateKey(key.to_owned())),
+                    DuplicateKeys::KeepFirst => {}
+                    DuplicateKeys::KeepLast => { entry.insert(value); }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_size(&self, event: ParserEvent) -> ParserEventResult {
+        let limits = &self.options.limits;
+        match event {
+            ev::String(size) => DecodeLimits::check(limits.max_string_size, "STRING", size).map(ev::String),
+            ev::Bytes(size) => DecodeLimits::check(limits.max_string_size, "BYTES", size).map(ev::Bytes),
+            ev::List(size) => DecodeLimits::check(limits.max_list_size, "LIST", size).map(ev::List),
+            ev::Map(size) => DecodeLimits::check(limits.max_map_size, "MAP", size).map(ev::Map),
+            ev::Struct(s, size) => DecodeLimits::check(limits.max_struct_size, "STRUCTURE", size).map(|size| ev::Struct(s, size)),
+            e => Ok(e),
+        }
+    }
+
     fn read_int(&mut self, size: u8) -> ParserEventResult {
         match size {
             8 => self.reader.read_i8().map(|v| ev::Integer(v as i64)).map_err(From::from),
             16 => self.reader.read_i16::<BigEndian>().map(|v| ev::Integer(v as i64)).map_err(From::from),
             32 => self.reader.read_i32::<BigEndian>().map(|v| ev::Integer(v as i64)).map_err(From::from),
-            64 => self.reader.read_i64::<BigEndian>().map(|v| ev::Integer(v as i64)).map_err(From::from),
+            64 => self.reader.read_i64::<BigEndian>().map(ev::Integer).map_err(From::from),
             _ => unreachable!(),
         }
     }
@@ -192,27 +239,7 @@ impl<'a, R: Read + 'a> Builder<'a, R> {
     }
 
     fn read_string(&mut self, size: usize) -> DecodeResult<String> {
-        let mut store;
-        if size < 4096 {
-            store = vec![0u8; size];
-            try!(self.reader.read(&mut store));
-        } else {
-            store = Vec::with_capacity(size);
-            let mut buf = [0u8; 4096];
-
-            let loops = (size as f32 / 4096.0).floor() as usize;
-            for _ in 0..loops {
-                let bytes = try!(self.reader.read(&mut buf));
-                store.extend(buf[0..bytes].iter());
-            }
-
-            if size % 4096 > 0 {
-                let mut buf = vec![0u8; size % 4096];
-                try!(self.reader.read(&mut buf));
-                store.append(&mut buf);
-            }
-        }
-
+        let store = read_bytes(self.reader, size)?;
         String::from_utf8(store).map_err(From::from)
     }
 }
@@ -223,8 +250,15 @@ mod tests {
     use std::string::String;
     use std::io::Cursor;
     use super::from_reader;
-    use super::super::Value;
+    use super::super::{Value, StructureSignature};
     use ::v1::packstream::marker as m;
+    use ::v1::testing::ShortReads;
+
+    #[test]
+    fn build_bytes() {
+        let mut input = Cursor::new(vec![m::BYTES_8, 0x02, 0x00, 0xFF]);
+        assert_eq!(Value::Bytes(vec![0x00, 0xFF]), from_reader(&mut input).unwrap());
+    }
 
     #[test]
     fn decode_nil() {
@@ -322,6 +356,18 @@ mod tests {
         assert_eq!(Value::Float(-1.1), result);
     }
 
+    #[test]
+    fn decode_string_and_bytes_from_short_reads() {
+        let text = "A".repeat(10_000);
+        let mut input = vec![m::STRING_16, 0x27, 0x10];
+        input.extend(text.as_bytes());
+        assert_eq!(Value::String(text), from_reader(&mut ShortReads::new(Cursor::new(input), 7)).unwrap());
+
+        let mut input = vec![m::BYTES_16, 0x10, 0x00];
+        input.extend(vec![0xFF; 4096]);
+        assert_eq!(Value::Bytes(vec![0xFF; 4096]), from_reader(&mut ShortReads::new(Cursor::new(input), 7)).unwrap());
+    }
+
     #[test]
     fn decode_string32() {
         let size = 70_000;
@@ -382,7 +428,7 @@ mod tests {
 
     #[test]
     fn decode_char() {
-        for c in b'A'..b'Z' {
+        for c in b'A'..=b'Z' {
             let mut input = Cursor::new(vec![0x81, c]);
             let result = from_reader(&mut input).unwrap();
 
@@ -764,9 +810,9 @@ mod tests {
             0x81, 0x41
         ]);
 
-        let expected = Value::Structure(0x22, vec![Value::Integer(1),
-                                                   Value::Float(1.1),
-                                                   Value::String("A".to_owned())]);
+        let expected = Value::Structure(StructureSignature::Unknown(0x22), vec![
+            Value::Integer(1), Value::Float(1.1), Value::String("A".to_owned()),
+        ]);
 
         let result = from_reader(&mut input).unwrap();
 
@@ -781,7 +827,7 @@ mod tests {
             |mut acc, _| { acc.push(0x01); acc }
         ));
 
-        let expected = Value::Structure(0x22, vec![Value::Integer(1); size]);
+        let expected = Value::Structure(StructureSignature::Unknown(0x22), vec![Value::Integer(1); size]);
         let result = from_reader(&mut input).unwrap();
 
         assert_eq!(expected, result);
@@ -795,9 +841,67 @@ mod tests {
             |mut acc, _| { acc.push(0x01); acc }
         ));
 
-        let expected = Value::Structure(0x22, vec![Value::Integer(1); size]);
+        let expected = Value::Structure(StructureSignature::Unknown(0x22), vec![Value::Integer(1); size]);
         let result = from_reader(&mut input).unwrap();
 
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn declared_size_beyond_limit_is_rejected() {
+        use ::v1::packstream::deserialize::DecoderError;
+
+        for &(ref input, expected) in &[
+            (vec![m::STRING_32, 0xFF, 0xFF, 0xFF, 0xFF], "STRING"),
+            (vec![m::BYTES_32, 0xFF, 0xFF, 0xFF, 0xFF], "BYTES"),
+            (vec![m::LIST_32, 0xFF, 0xFF, 0xFF, 0xFF], "LIST"),
+            (vec![m::MAP_32, 0xFF, 0xFF, 0xFF, 0xFF], "MAP"),
+            (vec![m::STRUCT_16, 0xFF, 0xFF, 0x22], "STRUCTURE"),
+        ] {
+            match from_reader(&mut Cursor::new(input.clone())) {
+                Err(DecoderError::SizeLimitExceeded { kind, .. }) => assert_eq!(expected, kind),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn custom_limits() {
+        use ::v1::packstream::deserialize::{DecoderError, DecodeLimits};
+        use super::super::Registry;
+
+        let limits = DecodeLimits { max_list_size: 2, ..DecodeLimits::default() };
+        let registry = Registry::default().with_limits(limits);
+
+        let input = vec![0x92, 0x01, 0x02];
+        assert_eq!(Value::from(vec![1, 2]), registry.read_value(&mut Cursor::new(input)).unwrap());
+
+        // nested values are checked too
+        let input = vec![0x91, 0x93, 0x01, 0x02, 0x03];
+        match registry.read_value(&mut Cursor::new(input)) {
+            Err(DecoderError::SizeLimitExceeded { kind: "LIST", size: 3, limit: 2 }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_map_keys() {
+        use ::v1::packstream::deserialize::{DecoderError, DecodeOptions, DuplicateKeys};
+        use super::super::Registry;
+
+        // {a: 1, a: 2}
+        let input = vec![0xA2, 0x81, b'a', 0x01, 0x81, b'a', 0x02];
+        let read = |duplicate_keys| {
+            let options = DecodeOptions { duplicate_keys, ..DecodeOptions::default() };
+            Registry::default().with_options(options).read_value(&mut Cursor::new(input.clone()))
+        };
+
+        assert_eq!(Value::map().entry("a", 2).build(), from_reader(&mut Cursor::new(input.clone())).unwrap());
+        assert_eq!(Value::map().entry("a", 2).build(), read(DuplicateKeys::KeepLast).unwrap());
+        assert_eq!(Value::map().entry("a", 1).build(), read(DuplicateKeys::KeepFirst).unwrap());
+        match read(DuplicateKeys::Error) {
+            Err(DecoderError::DuplicateKey(ref key)) => assert_eq!("a", key),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
 }
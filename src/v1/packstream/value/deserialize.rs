@@ -0,0 +1,529 @@
+use std::convert::TryFrom;
+use rustc_serialize::{Decodable, Decoder};
+
+use super::{Value, Map};
+use super::super::deserialize::{DecodeResult, DecoderError as DecErr, IntegerOverflow};
+
+/// Decode a `T` from `value`, the counterpart of `to_value`.
+///
+/// Struct fields are looked up by name, so the order of the entries in a `Value::Map` does not
+/// matter and missing entries are read as `Value::Null`, e.g. into an `Option`.
+pub fn from_value<T: Decodable>(value: Value) -> DecodeResult<T> {
+    let mut decoder = ValueDecoder::new(value);
+    Decodable::decode(&mut decoder)
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match *value {
+        Value::Null => "NULL",
+        Value::Boolean(_) => "BOOLEAN",
+        Value::Integer(_) => "INTEGER",
+        Value::Float(_) => "FLOAT",
+        Value::String(_) => "STRING",
+        Value::Bytes(_) => "BYTES",
+        Value::List(_) => "LIST",
+        Value::Map(_) => "MAP",
+        Value::Structure(_, _) => "STRUCTURE",
+        Value::Node(_) => "NODE",
+        Value::Relationship(_) => "RELATIONSHIP",
+        Value::Path(_) => "PATH",
+    }
+}
+
+macro_rules! wrong_value {
+    ($expected:expr, $got:expr) => {
+        Err(DecErr::UnexpectedInput($expected.to_owned(), type_name(&$got).to_owned()))
+    }
+}
+
+macro_rules! read_integer {
+    ($name:ident, $ty:ident) => {
+        fn $name(&mut self) -> Result<$ty, Self::Error> {
+            let value = self.read_i64()?;
+            IntegerOverflow::Error.narrow(value, stringify!($ty))
+        }
+    }
+}
+
+struct ValueDecoder {
+    stack: Vec<Value>,
+    // maps of the structs being read, `None` for a structure whose fields are on the stack
+    structs: Vec<Option<Map>>,
+}
+
+impl ValueDecoder {
+    pub fn new(value: Value) -> Self {
+        ValueDecoder {
+            stack: vec![value],
+            structs: vec![],
+        }
+    }
+
+    fn pop(&mut self) -> DecodeResult<Value> {
+        self.stack.pop().ok_or(DecErr::UnexpectedEOF)
+    }
+
+    fn push_all(&mut self, values: Vec<Value>) {
+        self.stack.extend(values.into_iter().rev());
+    }
+}
+
+impl Decoder for ValueDecoder {
+    type Error = DecErr;
+
+    // Primitive types:
+    fn read_nil(&mut self) -> Result<(), Self::Error> {
+        match self.pop()? {
+            Value::Null => Ok(()),
+            v => wrong_value!("NULL", v),
+        }
+    }
+
+    read_integer!(read_usize, usize);
+    read_integer!(read_u64, u64);
+    read_integer!(read_u32, u32);
+    read_integer!(read_u16, u16);
+    read_integer!(read_u8, u8);
+    read_integer!(read_isize, isize);
+    read_integer!(read_i32, i32);
+    read_integer!(read_i16, i16);
+    read_integer!(read_i8, i8);
+
+    fn read_i64(&mut self) -> Result<i64, Self::Error> {
+        match self.pop()? {
+            Value::Integer(v) => Ok(v),
+            v => wrong_value!("INTEGER", v),
+        }
+    }
+
+    fn read_bool(&mut self) -> Result<bool, Self::Error> {
+        match self.pop()? {
+            Value::Boolean(v) => Ok(v),
+            v => wrong_value!("BOOLEAN", v),
+        }
+    }
+
+    fn read_f64(&mut self) -> Result<f64, Self::Error> {
+        match self.pop()? {
+            Value::Float(v) => Ok(v),
+            v => wrong_value!("FLOAT", v),
+        }
+    }
+
+    fn read_f32(&mut self) -> Result<f32, Self::Error> {
+        self.read_f64().map(|v| v as f32)
+    }
+
+    fn read_char(&mut self) -> Result<char, Self::Error> {
+        let value = self.read_str()?;
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err(DecErr::UnexpectedInput("CHAR".to_owned(), format!("'{}'", value))),
+        }
+    }
+
+    fn read_str(&mut self) -> Result<String, Self::Error> {
+        match self.pop()? {
+            Value::String(v) => Ok(v),
+            v => wrong_value!("STRING", v),
+        }
+    }
+
+    // Compound types:
+    fn read_enum<T, F>(&mut self, _: &str, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        f(self)
+    }
+
+    fn read_enum_variant<T, F>(&mut self, names: &[&str], mut f: F)
+                               -> Result<T, Self::Error>
+        where F: FnMut(&mut Self, usize) -> Result<T, Self::Error> {
+
+        // variants are written either as their name or as a map of the name to the arguments
+        let name = match self.pop()? {
+            Value::String(name) => name,
+            Value::Map(map) => {
+                if map.len() != 1 {
+                    return Err(DecErr::UnexpectedInput("Map(1)".to_owned(), format!("Map({})", map.len())))
+                }
+                let (name, args) = map.into_iter().next().unwrap();
+                match args {
+                    Value::List(args) => self.push_all(args),
+                    v => return wrong_value!("LIST", v),
+                }
+                name
+            }
+            v => return wrong_value!("ENUM_VARIANT", v),
+        };
+
+        match names.iter().position(|n| *n == name) {
+            Some(idx) => f(self, idx),
+            None => Err(DecErr::UnknownVariant(name)),
+        }
+    }
+
+    fn read_enum_variant_arg<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        f(self)
+    }
+
+    fn read_enum_struct_variant<T, F>(&mut self, names: &[&str], f: F) -> Result<T, Self::Error>
+        where F: FnMut(&mut Self, usize) -> Result<T, Self::Error> {
+
+        self.read_enum_variant(names, f)
+    }
+
+    fn read_enum_struct_variant_field<T, F>(&mut self, _: &str, _: usize, f: F)
+                                            -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        f(self)
+    }
+
+    fn read_struct<T, F>(&mut self, s_name: &str, len: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        match self.pop()?.into_structure() {
+            Value::Map(map) => self.structs.push(Some(map)),
+            Value::Structure(signature, fields) => {
+                // the signature is read as the first field, like the packstream decoder does
+                if fields.len() + 1 != len {
+                    return Err(DecErr::UnexpectedInput(format!("{} ({} fields)", s_name, len),
+                                                       format!("? ({} fields)", fields.len() + 1)))
+                }
+                self.push_all(fields);
+                self.stack.push(Value::Integer(signature.byte() as i64));
+                self.structs.push(None);
+            }
+            v => return wrong_value!("MAP or STRUCTURE", v),
+        }
+
+        let result = f(self);
+        self.structs.pop();
+        result
+    }
+
+    fn read_struct_field<T, F>(&mut self, f_name: &str, _: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        if let Some(&mut Some(ref mut map)) = self.structs.last_mut() {
+            match map.remove(f_name) {
+                Some(value) => self.stack.push(value),
+                // a missing field is read as null, which only an `Option` accepts
+                None => {
+                    self.stack.push(Value::Null);
+                    return f(self).map_err(|_| DecErr::MissingField(f_name.to_owned()))
+                }
+            }
+        }
+
+        f(self)
+    }
+
+    fn read_tuple<T, F>(&mut self, len: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        self.read_seq(move |d, l| {
+            if l == len {
+                f(d)
+            } else {
+                Err(DecErr::UnexpectedInput(format!("Tuple({})", len), format!("List({})", l)))
+            }
+        })
+    }
+
+    fn read_tuple_arg<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        f(self)
+    }
+
+    fn read_tuple_struct<T, F>(&mut self, _: &str, len: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        if len == 1 {
+            f(self)
+        } else {
+            self.read_tuple(len, f)
+        }
+    }
+
+    fn read_tuple_struct_arg<T, F>(&mut self, a_idx: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        self.read_tuple_arg(a_idx, f)
+    }
+
+    // Specialized types:
+    fn read_option<T, F>(&mut self, mut f: F) -> Result<T, Self::Error>
+        where F: FnMut(&mut Self, bool) -> Result<T, Self::Error> {
+
+        match self.pop()? {
+            Value::Null => f(self, false),
+            v => {
+                self.stack.push(v);
+                f(self, true)
+            }
+        }
+    }
+
+    fn read_seq<T, F>(&mut self, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self, usize) -> Result<T, Self::Error> {
+
+        let len = match self.pop()? {
+            Value::List(list) => {
+                let len = list.len();
+                self.push_all(list);
+                len
+            }
+            Value::Bytes(bytes) => {
+                let len = bytes.len();
+                self.push_all(bytes.into_iter().map(|b| Value::Integer(b as i64)).collect());
+                len
+            }
+            v => return wrong_value!("LIST", v),
+        };
+
+        f(self, len)
+    }
+
+    fn read_seq_elt<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        f(self)
+    }
+
+    fn read_map<T, F>(&mut self, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self, usize) -> Result<T, Self::Error> {
+
+        let map = match self.pop()? {
+            Value::Map(map) => map,
+            v => return wrong_value!("MAP", v),
+        };
+
+        let len = map.len();
+        for (key, value) in map.into_iter().rev() {
+            self.stack.push(value);
+            self.stack.push(Value::String(key));
+        }
+
+        f(self, len)
+    }
+
+    fn read_map_elt_key<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        f(self)
+    }
+
+    fn read_map_elt_val<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
+
+        f(self)
+    }
+
+    fn error(&mut self, err: &str) -> Self::Error {
+        DecErr::ApplicationError(err.to_owned())
+    }
+}
+
+// `TryFrom` a value into the Rust type of its variant, so a field can be read with
+// `let age: i64 = value.try_into()?`. Other variants fail with `DecoderError::UnexpectedInput`.
+macro_rules! try_from_value {
+    ($ty:ty, $expected:expr, $variant:ident) => {
+        impl TryFrom<Value> for $ty {
+            type Error = DecErr;
+
+            fn try_from(value: Value) -> DecodeResult<Self> {
+                match value {
+                    Value::$variant(v) => Ok(v),
+                    other => wrong_value!($expected, other),
+                }
+            }
+        }
+    }
+}
+
+try_from_value!(bool, "BOOLEAN", Boolean);
+try_from_value!(i64, "INTEGER", Integer);
+try_from_value!(f64, "FLOAT", Float);
+try_from_value!(String, "STRING", String);
+try_from_value!(Vec<u8>, "BYTES", Bytes);
+try_from_value!(Vec<Value>, "LIST", List);
+try_from_value!(Map, "MAP", Map);
+
+// The same for a borrowed value, `$v` being a reference to the content of the variant.
+macro_rules! try_from_value_ref {
+    ($ty:ty, $expected:expr, $variant:ident, $v:ident => $out:expr) => {
+        impl<'a> TryFrom<&'a Value> for $ty {
+            type Error = DecErr;
+
+            fn try_from(value: &'a Value) -> DecodeResult<Self> {
+                match *value {
+                    Value::$variant(ref $v) => Ok($out),
+                    ref other => wrong_value!($expected, other),
+                }
+            }
+        }
+    }
+}
+
+try_from_value_ref!(bool, "BOOLEAN", Boolean, v => *v);
+try_from_value_ref!(i64, "INTEGER", Integer, v => *v);
+try_from_value_ref!(f64, "FLOAT", Float, v => *v);
+try_from_value_ref!(&'a str, "STRING", String, v => v);
+try_from_value_ref!(&'a [u8], "BYTES", Bytes, v => v);
+try_from_value_ref!(&'a [Value], "LIST", List, v => v);
+try_from_value_ref!(&'a Map, "MAP", Map, v => v);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::from_value;
+    use super::super::{Value, Bytes, StructureSignature, to_value};
+    use super::super::super::deserialize::DecoderError;
+
+    decodable_struct! {
+        #[derive(Debug, PartialEq)]
+        struct Person {
+            name: String,
+            age: i64,
+            nickname: Option<String>,
+            emails: Vec<String>,
+        }
+    }
+
+    #[test]
+    fn decode_struct_from_map() {
+        let value = Value::map()
+            .entry("name", "Alice")
+            .entry("age", 33)
+            .entry("emails", Value::list().item("alice@example.com").build())
+            .build();
+
+        let expected = Person {
+            name: "Alice".to_owned(),
+            age: 33,
+            nickname: None,
+            emails: vec!["alice@example.com".to_owned()],
+        };
+        assert_eq!(expected, from_value(value).unwrap());
+    }
+
+    #[test]
+    fn decode_missing_field_should_fail() {
+        let value = Value::map().entry("name", "Alice").build();
+        match from_value::<Person>(value) {
+            Err(DecoderError::MissingField(ref name)) if name == "age" => {}
+            other => panic!("expected a missing field, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_primitives() {
+        assert_eq!(1u8, from_value(Value::Integer(1)).unwrap());
+        assert_eq!(1.5, from_value::<f64>(Value::Float(1.5)).unwrap());
+        assert_eq!('a', from_value(Value::String("a".to_owned())).unwrap());
+        assert_eq!(Some(true), from_value(Value::Boolean(true)).unwrap());
+        assert_eq!(None::<bool>, from_value(Value::Null).unwrap());
+    }
+
+    #[test]
+    fn decode_out_of_range_integer_should_fail() {
+        assert!(from_value::<u8>(Value::Integer(256)).is_err());
+        assert!(from_value::<u64>(Value::Integer(-1)).is_err());
+    }
+
+    #[test]
+    fn decode_wrong_type_should_fail() {
+        assert!(from_value::<i64>(Value::String("1".to_owned())).is_err());
+        assert!(from_value::<Vec<i64>>(Value::Map(BTreeMap::new())).is_err());
+    }
+
+    #[test]
+    fn decode_map_and_bytes() {
+        let value = Value::map().entry("a", 1).entry("b", 2).build();
+        let result: BTreeMap<String, i64> = from_value(value).unwrap();
+        assert_eq!(vec![("a".to_owned(), 1), ("b".to_owned(), 2)], result.into_iter().collect::<Vec<_>>());
+
+        let result: Bytes = from_value(Value::Bytes(vec![0x00, 0xFF])).unwrap();
+        assert_eq!(vec![0x00, 0xFF], result.0);
+    }
+
+    #[test]
+    fn decode_structure() {
+        decodable_struct! {
+            #[derive(Debug, PartialEq)]
+            struct Point {
+                signature: u8,
+                x: i64,
+            }
+        }
+
+        let value = Value::Structure(StructureSignature::Unknown(0x58), vec![Value::Integer(1)]);
+        assert_eq!(Point { signature: 0x58, x: 1 }, from_value(value).unwrap());
+
+        let value = Value::Structure(StructureSignature::Unknown(0x58), vec![]);
+        assert!(from_value::<Point>(value).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = (1, "a".to_owned(), vec![true, false]);
+        assert_eq!(input, from_value::<(i64, String, Vec<bool>)>(to_value(&input)).unwrap());
+    }
+
+    #[test]
+    fn every_u8_round_trips() {
+        for v in 0..=u8::MAX {
+            assert_eq!(v, from_value::<u8>(to_value(&v)).unwrap());
+            assert_eq!(v, from_value::<u8>(Value::from(v)).unwrap());
+        }
+        assert!(from_value::<u8>(Value::Integer(256)).is_err());
+    }
+
+    #[test]
+    fn scalar_try_from_value() {
+        use std::convert::{TryFrom, TryInto};
+
+        let age: i64 = Value::from(42).try_into().unwrap();
+        assert_eq!(42, age);
+        assert!(bool::try_from(Value::from(true)).unwrap());
+        assert_eq!(1.5, f64::try_from(Value::from(1.5)).unwrap());
+        assert_eq!("Alice", String::try_from(Value::from("Alice")).unwrap());
+        assert_eq!(vec![0xFF], Vec::<u8>::try_from(Value::Bytes(vec![0xFF])).unwrap());
+        assert_eq!(vec![Value::from(1)], Vec::<Value>::try_from(Value::from(vec![1])).unwrap());
+        assert_eq!(BTreeMap::new(), BTreeMap::<String, Value>::try_from(Value::map().build()).unwrap());
+    }
+
+    #[test]
+    fn scalar_try_from_borrowed_value() {
+        use std::convert::{TryFrom, TryInto};
+
+        let value = Value::map().entry("name", "Alice").entry("tags", vec!["a"]).build();
+        let map: &BTreeMap<String, Value> = (&value).try_into().unwrap();
+        assert_eq!("Alice", <&str>::try_from(&map["name"]).unwrap());
+        assert_eq!(&[Value::from("a")][..], <&[Value]>::try_from(&map["tags"]).unwrap());
+        assert_eq!(7, i64::try_from(&Value::from(7)).unwrap());
+        assert_eq!(&[1u8, 2][..], <&[u8]>::try_from(&Value::Bytes(vec![1, 2])).unwrap());
+    }
+
+    #[test]
+    fn try_from_wrong_variant_should_fail() {
+        use std::convert::TryFrom;
+
+        match i64::try_from(Value::from("42")) {
+            Err(e @ DecoderError::UnexpectedInput(..)) => assert_eq!("Expected 'INTEGER', Found 'STRING'", e.to_string()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match <&str>::try_from(&Value::Null) {
+            Err(DecoderError::UnexpectedInput(ref expected, ref found)) => assert_eq!(("STRING", "NULL"), (&expected[..], &found[..])),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(f64::try_from(Value::from(1)).is_err());
+    }
+}
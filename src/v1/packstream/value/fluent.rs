@@ -0,0 +1,90 @@
+use super::{Value, List, Map};
+
+/// Fluent construction of `Value::Map`, see `Value::map()`.
+#[derive(Debug, Clone, Default)]
+pub struct MapBuilder {
+    map: Map,
+}
+
+impl MapBuilder {
+    pub fn new() -> Self {
+        MapBuilder::default()
+    }
+
+    pub fn entry<K: Into<String>, V: Into<Value>>(mut self, key: K, value: V) -> Self {
+        self.map.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn build(self) -> Value {
+        Value::Map(self.map)
+    }
+}
+
+/// Fluent construction of `Value::List`, see `Value::list()`.
+#[derive(Debug, Clone, Default)]
+pub struct ListBuilder {
+    list: List,
+}
+
+impl ListBuilder {
+    pub fn new() -> Self {
+        ListBuilder::default()
+    }
+
+    pub fn item<V: Into<Value>>(mut self, value: V) -> Self {
+        self.list.push(value.into());
+        self
+    }
+
+    pub fn build(self) -> Value {
+        Value::List(self.list)
+    }
+}
+
+impl From<MapBuilder> for Value {
+    fn from(builder: MapBuilder) -> Self { builder.build() }
+}
+
+impl From<ListBuilder> for Value {
+    fn from(builder: ListBuilder) -> Self { builder.build() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Value, Map};
+
+    #[test]
+    fn build_map() {
+        let result = Value::map().entry("a", 1).entry("b", vec![1, 2]).build();
+
+        let mut expected = Map::new();
+        expected.insert("a".to_owned(), Value::Integer(1));
+        expected.insert("b".to_owned(), Value::List(vec![Value::Integer(1), Value::Integer(2)]));
+
+        assert_eq!(Value::Map(expected), result);
+    }
+
+    #[test]
+    fn build_nested() {
+        let result = Value::map()
+            .entry("list", Value::list().item(1).item("a"))
+            .entry("map", Value::map().entry("b", true))
+            .build();
+
+        let mut inner = Map::new();
+        inner.insert("b".to_owned(), Value::Boolean(true));
+
+        let mut expected = Map::new();
+        expected.insert("list".to_owned(), Value::List(vec![Value::Integer(1), Value::String("a".to_owned())]));
+        expected.insert("map".to_owned(), Value::Map(inner));
+
+        assert_eq!(Value::Map(expected), result);
+    }
+
+    #[test]
+    fn build_empty() {
+        assert_eq!(Value::Map(Map::new()), Value::map().build());
+        assert_eq!(Value::List(vec![]), Value::list().build());
+    }
+}
@@ -0,0 +1,141 @@
+// Conversions between `Value` and the `Json` of rustc-serialize, e.g. to pass the parameters
+// of a query from a JSON payload.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use rustc_serialize::json::Json;
+
+use super::{Value, Map};
+
+#[derive(Debug, PartialEq)]
+pub enum JsonError {
+    /// A JSON number above `i64::MAX`, which PackStream integers cannot hold.
+    IntegerOverflow(u64),
+    /// A value with no JSON counterpart, e.g. a structure.
+    Unsupported(String),
+    /// Parameters given as something else than a JSON object.
+    NotAnObject,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonError::IntegerOverflow(n) => write!(f, "{} does not fit in a 64-bit signed integer", n),
+            JsonError::Unsupported(ref found) => write!(f, "{} has no JSON representation", found),
+            JsonError::NotAnObject => f.write_str("parameters must be a JSON object"),
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+/// Integers are kept as integers and other numbers read as floats, so `1` and `1.0` stay
+/// distinct as they are in Cypher.
+impl TryFrom<Json> for Value {
+    type Error = JsonError;
+
+    fn try_from(json: Json) -> Result<Self, JsonError> {
+        Ok(match json {
+            Json::Null => Value::Null,
+            Json::Boolean(b) => Value::Boolean(b),
+            Json::I64(n) => Value::Integer(n),
+            Json::U64(n) => match i64::try_from(n) {
+                Ok(n) => Value::Integer(n),
+                Err(_) => return Err(JsonError::IntegerOverflow(n)),
+            },
+            Json::F64(n) => Value::Float(n),
+            Json::String(s) => Value::String(s),
+            Json::Array(items) => Value::List(items.into_iter().map(Value::try_from).collect::<Result<_, _>>()?),
+            Json::Object(entries) => Value::Map(params_from_entries(entries)?),
+        })
+    }
+}
+
+/// Byte arrays become arrays of numbers. Structures, graph ones included, are not converted.
+impl TryFrom<Value> for Json {
+    type Error = JsonError;
+
+    fn try_from(value: Value) -> Result<Self, JsonError> {
+        Ok(match value {
+            Value::Null => Json::Null,
+            Value::Boolean(b) => Json::Boolean(b),
+            Value::Integer(n) => Json::I64(n),
+            Value::Float(n) => Json::F64(n),
+            Value::String(s) => Json::String(s),
+            Value::Bytes(bytes) => Json::Array(bytes.into_iter().map(|b| Json::U64(b as u64)).collect()),
+            Value::List(items) => Json::Array(items.into_iter().map(Json::try_from).collect::<Result<_, _>>()?),
+            Value::Map(entries) => Json::Object(entries.into_iter()
+                .map(|(k, v)| Json::try_from(v).map(|v| (k, v)))
+                .collect::<Result<_, _>>()?),
+            Value::Structure(signature, _) => return Err(JsonError::Unsupported(format!("{:?}", signature))),
+            Value::Node(_) => return Err(JsonError::Unsupported("Node".to_owned())),
+            Value::Relationship(_) => return Err(JsonError::Unsupported("Relationship".to_owned())),
+            Value::Path(_) => return Err(JsonError::Unsupported("Path".to_owned())),
+        })
+    }
+}
+
+/// The parameters of a query from a JSON object, e.g. `{"name": "Alice", "age": 42}`.
+pub fn params_from_json(json: Json) -> Result<Map, JsonError> {
+    match json {
+        Json::Object(entries) => params_from_entries(entries),
+        _ => Err(JsonError::NotAnObject),
+    }
+}
+
+fn params_from_entries(entries: ::rustc_serialize::json::Object) -> Result<Map, JsonError> {
+    entries.into_iter().map(|(k, v)| Value::try_from(v).map(|v| (k, v))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use rustc_serialize::json::Json;
+    use super::*;
+    use v1::graph::Node;
+
+    #[test]
+    fn json_to_value() {
+        let json = Json::from_str(r#"{"name": "Alice", "age": 42, "score": 1.0, "tags": ["a", null, true]}"#).unwrap();
+
+        let expected = Value::map()
+            .entry("name", "Alice")
+            .entry("age", 42)
+            .entry("score", 1.0)
+            .entry("tags", Value::list().item("a").item(Value::Null).item(true).build())
+            .build();
+        assert_eq!(expected, Value::try_from(json).unwrap());
+    }
+
+    #[test]
+    fn integer_above_i64_max_overflows() {
+        assert_eq!(Value::Integer(i64::MAX), Value::try_from(Json::U64(i64::MAX as u64)).unwrap());
+        assert_eq!(Err(JsonError::IntegerOverflow(u64::MAX)), Value::try_from(Json::U64(u64::MAX)));
+    }
+
+    #[test]
+    fn value_to_json() {
+        let value = Value::map()
+            .entry("n", 1)
+            .entry("x", 1.5)
+            .entry("bytes", Value::Bytes(vec![0, 255]))
+            .entry("list", Value::list().item("a").item(Value::Null).build())
+            .build();
+
+        assert_eq!(r#"{"bytes":[0,255],"list":["a",null],"n":1,"x":1.5}"#, Json::try_from(value).unwrap().to_string());
+    }
+
+    #[test]
+    fn structures_are_not_converted() {
+        let node = Node { id: Some(1), element_id: None, labels: vec![], properties: Map::new() };
+        assert_eq!(Err(JsonError::Unsupported("Node".to_owned())), Json::try_from(Value::Node(node)));
+    }
+
+    #[test]
+    fn params_must_be_an_object() {
+        let params = params_from_json(Json::from_str(r#"{"id": 7}"#).unwrap()).unwrap();
+        assert_eq!(Some(&Value::Integer(7)), params.get("id"));
+        assert_eq!(Err(JsonError::NotAnObject), params_from_json(Json::from_str("[1]").unwrap()));
+    }
+}
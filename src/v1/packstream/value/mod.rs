@@ -1,14 +1,27 @@
 use std::io::Read;
-use std::collections::BTreeMap;
-use std::convert::{From, Into};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{From, Into, TryFrom};
 use std::string;
-use rustc_serialize::{Encodable, Encoder};
+use rustc_serialize::{Encodable, Encoder, Decodable, Decoder};
 
 pub mod serialize;
+pub mod deserialize;
+pub mod registry;
+mod signature;
 mod builder;
+mod fluent;
+mod json;
 
 use super::deserialize::DecodeResult;
-pub use self::serialize::to_value;
+use super::serialize::EncoderError;
+use super::BYTES_NAME;
+use v1::graph::{Node, Relationship, Path};
+pub use self::serialize::{to_value, try_to_value, to_value_skipping_none};
+pub use self::deserialize::from_value;
+pub use self::fluent::{MapBuilder, ListBuilder};
+pub use self::json::{JsonError, params_from_json};
+pub use self::registry::{Registry, StructureDecoder};
+pub use self::signature::StructureSignature;
 
 #[derive(Clone, PartialEq, PartialOrd, Debug)]
 pub enum Value {
@@ -17,19 +30,44 @@ pub enum Value {
     Integer(i64),
     Float(f64),
     String(string::String),
+    Bytes(Vec<u8>),
     List(self::List),
     Map(self::Map),
-    Structure(u8, self::List)
+    Structure(StructureSignature, self::List),
+    /// A `Node` structure, read by the default `Registry`.
+    Node(Node),
+    Relationship(Relationship),
+    Path(Path),
 }
 
 pub type List = Vec<Value>;
 pub type Map = BTreeMap<String, Value>;
 
 impl Value {
+    /// Read a value from `reader`, decoding graph structures into `Value::Node`,
+    /// `Value::Relationship` and `Value::Path`. See `Registry` for other structures.
     pub fn from_reader<R: Read>(reader: &mut R) -> DecodeResult<Self> {
         builder::from_reader(reader)
     }
 
+    // The value with a typed structure turned back into the `Value::Structure` it was read from.
+    pub(crate) fn into_structure(self) -> Self {
+        match self {
+            Value::Node(node) => node.to_structure(),
+            Value::Relationship(rel) => rel.to_structure(),
+            Value::Path(path) => path.to_structure(),
+            v => v,
+        }
+    }
+
+    pub fn map() -> MapBuilder {
+        MapBuilder::new()
+    }
+
+    pub fn list() -> ListBuilder {
+        ListBuilder::new()
+    }
+
     pub fn is_null(&self) -> bool {
         *self == Value::Null
     }
@@ -78,9 +116,20 @@ impl Value {
         self.as_string().is_some()
     }
 
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Value::Bytes(ref v) => Some(v),
+            _ => None
+        }
+    }
+
+    pub fn is_bytes(&self) -> bool {
+        self.as_bytes().is_some()
+    }
+
     pub fn as_list(&self) -> Option<&List> {
         match self {
-            &Value::List(ref v) => Some(v),
+            Value::List(v) => Some(v),
             _ => None
         }
     }
@@ -98,7 +147,7 @@ impl Value {
 
     pub fn as_map(&self) -> Option<&Map> {
         match self {
-            &Value::Map(ref v) => Some(v),
+            Value::Map(v) => Some(v),
             _ => None
         }
     }
@@ -114,14 +163,14 @@ impl Value {
         self.as_map().is_some()
     }
 
-    pub fn as_struct(&self) -> Option<(u8, &List)> {
+    pub fn as_struct(&self) -> Option<(StructureSignature, &List)> {
         match self {
             &Value::Structure(s, ref v) => Some((s, v)),
             _ => None
         }
     }
 
-    pub fn as_struct_mut(&mut self) -> Option<(&mut u8, &mut List)> {
+    pub fn as_struct_mut(&mut self) -> Option<(&mut StructureSignature, &mut List)> {
         match self {
             &mut Value::Structure(ref mut s, ref mut v) => Some((s, v)),
             _ => None
@@ -141,11 +190,15 @@ impl Encodable for Value {
             Value::Integer(v) => v.encode(e),
             Value::Float(v) => v.encode(e),
             Value::String(ref v) => v.encode(e),
+            Value::Bytes(ref v) => emit_bytes(v, e),
             Value::List(ref v) => v.encode(e),
             Value::Map(ref v) => v.encode(e),
+            Value::Node(ref v) => v.to_structure().encode(e),
+            Value::Relationship(ref v) => v.to_structure().encode(e),
+            Value::Path(ref v) => v.to_structure().encode(e),
             Value::Structure(s, ref v) => {
-                e.emit_struct(&format!("__STRUCTURE__{}", s as char), v.len(), |e| {
-                    for f in v { try!(f.encode(e)); }
+                e.emit_struct(&format!("__STRUCTURE__{}", s.byte() as char), v.len(), |e| {
+                    for f in v { f.encode(e)?; }
                     Ok(())
                 })
             }
@@ -153,6 +206,31 @@ impl Encodable for Value {
     }
 }
 
+/// A byte array, written with the BYTES markers instead of as a list of integers like a
+/// plain `Vec<u8>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes(pub Vec<u8>);
+
+fn emit_bytes<S: Encoder>(bytes: &[u8], e: &mut S) -> Result<(), S::Error> {
+    e.emit_tuple_struct(BYTES_NAME, 1, |e| e.emit_tuple_struct_arg(0, |e| bytes.encode(e)))
+}
+
+impl Encodable for Bytes {
+    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        emit_bytes(&self.0, e)
+    }
+}
+
+impl Decodable for Bytes {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        d.read_tuple_struct(BYTES_NAME, 1, |d| d.read_tuple_struct_arg(0, Vec::decode).map(Bytes))
+    }
+}
+
+impl From<Bytes> for Value {
+    fn from(val: Bytes) -> Self { Value::Bytes(val.0) }
+}
+
 impl<T> From<Option<T>> for Value where T: Into<Value> {
     fn from(value: Option<T>) -> Self {
         value.map(|v| v.into()).unwrap_or(Value::Null)
@@ -175,7 +253,22 @@ macro_rules! impl_from_int {
     )
 }
 
-impl_from_int!(usize, isize, u8, i8, u16, i16, u32, i32, u64, i64);
+impl_from_int!(isize, u8, i8, u16, i16, u32, i32, i64);
+
+// Unsigned integers above `i64::MAX` do not fit a PackStream integer.
+macro_rules! impl_try_from_unsigned {
+    ($($t:ty), +) => (
+        $(impl TryFrom<$t> for Value {
+            type Error = EncoderError;
+
+            fn try_from(v: $t) -> Result<Value, EncoderError> {
+                i64::try_from(v).map(Value::Integer).map_err(|_| EncoderError::ValueOutOfRange(v as u64))
+            }
+        })+
+    )
+}
+
+impl_try_from_unsigned!(usize, u64);
 
 impl From<f32> for Value {
     fn from(val: f32) -> Self { Value::Float(val as f64) }
@@ -199,6 +292,12 @@ impl<T: Into<Value>> From<Vec<T>> for Value {
     }
 }
 
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+    fn from(val: HashMap<String, T>) -> Self {
+        Value::Map(val.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
 impl<T: Into<Value>> From<BTreeMap<String, T>> for Value {
     fn from(val: BTreeMap<String, T>) -> Self {
         Value::Map(val.into_iter().fold(
@@ -220,7 +319,13 @@ impl<'a, T: Into<Value>> From<BTreeMap<&'a str, T>> for Value {
 #[cfg(test)]
 mod tests {
     use ::v1::packstream::serialize::encode;
-    use super::{Value, Map};
+    use super::{Value, Map, StructureSignature};
+
+    #[test]
+    fn serialize_bytes() {
+        let input = vec![0x00, 0xFF];
+        assert_eq!(encode(&super::Bytes(input.clone())).unwrap(), encode(&Value::Bytes(input)).unwrap());
+    }
 
     #[test]
     fn serialize_null() {
@@ -285,8 +390,8 @@ mod tests {
 
         impl Encodable for MyStruct {
             fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
-                try!(e.emit_struct("__STRUCTURE__\x22", 2, |_| Ok(())));
-                try!(self.name.encode(e));
+                e.emit_struct("__STRUCTURE__\x22", 2, |_| Ok(()))?;
+                self.name.encode(e)?;
                 self.value.encode(e)
             }
         }
@@ -297,7 +402,7 @@ mod tests {
         };
 
         let expected = Value::Structure(
-            0x22, vec![Value::String("MyStruct".to_owned()), Value::Integer(42)]
+            StructureSignature::Unknown(0x22), vec![Value::String("MyStruct".to_owned()), Value::Integer(42)]
         );
 
         assert_eq!(encode(&input).unwrap(), encode(&expected).unwrap());
@@ -316,7 +421,9 @@ mod tests {
 
     #[test]
     fn from_int() {
-        assert_eq!(Value::Integer(42), Value::from(42usize));
+        use std::convert::TryFrom;
+
+        assert_eq!(Value::Integer(42), Value::try_from(42usize).unwrap());
         assert_eq!(Value::Integer(42), Value::from(42isize));
 
         assert_eq!(Value::Integer(42), Value::from(42u8));
@@ -328,7 +435,9 @@ mod tests {
         assert_eq!(Value::Integer(42), Value::from(42u32));
         assert_eq!(Value::Integer(42), Value::from(42i32));
 
-        assert_eq!(Value::Integer(42), Value::from(42u64));
+        assert_eq!(Value::Integer(42), Value::try_from(42u64).unwrap());
+        assert_eq!(Value::Integer(i64::MAX), Value::try_from(i64::MAX as u64).unwrap());
+        assert!(Value::try_from(u64::MAX).is_err());
         assert_eq!(Value::Integer(42), Value::from(42i64));
     }
 
@@ -0,0 +1,155 @@
+use std::io::Read;
+
+use super::{Value, StructureSignature};
+use super::builder::Builder;
+use super::super::deserialize::{DecodeLimits, DecodeOptions, DecodeResult};
+use v1::graph::{Node, Relationship, Path};
+
+/// Read a structure into a typed value, or `None` to keep it as a `Value::Structure`, e.g. if
+/// it has the wrong fields.
+pub type StructureDecoder = fn(&Value) -> Option<Value>;
+
+// Graph structures known to every Bolt version, used by `Value::from_reader`.
+pub(crate) static GRAPH: &[(StructureSignature, StructureDecoder)] = &[
+    (StructureSignature::Node, decode_node),
+    (StructureSignature::Relationship, decode_relationship),
+    (StructureSignature::Path, decode_path),
+];
+
+/// Decoders of the structures read into typed values instead of `Value::Structure`, by
+/// signature.
+///
+/// The default registry knows the graph structures (`Node`, `Relationship` and `Path`).
+/// Structures added by later protocol versions, e.g. temporal and spatial types, can be
+/// registered as they are implemented.
+#[derive(Clone)]
+pub struct Registry {
+    decoders: Vec<(StructureSignature, StructureDecoder)>,
+    options: DecodeOptions,
+}
+
+impl Registry {
+    /// A registry without decoders, reading every structure as `Value::Structure`.
+    pub fn empty() -> Self {
+        Registry { decoders: Vec::new(), options: DecodeOptions::default() }
+    }
+
+    /// Decode structures with `signature`, a `StructureSignature` or its byte, using `decoder`,
+    /// replacing any decoder registered before for it.
+    pub fn register<S: Into<StructureSignature>>(mut self, signature: S, decoder: StructureDecoder) -> Self {
+        let signature = signature.into();
+        self.decoders.retain(|&(s, _)| s != signature);
+        self.decoders.push((signature, decoder));
+        self
+    }
+
+    /// Reject values larger than `limits` when reading, see `DecodeLimits`.
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.options.limits = limits;
+        self
+    }
+
+    /// Read values with `options`, e.g. to fail on duplicate map keys.
+    pub fn with_options(mut self, options: DecodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn is_registered<S: Into<StructureSignature>>(&self, signature: S) -> bool {
+        let signature = signature.into();
+        self.decoders.iter().any(|&(s, _)| s == signature)
+    }
+
+    /// Read a value from `reader`, decoding the registered structures.
+    pub fn read_value<R: Read>(&self, reader: &mut R) -> DecodeResult<Value> {
+        Builder::with_decoders(reader, &self.decoders).with_options(self.options).build()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry { decoders: GRAPH.to_vec(), options: DecodeOptions::default() }
+    }
+}
+
+// The structure `signature` with `fields`, decoded by the first matching decoder.
+pub(crate) fn decode(decoders: &[(StructureSignature, StructureDecoder)], signature: u8, fields: Vec<Value>)
+    -> Value {
+
+    let signature = StructureSignature::from(signature);
+    let structure = Value::Structure(signature, fields);
+    match decoders.iter().find(|&&(s, _)| s == signature) {
+        Some(&(_, decoder)) => decoder(&structure).unwrap_or(structure),
+        None => structure,
+    }
+}
+
+fn decode_node(value: &Value) -> Option<Value> {
+    Node::from_value(value).map(Value::Node)
+}
+
+fn decode_relationship(value: &Value) -> Option<Value> {
+    Relationship::from_value(value).map(Value::Relationship)
+}
+
+fn decode_path(value: &Value) -> Option<Value> {
+    Path::from_value(value).map(Value::Path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::Registry;
+    use v1::graph::Node;
+    use v1::packstream::encode;
+    use v1::packstream::value::{Value, Map, StructureSignature};
+
+    fn node() -> Value {
+        Value::Structure(StructureSignature::Node, vec![
+            Value::from(1), Value::List(vec![Value::from("Person")]), Value::Map(Map::new()),
+        ])
+    }
+
+    #[test]
+    fn read_graph_structures() {
+        let data = encode(&Value::List(vec![node(), Value::Structure(StructureSignature::Unknown(0x58), vec![])])).unwrap();
+        let value = Value::from_reader(&mut Cursor::new(data)).unwrap();
+
+        let expected = Node {
+            id: Some(1),
+            element_id: None,
+            labels: vec!["Person".to_owned()],
+            properties: Map::new(),
+        };
+        assert_eq!(Value::List(vec![Value::Node(expected), Value::Structure(StructureSignature::Unknown(0x58), vec![])]), value);
+    }
+
+    #[test]
+    fn malformed_structure_is_kept() {
+        let malformed = Value::Structure(StructureSignature::Node, vec![Value::from("1")]);
+        let data = encode(&malformed).unwrap();
+        assert_eq!(malformed, Value::from_reader(&mut Cursor::new(data)).unwrap());
+    }
+
+    #[test]
+    fn empty_registry() {
+        let data = encode(&node()).unwrap();
+        assert_eq!(node(), Registry::empty().read_value(&mut Cursor::new(data)).unwrap());
+    }
+
+    #[test]
+    fn register_decoder() {
+        fn point(value: &Value) -> Option<Value> {
+            let (_, fields) = value.as_struct()?;
+            Some(Value::List(fields[1..].to_vec()))
+        }
+
+        let registry = Registry::default().register(0x58, point);
+        assert!(registry.is_registered(0x58));
+        assert!(registry.is_registered(StructureSignature::Node));
+
+        let point = Value::Structure(StructureSignature::Unknown(0x58), vec![Value::from(7203), Value::from(1.0), Value::from(2.0)]);
+        let data = encode(&point).unwrap();
+        assert_eq!(Value::from(vec![1.0, 2.0]), registry.read_value(&mut Cursor::new(data)).unwrap());
+    }
+}
@@ -2,26 +2,48 @@ use std::collections::BTreeMap;
 use rustc_serialize::{Encodable, Encoder};
 
 use super::Value;
-use super::super::STRUCTURE_PREFIX;
+use super::super::{STRUCTURE_PREFIX, BYTES_NAME};
+use super::super::serialize::{EncoderError, EncodeResult};
 
 // It is unlikely that the code here will fail, but if it does, it means that something really bad
 // happened that is out of our control.
 // Given this situation, the develpers decided that this function will return a Value instead of a
-// Result<Value, Err> for convenience.
+// Result<Value, Err> for convenience. The only failure, an unsigned integer above `i64::MAX`, can
+// be handled with `try_to_value`.
 pub fn to_value<T: Encodable>(value: &T) -> Value {
     let mut encoder = ValueEncoder::new();
     value.encode(&mut encoder).expect("Something wrong happened while encoding data into `Value`");
     encoder.into_value()
 }
 
+/// Like `to_value`, failing with `EncoderError::ValueOutOfRange` for an unsigned integer above
+/// `i64::MAX` instead of panicking.
+pub fn try_to_value<T: Encodable>(value: &T) -> EncodeResult<Value> {
+    let mut encoder = ValueEncoder::new();
+    value.encode(&mut encoder)?;
+    Ok(encoder.into_value())
+}
+
+/// Like `try_to_value`, leaving out the struct fields written as `Value::Null`, e.g. an `Option`
+/// that is `None`.
+pub fn to_value_skipping_none<T: Encodable>(value: &T) -> EncodeResult<Value> {
+    let mut encoder = ValueEncoder::new();
+    encoder.skip_none = true;
+    value.encode(&mut encoder)?;
+    Ok(encoder.into_value())
+}
+
 struct ValueEncoder {
-    stack: Vec<Value>
+    stack: Vec<Value>,
+    // leave out the struct fields written as null
+    skip_none: bool,
 }
 
 impl ValueEncoder {
     pub fn new() -> Self {
         ValueEncoder {
             stack: vec![],
+            skip_none: false,
         }
     }
 
@@ -31,7 +53,7 @@ impl ValueEncoder {
 }
 
 impl Encoder for ValueEncoder {
-    type Error = ();
+    type Error = EncoderError;
 
     // Primitive types:
     fn emit_nil(&mut self) -> Result<(), Self::Error> {
@@ -40,10 +62,13 @@ impl Encoder for ValueEncoder {
     }
 
     fn emit_usize(&mut self, v: usize) -> Result<(), Self::Error> {
-        self.emit_i64(v as i64)
+        self.emit_u64(v as u64)
     }
 
     fn emit_u64(&mut self, v: u64) -> Result<(), Self::Error> {
+        if v > i64::MAX as u64 {
+            return Err(EncoderError::ValueOutOfRange(v))
+        }
         self.emit_i64(v as i64)
     }
 
@@ -152,7 +177,7 @@ impl Encoder for ValueEncoder {
                                          f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-        try!(self.emit_str(f_name));
+        self.emit_str(f_name)?;
         self.emit_map_elt_val(f_idx, f)
     }
 
@@ -164,12 +189,12 @@ impl Encoder for ValueEncoder {
             debug_assert!(name.len() == STRUCTURE_PREFIX.len() + 1, "Invalid structure name: '{}'", name);
             // it is garanteed that the name is not empty
             let signature = *name.as_bytes().last().unwrap();
-            try!(f(self));
-            try!(self.emit_seq(len, |_| Ok(())));
+            f(self)?;
+            self.emit_seq(len, |_| Ok(()))?;
             let values = self.stack.pop().unwrap_or_else(|| panic!("Unexpected end of struct"));
 
             if let Value::List(values) = values {
-                self.stack.push(Value::Structure(signature, values));
+                self.stack.push(Value::Structure(signature.into(), values));
             } else {
                 panic!("Invalid structure data: {:?}", values);
             }
@@ -184,8 +209,15 @@ impl Encoder for ValueEncoder {
                             -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-        try!(self.emit_str(f_name));
-        f(self)
+        let start = self.stack.len();
+        self.emit_str(f_name)?;
+        f(self)?;
+
+        if self.skip_none && self.stack.len() == start + 2 && self.stack[start + 1] == Value::Null {
+            self.stack.truncate(start);
+        }
+
+        Ok(())
     }
 
     fn emit_tuple<F>(&mut self, len: usize, f: F) -> Result<(), Self::Error>
@@ -200,11 +232,26 @@ impl Encoder for ValueEncoder {
         f(self)
     }
 
-    fn emit_tuple_struct<F>(&mut self, _: &str, len: usize, f: F)
+    // newtype structs (e.g. `struct NodeId(i64)`) are written as the value they wrap
+    fn emit_tuple_struct<F>(&mut self, name: &str, len: usize, f: F)
                             -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-        self.emit_seq(len, f)
+        if name == BYTES_NAME {
+            f(self)?;
+            let bytes = match self.stack.pop() {
+                Some(Value::List(values)) => {
+                    values.iter().map(|v| v.as_integer().unwrap_or(0) as u8).collect()
+                }
+                v => panic!("Invalid bytes data: {:?}", v),
+            };
+            self.stack.push(Value::Bytes(bytes));
+            Ok(())
+        } else if len == 1 {
+            f(self)
+        } else {
+            self.emit_seq(len, f)
+        }
     }
 
     fn emit_tuple_struct_arg<F>(&mut self, _: usize, f: F)
@@ -234,9 +281,13 @@ impl Encoder for ValueEncoder {
     fn emit_seq<F>(&mut self, len: usize, f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-        try!(f(self));
+        let start = self.stack.len();
+        f(self)?;
 
-        let values = {
+        // a length of 0 is taken as unknown, so everything pushed by `f` belongs to the list
+        let values = if len == 0 {
+            self.stack.split_off(start)
+        } else {
             let mut values = vec![];
             for _ in 0..len {
                 match self.stack.pop() {
@@ -260,31 +311,23 @@ impl Encoder for ValueEncoder {
         f(self)
     }
 
-    fn emit_map<F>(&mut self, len: usize, f: F) -> Result<(), Self::Error>
+    fn emit_map<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
 
-        try!(f(self));
-
-        let len = len * 2;
-
-        let values = {
-            let mut cur_val: Option<Value> = None;
-            let mut values: BTreeMap<String, Value> = BTreeMap::new();
-            for i in 1..(len + 1) {
-                match self.stack.pop() {
-                    Some(Value::String(ref k)) if i % 2 == 0 => {
-                        match cur_val.take() {
-                            Some(v) => { values.insert(k.to_owned(), v); }
-                            _ => panic!("Invalid map key")
-                        }
-                    },
-                    Some(_) if i % 2 == 0 => panic!("Invalid map key"),
-                    Some(v) => cur_val = Some(v),
-                    _ => panic!("Unexpected end of map")
-                }
+        // everything pushed by `f` belongs to the map, so a length of 0 can be used when it is
+        // not known up front, like for sequences
+        let start = self.stack.len();
+        f(self)?;
+
+        let mut values = BTreeMap::new();
+        let mut entries = self.stack.split_off(start).into_iter();
+        while let Some(key) = entries.next() {
+            match (key, entries.next()) {
+                (Value::String(k), Some(v)) => { values.insert(k, v); }
+                (Value::String(_), None) => panic!("Unexpected end of map"),
+                _ => panic!("Invalid map key"),
             }
-            values
-        };
+        }
 
         self.stack.push(Value::Map(values));
 
@@ -307,8 +350,45 @@ impl Encoder for ValueEncoder {
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
-    use super::to_value;
-    use super::super::Value;
+    use super::{to_value, try_to_value, to_value_skipping_none};
+    use super::super::{Value, StructureSignature};
+    use ::v1::packstream::serialize::EncoderError;
+    use ::v1::testing::{Evens, EvenNames};
+
+    #[test]
+    fn unsigned_integer_out_of_range() {
+        assert_eq!(Value::Integer(i64::MAX), try_to_value(&(i64::MAX as u64)).unwrap());
+
+        match try_to_value(&vec![1u64, u64::MAX]) {
+            Err(EncoderError::ValueOutOfRange(v)) => assert_eq!(u64::MAX, v),
+            other => panic!("expected out of range error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bytes_to_value() {
+        let input = ::v1::packstream::Bytes(vec![0x00, 0xFF]);
+        assert_eq!(Value::Bytes(vec![0x00, 0xFF]), to_value(&input));
+    }
+
+    #[test]
+    fn serialize_list_of_unknown_length() {
+        let input = vec![Evens(vec![1, 2, 3, 4]), Evens(vec![])];
+
+        let expected = Value::List(vec![
+            Value::List(vec![Value::Integer(2), Value::Integer(4)]),
+            Value::List(vec![]),
+        ]);
+
+        assert_eq!(expected, to_value(&input));
+    }
+
+    #[test]
+    fn serialize_map_of_unknown_length() {
+        let expected = Value::map().entry("n2", 2).entry("n4", 4).build();
+        assert_eq!(expected, to_value(&EvenNames(vec![1, 2, 3, 4])));
+        assert_eq!(Value::map().build(), to_value(&EvenNames(vec![1])));
+    }
 
     #[test]
     fn serialize_nil() {
@@ -451,12 +531,13 @@ mod tests {
 
     #[test]
     fn encode_struct() {
-        #[derive(RustcEncodable)]
-        #[allow(non_snake_case)]
-        struct MyStruct {
-            A: u32,
-            B: f64,
-            C: String,
+        encodable_struct! {
+            #[allow(non_snake_case)]
+            struct MyStruct {
+                A: u32,
+                B: f64,
+                C: String,
+            }
         }
 
         let input = MyStruct {
@@ -485,13 +566,37 @@ mod tests {
         assert_eq!(expected, to_value(&input));
     }
 
+    #[test]
+    fn encode_newtype_struct() {
+        use rustc_serialize::{Encodable, Encoder};
+
+        struct RelId(i64);
+
+        impl Encodable for RelId {
+            fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+                e.emit_tuple_struct("RelId", 1, |e| e.emit_tuple_struct_arg(0, |e| self.0.encode(e)))
+            }
+        }
+
+        assert_eq!(Value::Integer(42), to_value(&RelId(42)));
+    }
+
     #[test]
     fn encode_enum() {
-        #[derive(RustcEncodable)]
+        use rustc_serialize::{Encodable, Encoder};
+
         enum MyEnum {
             MyVariant,
         }
 
+        impl Encodable for MyEnum {
+            fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+                e.emit_enum("MyEnum", |e| match *self {
+                    MyEnum::MyVariant => e.emit_enum_variant("MyVariant", 0, 0, |_| Ok(())),
+                })
+            }
+        }
+
         let input = MyEnum::MyVariant;
         let expected = Value::String("MyVariant".to_owned());
 
@@ -500,11 +605,24 @@ mod tests {
 
     #[test]
     fn encode_enum_tuple_variant() {
-        #[derive(RustcEncodable)]
+        use rustc_serialize::{Encodable, Encoder};
+
         enum MyEnum {
             MyVariant(u32, f64, String),
         }
 
+        impl Encodable for MyEnum {
+            fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+                e.emit_enum("MyEnum", |e| match *self {
+                    MyEnum::MyVariant(a, b, ref c) => e.emit_enum_variant("MyVariant", 0, 3, |e| {
+                        e.emit_enum_variant_arg(0, |e| a.encode(e))?;
+                        e.emit_enum_variant_arg(1, |e| b.encode(e))?;
+                        e.emit_enum_variant_arg(2, |e| c.encode(e))
+                    }),
+                })
+            }
+        }
+
         let input = MyEnum::MyVariant(1, 1.1, "A".to_owned());
         let expected = Value::List(vec![Value::Integer(1), Value::Float(1.1), Value::String("A".to_owned())]);
 
@@ -523,7 +641,7 @@ mod tests {
         impl Encodable for MyStruct {
             fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
                 e.emit_struct("__STRUCTURE__\x22", 2, |e| {
-                    try!(self.name.encode(e));
+                    self.name.encode(e)?;
                     self.value.encode(e)
                 })
             }
@@ -535,9 +653,37 @@ mod tests {
         };
 
         let expected = Value::Structure(
-            0x22, vec![Value::String("MyStruct".to_owned()), Value::Integer(42)]
+            StructureSignature::Unknown(0x22), vec![Value::String("MyStruct".to_owned()), Value::Integer(42)]
         );
 
         assert_eq!(expected, to_value(&input));
     }
+
+    codable_struct! {
+        #[derive(Debug, PartialEq)]
+        struct Person {
+            name: String,
+            nickname: Option<String>,
+            emails: Vec<Option<String>>,
+        }
+    }
+
+    #[test]
+    fn to_value_skipping_none_fields() {
+        let input = Person { name: "Alice".to_owned(), nickname: None, emails: vec![None] };
+
+        let expected = Value::map()
+            .entry("name", "Alice")
+            .entry("emails", Value::List(vec![Value::Null]))
+            .build();
+
+        let value = to_value_skipping_none(&input).unwrap();
+        assert_eq!(expected, value);
+        assert_eq!(input, ::v1::packstream::value::from_value(value).unwrap());
+
+        match to_value(&input) {
+            Value::Map(map) => assert_eq!(Some(&Value::Null), map.get("nickname")),
+            other => panic!("expected a map, got {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,175 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// The signature of a structure, named if it is one of the messages or graph structures of
+/// the protocol.
+///
+/// Signatures compare by their byte, so `Unknown(0x4E)` equals `Node`. Use `from` to get the
+/// named variant of a byte.
+#[derive(Debug, Clone, Copy)]
+pub enum StructureSignature {
+    Node,
+    Relationship,
+    UnboundRelationship,
+    Path,
+    /// `INIT`, or `HELLO` from Bolt v3, which has the same signature.
+    Init,
+    Goodbye,
+    AckFailure,
+    Reset,
+    Run,
+    DiscardAll,
+    PullAll,
+    Begin,
+    Commit,
+    Rollback,
+    Success,
+    Record,
+    Ignored,
+    Failure,
+    Unknown(u8),
+}
+
+use self::StructureSignature as Sig;
+
+impl StructureSignature {
+    pub const fn byte(self) -> u8 {
+        match self {
+            Sig::Node => 0x4E,
+            Sig::Relationship => 0x52,
+            Sig::UnboundRelationship => 0x72,
+            Sig::Path => 0x50,
+            Sig::Init => 0x01,
+            Sig::Goodbye => 0x02,
+            Sig::AckFailure => 0x0E,
+            Sig::Reset => 0x0F,
+            Sig::Run => 0x10,
+            Sig::DiscardAll => 0x2F,
+            Sig::PullAll => 0x3F,
+            Sig::Begin => 0x11,
+            Sig::Commit => 0x12,
+            Sig::Rollback => 0x13,
+            Sig::Success => 0x70,
+            Sig::Record => 0x71,
+            Sig::Ignored => 0x7E,
+            Sig::Failure => 0x7F,
+            Sig::Unknown(byte) => byte,
+        }
+    }
+}
+
+impl From<u8> for StructureSignature {
+    fn from(byte: u8) -> Self {
+        match byte {
+            0x4E => Sig::Node,
+            0x52 => Sig::Relationship,
+            0x72 => Sig::UnboundRelationship,
+            0x50 => Sig::Path,
+            0x01 => Sig::Init,
+            0x02 => Sig::Goodbye,
+            0x0E => Sig::AckFailure,
+            0x0F => Sig::Reset,
+            0x10 => Sig::Run,
+            0x2F => Sig::DiscardAll,
+            0x3F => Sig::PullAll,
+            0x11 => Sig::Begin,
+            0x12 => Sig::Commit,
+            0x13 => Sig::Rollback,
+            0x70 => Sig::Success,
+            0x71 => Sig::Record,
+            0x7E => Sig::Ignored,
+            0x7F => Sig::Failure,
+            byte => Sig::Unknown(byte),
+        }
+    }
+}
+
+impl From<StructureSignature> for u8 {
+    fn from(signature: StructureSignature) -> Self {
+        signature.byte()
+    }
+}
+
+impl PartialEq for StructureSignature {
+    fn eq(&self, other: &Self) -> bool {
+        self.byte() == other.byte()
+    }
+}
+
+impl Eq for StructureSignature {}
+
+impl PartialOrd for StructureSignature {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StructureSignature {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.byte().cmp(&other.byte())
+    }
+}
+
+impl Hash for StructureSignature {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.byte().hash(state)
+    }
+}
+
+impl fmt::Display for StructureSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Sig::Node => "NODE",
+            Sig::Relationship => "RELATIONSHIP",
+            Sig::UnboundRelationship => "UNBOUND_RELATIONSHIP",
+            Sig::Path => "PATH",
+            Sig::Init => "INIT",
+            Sig::Goodbye => "GOODBYE",
+            Sig::AckFailure => "ACK_FAILURE",
+            Sig::Reset => "RESET",
+            Sig::Run => "RUN",
+            Sig::DiscardAll => "DISCARD_ALL",
+            Sig::PullAll => "PULL_ALL",
+            Sig::Begin => "BEGIN",
+            Sig::Commit => "COMMIT",
+            Sig::Rollback => "ROLLBACK",
+            Sig::Success => "SUCCESS",
+            Sig::Record => "RECORD",
+            Sig::Ignored => "IGNORED",
+            Sig::Failure => "FAILURE",
+            Sig::Unknown(byte) => return write!(f, "0x{:02X}", byte),
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StructureSignature;
+
+    #[test]
+    fn convert_from_and_to_byte() {
+        for byte in 0..=255u8 {
+            let signature = StructureSignature::from(byte);
+            assert_eq!(byte, u8::from(signature));
+            assert_eq!(signature, StructureSignature::from(signature.byte()));
+        }
+
+        assert_eq!(StructureSignature::Node, StructureSignature::from(0x4E));
+        assert_eq!(StructureSignature::Unknown(0x58), StructureSignature::from(0x58));
+    }
+
+    #[test]
+    fn unknown_equals_named_signature_of_same_byte() {
+        assert_eq!(StructureSignature::Node, StructureSignature::Unknown(0x4E));
+        assert!(StructureSignature::Init < StructureSignature::Unknown(0x02));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!("PULL_ALL", StructureSignature::PullAll.to_string());
+        assert_eq!("UNBOUND_RELATIONSHIP", StructureSignature::from(0x72).to_string());
+        assert_eq!("0x58", StructureSignature::Unknown(0x58).to_string());
+    }
+}
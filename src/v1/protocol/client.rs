@@ -1,39 +1,81 @@
 use std::collections::BTreeMap;
 use rustc_serialize::{Encodable, Encoder};
 
-use ::v1::packstream::value::{self, Value};
+use ::v1::auth::AuthToken;
+use ::v1::packstream::value::{self, Value, Map};
+use ::v1::packstream::serialize::EncodeResult;
 
-const INIT_SIZE: usize = 1;
-const INIT_SIG: &'static str = "__STRUCTURE__\x01";
+const INIT_SIZE: usize = 2;
+const INIT_SIG: &str = "__STRUCTURE__\x01";
 
 const RUN_SIZE: usize = 2;
-const RUN_SIG: &'static str = "__STRUCTURE__\x10";
+const RUN_WITH_EXTRA_SIZE: usize = 3;
+const RUN_SIG: &str = "__STRUCTURE__\x10";
 
 const DISCARD_ALL_SIZE: usize = 0;
-const DISCARD_ALL_SIG: &'static str = "__STRUCTURE__\x2F";
+const DISCARD_ALL_SIG: &str = "__STRUCTURE__\x2F";
 
 const PULL_ALL_SIZE: usize = 0;
-const PULL_ALL_SIG: &'static str = "__STRUCTURE__\x3F";
+const PULL_ALL_SIG: &str = "__STRUCTURE__\x3F";
 
 const ACK_FAILURE_SIZE: usize = 0;
-const ACK_FAILURE_SIG: &'static str = "__STRUCTURE__\x0F";
+const ACK_FAILURE_SIG: &str = "__STRUCTURE__\x0E";
+
+const RESET_SIZE: usize = 0;
+const RESET_SIG: &str = "__STRUCTURE__\x0F";
+
+// Bolt v3
+
+const HELLO_SIZE: usize = 1;
+const HELLO_SIG: &str = "__STRUCTURE__\x01";
+
+const GOODBYE_SIZE: usize = 0;
+const GOODBYE_SIG: &str = "__STRUCTURE__\x02";
+
+const BEGIN_SIZE: usize = 1;
+const BEGIN_SIG: &str = "__STRUCTURE__\x11";
+
+const COMMIT_SIZE: usize = 0;
+const COMMIT_SIG: &str = "__STRUCTURE__\x12";
+
+const ROLLBACK_SIZE: usize = 0;
+const ROLLBACK_SIG: &str = "__STRUCTURE__\x13";
+
+// Bolt v4
+
+const PULL_SIZE: usize = 1;
+const PULL_SIG: &str = "__STRUCTURE__\x3F";
+
+const DISCARD_SIZE: usize = 1;
+const DISCARD_SIG: &str = "__STRUCTURE__\x2F";
+
+/// Value of `n` in PULL and DISCARD for all the remaining records.
+pub const ALL_RECORDS: i64 = -1;
 
 pub struct Init {
     client_name: String,
+    auth_token: Map,
 }
 
 impl Init {
     pub fn new(client_name: &str) -> Self {
         Init {
             client_name: client_name.into(),
+            auth_token: AuthToken::None.to_map(),
         }
     }
+
+    pub fn with_auth(mut self, auth: &AuthToken) -> Self {
+        self.auth_token = auth.to_map();
+        self
+    }
 }
 
 impl Encodable for Init {
     fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
         e.emit_struct(INIT_SIG, INIT_SIZE, |e| {
-            self.client_name.encode(e)
+            self.client_name.encode(e)?;
+            self.auth_token.encode(e)
         })
     }
 }
@@ -41,6 +83,7 @@ impl Encodable for Init {
 pub struct Run {
     statement: String,
     parameters: BTreeMap<String, Value>,
+    extra: Option<Map>,
 }
 
 impl Run {
@@ -48,24 +91,48 @@ impl Run {
         Run {
             statement: statement.to_owned(),
             parameters: BTreeMap::new(),
+            extra: None,
         }
     }
 
-    pub fn add_param<T: Encodable>(&mut self, name: &str, param: T) {
-        self.parameters.insert(name.to_owned(), value::to_value(&param));
+    /// Send `extra` (e.g. the access mode) as the third field, which only Bolt v3 and later
+    /// accept.
+    pub fn with_extra(mut self, extra: Map) -> Self {
+        self.extra = Some(extra);
+        self
+    }
+
+    /// Bind `param` to `name`, failing with `EncoderError::ValueOutOfRange` if it holds an
+    /// unsigned integer above `i64::MAX`.
+    pub fn add_param<T: Encodable>(&mut self, name: &str, param: T) -> EncodeResult<()> {
+        self.parameters.insert(name.to_owned(), value::try_to_value(&param)?);
+        Ok(())
+    }
+
+    pub fn with_param<T: Encodable>(mut self, name: &str, param: T) -> EncodeResult<Self> {
+        self.add_param(name, param)?;
+        Ok(self)
     }
 
-    pub fn with_param<T: Encodable>(mut self, name: &str, param: T) -> Self {
-        self.add_param(name, param);
+    /// Add every entry of `params`, e.g. a `Map` or a `HashMap<&str, i64>`.
+    pub fn with_params<I, K, V>(mut self, params: I) -> Self
+        where I: IntoIterator<Item = (K, V)>, K: Into<String>, V: Into<Value> {
+
+        self.parameters.extend(params.into_iter().map(|(k, v)| (k.into(), v.into())));
         self
     }
 }
 
 impl Encodable for Run {
     fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
-        e.emit_struct(RUN_SIG, RUN_SIZE, |e| {
-            try!(self.statement.encode(e));
-            self.parameters.encode(e)
+        let size = if self.extra.is_some() { RUN_WITH_EXTRA_SIZE } else { RUN_SIZE };
+        e.emit_struct(RUN_SIG, size, |e| {
+            self.statement.encode(e)?;
+            self.parameters.encode(e)?;
+            match self.extra {
+                Some(ref extra) => extra.encode(e),
+                None => Ok(()),
+            }
         })
     }
 }
@@ -94,6 +161,151 @@ impl Encodable for AckFailure {
     }
 }
 
+pub struct Reset;
+
+impl Encodable for Reset {
+    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_struct(RESET_SIG, RESET_SIZE, |_| Ok(()))
+    }
+}
+
+/// The Bolt v3 replacement of INIT, whose single map holds the user agent along with the
+/// fields of the auth token.
+pub struct Hello {
+    extra: Map,
+}
+
+impl Hello {
+    pub fn new(user_agent: &str) -> Self {
+        let mut extra = AuthToken::None.to_map();
+        extra.insert("user_agent".to_owned(), Value::from(user_agent));
+        Hello { extra }
+    }
+
+    pub fn with_auth(mut self, auth: &AuthToken) -> Self {
+        self.extra.retain(|k, _| k == "user_agent");
+        self.extra.extend(auth.to_map());
+        self
+    }
+}
+
+impl Encodable for Hello {
+    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_struct(HELLO_SIG, HELLO_SIZE, |e| self.extra.encode(e))
+    }
+}
+
+/// Tell the server the connection is about to be closed. The server does not reply.
+pub struct Goodbye;
+
+impl Encodable for Goodbye {
+    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_struct(GOODBYE_SIG, GOODBYE_SIZE, |_| Ok(()))
+    }
+}
+
+/// Begin an explicit transaction, with the options in `extra` (e.g. `tx_timeout`).
+pub struct Begin {
+    extra: Map,
+}
+
+impl Begin {
+    pub fn new() -> Self {
+        Begin { extra: Map::new() }
+    }
+
+    pub fn with_extra(mut self, extra: Map) -> Self {
+        self.extra = extra;
+        self
+    }
+}
+
+impl Default for Begin {
+    fn default() -> Self {
+        Begin::new()
+    }
+}
+
+impl Encodable for Begin {
+    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_struct(BEGIN_SIG, BEGIN_SIZE, |e| self.extra.encode(e))
+    }
+}
+
+pub struct Commit;
+
+impl Encodable for Commit {
+    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_struct(COMMIT_SIG, COMMIT_SIZE, |_| Ok(()))
+    }
+}
+
+pub struct Rollback;
+
+impl Encodable for Rollback {
+    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_struct(ROLLBACK_SIG, ROLLBACK_SIZE, |_| Ok(()))
+    }
+}
+
+// The single field of PULL and DISCARD.
+fn batch(n: i64, qid: Option<i64>) -> Map {
+    let mut extra = Map::new();
+    extra.insert("n".to_owned(), Value::from(n));
+    if let Some(qid) = qid {
+        extra.insert("qid".to_owned(), Value::from(qid));
+    }
+    extra
+}
+
+/// The Bolt v4 replacement of PULL_ALL, requesting the next `n` records (`ALL_RECORDS` for all
+/// of them) of a result. The reply ends with a SUCCESS whose `has_more` tells if there are more.
+pub struct Pull {
+    n: i64,
+    qid: Option<i64>,
+}
+
+impl Pull {
+    pub fn new(n: i64) -> Self {
+        Pull { n, qid: None }
+    }
+
+    /// Pull from the result of the statement `qid` of the transaction, instead of the last one.
+    pub fn with_qid(mut self, qid: i64) -> Self {
+        self.qid = Some(qid);
+        self
+    }
+}
+
+impl Encodable for Pull {
+    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_struct(PULL_SIG, PULL_SIZE, |e| batch(self.n, self.qid).encode(e))
+    }
+}
+
+/// The Bolt v4 replacement of DISCARD_ALL, like `Pull` but without transferring the records.
+pub struct Discard {
+    n: i64,
+    qid: Option<i64>,
+}
+
+impl Discard {
+    pub fn new(n: i64) -> Self {
+        Discard { n, qid: None }
+    }
+
+    pub fn with_qid(mut self, qid: i64) -> Self {
+        self.qid = Some(qid);
+        self
+    }
+}
+
+impl Encodable for Discard {
+    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_struct(DISCARD_SIG, DISCARD_SIZE, |e| batch(self.n, self.qid).encode(e))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,13 +313,16 @@ mod tests {
 
     #[test]
     fn serialize_init() {
-        let input = Init::new("MyClient/1.0".into());
+        let input = Init::new("MyClient/1.0");
 
         let result = encode(&input).unwrap();
-        let expected = vec![0xB1, 0x01, 0x8C, 0x4D,
+        let expected = vec![0xB2, 0x01, 0x8C, 0x4D,
                             0x79, 0x43, 0x6C, 0x69,
                             0x65, 0x6E, 0x74, 0x2F,
-                            0x31, 0x2E, 0x30];
+                            0x31, 0x2E, 0x30, 0xA1,
+                            0x86, 0x73, 0x63, 0x68,
+                            0x65, 0x6D, 0x65, 0x84,
+                            0x6E, 0x6F, 0x6E, 0x65];
 
         assert_eq!(expected, result);
     }
@@ -128,7 +343,8 @@ mod tests {
 
     #[test]
     fn serialize_run_with_parameters() {
-        let input = Run::new("CREATE (n {p: {v}})").with_param("v", 1);
+        assert!(Run::new("CREATE (n {p: {v}})").with_param("v", u64::MAX).is_err());
+        let input = Run::new("CREATE (n {p: {v}})").with_param("v", 1).unwrap();
 
         let result = encode(&input).unwrap();
         let expected = vec![0xB2, 0x10, 0xD0, 0x13,
@@ -142,6 +358,42 @@ mod tests {
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn serialize_run_with_parameter_map() {
+        let mut params = Map::new();
+        params.insert("v".to_owned(), Value::from(1));
+        let input = Run::new("CREATE (n {p: {v}})").with_params(params);
+
+        let expected = encode(&Run::new("CREATE (n {p: {v}})").with_param("v", 1).unwrap()).unwrap();
+        assert_eq!(expected, encode(&input).unwrap());
+    }
+
+    #[test]
+    fn serialize_run_with_extra() {
+        let mut extra = Map::new();
+        extra.insert("mode".to_owned(), Value::from("r"));
+        let result = encode(&Run::new("RETURN 1").with_extra(extra)).unwrap();
+
+        let mut expected = vec![0xB3, 0x10, 0x88];
+        expected.extend(b"RETURN 1");
+        expected.extend(&[0xA0, 0xA1, 0x84]);
+        expected.extend(b"mode");
+        expected.extend(&[0x81, 0x72]);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn serialize_run_with_parameter_hash_map() {
+        use std::collections::HashMap;
+
+        let mut params = HashMap::new();
+        params.insert("v", 1);
+        let input = Run::new("CREATE (n {p: {v}})").with_params(params);
+
+        let expected = encode(&Run::new("CREATE (n {p: {v}})").with_param("v", 1).unwrap()).unwrap();
+        assert_eq!(expected, encode(&input).unwrap());
+    }
+
     #[test]
     fn serialize_discard_all() {
         let result = encode(&DiscardAll).unwrap();
@@ -161,8 +413,70 @@ mod tests {
     #[test]
     fn serialize_ack_failure() {
         let result = encode(&AckFailure).unwrap();
+        let expected = vec![0xB0, 0x0E];
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn serialize_reset() {
+        let result = encode(&Reset).unwrap();
         let expected = vec![0xB0, 0x0F];
 
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn serialize_hello() {
+        let result = encode(&Hello::new("MyClient/1.0").with_auth(&AuthToken::basic("neo4j", "secret"))).unwrap();
+
+        let mut expected = vec![0xB1, 0x01, 0xA4, 0x8B];
+        expected.extend(b"credentials");
+        expected.push(0x86);
+        expected.extend(b"secret");
+        expected.push(0x89);
+        expected.extend(b"principal");
+        expected.push(0x85);
+        expected.extend(b"neo4j");
+        expected.push(0x86);
+        expected.extend(b"scheme");
+        expected.push(0x85);
+        expected.extend(b"basic");
+        expected.push(0x8A);
+        expected.extend(b"user_agent");
+        expected.push(0x8C);
+        expected.extend(b"MyClient/1.0");
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn serialize_begin() {
+        assert_eq!(vec![0xB1, 0x11, 0xA0], encode(&Begin::new()).unwrap());
+
+        let mut extra = Map::new();
+        extra.insert("tx_timeout".to_owned(), Value::from(1000));
+        let mut expected = vec![0xB1, 0x11, 0xA1, 0x8A];
+        expected.extend(b"tx_timeout");
+        expected.extend(&[0xC9, 0x03, 0xE8]);
+        assert_eq!(expected, encode(&Begin::new().with_extra(extra)).unwrap());
+    }
+
+    #[test]
+    fn serialize_pull_and_discard() {
+        let mut expected = vec![0xB1, 0x3F, 0xA1, 0x81, 0x6E, 0x64];
+        assert_eq!(expected, encode(&Pull::new(100)).unwrap());
+
+        expected = vec![0xB1, 0x2F, 0xA2, 0x81, 0x6E, 0xFF, 0x83];
+        expected.extend(b"qid");
+        expected.push(0x02);
+        assert_eq!(expected, encode(&Discard::new(ALL_RECORDS).with_qid(2)).unwrap());
+    }
+
+    #[test]
+    fn serialize_v3_messages_without_fields() {
+        assert_eq!(vec![0xB0, 0x02], encode(&Goodbye).unwrap());
+        assert_eq!(vec![0xB0, 0x12], encode(&Commit).unwrap());
+        assert_eq!(vec![0xB0, 0x13], encode(&Rollback).unwrap());
+    }
 }
@@ -0,0 +1,358 @@
+use std::error::Error;
+use std::fmt;
+use std::io::prelude::*;
+use std::io;
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+
+/// Magic bytes sent before the proposed versions to identify a Bolt connection.
+pub const PREAMBLE: [u8; 4] = [0x60, 0x60, 0xB0, 0x17];
+
+/// Protocol versions proposed to the server, in order of preference. Unused slots are `0`.
+///
+/// Bolt v2 (see the `v2` module) is not proposed until its structure types are implemented.
+pub const SUPPORTED_VERSIONS: [u32; 4] = [1, 0, 0, 0];
+
+pub const NO_VERSION: u32 = 0;
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    Io(io::Error),
+    NoAgreedVersion,
+    /// The server answered with a version that was not proposed.
+    Malformed(u32),
+}
+
+impl Error for HandshakeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            HandshakeError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HandshakeError::Io(ref e) => write!(f, "{}", e),
+            HandshakeError::NoAgreedVersion => write!(f, "No protocol version could be agreed with the server"),
+            HandshakeError::Malformed(v) => {
+                write!(f, "Server responded with unexpected version word 0x{:08X}, is this a Bolt server?", v)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(error: io::Error) -> Self {
+        HandshakeError::Io(error)
+    }
+}
+
+/// A protocol version as written in the handshake. From Bolt 4 a proposal also covers the
+/// `range` minor versions before `minor`, e.g. 4.4 with a range of 2 accepts 4.2 to 4.4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub range: u8,
+}
+
+impl Version {
+    pub fn new(major: u8, minor: u8) -> Self {
+        Version { major, minor, range: 0 }
+    }
+
+    pub fn with_range(mut self, range: u8) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// The version from its 32 bit word: the range, minor and major versions in the three
+    /// low bytes.
+    pub fn from_word(word: u32) -> Self {
+        Version { major: word as u8, minor: (word >> 8) as u8, range: (word >> 16) as u8 }
+    }
+
+    pub fn to_word(self) -> u32 {
+        (self.range as u32) << 16 | (self.minor as u32) << 8 | self.major as u32
+    }
+
+    /// Whether the server may answer this proposal with `version`.
+    pub fn accepts(self, version: Version) -> bool {
+        version.range == 0 && version.major == self.major
+            && version.minor <= self.minor && version.minor >= self.minor.saturating_sub(self.range)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The bytes sent by the client: the preamble and four proposed versions, in order of
+/// preference, unused slots being `NO_VERSION`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    versions: [u32; 4],
+}
+
+impl Request {
+    /// Propose the versions as their words, see `Version::to_word`.
+    pub fn new(versions: [u32; 4]) -> Self {
+        Request { versions }
+    }
+
+    /// Propose up to four `versions`, the remaining slots being left unused.
+    ///
+    /// # Panics
+    ///
+    /// If more than four versions are given.
+    pub fn from_versions(versions: &[Version]) -> Self {
+        assert!(versions.len() <= 4, "at most 4 versions can be proposed, got {}", versions.len());
+        let mut words = [NO_VERSION; 4];
+        for (word, version) in words.iter_mut().zip(versions) {
+            *word = version.to_word();
+        }
+        Request::new(words)
+    }
+
+    pub fn versions(&self) -> [u32; 4] {
+        self.versions
+    }
+
+    /// Whether `version` answers one of the proposals.
+    pub fn accepts(&self, version: u32) -> bool {
+        self.versions.iter()
+            .filter(|&&v| v != NO_VERSION)
+            .any(|&v| Version::from_word(v).accepts(Version::from_word(version)))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(PREAMBLE.len() + 4 * self.versions.len());
+        data.extend_from_slice(&PREAMBLE);
+        for v in self.versions.iter() {
+            data.write_u32::<BigEndian>(*v).unwrap();
+        }
+        data
+    }
+}
+
+/// `SUPPORTED_VERSIONS`.
+impl Default for Request {
+    fn default() -> Self {
+        Request::new(SUPPORTED_VERSIONS)
+    }
+}
+
+/// The version chosen by the server, never `NO_VERSION`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response {
+    version: u32,
+}
+
+impl Response {
+    /// Read the 4 bytes of the reply to `request`.
+    pub fn read<R: Read>(reader: &mut R, request: &Request) -> Result<Self, HandshakeError> {
+        let version = reader.read_u32::<BigEndian>()?;
+        debug!("Received handshake data: {:08X}", version);
+        Response::parse(version, request)
+    }
+
+    /// Check `version`, as answered by the server, against `request`.
+    pub fn parse(version: u32, request: &Request) -> Result<Self, HandshakeError> {
+        if version == NO_VERSION {
+            return Err(HandshakeError::NoAgreedVersion)
+        }
+
+        if !request.accepts(version) {
+            return Err(HandshakeError::Malformed(version))
+        }
+
+        Ok(Response { version })
+    }
+
+    /// The version as its word, e.g. `1` for Bolt v1 or `0x0404` for Bolt 4.4.
+    pub fn word(&self) -> u32 {
+        self.version
+    }
+
+    pub fn version(&self) -> Version {
+        Version::from_word(self.version)
+    }
+}
+
+/// Send the preamble and `SUPPORTED_VERSIONS`, returning the version chosen by the server.
+pub fn perform<S: Read + Write>(stream: &mut S) -> Result<u32, HandshakeError> {
+    propose(stream, SUPPORTED_VERSIONS)
+}
+
+/// Send the preamble and `versions`, returning the one chosen by the server.
+pub fn propose<S: Read + Write>(stream: &mut S, versions: [u32; 4]) -> Result<u32, HandshakeError> {
+    negotiate(stream, &Request::new(versions)).map(|response| response.word())
+}
+
+/// Send `request` and read the server's response.
+pub fn negotiate<S: Read + Write>(stream: &mut S, request: &Request) -> Result<Response, HandshakeError> {
+    let data = request.to_bytes();
+    debug!("Sending handshake data: {:?}", &data);
+    stream.write_all(&data)?;
+    stream.flush()?;
+
+    Response::read(stream, request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::testing::MockStream;
+
+    #[test]
+    fn sends_preamble_and_versions() {
+        let mut stream = MockStream::new().raw(&[0x00, 0x00, 0x00, 0x01]);
+        perform(&mut stream).unwrap();
+
+        assert_eq!(vec![0x60, 0x60, 0xB0, 0x17,
+                        0x00, 0x00, 0x00, 0x01,
+                        0x00, 0x00, 0x00, 0x00,
+                        0x00, 0x00, 0x00, 0x00,
+                        0x00, 0x00, 0x00, 0x00], stream.written());
+    }
+
+    #[test]
+    fn agreed_version() {
+        let mut stream = MockStream::new().raw(&[0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(1, perform(&mut stream).unwrap());
+    }
+
+    #[test]
+    fn no_agreed_version_should_fail() {
+        let mut stream = MockStream::new().raw(&[0x00, 0x00, 0x00, 0x00]);
+        match perform(&mut stream) {
+            Err(HandshakeError::NoAgreedVersion) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn garbled_version_should_fail() {
+        // an HTTP server answering the handshake
+        let mut stream = MockStream::new().raw(b"HTTP/1.1 400");
+        match perform(&mut stream) {
+            Err(HandshakeError::Malformed(0x48545450)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unproposed_version_should_fail() {
+        let mut stream = MockStream::new().raw(&[0x00, 0x00, 0x00, 0x02]);
+        match perform(&mut stream) {
+            Err(HandshakeError::Malformed(2)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn propose_several_versions() {
+        let mut stream = MockStream::new().raw(&[0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(1, propose(&mut stream, [2, 1, 0, 0]).unwrap());
+
+        assert_eq!(vec![0x60, 0x60, 0xB0, 0x17,
+                        0x00, 0x00, 0x00, 0x02,
+                        0x00, 0x00, 0x00, 0x01,
+                        0x00, 0x00, 0x00, 0x00,
+                        0x00, 0x00, 0x00, 0x00], stream.written());
+    }
+
+    #[test]
+    fn newer_version_agreed() {
+        let mut stream = MockStream::new().raw(&[0x00, 0x00, 0x00, 0x02]);
+        assert_eq!(2, propose(&mut stream, [2, 1, 0, 0]).unwrap());
+    }
+
+    #[test]
+    fn response_read_one_byte_at_a_time() {
+        use ::v1::testing::ShortReads;
+
+        let mut stream = ShortReads::new(MockStream::new().raw(&[0x00, 0x00, 0x00, 0x01]), 1);
+        assert_eq!(1, perform(&mut stream).unwrap());
+
+        let mut stream = ShortReads::new(MockStream::new().raw(&[0x00, 0x00, 0x00]), 1);
+        match perform(&mut stream) {
+            Err(HandshakeError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn short_response_should_fail() {
+        let mut stream = MockStream::new().raw(&[0x00, 0x00]);
+        match perform(&mut stream) {
+            Err(HandshakeError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn version_words() {
+        assert_eq!(1, Version::new(1, 0).to_word());
+        assert_eq!(0x0002_0404, Version::new(4, 4).with_range(2).to_word());
+        assert_eq!(Version::new(4, 4).with_range(2), Version::from_word(0x0002_0404));
+        assert_eq!("4.4", Version::new(4, 4).to_string());
+    }
+
+    #[test]
+    fn range_accepts_earlier_minor_versions() {
+        let proposed = Version::new(4, 4).with_range(2);
+        assert!(proposed.accepts(Version::new(4, 4)));
+        assert!(proposed.accepts(Version::new(4, 2)));
+        assert!(!proposed.accepts(Version::new(4, 1)));
+        assert!(!proposed.accepts(Version::new(5, 4)));
+        assert!(!Version::new(4, 0).accepts(Version::new(4, 1)));
+    }
+
+    #[test]
+    fn request_bytes() {
+        let request = Request::from_versions(&[Version::new(4, 4).with_range(3), Version::new(3, 0)]);
+        assert_eq!([0x0003_0404, 3, 0, 0], request.versions());
+        assert_eq!(vec![0x60, 0x60, 0xB0, 0x17,
+                        0x00, 0x03, 0x04, 0x04,
+                        0x00, 0x00, 0x00, 0x03,
+                        0x00, 0x00, 0x00, 0x00,
+                        0x00, 0x00, 0x00, 0x00], request.to_bytes());
+        assert_eq!(Request::new(SUPPORTED_VERSIONS), Request::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 4 versions")]
+    fn more_than_four_versions_should_panic() {
+        Request::from_versions(&[Version::new(1, 0); 5]);
+    }
+
+    #[test]
+    fn negotiate_version_in_range() {
+        let request = Request::from_versions(&[Version::new(4, 4).with_range(3)]);
+        let mut stream = MockStream::new().raw(&[0x00, 0x00, 0x02, 0x04]);
+
+        let response = negotiate(&mut stream, &request).unwrap();
+        assert_eq!(0x0204, response.word());
+        assert_eq!(Version::new(4, 2), response.version());
+        assert_eq!(request.to_bytes(), stream.written());
+    }
+
+    #[test]
+    fn parse_response() {
+        let request = Request::default();
+        assert_eq!(1, Response::parse(1, &request).unwrap().word());
+        match Response::parse(NO_VERSION, &request) {
+            Err(HandshakeError::NoAgreedVersion) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match Response::parse(0x0104, &request) {
+            Err(HandshakeError::Malformed(0x0104)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
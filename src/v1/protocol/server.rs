@@ -5,7 +5,7 @@ const SUCCESS_SIG: u8 = 0x70;
 const FAILURE_SIG: u8 = 0x7F;
 const IGNORED_SIG: u8 = 0x7E;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ServerMessage {
     Record,
     Success,
@@ -14,54 +14,140 @@ pub enum ServerMessage {
     Unknown(u8),
 }
 
+impl ServerMessage {
+    pub fn from_signature(signature: u8) -> Self {
+        match signature {
+            RECORD_SIG => ServerMessage::Record,
+            SUCCESS_SIG => ServerMessage::Success,
+            FAILURE_SIG => ServerMessage::Failure,
+            IGNORED_SIG => ServerMessage::Ignored,
+            v => ServerMessage::Unknown(v),
+        }
+    }
+
+    /// Kind of the encoded message in `data` without decoding it. Server messages have a
+    /// single field, so they always start with a tiny structure marker and the signature.
+    pub fn peek(data: &[u8]) -> Self {
+        ServerMessage::from_signature(data.get(1).cloned().unwrap_or(0))
+    }
+}
+
 #[derive(Debug)]
 pub struct Message<T: Decodable> {
-    kind: ServerMessage,
-    data: T,
+    pub kind: ServerMessage,
+    pub data: T,
 }
 
 impl<T: Decodable> Decodable for Message<T> {
     fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-        let mut msg_type = ServerMessage::Unknown(0);
-        let mut data: Option<T> = None;
-
-        try!(d.read_struct("Message", 2, |d| {
-            match try!(d.read_u8()) { // reading signature
-                RECORD_SIG => msg_type = ServerMessage::Record,
-                SUCCESS_SIG => msg_type = ServerMessage::Success,
-                FAILURE_SIG => msg_type = ServerMessage::Failure,
-                IGNORED_SIG => msg_type = ServerMessage::Ignored,
-                v @ _ => msg_type = ServerMessage::Unknown(v),
-            }
-            let result = try!(T::decode(d));
-            data = Some(result);
-            Ok(())
-        }));
-
-        Ok(Message { kind: msg_type, data: data.unwrap() })
+        d.read_struct("Message", 2, |d| {
+            let kind = ServerMessage::from_signature(d.read_u8()?); // reading signature
+            let data = T::decode(d)?;
+            Ok(Message { kind, data })
+        })
+    }
+}
+
+/// RECORD message, `fields` holds the values of one result row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record<T: Decodable> {
+    pub fields: T,
+}
+
+/// SUCCESS message, `metadata` holds the summary sent by the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Success<T: Decodable> {
+    pub metadata: T,
+}
+
+/// FAILURE message, `metadata` holds the error `code` and `message`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure<T: Decodable> {
+    pub metadata: T,
+}
+
+/// IGNORED message, sent for requests following a failure until it is acknowledged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ignored<T: Decodable> {
+    pub metadata: T,
+}
+
+// Decode the single field of a message, failing if its signature is not `signature`.
+fn decode_message<D: Decoder, T: Decodable>(d: &mut D, name: &str, signature: u8) -> Result<T, D::Error> {
+    d.read_struct(name, 2, |d| {
+        let found = d.read_u8()?;
+        if found != signature {
+            return Err(d.error(&format!("Expected {} message, found {:?}",
+                                        name, ServerMessage::from_signature(found))))
+        }
+        T::decode(d)
+    })
+}
+
+impl<T: Decodable> Decodable for Record<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        decode_message(d, "Record", RECORD_SIG).map(|fields| Record { fields })
+    }
+}
+
+impl<T: Decodable> Decodable for Success<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        decode_message(d, "Success", SUCCESS_SIG).map(|metadata| Success { metadata })
+    }
+}
+
+impl<T: Decodable> Decodable for Failure<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        decode_message(d, "Failure", FAILURE_SIG).map(|metadata| Failure { metadata })
     }
 }
 
-// #[derive(Debug, RustcDecodable)]
-// pub struct Record<T: Decodable> {
-//     pub signature: u8,
-//     pub fields: T,
-// }
-//
-// #[derive(Debug, RustcDecodable)]
-// pub struct Success<T: Decodable> {
-//     pub signature: u8,
-//     pub metadata: T,
-// }
-//
-// #[derive(Debug, RustcDecodable)]
-// pub struct Failure<T: Decodable> {
-//     pub signature: u8,
-//     pub metadata: T,
-// }
-//
-// #[derive(Debug, RustcDecodable)]
-// pub struct Ignored<T: Decodable> {
-//     pub signature: u8,
-//     pub metadata: T,
-// }
+impl<T: Decodable> Decodable for Ignored<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        decode_message(d, "Ignored", IGNORED_SIG).map(|metadata| Ignored { metadata })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+
+    use super::*;
+    use ::v1::packstream::decode;
+
+    #[test]
+    fn decode_record() {
+        let input = vec![0xB1, 0x71, 0x92, 0x01, 0x81, 0x61];
+        let result: Record<(i64, String)> = decode(&mut Cursor::new(input)).unwrap();
+        assert_eq!((1, "a".to_owned()), result.fields);
+    }
+
+    #[test]
+    fn decode_success() {
+        let input = vec![0xB1, 0x70, 0xA1, 0x81, 0x61, 0x81, 0x62];
+        let result: Success<BTreeMap<String, String>> = decode(&mut Cursor::new(input)).unwrap();
+
+        let mut expected = BTreeMap::new();
+        expected.insert("a".to_owned(), "b".to_owned());
+        assert_eq!(expected, result.metadata);
+    }
+
+    #[test]
+    fn decode_failure_and_ignored() {
+        let input = vec![0xB1, 0x7F, 0xA0];
+        let result: Failure<BTreeMap<String, String>> = decode(&mut Cursor::new(input)).unwrap();
+        assert!(result.metadata.is_empty());
+
+        let input = vec![0xB1, 0x7E, 0xA0];
+        let result: Ignored<BTreeMap<String, String>> = decode(&mut Cursor::new(input)).unwrap();
+        assert!(result.metadata.is_empty());
+    }
+
+    #[test]
+    fn decode_wrong_signature_should_fail() {
+        let input = vec![0xB1, 0x7F, 0xA0];
+        let result: Result<Success<BTreeMap<String, String>>, _> = decode(&mut Cursor::new(input));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,434 @@
+// The pieces of a cypher-shell style REPL, see `examples/repl.rs`: statements spanning several
+// lines up to a `;`, `:param` commands binding parameters, and results printed as tables.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Write as FmtWrite};
+use std::io::Write;
+use rustc_serialize::json::Json;
+
+use super::cypher::{escape_identifier, escape_string_literal};
+use super::error::GraphResult;
+use super::graph::Node;
+use super::packstream::value::{Value, Map};
+use super::session::Session;
+use super::statement::Statement;
+
+pub const HELP: &str = "\
+Enter Cypher statements ending with ';', or one of the commands:
+  :param <name> => <json>   bind a parameter, e.g. :param name => \"Alice\"
+  :params                   list the parameters bound
+  :clear                    remove all the parameters
+  :help                     show this message
+  :exit                     quit";
+
+/// What a line, or the lines entered before it, asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// A statement, without its final `;`.
+    Statement(String),
+    Param(String, Value),
+    Params,
+    Clear,
+    Help,
+    Exit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplError {
+    UnknownCommand(String),
+    /// A `:param` command not of the form `name => json`, with the reason.
+    InvalidParam(String),
+}
+
+impl fmt::Display for ReplError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplError::UnknownCommand(ref name) => write!(f, "Unknown command '{}', see :help", name),
+            ReplError::InvalidParam(ref reason) => write!(f, "Invalid parameter: {}, expected ':param name => json'", reason),
+        }
+    }
+}
+
+impl Error for ReplError {}
+
+/// The lines of the statement being entered.
+#[derive(Debug, Default)]
+pub struct Input {
+    buffer: String,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input::default()
+    }
+
+    /// Whether a statement has been started on a previous line, e.g. to change the prompt.
+    pub fn is_continuation(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// Add `line`, returning a command once one is complete: a statement at the line ending
+    /// with `;`, or a `:command` given on a line of its own.
+    pub fn push_line(&mut self, line: &str) -> Result<Option<Command>, ReplError> {
+        let line = line.trim();
+        if !self.is_continuation() && line.starts_with(':') {
+            return parse_command(line).map(Some)
+        }
+        if line.is_empty() {
+            return Ok(None)
+        }
+
+        if self.is_continuation() {
+            self.buffer.push('\n');
+        }
+        self.buffer.push_str(line);
+
+        if !line.ends_with(';') {
+            return Ok(None)
+        }
+        let statement = self.buffer.trim_end_matches(';').trim().to_owned();
+        self.buffer.clear();
+        Ok(Some(Command::Statement(statement)))
+    }
+}
+
+fn parse_command(line: &str) -> Result<Command, ReplError> {
+    let (name, args) = match line.find(char::is_whitespace) {
+        Some(idx) => (&line[..idx], line[idx..].trim()),
+        None => (line, ""),
+    };
+
+    match name {
+        ":param" => parse_param(args).map(|(name, value)| Command::Param(name, value)),
+        ":params" => Ok(Command::Params),
+        ":clear" => Ok(Command::Clear),
+        ":help" => Ok(Command::Help),
+        ":exit" | ":quit" => Ok(Command::Exit),
+        _ => Err(ReplError::UnknownCommand(name.to_owned())),
+    }
+}
+
+/// Parse `name => value`, the value being JSON, e.g. `ids => [1, 2]`.
+pub fn parse_param(args: &str) -> Result<(String, Value), ReplError> {
+    let idx = args.find("=>").ok_or_else(|| ReplError::InvalidParam("missing '=>'".to_owned()))?;
+    let name = args[..idx].trim();
+    if name.is_empty() {
+        return Err(ReplError::InvalidParam("missing name".to_owned()))
+    }
+
+    let json = Json::from_str(args[idx + 2..].trim()).map_err(|e| ReplError::InvalidParam(e.to_string()))?;
+    let value = Value::try_from(json).map_err(|e| ReplError::InvalidParam(e.to_string()))?;
+    Ok((name.to_owned(), value))
+}
+
+/// `value` as Cypher would show it, e.g. `'Alice'`, `[1, 2]` or `(:Person {name: 'Alice'})`.
+pub fn format_value(value: &Value) -> String {
+    let mut output = String::new();
+    write_value(&mut output, value);
+    output
+}
+
+fn write_value(output: &mut String, value: &Value) {
+    match *value {
+        Value::Null => output.push_str("NULL"),
+        Value::Boolean(b) => { let _ = write!(output, "{}", b); }
+        Value::Integer(n) => { let _ = write!(output, "{}", n); }
+        Value::Float(n) => { let _ = write!(output, "{:?}", n); }
+        Value::String(ref s) => output.push_str(&escape_string_literal(s)),
+        Value::Bytes(ref bytes) => {
+            output.push_str("0x");
+            for b in bytes {
+                let _ = write!(output, "{:02x}", b);
+            }
+        }
+        Value::List(ref items) => {
+            output.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 { output.push_str(", ") }
+                write_value(output, item);
+            }
+            output.push(']');
+        }
+        Value::Map(ref entries) => write_map(output, entries),
+        Value::Structure(signature, ref fields) => {
+            let _ = write!(output, "Structure({}, ", signature);
+            write_value(output, &Value::List(fields.clone()));
+            output.push(')');
+        }
+        Value::Node(ref node) => write_node(output, node),
+        Value::Relationship(ref rel) => write_relationship(output, &rel.rel_type, &rel.properties),
+        Value::Path(ref path) => {
+            // the sequence alternates the 1-based index of a relationship, negative when
+            // traversed backwards, and the index of the node reached
+            match path.start() {
+                Some(node) => write_node(output, node),
+                None => return,
+            }
+            for step in path.sequence.chunks(2) {
+                let (rel, node) = match *step {
+                    [rel, node] => (rel, node),
+                    _ => break,
+                };
+                let relationship = path.relationships.get((rel.unsigned_abs() as usize).wrapping_sub(1));
+                let node = path.nodes.get(node as usize);
+                let (relationship, node) = match (relationship, node) {
+                    (Some(r), Some(n)) => (r, n),
+                    _ => break,
+                };
+
+                output.push_str(if rel > 0 { "-" } else { "<-" });
+                write_relationship(output, &relationship.rel_type, &relationship.properties);
+                output.push_str(if rel > 0 { "->" } else { "-" });
+                write_node(output, node);
+            }
+        }
+    }
+}
+
+fn write_map(output: &mut String, entries: &Map) {
+    output.push('{');
+    for (i, (key, value)) in entries.iter().enumerate() {
+        if i > 0 { output.push_str(", ") }
+        write_name(output, key);
+        output.push_str(": ");
+        write_value(output, value);
+    }
+    output.push('}');
+}
+
+fn write_node(output: &mut String, node: &Node) {
+    output.push('(');
+    for label in &node.labels {
+        output.push(':');
+        write_name(output, label);
+    }
+    write_properties(output, &node.properties, !node.labels.is_empty());
+    output.push(')');
+}
+
+fn write_relationship(output: &mut String, rel_type: &str, properties: &Map) {
+    output.push_str("[:");
+    write_name(output, rel_type);
+    write_properties(output, properties, true);
+    output.push(']');
+}
+
+fn write_properties(output: &mut String, properties: &Map, space: bool) {
+    if properties.is_empty() {
+        return
+    }
+    if space {
+        output.push(' ');
+    }
+    write_map(output, properties);
+}
+
+// A label, type or key, in backticks unless it is a plain identifier.
+fn write_name(output: &mut String, name: &str) {
+    let plain = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if plain {
+        output.push_str(name);
+    } else {
+        output.push_str(&escape_identifier(name));
+    }
+}
+
+/// `rows` under a header of `keys`, framed like cypher-shell does.
+pub fn format_table(keys: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = keys.iter().map(|k| k.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut separator = String::from("+");
+    for width in &widths {
+        separator.push_str(&"-".repeat(width + 2));
+        separator.push('+');
+    }
+
+    let line = |cells: &[String]| {
+        let mut line = String::from("|");
+        for (width, cell) in widths.iter().zip(cells) {
+            let _ = write!(line, " {}{} |", cell, " ".repeat(width - cell.chars().count()));
+        }
+        line
+    };
+
+    let mut table = vec![separator.clone(), line(keys), separator.clone()];
+    table.extend(rows.iter().map(|row| line(row)));
+    table.push(separator);
+    table.join("\n")
+}
+
+/// Parameters bound with `:param`, sent with every statement run.
+#[derive(Debug, Default)]
+pub struct Repl {
+    params: Map,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl::default()
+    }
+
+    pub fn params(&self) -> &Map {
+        &self.params
+    }
+
+    /// Carry out `command`, writing its output to `out`. Returns `false` once asked to exit.
+    ///
+    /// A statement failing leaves the session usable, as the failure is acknowledged before
+    /// being returned.
+    pub fn execute<W: Write>(&mut self, session: &mut Session, command: Command, out: &mut W) -> GraphResult<bool> {
+        match command {
+            Command::Statement(text) => {
+                let statement = Statement::new(text).with_params(self.params.clone());
+                let mut result = session.run(statement)?;
+                let keys = result.keys().to_vec();
+                let available_after = result.result_available_after();
+
+                let mut rows = Vec::new();
+                for record in &mut result {
+                    rows.push(record?.values().iter().map(format_value).collect());
+                }
+
+                if !keys.is_empty() {
+                    writeln!(out, "{}", format_table(&keys, &rows))?;
+                }
+                write!(out, "{} row{}", rows.len(), if rows.len() == 1 { "" } else { "s" })?;
+                match available_after {
+                    Some(after) => writeln!(out, " available after {} ms", after.as_millis())?,
+                    None => writeln!(out)?,
+                }
+            }
+            Command::Param(name, value) => {
+                writeln!(out, "{} => {}", name, format_value(&value))?;
+                self.params.insert(name, value);
+            }
+            Command::Params => {
+                for (name, value) in &self.params {
+                    writeln!(out, ":param {} => {}", name, format_value(value))?;
+                }
+            }
+            Command::Clear => self.params.clear(),
+            Command::Help => writeln!(out, "{}", HELP)?,
+            Command::Exit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::v1::driver::Driver;
+    use ::v1::graph::{Node, Path, UnboundRelationship};
+    use ::v1::packstream::value::{Value, Map};
+    use ::v1::testing::{self, Step};
+
+    fn node(label: &str) -> Node {
+        Node { id: None, element_id: None, labels: vec![label.to_owned()], properties: Map::new() }
+    }
+
+    #[test]
+    fn statements_end_with_semicolon() {
+        let mut input = Input::new();
+
+        assert_eq!(Ok(None), input.push_line("MATCH (n)"));
+        assert!(input.is_continuation());
+        assert_eq!(Ok(None), input.push_line(""));
+        assert_eq!(Ok(Some(Command::Statement("MATCH (n)\nRETURN n".to_owned()))), input.push_line("RETURN n;"));
+        assert!(!input.is_continuation());
+    }
+
+    #[test]
+    fn commands_on_their_own_line() {
+        let mut input = Input::new();
+
+        assert_eq!(Ok(Some(Command::Exit)), input.push_line(" :exit "));
+        assert_eq!(Ok(Some(Command::Param("ids".to_owned(), Value::list().item(1).item(2).build()))),
+                   input.push_line(":param ids => [1, 2]"));
+        assert_eq!(Err(ReplError::UnknownCommand(":nope".to_owned())), input.push_line(":nope"));
+
+        input.push_line("RETURN").unwrap();
+        assert_eq!(Ok(Some(Command::Statement("RETURN\n:exit".to_owned()))), input.push_line(":exit;"));
+    }
+
+    #[test]
+    fn invalid_params() {
+        assert!(parse_param("name \"Alice\"").is_err());
+        assert!(parse_param("=> 1").is_err());
+        assert!(parse_param("name => Alice").is_err());
+        assert_eq!(Ok(("name".to_owned(), Value::from("Alice"))), parse_param("name => \"Alice\""));
+    }
+
+    #[test]
+    fn format_values_as_cypher() {
+        let map = Value::map().entry("name", "O'Neil").entry("first name", Value::Null).build();
+        assert_eq!("{`first name`: NULL, name: 'O\\'Neil'}", format_value(&map));
+        assert_eq!("[1, 1.0, true, 0x00ff]", format_value(&Value::list().item(1).item(1.0).item(true).item(Value::Bytes(vec![0, 255])).build()));
+
+        let mut alice = node("Person");
+        alice.properties.insert("name".to_owned(), Value::from("Alice"));
+        assert_eq!("(:Person {name: 'Alice'})", format_value(&Value::Node(alice)));
+    }
+
+    #[test]
+    fn format_path() {
+        let knows = UnboundRelationship { id: None, element_id: None, rel_type: "KNOWS".to_owned(), properties: Map::new() };
+        let path = Path { nodes: vec![node("A"), node("B"), node("C")], relationships: vec![knows], sequence: vec![1, 1, -1, 2] };
+
+        assert_eq!("(:A)-[:KNOWS]->(:B)<-[:KNOWS]-(:C)", format_value(&Value::Path(path)));
+    }
+
+    #[test]
+    fn format_table_pads_columns() {
+        let keys = vec!["n".to_owned(), "name".to_owned()];
+        let rows = vec![vec!["1".to_owned(), "'Alice'".to_owned()], vec!["10".to_owned(), "NULL".to_owned()]];
+
+        let expected = "\
++----+---------+
+| n  | name    |
++----+---------+
+| 1  | 'Alice' |
+| 10 | NULL    |
++----+---------+";
+        assert_eq!(expected, format_table(&keys, &rows));
+    }
+
+    #[test]
+    fn execute_carries_on_after_failure() {
+        let (uri, _) = testing::server_with(|socket| {
+            // RUN and PULL_ALL, for each statement
+            testing::expect(socket);
+            testing::expect(socket);
+            testing::play(socket, vec![Step::Failure("Neo.ClientError.Statement.SyntaxError"), Step::Ignored]);
+            testing::expect(socket);
+            testing::success(socket);
+
+            testing::expect(socket);
+            testing::expect(socket);
+            testing::play(socket, vec![
+                Step::Success(Value::map().entry("fields", Value::list().item("n")).entry("result_available_after", 2).build()),
+                Step::Record(Value::list().item("a").build()),
+                Step::Success(Value::map().build()),
+            ]);
+        });
+        let mut session = Driver::new(&uri).unwrap().session().unwrap();
+        let mut repl = Repl::new();
+        let mut out = Vec::new();
+
+        assert!(repl.execute(&mut session, Command::Statement("RETRUN 1".to_owned()), &mut out).is_err());
+        assert!(repl.execute(&mut session, Command::Param("n".to_owned(), Value::from("a")), &mut out).unwrap());
+        assert!(repl.execute(&mut session, Command::Statement("RETURN $n AS n".to_owned()), &mut out).unwrap());
+        assert!(!repl.execute(&mut session, Command::Exit, &mut out).unwrap());
+
+        let expected = "n => 'a'\n+-----+\n| n   |\n+-----+\n| 'a' |\n+-----+\n1 row available after 2 ms\n";
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+    }
+}
@@ -0,0 +1,660 @@
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::net::TcpStream;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+use std::time::Duration;
+use rustc_serialize::Decodable;
+
+use super::Connection;
+use super::connection::PULL_N_VERSION;
+use super::error::{GraphError, GraphResult, Neo4jError};
+use super::packstream::{encode, decode};
+use super::packstream::value::{self, Value, List, Map};
+use super::protocol::server::{Message, ServerMessage};
+use super::summary::{self, ResultSummary};
+
+/// A row of a query result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    keys: Rc<Vec<String>>,
+    values: List,
+}
+
+impl Record {
+    pub(crate) fn new(keys: Rc<Vec<String>>, values: List) -> Self {
+        Record { keys, values }
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> List {
+        self.values
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.values.get(index)
+    }
+
+    pub fn get_by_key(&self, key: &str) -> Option<&Value> {
+        self.keys.iter().position(|k| k == key).and_then(|i| self.values.get(i))
+    }
+
+    /// Decode the values into a `T`, e.g. a tuple with one element per key.
+    pub fn decode<T: Decodable>(&self) -> GraphResult<T> {
+        let data = encode(&self.values)?;
+        Ok(decode(&mut Cursor::new(data))?)
+    }
+
+    /// Decode the record into a `T` whose fields are named after the keys, e.g. a struct
+    /// `PersonRow { name: String, age: i64 }` for `RETURN p.name AS name, p.age AS age`.
+    ///
+    /// Keys without a field are ignored and fields without a key are read as null, so they
+    /// can be `Option`s.
+    pub fn to<T: Decodable>(&self) -> GraphResult<T> {
+        let map: Map = self.keys.iter().cloned().zip(self.values.iter().cloned()).collect();
+        Ok(value::from_value(Value::Map(map))?)
+    }
+}
+
+/// The records of a query, read from the connection as they are requested.
+///
+/// Records not read are discarded when the cursor is dropped, so the connection can be used
+/// for the next query.
+pub struct ResultCursor<'a, S: 'a + Read + Write = TcpStream> {
+    connection: &'a mut Connection<S>,
+    keys: Rc<Vec<String>>,
+    // metadata of the reply to RUN, until it is merged into the summary
+    metadata: Map,
+    summary: Option<ResultSummary>,
+    done: bool,
+    // from Bolt v4, the id of the statement in its transaction, sent with the next PULL
+    qid: Option<i64>,
+    // whether the records of the next batches are discarded rather than pulled
+    discarding: bool,
+    // the result of an auto-commit transaction, which can be stopped by resetting the
+    // connection, as there is no explicit transaction to roll back with it
+    auto_commit: bool,
+}
+
+impl<'a, S: Read + Write> ResultCursor<'a, S> {
+    /// A cursor over the records following the reply to RUN, whose metadata is `metadata`.
+    pub(crate) fn new(connection: &'a mut Connection<S>, metadata: Map) -> Self {
+        let keys = match metadata.get("fields") {
+            Some(Value::List(fields)) => {
+                fields.iter().filter_map(|f| f.as_string()).map(|f| f.to_owned()).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let qid = metadata.get("qid").and_then(Value::as_integer);
+        ResultCursor {
+            connection,
+            keys: Rc::new(keys),
+            metadata,
+            summary: None,
+            done: false,
+            qid,
+            discarding: false,
+            auto_commit: false,
+        }
+    }
+
+    // Mark the result as that of an auto-commit transaction.
+    pub(crate) fn auto_commit(mut self) -> Self {
+        self.auto_commit = true;
+        self
+    }
+
+    /// The names of the columns, from the `fields` of the reply to RUN.
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Time the server took before the first record was available, known from the reply to
+    /// RUN, so before any record is read. `None` if the server did not tell.
+    pub fn result_available_after(&self) -> Option<Duration> {
+        summary::available_after(&self.metadata)
+    }
+
+    /// The summary of the result, available once all the records have been read.
+    pub fn summary(&self) -> Option<&ResultSummary> {
+        self.summary.as_ref()
+    }
+
+    /// Read the next record, or `None` once all of them have been read.
+    pub fn fetch(&mut self) -> GraphResult<Option<Record>> {
+        let keys = self.keys.clone();
+        self.next_with(|data| {
+            let values = match field(data)? {
+                Value::List(values) => values,
+                _ => List::new(),
+            };
+            Ok(Record { keys, values })
+        })
+    }
+
+    /// Replace the contents of `records` with the remaining records, reusing its allocation.
+    pub fn collect_into(&mut self, records: &mut Vec<Record>) -> GraphResult<()> {
+        records.clear();
+        while let Some(record) = self.fetch()? {
+            records.push(record);
+        }
+        Ok(())
+    }
+
+    /// Replace the contents of `values` with the remaining records, each decoded from its list
+    /// of fields into a `T` (e.g. a tuple), reusing its allocation.
+    pub fn records_into_values<T: Decodable>(&mut self, values: &mut Vec<T>) -> GraphResult<()> {
+        values.clear();
+        while let Some(value) = self.next_with(|data| {
+            let message: Message<T> = decode(&mut Cursor::new(data))?;
+            Ok(message.data)
+        })? {
+            values.push(value);
+        }
+        Ok(())
+    }
+
+    /// Pass the remaining records to `handler` one at a time as they are received, so no more
+    /// than one is held in memory. Once `handler` breaks, the records left are discarded, see
+    /// `consume`.
+    pub fn for_each_record<F>(&mut self, mut handler: F) -> GraphResult<()>
+        where F: FnMut(Record) -> ControlFlow<()> {
+
+        while let Some(record) = self.fetch()? {
+            if handler(record).is_break() {
+                return self.consume()
+            }
+        }
+        Ok(())
+    }
+
+    /// Read and discard the remaining records. Those of the batch being received are read
+    /// without being decoded, and from Bolt v4 the following batches are discarded by the server
+    /// instead of being pulled.
+    pub fn consume(&mut self) -> GraphResult<()> {
+        self.discarding = true;
+        while self.next_with(|_| Ok(()))?.is_some() {}
+        Ok(())
+    }
+
+    /// Stop the result without reading the remaining records. The result has no summary
+    /// afterwards.
+    ///
+    /// Before Bolt v4 the server streams every record of a result once asked to, so the result
+    /// of `Session::run` is stopped by resetting the connection. Otherwise, and in an explicit
+    /// transaction which a RESET would roll back, the remaining records are discarded as by
+    /// `consume`.
+    pub fn cancel(&mut self) -> GraphResult<()> {
+        if self.done {
+            return Ok(())
+        }
+
+        if self.auto_commit && self.connection.version() < PULL_N_VERSION {
+            let result = self.reset();
+            self.connection.end_query(Some("cancelled".to_owned()));
+            result
+        } else {
+            self.consume()
+        }
+    }
+
+    // End the result by resetting the connection, which discards the records the server has
+    // yet to send and interrupts the query.
+    fn reset(&mut self) -> GraphResult<()> {
+        self.done = true;
+        self.connection.reset()
+    }
+
+    // Stop a result whose deadline passed, failing with `GraphError::Timeout`.
+    fn time_out(&mut self, timeout: Duration) -> GraphResult<()> {
+        self.connection.clear_deadline();
+        let error = GraphError::Timeout(timeout);
+        let result = if self.auto_commit { self.reset() } else { self.consume() };
+        if let Err(e) = result {
+            warn!("Failed to stop query after timeout: {}", e);
+        }
+        self.connection.end_query(Some(error.to_string()));
+        Err(error)
+    }
+
+    // Receive the next message, decoding it with `f` if it is a record. Any other message
+    // ends the result, except a SUCCESS with `has_more` ending a batch, after which the next
+    // one is requested. The records and the end of the result are reported to the connection
+    // timing the query.
+    fn next_with<T, F>(&mut self, f: F) -> GraphResult<Option<T>>
+        where F: FnOnce(&[u8]) -> GraphResult<T> {
+
+        if self.done {
+            return Ok(None)
+        }
+
+        match self.receive_next(f) {
+            Ok(Some(value)) => {
+                self.connection.query_record();
+                Ok(Some(value))
+            }
+            Ok(None) => {
+                self.connection.end_query(None);
+                Ok(None)
+            }
+            Err(GraphError::Timeout(timeout)) => self.time_out(timeout).map(|_| None),
+            Err(e) => {
+                self.connection.end_query(Some(e.to_string()));
+                Err(e)
+            }
+        }
+    }
+
+    fn receive_next<T, F>(&mut self, f: F) -> GraphResult<Option<T>>
+        where F: FnOnce(&[u8]) -> GraphResult<T> {
+
+        let mut data = self.connection.receive_raw()?;
+        while ServerMessage::peek(data) == ServerMessage::Success && has_more(data)? {
+            let (qid, discarding) = (self.qid, self.discarding);
+            self.connection.request_more(qid, discarding)?;
+            data = self.connection.receive_raw()?;
+        }
+
+        match ServerMessage::peek(data) {
+            ServerMessage::Record => f(data).map(Some),
+            ServerMessage::Failure => {
+                self.done = true;
+                let metadata = field(data)?.as_map().cloned().unwrap_or_default();
+                self.connection.ack_failure();
+                Err(GraphError::Neo4j(Neo4jError::from_metadata(&metadata)))
+            }
+            ServerMessage::Success => {
+                self.done = true;
+                let metadata = field(data)?.as_map().cloned().unwrap_or_default();
+                self.summary = Some(ResultSummary::new(&self.metadata, metadata));
+                Ok(None)
+            }
+            _ => {
+                self.done = true;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The result of `Session::run`, an iterator reading one record at a time from the
+/// connection, so large results are never held in memory at once.
+pub struct StatementResult<'a, S: 'a + Read + Write = TcpStream> {
+    cursor: ResultCursor<'a, S>,
+}
+
+impl<'a, S: Read + Write> StatementResult<'a, S> {
+    /// See `ResultCursor::keys`.
+    pub fn keys(&self) -> &[String] {
+        self.cursor.keys()
+    }
+
+    /// See `ResultCursor::result_available_after`.
+    pub fn result_available_after(&self) -> Option<Duration> {
+        self.cursor.result_available_after()
+    }
+
+    /// The summary of the result, available once iteration has finished.
+    pub fn summary(&self) -> Option<&ResultSummary> {
+        self.cursor.summary()
+    }
+
+    pub fn into_cursor(self) -> ResultCursor<'a, S> {
+        self.cursor
+    }
+
+    /// See `ResultCursor::for_each_record`.
+    pub fn for_each_record<F>(&mut self, handler: F) -> GraphResult<()>
+        where F: FnMut(Record) -> ControlFlow<()> {
+
+        self.cursor.for_each_record(handler)
+    }
+
+    /// See `ResultCursor::cancel`.
+    pub fn cancel(&mut self) -> GraphResult<()> {
+        self.cursor.cancel()
+    }
+}
+
+impl<'a, S: Read + Write> Iterator for StatementResult<'a, S> {
+    type Item = GraphResult<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.fetch().transpose()
+    }
+}
+
+impl<'a, S: Read + Write> IntoIterator for ResultCursor<'a, S> {
+    type Item = GraphResult<Record>;
+    type IntoIter = StatementResult<'a, S>;
+
+    fn into_iter(self) -> StatementResult<'a, S> {
+        StatementResult { cursor: self }
+    }
+}
+
+// Whether the SUCCESS in `data` ends a batch of records with more to pull.
+fn has_more(data: &[u8]) -> GraphResult<bool> {
+    Ok(field(data)?.as_map().and_then(|m| m.get("has_more")).and_then(Value::as_boolean) == Some(true))
+}
+
+// The single field of an encoded server message.
+pub(crate) fn field(data: &[u8]) -> GraphResult<Value> {
+    match Value::from_reader(&mut Cursor::new(data))? {
+        Value::Structure(_, mut fields) => Ok(fields.pop().unwrap_or(Value::Null)),
+        _ => Ok(Value::Null),
+    }
+}
+
+impl<'a, S: Read + Write> Drop for ResultCursor<'a, S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.consume() {
+            warn!("Error discarding remaining records: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use super::*;
+    use ::v1::connection::ConnectionState;
+    use ::v1::packstream::value::StructureSignature;
+    use ::v1::summary::QueryType;
+    use ::v1::testing::*;
+
+    fn reply_with_records(server: &mut TcpStream, records: &[(i64, &str)]) {
+        reply(server, SUCCESS, Value::map().entry("fields", Value::list().item("n").item("s")).build());
+        for &(n, s) in records {
+            reply(server, RECORD, Value::list().item(n).item(s).build());
+        }
+        reply(server, SUCCESS, Value::map().build());
+    }
+
+    #[test]
+    fn fetch_records() {
+        let (mut conn, mut server) = connection();
+        reply_with_records(&mut server, &[(1, "a"), (2, "b")]);
+
+        let mut cursor = conn.query("UNWIND ...").unwrap();
+        assert_eq!(&["n".to_owned(), "s".to_owned()], cursor.keys());
+
+        let record = cursor.fetch().unwrap().unwrap();
+        assert_eq!(Some(&Value::from(1)), record.get(0));
+        assert_eq!(Some(&Value::from("a")), record.get_by_key("s"));
+
+        assert!(cursor.fetch().unwrap().is_some());
+        assert!(cursor.fetch().unwrap().is_none());
+        assert!(cursor.fetch().unwrap().is_none());
+    }
+
+    #[test]
+    fn keys_and_timing_before_reading_records() {
+        let (mut conn, mut server) = connection();
+        reply(&mut server, SUCCESS, Value::map().entry("fields", Value::list().item("n")).entry("t_first", 3).build());
+        reply(&mut server, SUCCESS, Value::map().build());
+
+        let result = conn.query("RETURN 1 AS n").unwrap().into_iter();
+        assert_eq!(&["n".to_owned()], result.keys());
+        assert_eq!(Some(Duration::from_millis(3)), result.result_available_after());
+        assert_eq!(0, result.count());
+    }
+
+    #[test]
+    fn iterate_records() {
+        let (mut conn, mut server) = connection();
+        reply_with_records(&mut server, &[(1, "a"), (2, "b"), (3, "c")]);
+
+        let numbers: Vec<i64> = conn.query("UNWIND ...").unwrap().into_iter()
+            .map(|record| record.unwrap().get(0).and_then(Value::as_integer).unwrap())
+            .collect();
+
+        assert_eq!(vec![1, 2, 3], numbers);
+    }
+
+    #[test]
+    fn iterate_records_until_failure() {
+        let (mut conn, mut server) = connection();
+        reply(&mut server, SUCCESS, Value::map().entry("fields", Value::list().item("n")).build());
+        reply(&mut server, RECORD, Value::list().item(1).build());
+        failure(&mut server, "Neo.DatabaseError.General.UnknownError", "Oops");
+        success(&mut server);
+
+        let results: Vec<_> = conn.query("UNWIND ...").unwrap().into_iter().collect();
+
+        assert_eq!(2, results.len());
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn decode_record() {
+        let (mut conn, mut server) = connection();
+        reply_with_records(&mut server, &[(1, "a")]);
+
+        let record = conn.query("RETURN 1 AS n, 'a' AS s").unwrap().fetch().unwrap().unwrap();
+        let (n, s): (i64, String) = record.decode().unwrap();
+        assert_eq!((1, "a".to_owned()), (n, s));
+
+        match record.decode::<(String, i64)>() {
+            Err(GraphError::Decode(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn record_to_struct() {
+        decodable_struct! {
+            #[derive(Debug, PartialEq)]
+            struct Row { s: String, n: i64, missing: Option<i64> }
+        }
+
+        let (mut conn, mut server) = connection();
+        reply_with_records(&mut server, &[(1, "a")]);
+
+        let record = conn.query("RETURN 1 AS n, 'a' AS s").unwrap().fetch().unwrap().unwrap();
+        assert_eq!(Row { s: "a".to_owned(), n: 1, missing: None }, record.to().unwrap());
+
+        let record = Record::new(Rc::new(vec!["s".to_owned()]), vec![Value::from("a")]);
+        match record.to::<Row>() {
+            Err(GraphError::Decode(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn collect_into_reuses_vec() {
+        let (mut conn, mut server) = connection();
+        reply_with_records(&mut server, &[(1, "a"), (2, "b"), (3, "c")]);
+        reply_with_records(&mut server, &[(4, "d")]);
+
+        let mut records = Vec::new();
+        conn.query("UNWIND ...").unwrap().collect_into(&mut records).unwrap();
+        assert_eq!(3, records.len());
+        let capacity = records.capacity();
+
+        conn.query("UNWIND ...").unwrap().collect_into(&mut records).unwrap();
+        assert_eq!(1, records.len());
+        assert_eq!(vec![Value::from(4), Value::from("d")], records[0].values());
+        assert_eq!(capacity, records.capacity());
+    }
+
+    #[test]
+    fn records_into_values_decodes_fields() {
+        let (mut conn, mut server) = connection();
+        reply_with_records(&mut server, &[(1, "a"), (2, "b")]);
+        reply_with_records(&mut server, &[(3, "c")]);
+
+        let mut values: Vec<(i64, String)> = vec![(0, "x".to_owned())];
+        conn.query("UNWIND ...").unwrap().records_into_values(&mut values).unwrap();
+        assert_eq!(vec![(1, "a".to_owned()), (2, "b".to_owned())], values);
+
+        conn.query("UNWIND ...").unwrap().records_into_values(&mut values).unwrap();
+        assert_eq!(vec![(3, "c".to_owned())], values);
+    }
+
+    #[test]
+    fn for_each_record_until_break() {
+        let (mut conn, mut server) = connection();
+        reply_with_records(&mut server, &[(1, "a"), (2, "b"), (3, "c")]);
+        reply_with_records(&mut server, &[(4, "d")]);
+
+        let mut seen = Vec::new();
+        {
+            let mut cursor = conn.query("UNWIND ...").unwrap();
+            cursor.for_each_record(|record| {
+                seen.push(record.get(0).and_then(Value::as_integer).unwrap());
+                if seen.len() == 2 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+            }).unwrap();
+            assert!(cursor.summary().is_some());
+        }
+        assert_eq!(vec![1, 2], seen);
+
+        let mut seen = Vec::new();
+        conn.query("UNWIND ...").unwrap().for_each_record(|record| {
+            seen.push(record.into_values());
+            ControlFlow::Continue(())
+        }).unwrap();
+        assert_eq!(vec![vec![Value::from(4), Value::from("d")]], seen);
+        assert_eq!(0, conn.pending());
+    }
+
+    #[test]
+    fn records_are_pulled_in_batches() {
+        use ::testing::MockStream;
+        use ::v1::connection::PULL_N_VERSION;
+
+        let mut conn = MockStream::new()
+            .success(Value::map().entry("fields", Value::list().item("n")).entry("qid", 0).build())
+            .record(vec![Value::from(1)])
+            .record(vec![Value::from(2)])
+            .success(Value::map().entry("has_more", true).build())
+            .record(vec![Value::from(3)])
+            .success(Value::map().entry("type", "r").build())
+            .connection()
+            .with_version(PULL_N_VERSION);
+        conn.set_fetch_size(2);
+
+        let numbers: Vec<i64> = conn.query("UNWIND [1, 2, 3] AS n RETURN n").unwrap().into_iter()
+            .map(|record| record.unwrap().get(0).and_then(Value::as_integer).unwrap())
+            .collect();
+        assert_eq!(vec![1, 2, 3], numbers);
+
+        let requests = conn.socket().requests();
+        let batch = Value::map().entry("n", 2).build();
+        assert_eq!(Value::Structure(StructureSignature::PullAll, vec![batch]), requests[1]);
+        let batch = Value::map().entry("n", 2).entry("qid", 0).build();
+        assert_eq!(Value::Structure(StructureSignature::PullAll, vec![batch]), requests[2]);
+        assert_eq!(3, requests.len());
+        assert_eq!(0, conn.pending());
+    }
+
+    #[test]
+    fn consume_discards_remaining_batches() {
+        use ::testing::MockStream;
+        use ::v1::connection::PULL_N_VERSION;
+
+        let mut conn = MockStream::new()
+            .success(Value::map().entry("fields", Value::list().item("n")).build())
+            .record(vec![Value::from(1)])
+            .success(Value::map().entry("has_more", true).build())
+            .success(Value::map().entry("type", "r").build())
+            .connection()
+            .with_version(PULL_N_VERSION);
+        conn.set_fetch_size(1);
+
+        {
+            let mut cursor = conn.query("UNWIND range(1, 1000) AS n RETURN n").unwrap();
+            assert!(cursor.fetch().unwrap().is_some());
+            cursor.consume().unwrap();
+            assert!(cursor.summary().is_some());
+        }
+
+        let requests = conn.socket().requests();
+        let all = Value::map().entry("n", -1).build();
+        assert_eq!(Value::Structure(StructureSignature::DiscardAll, vec![all]), requests[2]);
+        assert_eq!(ConnectionState::Ready, conn.state());
+    }
+
+    #[test]
+    fn dropping_cursor_discards_remaining_records() {
+        let (mut conn, mut server) = connection();
+        reply_with_records(&mut server, &[(1, "a"), (2, "b")]);
+        reply_with_records(&mut server, &[(3, "c")]);
+
+        {
+            let mut cursor = conn.query("UNWIND ...").unwrap();
+            cursor.fetch().unwrap();
+        }
+
+        let mut records = Vec::new();
+        conn.query("UNWIND ...").unwrap().collect_into(&mut records).unwrap();
+        assert_eq!(vec![Value::from(3), Value::from("c")], records[0].values());
+    }
+
+    #[test]
+    fn summary_after_last_record() {
+        let (mut conn, mut server) = connection();
+        reply(&mut server, SUCCESS, Value::map()
+            .entry("fields", Value::list().item("n"))
+            .entry("result_available_after", 3)
+            .build());
+        reply(&mut server, RECORD, Value::list().item(1).build());
+        reply(&mut server, SUCCESS, Value::map()
+            .entry("type", "rw")
+            .entry("stats", Value::map().entry("nodes-created", 1))
+            .build());
+
+        let mut result = conn.query("CREATE (n) RETURN 1 AS n").unwrap().into_iter();
+        assert!(result.next().is_some());
+        assert!(result.summary().is_none());
+        assert!(result.next().is_none());
+
+        let summary = result.summary().unwrap();
+        assert_eq!(Some(&QueryType::ReadWrite), summary.query_type());
+        assert_eq!(1, summary.counters().nodes_created());
+        assert_eq!(Some(Duration::from_millis(3)), summary.result_available_after());
+    }
+
+    #[test]
+    fn no_summary_after_failure() {
+        let (mut conn, mut server) = connection();
+        reply(&mut server, SUCCESS, Value::map().entry("fields", Value::list().item("n")).build());
+        failure(&mut server, "Neo.DatabaseError.General.UnknownError", "Oops");
+        success(&mut server);
+
+        let mut cursor = conn.query("UNWIND ...").unwrap();
+        assert!(cursor.consume().is_err());
+        assert!(cursor.summary().is_none());
+    }
+
+    #[test]
+    fn query_failure() {
+        let (mut conn, mut server) = connection();
+        reply(&mut server, FAILURE, Value::map()
+            .entry("code", "Neo.ClientError.Statement.SyntaxError")
+            .entry("message", "Invalid input")
+            .build());
+        ignored(&mut server);
+        success(&mut server);
+
+        let result = conn.query("RETRUN 1");
+        match result {
+            Err(GraphError::Neo4j(ref e)) => {
+                assert_eq!("Neo.ClientError.Statement.SyntaxError", e.code);
+                assert_eq!("Invalid input", e.message);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("expected failure"),
+        }
+    }
+}
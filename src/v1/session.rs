@@ -0,0 +1,455 @@
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::ops::{Deref, DerefMut};
+use std::time::Instant;
+
+use super::Connection;
+use super::driver::DriverConnection;
+use super::error::{GraphError, GraphResult};
+use super::packstream::value::{Value, Map};
+use super::result::{Record, ResultCursor, StatementResult};
+use super::routing::AccessMode;
+use super::statement::Statement;
+use super::summary::ResultSummary;
+use super::transaction::{Transaction, TransactionConfig};
+
+/// First protocol version able to select the database a session works on.
+pub const MULTI_DATABASE_VERSION: u32 = 4;
+
+/// Options of a session, see `Driver::session_with_config`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionConfig {
+    database: Option<String>,
+    access_mode: AccessMode,
+}
+
+impl SessionConfig {
+    pub fn new() -> Self {
+        SessionConfig::default()
+    }
+
+    /// Work on `database`, e.g. `system`, instead of the default database of the server.
+    ///
+    /// Requires protocol version `MULTI_DATABASE_VERSION`: opening the session fails with
+    /// `GraphError::Unsupported` on older servers rather than using the default database.
+    pub fn with_database<S: Into<String>>(mut self, database: S) -> Self {
+        self.database = Some(database.into());
+        self
+    }
+
+    pub fn database(&self) -> Option<&str> {
+        self.database.as_deref()
+    }
+
+    /// Declare the kind of work done by the session, `Write` by default. Servers enforcing it
+    /// reject writes in a `Read` session, and a `RoutingDriver` sends those to readers.
+    pub fn with_access_mode(mut self, access_mode: AccessMode) -> Self {
+        self.access_mode = access_mode;
+        self
+    }
+
+    pub fn access_mode(&self) -> AccessMode {
+        self.access_mode
+    }
+
+    // The extra fields of RUN for these options, leaving out the defaults.
+    pub(crate) fn extra(&self) -> Map {
+        let mut extra = Map::new();
+        if self.access_mode == AccessMode::Read {
+            extra.insert("mode".to_owned(), Value::from("r"));
+        }
+        if let Some(ref database) = self.database {
+            extra.insert("db".to_owned(), Value::from(database.as_str()));
+        }
+        extra
+    }
+
+    // Fails if the connection cannot honour the options.
+    pub(crate) fn check<S: Read + Write>(&self, connection: &Connection<S>) -> GraphResult<()> {
+        if self.database.is_some() && connection.version() < MULTI_DATABASE_VERSION {
+            return Err(GraphError::Unsupported {
+                feature: "Database selection",
+                version: connection.version(),
+            })
+        }
+        Ok(())
+    }
+}
+
+/// A unit of work on a connection borrowed from a `Driver`'s pool.
+///
+/// The connection is given back to the pool when the session is dropped. A session over
+/// another stream, e.g. `testing::MockStream`, owns its connection.
+pub struct Session<S: Read + Write = TcpStream> {
+    // a `DriverConnection`, or a boxed connection owned by the session
+    connection: Box<dyn DerefMut<Target = Connection<S>> + Send>,
+    config: SessionConfig,
+}
+
+impl Session {
+    pub(crate) fn new(connection: DriverConnection) -> Self {
+        Session { connection: Box::new(connection), config: SessionConfig::default() }
+    }
+
+    pub(crate) fn with_config(connection: DriverConnection, config: SessionConfig) -> GraphResult<Self> {
+        config.check(&connection)?;
+        Ok(Session { connection: Box::new(connection), config })
+    }
+}
+
+impl<S: Read + Write + Send + 'static> Session<S> {
+    /// A session owning `connection`, which must be initialized.
+    pub(crate) fn over(connection: Connection<S>) -> Self {
+        Session { connection: Box::new(Box::new(connection)), config: SessionConfig::default() }
+    }
+}
+
+impl<S: Read + Write> Session<S> {
+    /// The database the session works on, `None` for the default database of the server.
+    pub fn database(&self) -> Option<&str> {
+        self.config.database()
+    }
+
+    pub fn access_mode(&self) -> AccessMode {
+        self.config.access_mode()
+    }
+
+    /// Run `statement` in an auto-commit transaction, streaming its records as the result is
+    /// iterated. Takes a `Statement` with its parameters, or a plain string.
+    ///
+    /// Waiting for the reply to RUN or reading the result fails with `GraphError::Timeout` once
+    /// the timeout of `statement` has passed, see `Statement::with_timeout`. The connection is
+    /// then reset, interrupting the query.
+    pub fn run<T: Into<Statement>>(&mut self, statement: T) -> GraphResult<StatementResult<'_, S>> {
+        self.query(statement.into()).map(|cursor| cursor.into_iter())
+    }
+
+    /// Like `run`, with `params` bound to the `{name}` placeholders of `statement`.
+    pub fn run_with_params(&mut self, statement: &str, params: Map) -> GraphResult<StatementResult<'_, S>> {
+        self.connection.query_with_extra(statement, params, self.config.extra())
+            .map(|cursor| cursor.auto_commit().into_iter())
+    }
+
+    /// Run `statement` and read all its records, for results small enough to be held in
+    /// memory. A failure is acknowledged before being returned, so the session can run the next
+    /// statement. The timeout of `statement` applies as for `run`.
+    pub fn execute<T: Into<Statement>>(&mut self, statement: T) -> GraphResult<Vec<Record>> {
+        let mut records = Vec::new();
+        self.query(statement.into())?.collect_into(&mut records)?;
+        Ok(records)
+    }
+
+    /// Like `execute`, returning only the first record, e.g. of a lookup by id. Any record
+    /// after it is discarded.
+    pub fn execute_single<T: Into<Statement>>(&mut self, statement: T) -> GraphResult<Option<Record>> {
+        let mut cursor = self.query(statement.into())?;
+        let record = cursor.fetch()?;
+        cursor.consume()?;
+        Ok(record)
+    }
+
+    // Run `statement` in an auto-commit transaction with the options of the session, timing
+    // out as set by `Statement::with_timeout`.
+    fn query(&mut self, statement: Statement) -> GraphResult<ResultCursor<'_, S>> {
+        let started = Instant::now();
+        let timeout = statement.timeout();
+        let (text, params, config) = statement.into_parts()?;
+        let mut extra = self.config.extra();
+        config.add_to_extra(&mut extra);
+        if let Some(timeout) = timeout {
+            self.connection.set_deadline(started, timeout);
+        }
+        self.connection.query_with_extra(&text, params, extra).map(ResultCursor::auto_commit)
+    }
+
+    /// Run `statement` and discard its records on the server, which is cheaper than reading
+    /// them when only the summary (e.g. update counters) is needed. The timeout of `statement`
+    /// applies as for `run`.
+    pub fn consume<T: Into<Statement>>(&mut self, statement: T) -> GraphResult<ResultSummary> {
+        let started = Instant::now();
+        let statement = statement.into();
+        let timeout = statement.timeout();
+        let (text, params, config) = statement.into_parts()?;
+        let mut extra = self.config.extra();
+        config.add_to_extra(&mut extra);
+        if let Some(timeout) = timeout {
+            self.connection.set_deadline(started, timeout);
+        }
+
+        let connection = &mut self.connection;
+        let result = connection.run_with_extra(&text, params, extra)
+            .and_then(|run| connection.discard_all().map(|metadata| ResultSummary::new(&run, metadata)));
+        if let Err(GraphError::Timeout(_)) = result {
+            if let Err(e) = connection.reset() {
+                warn!("Failed to stop query after timeout: {}", e);
+            }
+        }
+        result
+    }
+
+    /// Discard any pending results and clear a failure, see `Connection::reset`.
+    pub fn reset(&mut self) -> GraphResult<()> {
+        self.connection.reset()
+    }
+
+    /// Begin an explicit transaction, rolled back when dropped unless committed.
+    pub fn begin_transaction(&mut self) -> GraphResult<Transaction<'_, S>> {
+        self.begin_transaction_with_config(TransactionConfig::default())
+    }
+
+    /// Like `begin_transaction`, with the options in `config`.
+    pub fn begin_transaction_with_config(&mut self, config: TransactionConfig) -> GraphResult<Transaction<'_, S>> {
+        let mut extra = self.config.extra();
+        config.add_to_extra(&mut extra);
+        Transaction::begin(&mut self.connection, extra)
+    }
+}
+
+impl<S: Read + Write> Deref for Session<S> {
+    type Target = Connection<S>;
+
+    fn deref(&self) -> &Connection<S> {
+        &self.connection
+    }
+}
+
+impl<S: Read + Write> DerefMut for Session<S> {
+    fn deref_mut(&mut self) -> &mut Connection<S> {
+        &mut self.connection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use super::{Session, SessionConfig};
+    use ::testing::MockStream;
+    use ::v1::Driver;
+    use ::v1::error::GraphError;
+    use ::v1::packstream::serialize::EncoderError;
+    use ::v1::packstream::value::{Value, Map};
+    use ::v1::routing::AccessMode;
+    use ::v1::statement::Statement;
+    use ::v1::testing::{self, Step};
+    use ::v1::transaction::TransactionConfig;
+
+    const ACK_FAILURE: u8 = 0x0E;
+    const RESET: u8 = 0x0F;
+
+    #[test]
+    fn default_options_have_no_extra() {
+        let config = SessionConfig::new();
+        assert_eq!(AccessMode::Write, config.access_mode());
+        assert_eq!(None, config.database());
+        assert_eq!(Map::new(), config.extra());
+    }
+
+    #[test]
+    fn extra_of_read_session_on_database() {
+        let config = SessionConfig::new().with_access_mode(AccessMode::Read).with_database("movies");
+
+        let expected = Value::map().entry("mode", "r").entry("db", "movies").build();
+        assert_eq!(expected, Value::Map(config.extra()));
+    }
+
+    #[test]
+    fn execute_collects_records() {
+        let (uri, _) = testing::server_with(|socket| {
+            // RUN and PULL_ALL, for each statement
+            testing::expect(socket);
+            testing::expect(socket);
+            testing::play(socket, vec![
+                Step::Success(testing::fields(&["n"])),
+                Step::Record(Value::list().item(1).build()),
+                Step::Record(Value::list().item(2).build()),
+                Step::Success(Value::map().build()),
+            ]);
+
+            testing::expect(socket);
+            testing::expect(socket);
+            testing::play(socket, vec![Step::Failure("Neo.ClientError.Statement.SyntaxError"), Step::Ignored]);
+            assert_eq!(ACK_FAILURE, testing::expect(socket));
+            testing::success(socket);
+
+            testing::expect(socket);
+            testing::expect(socket);
+            testing::play(socket, vec![
+                Step::Success(testing::fields(&["n"])),
+                Step::Record(Value::list().item(3).build()),
+                Step::Record(Value::list().item(4).build()),
+                Step::Success(Value::map().build()),
+            ]);
+        });
+        let mut session = Driver::new(&uri).unwrap().session().unwrap();
+
+        let records = session.execute(Statement::new("UNWIND range(1, {n}) AS n RETURN n").param("n", 2)).unwrap();
+        let values: Vec<_> = records.iter().map(|r| r.get_by_key("n").cloned()).collect();
+        assert_eq!(vec![Some(Value::from(1)), Some(Value::from(2))], values);
+
+        match session.execute("RETRUN 1") {
+            Err(GraphError::Neo4j(ref e)) => assert_eq!("Neo.ClientError.Statement.SyntaxError", e.code),
+            other => panic!("unexpected result: {:?}", other.map(|r| r.len())),
+        }
+
+        let record = session.execute_single("UNWIND [3, 4] AS n RETURN n").unwrap().unwrap();
+        assert_eq!(Some(&Value::from(3)), record.get(0));
+        assert_eq!(0, session.pending());
+    }
+
+    #[test]
+    fn run_with_integer_out_of_range_fails() {
+        let (uri, _) = testing::server_with(|_| {});
+        let mut session = Driver::new(&uri).unwrap().session().unwrap();
+
+        match session.run(Statement::new("RETURN {n}").param("n", u64::MAX)) {
+            Err(GraphError::Encode(EncoderError::ValueOutOfRange(v))) => assert_eq!(u64::MAX, v),
+            other => panic!("unexpected result: {:?}", other.map(|r| r.keys().to_vec())),
+        }
+        assert_eq!(0, session.pending());
+    }
+
+    #[test]
+    fn execute_sends_transaction_config() {
+        let stream = MockStream::new()
+            .success(testing::fields(&["n"]))
+            .record(vec![Value::from(1)])
+            .success(Value::map().build());
+        let mut session = Session::over(stream.connection().with_version(3));
+
+        let config = TransactionConfig::new().with_metadata(vec![("app", "tests")]);
+        let records = session.execute(Statement::new("RETURN 1 AS n").with_transaction_config(config)).unwrap();
+        assert_eq!(1, records.len());
+
+        let requests = session.socket().requests();
+        let (_, fields) = requests[0].as_struct().unwrap();
+        let extra = fields[2].as_map().unwrap();
+        assert_eq!(Some(&Value::map().entry("app", "tests").build()), extra.get("tx_metadata"));
+    }
+
+    #[test]
+    fn execute_single_without_records() {
+        let (uri, _) = testing::server_with(|socket| {
+            testing::expect(socket);
+            testing::expect(socket);
+            testing::play(socket, vec![Step::Success(testing::fields(&["n"])), Step::Success(Value::map().build())]);
+        });
+        let mut session = Driver::new(&uri).unwrap().session().unwrap();
+
+        assert!(session.execute_single("MATCH (n) WHERE id(n) = -1 RETURN n").unwrap().is_none());
+    }
+
+    // Replies to a RUN and PULL_ALL streaming two records, then to a RESET interrupting them.
+    fn interrupted_result(socket: &mut ::std::net::TcpStream) {
+        testing::expect(socket);
+        testing::expect(socket);
+        testing::play(socket, vec![
+            Step::Success(testing::fields(&["n"])),
+            Step::Record(Value::list().item(1).build()),
+            Step::Record(Value::list().item(2).build()),
+        ]);
+        assert_eq!(RESET, testing::expect(socket));
+        testing::play(socket, vec![Step::Ignored, Step::Success(Value::map().build())]);
+    }
+
+    #[test]
+    fn run_times_out_and_resets_connection() {
+        let (uri, _) = testing::server_with(|socket| {
+            interrupted_result(socket);
+            testing::expect(socket);
+            testing::expect(socket);
+            testing::play(socket, vec![Step::Success(testing::fields(&["n"])), Step::Success(Value::map().build())]);
+        });
+        let mut session = Driver::new(&uri).unwrap().session().unwrap();
+
+        {
+            let statement = Statement::new("UNWIND [1, 2] AS n RETURN n").with_timeout(Duration::from_millis(200));
+            let mut result = session.run(statement).unwrap();
+            assert_eq!(Some(&Value::from(1)), result.next().unwrap().unwrap().get(0));
+
+            thread::sleep(Duration::from_millis(250));
+            match result.next() {
+                Some(Err(GraphError::Timeout(timeout))) => assert_eq!(Duration::from_millis(200), timeout),
+                other => panic!("unexpected result: {:?}", other),
+            }
+            assert!(result.next().is_none());
+            assert!(result.summary().is_none());
+        }
+
+        assert_eq!(0, session.pending());
+        assert_eq!(0, session.run("RETURN 1").unwrap().count());
+    }
+
+    #[test]
+    fn run_times_out_waiting_for_reply() {
+        let (uri, _) = testing::server_with(|socket| {
+            testing::expect(socket);
+            testing::expect(socket);
+            assert_eq!(RESET, testing::expect(socket));
+            testing::play(socket, vec![Step::Ignored, Step::Ignored, Step::Success(Value::map().build())]);
+        });
+        let mut session = Driver::new(&uri).unwrap().session().unwrap();
+
+        let started = Instant::now();
+        match session.run(Statement::new("RETURN 1").with_timeout(Duration::from_millis(100))) {
+            Err(GraphError::Timeout(timeout)) => assert_eq!(Duration::from_millis(100), timeout),
+            other => panic!("unexpected result: {:?}", other.map(|r| r.keys().to_vec())),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(0, session.pending());
+    }
+
+    #[test]
+    fn consume_times_out_waiting_for_reply() {
+        let (uri, _) = testing::server_with(|socket| {
+            // RUN is left unanswered, so DISCARD_ALL is never sent
+            testing::expect(socket);
+            assert_eq!(RESET, testing::expect(socket));
+            testing::play(socket, vec![Step::Ignored, Step::Success(Value::map().build())]);
+        });
+        let mut session = Driver::new(&uri).unwrap().session().unwrap();
+
+        let started = Instant::now();
+        match session.consume(Statement::new("RETURN 1").with_timeout(Duration::from_millis(100))) {
+            Err(GraphError::Timeout(timeout)) => assert_eq!(Duration::from_millis(100), timeout),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(0, session.pending());
+    }
+
+    #[test]
+    fn run_times_out_waiting_for_records() {
+        let (uri, _) = testing::server_with(|socket| {
+            testing::expect(socket);
+            testing::expect(socket);
+            testing::play(socket, vec![Step::Success(testing::fields(&["n"])), Step::Record(Value::list().item(1).build())]);
+            assert_eq!(RESET, testing::expect(socket));
+            testing::play(socket, vec![Step::Ignored, Step::Success(Value::map().build())]);
+        });
+        let mut session = Driver::new(&uri).unwrap().session().unwrap();
+
+        let statement = Statement::new("UNWIND [1, 2] AS n RETURN n").with_timeout(Duration::from_millis(100));
+        let mut result = session.run(statement).unwrap();
+        assert!(result.next().unwrap().is_ok());
+        match result.next() {
+            Some(Err(GraphError::Timeout(timeout))) => assert_eq!(Duration::from_millis(100), timeout),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(result.next().is_none());
+    }
+
+    #[test]
+    fn cancel_resets_connection_before_bolt_v4() {
+        let (uri, _) = testing::server_with(|socket| {
+            interrupted_result(socket);
+        });
+        let mut session = Driver::new(&uri).unwrap().session().unwrap();
+
+        let mut result = session.run("UNWIND [1, 2] AS n RETURN n").unwrap();
+        assert!(result.next().unwrap().is_ok());
+        result.cancel().unwrap();
+        assert!(result.next().is_none());
+        result.cancel().unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use rustc_serialize::Encodable;
+
+use super::error::GraphResult;
+use super::packstream::serialize::EncoderError;
+use super::packstream::value::{self, Value, Map, StructureSignature};
+use super::transaction::TransactionConfig;
+
+/// First protocol version accepting structures as parameters, e.g. the temporal types of Bolt
+/// v2. Graph structures are never accepted, as the server only sends them.
+pub const STRUCTURE_PARAMS_VERSION: u32 = 2;
+
+/// A Cypher statement and the values of its `{name}` parameters.
+///
+/// Strings convert into statements without parameters, so they can be passed wherever a
+/// `Statement` is expected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    text: String,
+    params: Map,
+    config: TransactionConfig,
+    timeout: Option<Duration>,
+    // a parameter bound to an integer above `i64::MAX`, failing the statement when it is run
+    out_of_range: Option<(String, u64)>,
+}
+
+impl Statement {
+    pub fn new<S: Into<String>>(text: S) -> Self {
+        Statement {
+            text: text.into(),
+            params: Map::new(),
+            config: TransactionConfig::default(),
+            timeout: None,
+            out_of_range: None,
+        }
+    }
+
+    /// Bind `value` to the `{name}` placeholder, replacing any value bound before.
+    ///
+    /// A value holding an unsigned integer above `i64::MAX`, which PackStream cannot send, is
+    /// not bound and makes running the statement fail with `EncoderError::ValueOutOfRange`.
+    pub fn param<T: Encodable>(mut self, name: &str, value: T) -> Self {
+        if self.out_of_range.as_ref().is_some_and(|(n, _)| n == name) {
+            self.out_of_range = None;
+        }
+
+        match value::try_to_value(&value) {
+            Ok(value) => {
+                self.params.insert(name.to_owned(), value);
+            }
+            Err(EncoderError::ValueOutOfRange(v)) => {
+                self.params.remove(name);
+                self.out_of_range.get_or_insert((name.to_owned(), v));
+            }
+            Err(e) => unreachable!("encoding into a Value only fails on integers out of range: {}", e),
+        }
+        self
+    }
+
+    /// Bind every entry of `params`, e.g. a `Map` or a `HashMap<&str, i64>`, replacing values
+    /// bound before under the same names.
+    pub fn with_params<I, K, V>(mut self, params: I) -> Self
+        where I: IntoIterator<Item = (K, V)>, K: Into<String>, V: Into<Value> {
+
+        self.params.extend(params.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Options of the auto-commit transaction the statement runs in when given to
+    /// `Session::run`, e.g. a timeout.
+    pub fn with_transaction_config(mut self, config: TransactionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Fail with `GraphError::Timeout` once `timeout` has passed since `Session::run` (or
+    /// `consume`) was called and the reply to RUN or the result is still being read, resetting
+    /// the connection to stop the query.
+    ///
+    /// The reads of the socket are bounded by the time left, so a server that stops replying
+    /// does not block the client. Unlike the timeout of `TransactionConfig`, enforced by the
+    /// server, it also bounds the time spent transferring the records.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn params(&self) -> &Map {
+        &self.params
+    }
+
+    pub fn transaction_config(&self) -> &TransactionConfig {
+        &self.config
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Check that every parameter can be sent with protocol `version`, see `validate_params`.
+    pub fn validate(&self, version: u32) -> Result<(), InvalidParameter> {
+        validate_params(&self.params, version)
+    }
+
+    // The text, parameters and options to send, failing if a parameter could not be bound.
+    pub(crate) fn into_parts(self) -> GraphResult<(String, Map, TransactionConfig)> {
+        if let Some((_, v)) = self.out_of_range {
+            return Err(EncoderError::ValueOutOfRange(v).into())
+        }
+        Ok((self.text, self.params, self.config))
+    }
+}
+
+impl<'a> From<&'a str> for Statement {
+    fn from(text: &'a str) -> Self {
+        Statement::new(text)
+    }
+}
+
+impl From<String> for Statement {
+    fn from(text: String) -> Self {
+        Statement::new(text)
+    }
+}
+
+/// A parameter the server would reject, found before the statement is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidParameter {
+    /// Where the value is in the parameters, e.g. `people[2].address`.
+    pub path: String,
+    /// What the value is, e.g. `NODE` or `structure 0x45`.
+    pub found: String,
+    pub version: u32,
+}
+
+impl fmt::Display for InvalidParameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Parameter {} is a {}, which cannot be sent with protocol version {}",
+               self.path, self.found, self.version)
+    }
+}
+
+impl Error for InvalidParameter {}
+
+/// Check that the server accepts `params` with protocol `version`, naming the first value
+/// found that it does not: nodes, relationships and paths, and other structures before
+/// `STRUCTURE_PARAMS_VERSION`.
+pub fn validate_params(params: &Map, version: u32) -> Result<(), InvalidParameter> {
+    let mut path = String::new();
+    for (name, value) in params {
+        path.clear();
+        path.push_str(name);
+        validate(value, &mut path, version)?;
+    }
+    Ok(())
+}
+
+// `path` is where `value` is, extended while visiting its items and restored after each.
+fn validate(value: &Value, path: &mut String, version: u32) -> Result<(), InvalidParameter> {
+    let items = match *value {
+        Value::List(ref items) => items,
+        Value::Map(ref entries) => {
+            let len = path.len();
+            for (key, value) in entries {
+                path.push('.');
+                path.push_str(key);
+                validate(value, path, version)?;
+                path.truncate(len);
+            }
+            return Ok(())
+        }
+        Value::Structure(StructureSignature::Unknown(_), ref fields) if version >= STRUCTURE_PARAMS_VERSION => fields,
+        Value::Structure(signature, _) => return Err(invalid(path, format!("structure {}", signature), version)),
+        Value::Node(_) => return Err(invalid(path, "NODE", version)),
+        Value::Relationship(_) => return Err(invalid(path, "RELATIONSHIP", version)),
+        Value::Path(_) => return Err(invalid(path, "PATH", version)),
+        _ => return Ok(()),
+    };
+
+    let len = path.len();
+    for (i, item) in items.iter().enumerate() {
+        path.push_str(&format!("[{}]", i));
+        validate(item, path, version)?;
+        path.truncate(len);
+    }
+    Ok(())
+}
+
+fn invalid<T: Into<String>>(path: &str, found: T, version: u32) -> InvalidParameter {
+    InvalidParameter { path: path.to_owned(), found: found.into(), version }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Statement, InvalidParameter, validate_params};
+    use ::v1::error::GraphError;
+    use ::v1::packstream::serialize::EncoderError;
+    use ::v1::graph::Node;
+    use ::v1::packstream::value::{Value, Map, StructureSignature};
+
+    #[test]
+    fn statement_with_params() {
+        let statement = Statement::new("MATCH (p:Person {name: {name}}) RETURN p")
+            .param("name", "Alice")
+            .param("age", 33);
+
+        assert_eq!("MATCH (p:Person {name: {name}}) RETURN p", statement.text());
+        assert_eq!(Some(&Value::from("Alice")), statement.params().get("name"));
+        assert_eq!(Some(&Value::Integer(33)), statement.params().get("age"));
+    }
+
+    #[test]
+    fn integer_above_i64_max_fails_when_run() {
+        let statement = Statement::new("RETURN {n}").param("n", u64::MAX).param("m", 1);
+        assert_eq!(None, statement.params().get("n"));
+        match statement.into_parts() {
+            Err(GraphError::Encode(EncoderError::ValueOutOfRange(v))) => assert_eq!(u64::MAX, v),
+            other => panic!("unexpected result: {:?}", other.map(|(text, _, _)| text)),
+        }
+
+        let statement = Statement::new("RETURN {n}").param("n", u64::MAX).param("n", 1u64);
+        assert_eq!(Some(&Value::Integer(1)), statement.params().get("n"));
+        assert!(statement.into_parts().is_ok());
+    }
+
+    #[test]
+    fn later_params_replace_earlier_ones() {
+        let mut params = Map::new();
+        params.insert("name".to_owned(), Value::from("Bob"));
+
+        let statement = Statement::new("RETURN {name}").param("name", "Alice").with_params(params);
+        assert_eq!(Some(&Value::from("Bob")), statement.params().get("name"));
+    }
+
+    #[test]
+    fn statement_with_transaction_config() {
+        use std::time::Duration;
+        use ::v1::transaction::TransactionConfig;
+
+        let config = TransactionConfig::new().with_timeout(Duration::from_secs(5));
+        let statement = Statement::new("RETURN 1").with_transaction_config(config.clone());
+        assert_eq!(&config, statement.transaction_config());
+        assert!(Statement::new("RETURN 1").transaction_config().is_empty());
+    }
+
+    #[test]
+    fn statement_from_string() {
+        let statement: Statement = "RETURN 1".into();
+        assert_eq!(Statement::new("RETURN 1"), statement);
+        assert!(statement.params().is_empty());
+
+        assert_eq!(statement, Statement::from("RETURN 1".to_owned()));
+    }
+
+    #[test]
+    fn graph_structures_are_invalid_params() {
+        let node = Node { id: Some(1), element_id: None, labels: vec![], properties: Map::new() };
+        let statement = Statement::new("CREATE (n {p: {people}})")
+            .param("name", "Alice")
+            .with_params(vec![("people", Value::list()
+                .item(Value::map().entry("name", "Bob").build())
+                .item(Value::map().entry("friend", Value::Node(node)).build())
+                .build())]);
+
+        let error = statement.validate(3).unwrap_err();
+        assert_eq!(InvalidParameter { path: "people[1].friend".to_owned(), found: "NODE".to_owned(), version: 3 }, error);
+        assert_eq!("Parameter people[1].friend is a NODE, which cannot be sent with protocol version 3", error.to_string());
+    }
+
+    #[test]
+    fn structures_need_bolt_v2() {
+        let duration = Value::Structure(StructureSignature::Unknown(0x45), vec![1.into(), 0.into(), 0.into(), 0.into()]);
+        let mut params = Map::new();
+        params.insert("d".to_owned(), Value::list().item(duration).build());
+
+        let error = validate_params(&params, 1).unwrap_err();
+        assert_eq!(("d[0]", "structure 0x45"), (error.path.as_str(), error.found.as_str()));
+        assert_eq!(Ok(()), validate_params(&params, 2));
+
+        params.insert("n".to_owned(), Value::Structure(StructureSignature::Node, vec![]));
+        assert_eq!("structure NODE", validate_params(&params, 2).unwrap_err().found);
+    }
+}
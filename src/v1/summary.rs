@@ -0,0 +1,260 @@
+use std::time::Duration;
+use rustc_serialize::{Decodable, Decoder};
+
+use super::packstream::value::{Map, Value};
+
+/// Kind of statement reported in the `type` entry of the summary metadata.
+///
+/// Values not known by the driver are kept in `Other` instead of failing to decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryType {
+    ReadOnly,
+    ReadWrite,
+    WriteOnly,
+    SchemaWrite,
+    Other(String),
+}
+
+impl QueryType {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            QueryType::ReadOnly => "r",
+            QueryType::ReadWrite => "rw",
+            QueryType::WriteOnly => "w",
+            QueryType::SchemaWrite => "s",
+            QueryType::Other(ref v) => v,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for QueryType {
+    fn from(value: &'a str) -> Self {
+        match value {
+            "r" => QueryType::ReadOnly,
+            "rw" => QueryType::ReadWrite,
+            "w" => QueryType::WriteOnly,
+            "s" => QueryType::SchemaWrite,
+            v => QueryType::Other(v.to_owned()),
+        }
+    }
+}
+
+impl Decodable for QueryType {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        d.read_str().map(|v| QueryType::from(v.as_ref()))
+    }
+}
+
+/// Update statistics reported by the server in the `stats` entry of a SUCCESS message.
+///
+/// Counters missing from the server response are reported as `0`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SummaryCounters {
+    stats: Map,
+}
+
+macro_rules! counter {
+    ($name:ident = $key:expr) => {
+        pub fn $name(&self) -> i64 {
+            self.get($key)
+        }
+    }
+}
+
+impl SummaryCounters {
+    pub fn new(stats: Map) -> Self {
+        SummaryCounters {
+            stats,
+        }
+    }
+
+    fn get(&self, key: &str) -> i64 {
+        self.stats.get(key).and_then(Value::as_integer).unwrap_or(0)
+    }
+
+    counter! { nodes_created = "nodes-created" }
+    counter! { nodes_deleted = "nodes-deleted" }
+    counter! { relationships_created = "relationships-created" }
+    counter! { relationships_deleted = "relationships-deleted" }
+    counter! { properties_set = "properties-set" }
+    counter! { labels_added = "labels-added" }
+    counter! { labels_removed = "labels-removed" }
+    counter! { indexes_added = "indexes-added" }
+    counter! { indexes_removed = "indexes-removed" }
+    counter! { constraints_added = "constraints-added" }
+    counter! { constraints_removed = "constraints-removed" }
+
+    /// Whether the statement changed anything in the database.
+    pub fn contains_updates(&self) -> bool {
+        self.stats.keys().any(|k| self.get(k) > 0)
+    }
+}
+
+impl From<Map> for SummaryCounters {
+    fn from(stats: Map) -> Self {
+        SummaryCounters::new(stats)
+    }
+}
+
+/// Time the server took before the first record of a result was available, read from the
+/// metadata of the reply to RUN: `result_available_after`, or `t_first` from Bolt v3.
+pub fn available_after(run: &Map) -> Option<Duration> {
+    millis(run, "result_available_after").or_else(|| millis(run, "t_first"))
+}
+
+// Time the server took to stream the records, `result_consumed_after` or `t_last` from Bolt v3.
+fn consumed_after(pull: &Map) -> Option<Duration> {
+    millis(pull, "result_consumed_after").or_else(|| millis(pull, "t_last"))
+}
+
+fn millis(metadata: &Map, key: &str) -> Option<Duration> {
+    metadata.get(key).and_then(Value::as_integer)
+        .filter(|&ms| ms >= 0)
+        .map(|ms| Duration::from_millis(ms as u64))
+}
+
+/// Information about a result once all of its records have been received, read from the
+/// SUCCESS messages replying to RUN and PULL_ALL.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResultSummary {
+    query_type: Option<QueryType>,
+    counters: SummaryCounters,
+    result_available_after: Option<Duration>,
+    result_consumed_after: Option<Duration>,
+    metadata: Map,
+}
+
+impl ResultSummary {
+    /// Build the summary from the metadata of the replies to RUN (`run`) and PULL_ALL (`pull`).
+    pub fn new(run: &Map, pull: Map) -> Self {
+        ResultSummary {
+            query_type: pull.get("type").and_then(Value::as_string).map(QueryType::from),
+            counters: pull.get("stats").and_then(Value::as_map).cloned().unwrap_or_default().into(),
+            result_available_after: available_after(run),
+            result_consumed_after: consumed_after(&pull),
+            metadata: pull,
+        }
+    }
+
+    pub fn query_type(&self) -> Option<&QueryType> {
+        self.query_type.as_ref()
+    }
+
+    pub fn counters(&self) -> &SummaryCounters {
+        &self.counters
+    }
+
+    /// Time the server took before the first record was available.
+    pub fn result_available_after(&self) -> Option<Duration> {
+        self.result_available_after
+    }
+
+    /// Time the server took to stream all the records.
+    pub fn result_consumed_after(&self) -> Option<Duration> {
+        self.result_consumed_after
+    }
+
+    /// All the entries of the trailing SUCCESS message, including those without an accessor.
+    pub fn metadata(&self) -> &Map {
+        &self.metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::Duration;
+    use super::{QueryType, SummaryCounters, ResultSummary};
+    use ::v1::packstream::decode;
+    use ::v1::packstream::value::{Map, Value};
+
+    #[test]
+    fn decode_query_type() {
+        let mut input = Cursor::new(vec![0x81, 0x72]);
+        let result: QueryType = decode(&mut input).unwrap();
+        assert_eq!(QueryType::ReadOnly, result);
+
+        let mut input = Cursor::new(vec![0x82, 0x72, 0x77]);
+        let result: QueryType = decode(&mut input).unwrap();
+        assert_eq!(QueryType::ReadWrite, result);
+    }
+
+    #[test]
+    fn decode_unknown_query_type() {
+        let mut input = Cursor::new(vec![0x81, 0x78]);
+        let result: QueryType = decode(&mut input).unwrap();
+        assert_eq!(QueryType::Other("x".to_owned()), result);
+        assert_eq!("x", result.as_str());
+    }
+
+    #[test]
+    fn missing_counters_default_to_zero() {
+        let counters = SummaryCounters::default();
+
+        assert_eq!(0, counters.nodes_created());
+        assert_eq!(0, counters.relationships_deleted());
+        assert!(!counters.contains_updates());
+    }
+
+    #[test]
+    fn counters_from_stats() {
+        let mut stats = Map::new();
+        stats.insert("nodes-created".to_owned(), Value::Integer(2));
+        stats.insert("properties-set".to_owned(), Value::Integer(3));
+
+        let counters = SummaryCounters::from(stats);
+
+        assert_eq!(2, counters.nodes_created());
+        assert_eq!(3, counters.properties_set());
+        assert_eq!(0, counters.labels_added());
+        assert!(counters.contains_updates());
+    }
+
+    #[test]
+    fn zero_counters_contain_no_updates() {
+        let mut stats = Map::new();
+        stats.insert("nodes-deleted".to_owned(), Value::Integer(0));
+
+        assert!(!SummaryCounters::from(stats).contains_updates());
+    }
+
+    #[test]
+    fn summary_from_metadata() {
+        let run = Value::map().entry("result_available_after", 5).build();
+        let pull = Value::map()
+            .entry("type", "w")
+            .entry("stats", Value::map().entry("nodes-created", 1))
+            .entry("result_consumed_after", 7)
+            .entry("bookmark", "bm:1")
+            .build();
+
+        let summary = ResultSummary::new(run.as_map().unwrap(), pull.as_map().cloned().unwrap());
+
+        assert_eq!(Some(&QueryType::WriteOnly), summary.query_type());
+        assert_eq!(1, summary.counters().nodes_created());
+        assert_eq!(Some(Duration::from_millis(5)), summary.result_available_after());
+        assert_eq!(Some(Duration::from_millis(7)), summary.result_consumed_after());
+        assert_eq!(Some(&Value::from("bm:1")), summary.metadata().get("bookmark"));
+    }
+
+    #[test]
+    fn summary_from_bolt_v3_metadata() {
+        let run = Value::map().entry("t_first", 5).build();
+        let pull = Value::map().entry("t_last", 7).build();
+
+        let summary = ResultSummary::new(run.as_map().unwrap(), pull.as_map().cloned().unwrap());
+
+        assert_eq!(Some(Duration::from_millis(5)), summary.result_available_after());
+        assert_eq!(Some(Duration::from_millis(7)), summary.result_consumed_after());
+    }
+
+    #[test]
+    fn summary_from_empty_metadata() {
+        let summary = ResultSummary::new(&Map::new(), Map::new());
+
+        assert_eq!(None, summary.query_type());
+        assert!(!summary.counters().contains_updates());
+        assert_eq!(None, summary.result_available_after());
+        assert_eq!(None, summary.result_consumed_after());
+    }
+}
@@ -0,0 +1,240 @@
+// Helpers for tests talking to a fake server through a local socket.
+
+use std::cell::RefCell;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream, Shutdown};
+use std::sync::{Arc, Once};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
+
+use super::Connection;
+use super::packstream::encode;
+use super::packstream::value::Value;
+
+pub const INIT: u8 = 0x01;
+pub const RECORD: u8 = 0x71;
+pub const SUCCESS: u8 = 0x70;
+pub const FAILURE: u8 = 0x7F;
+pub const IGNORED: u8 = 0x7E;
+
+/// A stream returning at most `max` bytes per `read`, as a fragmented TCP connection may.
+/// Writes are passed through.
+pub struct ShortReads<S> {
+    pub inner: S,
+    pub max: usize,
+}
+
+impl<S> ShortReads<S> {
+    pub fn new(inner: S, max: usize) -> Self {
+        ShortReads { inner, max }
+    }
+}
+
+impl<S: Read> Read for ShortReads<S> {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        let len = buf.len().min(self.max);
+        self.inner.read(&mut buf[..len])
+    }
+}
+
+impl<S: Write> Write for ShortReads<S> {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> { self.inner.write(buf) }
+    fn flush(&mut self) -> ::std::io::Result<()> { self.inner.flush() }
+}
+
+/// Emits the even numbers only, without knowing how many there are up front.
+pub struct Evens(pub Vec<i64>);
+
+impl ::rustc_serialize::Encodable for Evens {
+    fn encode<S: ::rustc_serialize::Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_seq(0, |e| {
+            for (i, v) in self.0.iter().filter(|v| *v % 2 == 0).enumerate() {
+                e.emit_seq_elt(i, |e| v.encode(e))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A map of the even numbers by their name, e.g. `n2`, without knowing how many there are up
+/// front.
+pub struct EvenNames(pub Vec<i64>);
+
+impl ::rustc_serialize::Encodable for EvenNames {
+    fn encode<S: ::rustc_serialize::Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
+        e.emit_map(0, |e| {
+            for (i, v) in self.0.iter().filter(|v| *v % 2 == 0).enumerate() {
+                e.emit_map_elt_key(i, |e| format!("n{}", v).encode(e))?;
+                e.emit_map_elt_val(i, |e| v.encode(e))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+thread_local!(static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) });
+
+// Collects the records logged by the threads capturing them, see `capture_logs`.
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _: &LogMetadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &LogRecord) {
+        CAPTURED.with(|captured| {
+            if let Some(ref mut lines) = *captured.borrow_mut() {
+                lines.push(format!("{}: {}", record.target(), record.args()));
+            }
+        });
+    }
+}
+
+/// The records up to debug level logged by the current thread while `f` runs, as
+/// `target: message`. Installs a logger for the whole test process on first use.
+pub fn capture_logs<F: FnOnce()>(f: F) -> Vec<String> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let _ = log::set_logger(|max_level| {
+            max_level.set(LogLevelFilter::Debug);
+            Box::new(CaptureLogger)
+        });
+    });
+
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+    f();
+    CAPTURED.with(|captured| captured.borrow_mut().take().unwrap_or_default())
+}
+
+/// A connection and the server side of its socket.
+pub fn connection() -> (Connection, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (Connection::new(socket), server)
+}
+
+/// Like `raw_server_with`, answering the INIT sent by the driver with SUCCESS before handing
+/// each socket to `handler`.
+pub fn server_with<F: Fn(&mut TcpStream) + Send + Sync + 'static>(handler: F) -> (String, Arc<AtomicUsize>) {
+    raw_server_with(move |socket| {
+        if try_expect(socket) == Some(INIT) {
+            success(socket);
+            handler(socket);
+        }
+    })
+}
+
+/// A server accepting connections on a local port, agreeing on protocol version 1 and handing
+/// each socket to `handler`, which is kept open until the client closes it. Returns a
+/// `bolt://` URI for the server and the number of handshakes performed.
+pub fn raw_server_with<F: Fn(&mut TcpStream) + Send + Sync + 'static>(handler: F) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri = format!("bolt://{}", listener.local_addr().unwrap());
+    let handler = Arc::new(handler);
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+
+    thread::spawn(move || {
+        for socket in listener.incoming() {
+            let mut socket = socket.unwrap();
+            let handler = handler.clone();
+            let counter = counter.clone();
+            thread::spawn(move || {
+                let mut handshake = [0u8; 20];
+                socket.read_exact(&mut handshake).unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                socket.write_all(&[0x00, 0x00, 0x00, 0x01]).unwrap();
+
+                handler(&mut socket);
+
+                let mut buf = [0u8; 1];
+                let _ = socket.read(&mut buf);
+            });
+        }
+    });
+
+    (uri, accepted)
+}
+
+/// Write a single chunk message with the given signature and field.
+pub fn reply(server: &mut TcpStream, signature: u8, field: Value) {
+    let data = encode(&Value::Structure(signature.into(), vec![field])).unwrap();
+    server.write_all(&[(data.len() >> 8) as u8, data.len() as u8]).unwrap();
+    server.write_all(&data).unwrap();
+    server.write_all(&[0x00, 0x00]).unwrap();
+}
+
+pub fn success(server: &mut TcpStream) {
+    reply(server, SUCCESS, Value::map().build());
+}
+
+pub fn ignored(server: &mut TcpStream) {
+    reply(server, IGNORED, Value::map().build());
+}
+
+pub fn failure(server: &mut TcpStream, code: &str, message: &str) {
+    reply(server, FAILURE, Value::map().entry("code", code).entry("message", message).build());
+}
+
+/// Read one message sent by the client and return its signature.
+pub fn expect(server: &mut TcpStream) -> u8 {
+    try_expect(server).expect("client closed the connection")
+}
+
+/// Like `expect`, returning `None` once the client closes the connection.
+pub fn try_expect(server: &mut TcpStream) -> Option<u8> {
+    read_message(server)?.get(1).cloned()
+}
+
+/// Read one message sent by the client and return its bytes, without chunk headers.
+pub fn read_message(server: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut message = Vec::new();
+    loop {
+        let mut header = [0u8; 2];
+        server.read_exact(&mut header).ok()?;
+        let size = ((header[0] as usize) << 8) | header[1] as usize;
+        if size == 0 { break }
+
+        let mut chunk = vec![0u8; size];
+        server.read_exact(&mut chunk).ok()?;
+        message.extend(chunk);
+    }
+    Some(message)
+}
+
+/// What the fake server does next in a scripted exchange.
+pub enum Step {
+    Success(Value),
+    Record(Value),
+    Failure(&'static str),
+    /// The reply to a request sent after a failure, until it is acknowledged.
+    Ignored,
+    /// Bytes written as they are, e.g. a truncated message.
+    Raw(Vec<u8>),
+    /// Close the socket, as a server going away mid-stream.
+    Close,
+}
+
+pub fn play(server: &mut TcpStream, script: Vec<Step>) {
+    for step in script {
+        match step {
+            Step::Success(metadata) => reply(server, SUCCESS, metadata),
+            Step::Record(fields) => reply(server, RECORD, fields),
+            Step::Failure(code) => failure(server, code, "scripted failure"),
+            Step::Ignored => ignored(server),
+            Step::Raw(bytes) => server.write_all(&bytes).unwrap(),
+            Step::Close => server.shutdown(Shutdown::Write).unwrap(),
+        }
+    }
+}
+
+pub fn fields(names: &[&str]) -> Value {
+    let mut fields = Value::list();
+    for name in names {
+        fields = fields.item(*name);
+    }
+    Value::map().entry("fields", fields).build()
+}
@@ -0,0 +1,272 @@
+use std::convert::TryFrom;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use super::Connection;
+use super::connection::TRANSACTION_MESSAGES_VERSION;
+use super::error::{GraphError, GraphResult};
+use super::packstream::value::{Value, Map};
+use super::result::ResultCursor;
+use super::statement::Statement;
+
+/// Options of a transaction, sent with BEGIN or with the RUN of an auto-commit transaction for
+/// protocol versions supporting them (see `RUN_EXTRA_VERSION`), and ignored by older ones.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransactionConfig {
+    timeout: Option<Duration>,
+    metadata: Map,
+}
+
+impl TransactionConfig {
+    pub fn new() -> Self {
+        TransactionConfig::default()
+    }
+
+    /// Have the server terminate the transaction if it runs for longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Attach every entry of `metadata`, e.g. `{"app": "billing"}`, to the transaction, as shown
+    /// by `dbms.listTransactions` and in the query log.
+    pub fn with_metadata<I, K, V>(mut self, metadata: I) -> Self
+        where I: IntoIterator<Item = (K, V)>, K: Into<String>, V: Into<Value> {
+
+        self.metadata.extend(metadata.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn metadata(&self) -> &Map {
+        &self.metadata
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timeout.is_none() && self.metadata.is_empty()
+    }
+
+    // Add the options to the extra fields of RUN or BEGIN, leaving out those not set.
+    pub(crate) fn add_to_extra(&self, extra: &mut Map) {
+        if let Some(timeout) = self.timeout {
+            let millis = timeout.as_secs() * 1000 + timeout.subsec_millis() as u64;
+            // a timeout too long to send is as good as none
+            extra.insert("tx_timeout".to_owned(), Value::Integer(i64::try_from(millis).unwrap_or(i64::MAX)));
+        }
+        if !self.metadata.is_empty() {
+            extra.insert("tx_metadata".to_owned(), Value::Map(self.metadata.clone()));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionState {
+    Open,
+    Committed,
+    RolledBack,
+}
+
+/// An explicit transaction, rolled back when dropped unless committed.
+pub struct Transaction<'a, S: 'a + Read + Write = TcpStream> {
+    connection: &'a mut Connection<S>,
+    state: TransactionState,
+}
+
+impl<'a, S: Read + Write> Transaction<'a, S> {
+    // `extra` is sent along with BEGIN, see `Connection::query_with_extra`. From Bolt v3 the
+    // transaction is begun and finished with messages rather than statements.
+    pub(crate) fn begin(connection: &'a mut Connection<S>, extra: Map) -> GraphResult<Self> {
+        if connection.version() >= TRANSACTION_MESSAGES_VERSION {
+            connection.begin(extra)?;
+        } else {
+            connection.query_with_extra("BEGIN", Map::new(), extra)?.consume()?;
+        }
+        Ok(Transaction { connection, state: TransactionState::Open })
+    }
+
+    pub fn state(&self) -> TransactionState {
+        self.state
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state == TransactionState::Open
+    }
+
+    /// Run `statement`, a `Statement` or a plain string, as part of the transaction. The
+    /// transaction options of `statement` are ignored, those given when beginning apply.
+    pub fn query<Q: Into<Statement>>(&mut self, statement: Q) -> GraphResult<ResultCursor<'_, S>> {
+        if !self.is_open() {
+            return Err(GraphError::TransactionClosed)
+        }
+
+        let (text, params, _) = statement.into().into_parts()?;
+        self.connection.query_with_params(&text, params)
+    }
+
+    pub fn commit(mut self) -> GraphResult<()> {
+        self.finish("COMMIT", TransactionState::Committed)
+    }
+
+    pub fn rollback(mut self) -> GraphResult<()> {
+        self.finish("ROLLBACK", TransactionState::RolledBack)
+    }
+
+    // The transaction is closed even if the statement fails, as the server rolls it back.
+    fn finish(&mut self, statement: &str, state: TransactionState) -> GraphResult<()> {
+        if !self.is_open() {
+            return Err(GraphError::TransactionClosed)
+        }
+
+        let result = if self.connection.version() < TRANSACTION_MESSAGES_VERSION {
+            self.connection.query(statement).and_then(|mut cursor| cursor.consume())
+        } else if state == TransactionState::Committed {
+            self.connection.commit().map(|_| ())
+        } else {
+            self.connection.rollback().map(|_| ())
+        };
+        self.state = match result {
+            Ok(_) => state,
+            Err(_) => TransactionState::RolledBack,
+        };
+        result
+    }
+}
+
+impl<'a, S: Read + Write> Drop for Transaction<'a, S> {
+    fn drop(&mut self) {
+        if self.is_open() {
+            if let Err(e) = self.finish("ROLLBACK", TransactionState::RolledBack) {
+                warn!("Error rolling back transaction: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::v1::packstream::value::Value;
+    use ::v1::testing::*;
+
+    const RUN: u8 = 0x10;
+    const PULL_ALL: u8 = 0x3F;
+
+    fn statement(server: &mut ::std::net::TcpStream) {
+        success(server);
+        success(server);
+    }
+
+    #[test]
+    fn commit_transaction() {
+        let (mut conn, mut server) = connection();
+        statement(&mut server);
+        reply(&mut server, SUCCESS, Value::map().entry("fields", Value::list().item("n")).build());
+        reply(&mut server, RECORD, Value::list().item(1).build());
+        success(&mut server);
+        statement(&mut server);
+
+        let mut tx = Transaction::begin(&mut conn, Map::new()).unwrap();
+        assert_eq!(TransactionState::Open, tx.state());
+        assert!(tx.query("RETURN 1 AS n").unwrap().fetch().unwrap().is_some());
+        tx.commit().unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(RUN, expect(&mut server));
+            assert_eq!(PULL_ALL, expect(&mut server));
+        }
+    }
+
+    #[test]
+    fn drop_rolls_back_open_transaction() {
+        let (mut conn, mut server) = connection();
+        statement(&mut server);
+        statement(&mut server);
+
+        {
+            let tx = Transaction::begin(&mut conn, Map::new()).unwrap();
+            assert!(tx.is_open());
+        }
+
+        expect(&mut server);
+        expect(&mut server);
+        assert_eq!(RUN, expect(&mut server));
+        assert_eq!(PULL_ALL, expect(&mut server));
+    }
+
+    #[test]
+    fn failed_commit_closes_transaction() {
+        let (mut conn, mut server) = connection();
+        statement(&mut server);
+        failure(&mut server, "Neo.TransientError.Transaction.DeadlockDetected", "Deadlock");
+        ignored(&mut server);
+        success(&mut server);
+
+        let tx = Transaction::begin(&mut conn, Map::new()).unwrap();
+        match tx.commit() {
+            Err(GraphError::Neo4j(ref e)) => assert!(e.is_transient()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn transaction_config_extra() {
+        let mut extra = Map::new();
+        TransactionConfig::new().add_to_extra(&mut extra);
+        assert!(extra.is_empty());
+
+        let config = TransactionConfig::new()
+            .with_timeout(Duration::from_millis(2500))
+            .with_metadata(vec![("app", "billing")]);
+        assert!(!config.is_empty());
+        config.add_to_extra(&mut extra);
+
+        let expected = Value::map()
+            .entry("tx_timeout", 2500)
+            .entry("tx_metadata", Value::map().entry("app", "billing").build())
+            .build();
+        assert_eq!(expected, Value::Map(extra));
+    }
+
+    #[test]
+    fn begin_sends_transaction_config_with_begin_message() {
+        use ::testing::MockStream;
+        use ::v1::packstream::value::StructureSignature;
+
+        let mut conn = MockStream::new()
+            .success(Value::map().build())
+            .success(Value::map().build())
+            .connection()
+            .with_version(TRANSACTION_MESSAGES_VERSION);
+
+        let mut extra = Map::new();
+        TransactionConfig::new().with_timeout(Duration::from_secs(1)).add_to_extra(&mut extra);
+        drop(Transaction::begin(&mut conn, extra).unwrap());
+
+        let requests = conn.socket().requests();
+        let (signature, fields) = requests[0].as_struct().unwrap();
+        assert_eq!(StructureSignature::Begin, signature);
+        assert_eq!(Some(&Value::Integer(1000)), fields[0].as_map().and_then(|m| m.get("tx_timeout")));
+        assert_eq!(Some((StructureSignature::Rollback, 0)), requests[1].as_struct().map(|(s, f)| (s, f.len())));
+    }
+
+    #[test]
+    fn commit_with_commit_message() {
+        use ::testing::MockStream;
+        use ::v1::packstream::value::StructureSignature;
+
+        let mut conn = MockStream::new()
+            .success(Value::map().build())
+            .success(Value::map().entry("bookmark", "bm:1").build())
+            .connection()
+            .with_version(TRANSACTION_MESSAGES_VERSION);
+
+        Transaction::begin(&mut conn, Map::new()).unwrap().commit().unwrap();
+
+        let signatures: Vec<_> = conn.socket().requests().iter().map(|r| r.as_struct().unwrap().0).collect();
+        assert_eq!(vec![StructureSignature::Begin, StructureSignature::Commit], signatures);
+    }
+}
@@ -1,107 +1,577 @@
+use std::cmp;
 use std::io::prelude::*;
 use std::io::{self, Cursor};
 use std::net::TcpStream;
+use std::time::Instant;
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 
+use super::error::{GraphResult, ProtocolViolation};
+use super::metrics::ConnectionMetrics;
+use super::packstream::value::StructureSignature;
+
 const MAX_CHUNK_SIZE: usize = 65535;
+const CHUNK_HEADER_SIZE: usize = 2;
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_BUFFERED_OUTPUT: usize = 1024 * 1024;
+
+/// When the messages written to a `ChunkedStream` are sent by `send_due`. Whatever the policy,
+/// the messages waiting are sent in a single write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Send every message once it is complete.
+    #[default]
+    PerMessage,
+    /// Leave the messages to be sent when a reply is awaited, or by an explicit `send`.
+    PerSync,
+    /// Send once at least this many bytes are waiting, like `PerSync` otherwise.
+    SizeThreshold(usize),
+}
 
-pub struct ChunkedStream {
-    socket: TcpStream,
+/// Bolt chunking over `S`, a `TcpStream` unless connected through `::handshake`. Any stream
+/// can be wrapped, e.g. a TLS stream or an in-memory one in tests.
+pub struct ChunkedStream<S: Read + Write = TcpStream> {
+    socket: S,
     raw: Cursor<Vec<u8>>,
     output_buffer: Vec<u8>,
     output_size: usize,
+    input_buffer: Vec<u8>,
+    max_message_size: usize,
+    max_buffered_output: usize,
+    flush_policy: FlushPolicy,
+    metrics: ConnectionMetrics,
+    // complete chunks in `raw`, counted as sent once they are
+    chunks_waiting: u64,
+    // whether requests were sent since the last message received, making the next one a new
+    // round trip
+    awaiting_reply: bool,
 }
 
+/// Chunking over a plain TCP socket, as opened by `::connect`.
+pub type TcpChunkedStream = ChunkedStream<TcpStream>;
+
 // based on https://github.com/neo4j/neo4j-python-driver/blob/1.0/neo4j/v1/connection.py
-impl ChunkedStream {
-    pub fn new(socket: TcpStream) -> Self {
+impl<S: Read + Write> ChunkedStream<S> {
+    pub fn new(socket: S) -> Self {
         ChunkedStream {
-            socket: socket,
+            socket,
             raw: Cursor::new(Vec::new()),
-            output_buffer: Vec::new(),
+            output_buffer: vec![0u8; CHUNK_HEADER_SIZE],
             output_size: 0,
+            input_buffer: Vec::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_buffered_output: DEFAULT_MAX_BUFFERED_OUTPUT,
+            flush_policy: FlushPolicy::default(),
+            metrics: ConnectionMetrics::default(),
+            chunks_waiting: 0,
+            awaiting_reply: false,
         }
     }
 
+    pub fn socket(&self) -> &S {
+        &self.socket
+    }
+
+    pub fn socket_mut(&mut self) -> &mut S {
+        &mut self.socket
+    }
+
+    /// Limit the size of an assembled message, protecting against unbounded data from the server.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Send the chunks written once more than `max_buffered_output` bytes of them are waiting,
+    /// whatever the flush policy, so a large message is not held in memory twice.
+    pub fn set_max_buffered_output(&mut self, max_buffered_output: usize) {
+        self.max_buffered_output = max_buffered_output;
+    }
+
+    pub fn max_buffered_output(&self) -> usize {
+        self.max_buffered_output
+    }
+
+    pub fn set_flush_policy(&mut self, flush_policy: FlushPolicy) {
+        self.flush_policy = flush_policy;
+    }
+
+    pub fn flush_policy(&self) -> FlushPolicy {
+        self.flush_policy
+    }
+
+    /// Bytes and chunks sent and received, round trips and time spent on the socket, and the
+    /// messages received. Messages sent are counted by the `Connection`, as they reach the
+    /// stream in pieces.
+    pub fn metrics(&self) -> &ConnectionMetrics {
+        &self.metrics
+    }
+
+    pub(crate) fn metrics_mut(&mut self) -> &mut ConnectionMetrics {
+        &mut self.metrics
+    }
+
+    /// Number of bytes written but not yet sent.
+    pub fn pending(&self) -> usize {
+        self.output_size + self.raw.get_ref().len()
+    }
+
+    /// Discard everything written but not yet sent.
+    pub fn clear(&mut self) {
+        self.output_buffer.truncate(CHUNK_HEADER_SIZE);
+        self.output_size = 0;
+        self.raw.get_mut().clear();
+        self.raw.set_position(0);
+        self.chunks_waiting = 0;
+    }
+
     pub fn raw(&self) -> &[u8] {
         self.raw.get_ref()
     }
 
-    pub fn write(&mut self, buf: &[u8]) -> io::Result<()> {
-        let mut b = buf;
+    /// Add `buf` to the message being written, completing a chunk every `MAX_CHUNK_SIZE` bytes.
+    pub fn write(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let room = MAX_CHUNK_SIZE - self.output_size;
+            let (now, rest) = buf.split_at(cmp::min(room, buf.len()));
+            self.output_buffer.extend_from_slice(now);
+            self.output_size += now.len();
+            buf = rest;
 
-        loop {
-            let size = b.len();
-            let future_size = self.output_size + size;
-
-            if future_size >= MAX_CHUNK_SIZE {
-                let end = MAX_CHUNK_SIZE - self.output_size;
-                for i in b[0..end].iter() {
-                    self.output_buffer.push(*i);
-                }
-                self.output_size = MAX_CHUNK_SIZE;
-                b = &b[end..size];
-                try!(self.flush(false));
-            } else {
-                for i in b.iter() {
-                    self.output_buffer.push(*i);
-                    self.output_size = future_size;
-                }
-                break
+            if self.output_size == MAX_CHUNK_SIZE {
+                self.flush(false)?;
             }
         }
 
         Ok(())
     }
 
+    // The first bytes of `output_buffer` are reserved for the chunk header, which is filled in
+    // here so the header and body are copied to `raw` in a single write. Complete chunks are
+    // sent as soon as they exceed the cap on buffered output, even halfway through a message.
     pub fn flush(&mut self, end_of_message: bool) -> io::Result<()> {
-        let mut buf: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        if self.output_size > 0 {
+            (&mut self.output_buffer[0..CHUNK_HEADER_SIZE])
+                .write_u16::<BigEndian>(self.output_size as u16)?;
+            self.raw.write_all(&self.output_buffer)?;
 
-        if self.output_buffer.len() > 0 {
-            try!(buf.write_u16::<BigEndian>(self.output_size as u16));
-            try!(buf.write_all(self.output_buffer.as_ref()));
+            self.output_buffer.truncate(CHUNK_HEADER_SIZE);
+            self.output_size = 0;
+            self.chunks_waiting += 1;
         }
 
         if end_of_message {
-            try!(buf.write(&[0x00, 0x00]));
+            self.raw.write_all(&[0x00, 0x00])?;
         }
 
-        if buf.get_ref().len() > 0 {
-            try!(self.raw.write_all(buf.into_inner().as_ref()));
-            try!(self.raw.flush());
-
-            self.output_buffer.clear();
-            self.output_size = 0;
+        if self.raw.get_ref().len() > self.max_buffered_output {
+            self.send()?;
         }
 
         Ok(())
     }
 
     pub fn send(&mut self) -> io::Result<()> {
-        try!(self.socket.write_all(self.raw.get_ref()));
+        let started = Instant::now();
+        let result = self.socket.write_all(self.raw.get_ref()).and_then(|_| self.socket.flush());
+        self.metrics.write_time += started.elapsed();
+        result?;
 
-        debug!("C:{}", self.raw.get_ref().iter().fold(
-            String::new(), |acc, i| format!("{} {:02X}", acc, i)
-        ));
+        let sent = self.raw.get_ref().len() as u64;
+        self.metrics.bytes_sent += sent;
+        self.metrics.chunks_sent += self.chunks_waiting;
+        self.awaiting_reply |= sent > 0;
 
         self.raw.get_mut().clear();
         self.raw.set_position(0);
+        self.chunks_waiting = 0;
         Ok(())
     }
 
-    pub fn receive(&mut self) -> io::Result<Vec<u8>> {
-        let mut result: Vec<u8> = Vec::new();
+    /// Send the complete messages written so far if the flush policy says they are due.
+    pub fn send_due(&mut self) -> io::Result<()> {
+        let waiting = self.raw.get_ref().len();
+        let due = match self.flush_policy {
+            FlushPolicy::PerMessage => waiting > 0,
+            FlushPolicy::PerSync => false,
+            FlushPolicy::SizeThreshold(threshold) => waiting >= threshold,
+        };
+
+        if due { self.send() } else { Ok(()) }
+    }
+
+    /// Read exactly one message, whose chunk bodies are assembled in a buffer reused across
+    /// calls. Reading stops at the end marker, so the replies to pipelined requests that
+    /// follow are left for the next calls.
+    ///
+    /// Empty chunks received before a message are keep-alive NOOPs sent by newer servers while
+    /// a reply is being prepared, and are skipped.
+    ///
+    /// Fails with `ProtocolViolation::MessageTooLarge` as soon as the message outgrows the limit,
+    /// leaving the rest of it unread.
+    pub fn receive_message(&mut self) -> GraphResult<&[u8]> {
+        if self.awaiting_reply {
+            self.metrics.round_trips += 1;
+            self.awaiting_reply = false;
+        }
+
+        let started = Instant::now();
+        let result = self.read_chunks();
+        self.metrics.read_time += started.elapsed();
+        result?;
+
+        let signature = StructureSignature::from(self.input_buffer.get(1).cloned().unwrap_or(0));
+        self.metrics.message_received(signature);
+        Ok(&self.input_buffer)
+    }
+
+    fn read_chunks(&mut self) -> GraphResult<()> {
+        self.input_buffer.clear();
 
         loop {
-            let chunk_size = try!(self.socket.read_u16::<BigEndian>());
+            let chunk_size = self.socket.read_u16::<BigEndian>()? as usize;
+            self.metrics.bytes_received += CHUNK_HEADER_SIZE as u64;
+
+            if chunk_size == 0 {
+                if self.input_buffer.is_empty() { continue }
+                break
+            }
+
+            let start = self.input_buffer.len();
+            if start + chunk_size > self.max_message_size {
+                self.input_buffer.clear();
+                return Err(ProtocolViolation::MessageTooLarge { limit: self.max_message_size }.into())
+            }
+
+            self.input_buffer.resize(start + chunk_size, 0);
+            if let Err(e) = self.socket.read_exact(&mut self.input_buffer[start..]) {
+                self.input_buffer.truncate(start);
+                return Err(e.into())
+            }
+            self.metrics.bytes_received += chunk_size as u64;
+            self.metrics.chunks_received += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Read the next `count` messages, e.g. the replies to pipelined requests, passing each one
+    /// to `f` in order. Stops at the first error, from the transport or from `f`.
+    pub fn receive_each<F>(&mut self, count: usize, mut f: F) -> GraphResult<()>
+        where F: FnMut(&[u8]) -> GraphResult<()> {
+
+        for _ in 0..count {
+            f(self.receive_message()?)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+    use std::io::prelude::*;
+    use std::net::{TcpListener, TcpStream};
+    use super::{ChunkedStream, FlushPolicy, MAX_CHUNK_SIZE};
+    use ::v1::error::{GraphError, ProtocolViolation};
+
+    fn chunked_stream() -> ChunkedStream {
+        chunked_stream_pair().0
+    }
+
+    fn chunked_stream_pair() -> (ChunkedStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (ChunkedStream::new(socket), server)
+    }
+
+    #[test]
+    fn flush_writes_header_and_body() {
+        let mut stream = chunked_stream();
+        stream.write(&[0xB0, 0x3F]).unwrap();
+        stream.flush(true).unwrap();
+
+        assert_eq!(&[0x00, 0x02, 0xB0, 0x3F, 0x00, 0x00], stream.raw());
+    }
 
-            if chunk_size == 0 { break }
+    #[test]
+    fn flush_without_data_only_writes_end_marker() {
+        let mut stream = chunked_stream();
+        stream.flush(false).unwrap();
+        assert!(stream.raw().is_empty());
 
-            let mut buf = vec![0u8; chunk_size as usize];
-            try!(self.socket.read(&mut buf));
-            result.append(&mut buf);
+        stream.flush(true).unwrap();
+        assert_eq!(&[0x00, 0x00], stream.raw());
+    }
+
+    #[test]
+    fn flush_consecutive_messages() {
+        let mut stream = chunked_stream();
+        stream.write(&[0xB0, 0x2F]).unwrap();
+        stream.flush(true).unwrap();
+        stream.write(&[0xB0, 0x3F]).unwrap();
+        stream.flush(true).unwrap();
+
+        assert_eq!(&[0x00, 0x02, 0xB0, 0x2F, 0x00, 0x00,
+                     0x00, 0x02, 0xB0, 0x3F, 0x00, 0x00], stream.raw());
+    }
+
+    #[test]
+    fn receive_assembles_chunks() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        server.write_all(&[0x00, 0x02, 0xB1, 0x70, 0x00, 0x01, 0xA0, 0x00, 0x00]).unwrap();
+
+        assert_eq!(&[0xB1, 0x70, 0xA0], stream.receive_message().unwrap());
+    }
+
+    #[test]
+    fn receive_skips_noop_chunks() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        server.write_all(&[0x00, 0x00, 0x00, 0x00,
+                           0x00, 0x02, 0xB1, 0x70, 0x00, 0x01, 0xA0, 0x00, 0x00,
+                           0x00, 0x00,
+                           0x00, 0x02, 0xB0, 0x7E, 0x00, 0x00]).unwrap();
+
+        assert_eq!(&[0xB1, 0x70, 0xA0], stream.receive_message().unwrap());
+        assert_eq!(&[0xB0, 0x7E], stream.receive_message().unwrap());
+    }
+
+    #[test]
+    fn receive_reuses_buffer_between_messages() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        server.write_all(&[0x00, 0x03, 0xB1, 0x70, 0xA0, 0x00, 0x00,
+                           0x00, 0x02, 0xB0, 0x7E, 0x00, 0x00]).unwrap();
+
+        stream.receive_message().unwrap();
+        let capacity = stream.input_buffer.capacity();
+
+        assert_eq!(&[0xB0, 0x7E], stream.receive_message().unwrap());
+        assert_eq!(capacity, stream.input_buffer.capacity());
+    }
+
+    #[test]
+    fn receive_message_larger_than_limit_should_fail() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        stream.set_max_message_size(4);
+        server.write_all(&[0x00, 0x03, 0xB1, 0x70, 0xA0, 0x00, 0x02, 0x01, 0x02, 0x00, 0x00]).unwrap();
+
+        match stream.receive_message() {
+            Err(GraphError::Protocol(ProtocolViolation::MessageTooLarge { limit: 4 })) => {}
+            other => panic!("unexpected result: {:?}", other),
         }
+    }
+
+    #[test]
+    fn chunked_stream_over_in_memory_stream() {
+        use ::testing::MockStream;
+
+        let mut stream = ChunkedStream::new(MockStream::new().raw(&[0x00, 0x02, 0xB0, 0x7E, 0x00, 0x00]));
+        stream.write(&[0xB0, 0x0F]).unwrap();
+        stream.flush(true).unwrap();
+        stream.send().unwrap();
+
+        assert_eq!(&[0xB0, 0x7E], stream.receive_message().unwrap());
+        assert_eq!(1, stream.socket().requests().len());
+    }
+
+    #[test]
+    fn receive_message_from_one_byte_reads() {
+        use ::testing::MockStream;
+        use ::v1::testing::ShortReads;
+
+        let socket = MockStream::new().raw(&[0x00, 0x00, 0x00, 0x03, 0xB1, 0x70, 0xA0, 0x00, 0x02, 0x01, 0x02, 0x00, 0x00]);
+        let mut stream = ChunkedStream::new(ShortReads::new(socket, 1));
+
+        assert_eq!(&[0xB1, 0x70, 0xA0, 0x01, 0x02], stream.receive_message().unwrap());
+    }
+
+    #[test]
+    fn truncated_message_is_unexpected_eof() {
+        use std::io::ErrorKind;
+        use ::testing::MockStream;
+        use ::v1::testing::ShortReads;
+
+        // in a chunk header, then in a chunk
+        for input in &[&[0x00, 0x03, 0xB1, 0x70, 0xA0, 0x00][..], &[0x00, 0x03, 0xB1][..]] {
+            let mut stream = ChunkedStream::new(ShortReads::new(MockStream::new().raw(input), 1));
+            match stream.receive_message() {
+                Err(GraphError::Io(ref e)) if e.kind() == ErrorKind::UnexpectedEof => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn receive_message_at_limit() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        stream.set_max_message_size(3);
+        server.write_all(&[0x00, 0x03, 0xB1, 0x70, 0xA0, 0x00, 0x00]).unwrap();
+
+        assert_eq!(&[0xB1, 0x70, 0xA0], stream.receive_message().unwrap());
+    }
+
+    #[test]
+    fn receive_each_message_in_order() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        server.write_all(&[0x00, 0x03, 0xB1, 0x70, 0xA0, 0x00, 0x00,
+                           0x00, 0x02, 0xB0, 0x7E, 0x00, 0x00,
+                           0x00, 0x02, 0xB0, 0x71, 0x00, 0x00]).unwrap();
+
+        let mut signatures = Vec::new();
+        stream.receive_each(2, |data| { signatures.push(data[1]); Ok(()) }).unwrap();
+
+        assert_eq!(vec![0x70, 0x7E], signatures);
+        assert_eq!(&[0xB0, 0x71], stream.receive_message().unwrap());
+    }
+
+    #[test]
+    fn receive_each_stops_at_error() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        server.write_all(&[0x00, 0x02, 0xB0, 0x7E, 0x00, 0x00,
+                           0x00, 0x02, 0xB0, 0x71, 0x00, 0x00]).unwrap();
+
+        let mut count = 0;
+        let result = stream.receive_each(2, |_| { count += 1; Err(GraphError::ConnectionDefunct) });
+
+        assert!(result.is_err());
+        assert_eq!(1, count);
+        assert_eq!(&[0xB0, 0x71], stream.receive_message().unwrap());
+    }
+
+    #[test]
+    fn send_due_follows_flush_policy() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        let message = [0x00, 0x02, 0xB0, 0x2F, 0x00, 0x00];
+
+        stream.set_flush_policy(FlushPolicy::PerSync);
+        stream.write(&[0xB0, 0x2F]).unwrap();
+        stream.flush(true).unwrap();
+        stream.send_due().unwrap();
+        assert_eq!(&message, stream.raw());
+
+        stream.set_flush_policy(FlushPolicy::SizeThreshold(12));
+        stream.send_due().unwrap();
+        assert_eq!(6, stream.pending());
+        stream.write(&[0xB0, 0x2F]).unwrap();
+        stream.flush(true).unwrap();
+        stream.send_due().unwrap();
+        assert_eq!(0, stream.pending());
+
+        stream.set_flush_policy(FlushPolicy::PerMessage);
+        stream.write(&[0xB0, 0x2F]).unwrap();
+        stream.flush(true).unwrap();
+        stream.send_due().unwrap();
+        assert_eq!(0, stream.pending());
+
+        let mut received = [0u8; 18];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(message.repeat(3), received.to_vec());
+    }
+
+    #[test]
+    fn metrics_count_bytes_chunks_and_round_trips() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        server.write_all(&[0x00, 0x00,
+                           0x00, 0x02, 0xB1, 0x70, 0x00, 0x01, 0xA0, 0x00, 0x00,
+                           0x00, 0x02, 0xB0, 0x7E, 0x00, 0x00]).unwrap();
+
+        stream.write(&[0xB0, 0x2F]).unwrap();
+        stream.flush(true).unwrap();
+        stream.write(&vec![0xA5; MAX_CHUNK_SIZE + 1]).unwrap();
+        stream.flush(true).unwrap();
+        assert_eq!(0, stream.metrics().chunks_sent);
+        stream.send().unwrap();
+
+        stream.receive_message().unwrap();
+        stream.receive_message().unwrap();
+        stream.send().unwrap();
+
+        let metrics = stream.metrics();
+        assert_eq!((6 + 2 + MAX_CHUNK_SIZE + 2 + 1 + 2) as u64, metrics.bytes_sent);
+        assert_eq!(3, metrics.chunks_sent);
+        assert_eq!(17, metrics.bytes_received);
+        assert_eq!(3, metrics.chunks_received);
+        assert_eq!(1, metrics.round_trips);
+        assert_eq!(Some(&1), metrics.messages_received.get("SUCCESS"));
+        assert_eq!(Some(&1), metrics.messages_received.get("IGNORED"));
+    }
+
+    #[test]
+    fn cleared_chunks_are_not_counted() {
+        let mut stream = chunked_stream();
+        stream.write(&[0xB0, 0x2F]).unwrap();
+        stream.flush(true).unwrap();
+        stream.clear();
+        stream.send().unwrap();
+
+        assert_eq!(0, stream.metrics().chunks_sent);
+        assert_eq!(0, stream.metrics().bytes_sent);
+    }
+
+    // Chunks `message` as the protocol specifies: full chunks of MAX_CHUNK_SIZE bytes, then
+    // the remainder, if any, then the end marker.
+    fn reference_chunks(message: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        for chunk in message.chunks(MAX_CHUNK_SIZE) {
+            output.extend_from_slice(&[(chunk.len() >> 8) as u8, chunk.len() as u8]);
+            output.extend_from_slice(chunk);
+        }
+        output.extend_from_slice(&[0x00, 0x00]);
+        output
+    }
+
+    // A xorshift generator, so the cases are the same on every run.
+    fn next_random(state: &mut u64) -> usize {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state as usize
+    }
+
+    #[test]
+    fn write_matches_reference_chunker() {
+        let mut state = 0x2545_F491_4F6C_DD1D;
+        let sizes = [0, 1, MAX_CHUNK_SIZE - 1, MAX_CHUNK_SIZE, MAX_CHUNK_SIZE + 1, 2 * MAX_CHUNK_SIZE, 3 * MAX_CHUNK_SIZE - 2];
+
+        for (case, &size) in sizes.iter().chain([1000, 70_000, 200_000].iter()).enumerate() {
+            let message: Vec<u8> = (0..size).map(|i| (i * 31 + case) as u8).collect();
+            let mut stream = chunked_stream();
+            stream.set_max_buffered_output(usize::MAX);
+
+            // the message is written in pieces of random sizes, some crossing chunk boundaries
+            let mut rest = &message[..];
+            while !rest.is_empty() {
+                let len = cmp::min(rest.len(), next_random(&mut state) % (MAX_CHUNK_SIZE + MAX_CHUNK_SIZE / 2) + 1);
+                stream.write(&rest[..len]).unwrap();
+                rest = &rest[len..];
+            }
+            stream.flush(true).unwrap();
+
+            assert!(stream.raw() == &reference_chunks(&message)[..], "message of {} bytes", size);
+        }
+    }
+
+    #[test]
+    fn output_above_cap_is_sent() {
+        let (mut stream, mut server) = chunked_stream_pair();
+        stream.set_flush_policy(FlushPolicy::PerSync);
+        stream.set_max_buffered_output(MAX_CHUNK_SIZE);
+
+        let message = vec![0xA5; 2 * MAX_CHUNK_SIZE + 10];
+        stream.write(&message).unwrap();
+        // the first chunk fits within the cap, the second one does not
+        assert_eq!(0, stream.raw().len());
+        stream.flush(true).unwrap();
+        assert_eq!(14, stream.pending());
+        stream.send().unwrap();
 
-        Ok(result)
+        let expected = reference_chunks(&message);
+        let mut received = vec![0u8; expected.len()];
+        server.read_exact(&mut received).unwrap();
+        assert!(expected == received);
     }
 }
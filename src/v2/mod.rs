@@ -0,0 +1,18 @@
+// Bolt v2. Messages and their exchange are unchanged from v1, it only adds structure types to
+// PackStream (dates, times, durations and points). They are read as plain `Value::Structure`,
+// see `temporal` for the date times, and the version is not proposed in the handshake until
+// all of them are implemented.
+
+use std::io::prelude::*;
+
+pub mod temporal;
+
+use v1::Connection;
+
+/// Bolt protocol version implemented by this module.
+pub const VERSION: u32 = 2;
+
+/// A connection speaking Bolt v2 on `stream`, after the handshake.
+pub fn connection<S: Read + Write>(stream: S) -> Connection<S> {
+    Connection::new(stream).with_version(VERSION)
+}
@@ -0,0 +1,419 @@
+// The date time structures of Bolt v2, read from and written as `Value::Structure` like the
+// graph types. Times are kept as the server sends them: the local date time in seconds and
+// nanoseconds since the Unix epoch, with the offset or zone it is in.
+
+use std::fmt;
+use std::time;
+
+use v1::packstream::value::{Value, StructureSignature};
+
+/// Signature of `LocalDateTime`, `d`.
+pub const LOCAL_DATE_TIME: u8 = 0x64;
+/// Signature of `DateTime`, `F`.
+pub const DATE_TIME_WITH_OFFSET: u8 = 0x46;
+/// Signature of `ZonedDateTime`, `f`.
+pub const DATE_TIME_WITH_ZONE_ID: u8 = 0x66;
+/// Signature of `Duration`, `E`.
+pub const DURATION: u8 = 0x45;
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// A date time without time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LocalDateTime {
+    /// Seconds since `1970-01-01T00:00:00`.
+    pub seconds: i64,
+    pub nanoseconds: i64,
+}
+
+impl LocalDateTime {
+    pub fn new(seconds: i64, nanoseconds: i64) -> Self {
+        LocalDateTime { seconds, nanoseconds }
+    }
+
+    /// Read a date time from a `LocalDateTime` structure.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let fields = fields(value, LOCAL_DATE_TIME, 2)?;
+        Some(LocalDateTime::new(fields[0].as_integer()?, nanoseconds(&fields[1])?))
+    }
+
+    pub fn to_structure(&self) -> Value {
+        Value::Structure(LOCAL_DATE_TIME.into(), vec![self.seconds.into(), self.nanoseconds.into()])
+    }
+}
+
+/// A date time at a fixed offset from UTC, e.g. `2018-06-01T12:30:00+02:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DateTime {
+    pub local: LocalDateTime,
+    /// Seconds east of UTC.
+    pub offset_seconds: i32,
+}
+
+impl DateTime {
+    pub fn new(local: LocalDateTime, offset_seconds: i32) -> Self {
+        DateTime { local, offset_seconds }
+    }
+
+    /// The date time at `offset_seconds` of the instant `utc_seconds` after the epoch.
+    pub fn from_utc(utc_seconds: i64, nanoseconds: i64, offset_seconds: i32) -> Self {
+        DateTime::new(LocalDateTime::new(utc_seconds + offset_seconds as i64, nanoseconds), offset_seconds)
+    }
+
+    /// Seconds since the epoch of the instant, in UTC.
+    pub fn utc_seconds(&self) -> i64 {
+        self.local.seconds - self.offset_seconds as i64
+    }
+
+    /// Read a date time from a structure with an offset, `F`.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let fields = fields(value, DATE_TIME_WITH_OFFSET, 3)?;
+        let local = LocalDateTime::new(fields[0].as_integer()?, nanoseconds(&fields[1])?);
+        let offset = fields[2].as_integer()?;
+        if offset.abs() >= 24 * 3600 {
+            return None
+        }
+        Some(DateTime::new(local, offset as i32))
+    }
+
+    pub fn to_structure(&self) -> Value {
+        Value::Structure(DATE_TIME_WITH_OFFSET.into(), vec![
+            self.local.seconds.into(), self.local.nanoseconds.into(), self.offset_seconds.into(),
+        ])
+    }
+}
+
+/// A date time in a zone named by its IANA id, e.g. `Europe/Lisbon`.
+///
+/// The offset of a zone depends on its rules at that date, which the driver does not know, so
+/// the zone is kept as its name and sent back as it was received.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ZonedDateTime {
+    pub local: LocalDateTime,
+    pub zone_id: String,
+}
+
+impl ZonedDateTime {
+    pub fn new<Z: Into<String>>(local: LocalDateTime, zone_id: Z) -> Self {
+        ZonedDateTime { local, zone_id: zone_id.into() }
+    }
+
+    pub fn zone_id(&self) -> &str {
+        &self.zone_id
+    }
+
+    /// Read a date time from a structure with a zone id, `f`.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let fields = fields(value, DATE_TIME_WITH_ZONE_ID, 3)?;
+        let local = LocalDateTime::new(fields[0].as_integer()?, nanoseconds(&fields[1])?);
+        Some(ZonedDateTime::new(local, fields[2].as_string()?))
+    }
+
+    pub fn to_structure(&self) -> Value {
+        Value::Structure(DATE_TIME_WITH_ZONE_ID.into(), vec![
+            self.local.seconds.into(), self.local.nanoseconds.into(), self.zone_id.as_str().into(),
+        ])
+    }
+}
+
+/// Either flavour of the date time structure with a time zone.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AnyDateTime {
+    Offset(DateTime),
+    Zoned(ZonedDateTime),
+}
+
+impl AnyDateTime {
+    pub fn from_value(value: &Value) -> Option<Self> {
+        DateTime::from_value(value).map(AnyDateTime::Offset)
+            .or_else(|| ZonedDateTime::from_value(value).map(AnyDateTime::Zoned))
+    }
+
+    pub fn local(&self) -> LocalDateTime {
+        match *self {
+            AnyDateTime::Offset(ref dt) => dt.local,
+            AnyDateTime::Zoned(ref dt) => dt.local,
+        }
+    }
+
+    pub fn to_structure(&self) -> Value {
+        match *self {
+            AnyDateTime::Offset(ref dt) => dt.to_structure(),
+            AnyDateTime::Zoned(ref dt) => dt.to_structure(),
+        }
+    }
+}
+
+/// An amount of time as Cypher sees it: months and days have no fixed length (a month may
+/// have 28 to 31 days, a day 23 to 25 hours across a DST change), so they are kept apart from
+/// the seconds and nanoseconds.
+///
+/// The nanoseconds are always in `0..1_000_000_000`, the seconds carrying the sign of the
+/// time part. Arithmetic is checked, adding each component on its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Duration {
+    months: i64,
+    days: i64,
+    seconds: i64,
+    nanoseconds: i64,
+}
+
+impl Duration {
+    /// A duration with `nanoseconds` beyond a second carried into `seconds`. Fails if that
+    /// overflows.
+    pub fn new(months: i64, days: i64, seconds: i64, nanoseconds: i64) -> Option<Self> {
+        let seconds = seconds.checked_add(nanoseconds.div_euclid(NANOS_PER_SECOND))?;
+        Some(Duration { months, days, seconds, nanoseconds: nanoseconds.rem_euclid(NANOS_PER_SECOND) })
+    }
+
+    pub fn months(&self) -> i64 { self.months }
+    pub fn days(&self) -> i64 { self.days }
+    pub fn seconds(&self) -> i64 { self.seconds }
+    pub fn nanoseconds(&self) -> i64 { self.nanoseconds }
+
+    pub fn checked_add(&self, other: &Duration) -> Option<Self> {
+        Duration::new(
+            self.months.checked_add(other.months)?,
+            self.days.checked_add(other.days)?,
+            self.seconds.checked_add(other.seconds)?,
+            self.nanoseconds + other.nanoseconds,
+        )
+    }
+
+    pub fn checked_neg(&self) -> Option<Self> {
+        Duration::new(self.months.checked_neg()?, self.days.checked_neg()?, self.seconds.checked_neg()?, -self.nanoseconds)
+    }
+
+    pub fn checked_sub(&self, other: &Duration) -> Option<Self> {
+        self.checked_add(&other.checked_neg()?)
+    }
+
+    /// The duration as a `std::time::Duration`, taking days as 24 hours, which is wrong across
+    /// DST changes. `None` if it has months, whose length is unknown, or is negative.
+    pub fn to_std(&self) -> Option<time::Duration> {
+        if self.months != 0 {
+            return None
+        }
+        let seconds = self.days.checked_mul(86_400)?.checked_add(self.seconds)?;
+        if seconds < 0 {
+            return None
+        }
+        Some(time::Duration::new(seconds as u64, self.nanoseconds as u32))
+    }
+
+    /// `duration` as seconds and nanoseconds, `None` if it has more seconds than an `i64`.
+    pub fn from_std(duration: time::Duration) -> Option<Self> {
+        if duration.as_secs() > i64::MAX as u64 {
+            return None
+        }
+        Duration::new(0, 0, duration.as_secs() as i64, duration.subsec_nanos() as i64)
+    }
+
+    /// Read a duration from a `Duration` structure.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let fields = fields(value, DURATION, 4)?;
+        Duration::new(fields[0].as_integer()?, fields[1].as_integer()?, fields[2].as_integer()?, fields[3].as_integer()?)
+    }
+
+    pub fn to_structure(&self) -> Value {
+        Value::Structure(DURATION.into(), vec![
+            self.months.into(), self.days.into(), self.seconds.into(), self.nanoseconds.into(),
+        ])
+    }
+}
+
+/// ISO 8601, e.g. `P1M2DT3.5S`.
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "P{}M{}DT", self.months, self.days)?;
+        if self.nanoseconds == 0 {
+            return write!(f, "{}S", self.seconds)
+        }
+
+        // -1.5s is kept as -2 seconds and 500000000 nanoseconds
+        let (seconds, nanoseconds) = if self.seconds < 0 {
+            (self.seconds + 1, NANOS_PER_SECOND - self.nanoseconds)
+        } else {
+            (self.seconds, self.nanoseconds)
+        };
+        let sign = if self.seconds < 0 && seconds == 0 { "-" } else { "" };
+        let fraction = format!("{:09}", nanoseconds);
+        write!(f, "{}{}.{}S", sign, seconds, fraction.trim_end_matches('0'))
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.offset_seconds < 0 { '-' } else { '+' };
+        let offset = self.offset_seconds.abs();
+        write!(f, "{}{}{:02}:{:02}", self.local, sign, offset / 3600, offset % 3600 / 60)
+    }
+}
+
+impl fmt::Display for ZonedDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]", self.local, self.zone_id)
+    }
+}
+
+/// ISO 8601, e.g. `2018-06-01T12:30:00.5`, with the fraction of a second only when not zero.
+impl fmt::Display for LocalDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let days = self.seconds.div_euclid(86_400);
+        let time = self.seconds.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, time / 3600, time % 3600 / 60, time % 60)?;
+        if self.nanoseconds != 0 {
+            let fraction = format!("{:09}", self.nanoseconds);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        Ok(())
+    }
+}
+
+// The date `days` after 1970-01-01 in the proleptic Gregorian calendar, after Howard Hinnant's
+// `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// The fields of `value` if it is a structure with `signature` and `count` fields.
+fn fields(value: &Value, signature: u8, count: usize) -> Option<&[Value]> {
+    match value.as_struct() {
+        Some((s, fields)) if s == StructureSignature::from(signature) && fields.len() == count => Some(fields),
+        _ => None,
+    }
+}
+
+fn nanoseconds(value: &Value) -> Option<i64> {
+    value.as_integer().filter(|&n| (0..NANOS_PER_SECOND).contains(&n))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+    use v1::packstream::encode;
+
+    #[test]
+    fn local_date_time_structure() {
+        let value = Value::Structure(LOCAL_DATE_TIME.into(), vec![Value::from(1_527_856_200), Value::from(500_000_000)]);
+        let local = LocalDateTime::from_value(&value).unwrap();
+
+        assert_eq!(LocalDateTime::new(1_527_856_200, 500_000_000), local);
+        assert_eq!("2018-06-01T12:30:00.5", local.to_string());
+        assert_eq!(value, local.to_structure());
+    }
+
+    #[test]
+    fn date_time_with_offset() {
+        let value = Value::Structure(DATE_TIME_WITH_OFFSET.into(), vec![
+            Value::from(1_527_856_200), Value::from(0), Value::from(7200),
+        ]);
+        let dt = DateTime::from_value(&value).unwrap();
+
+        assert_eq!(7200, dt.offset_seconds);
+        assert_eq!(1_527_849_000, dt.utc_seconds());
+        assert_eq!(dt, DateTime::from_utc(1_527_849_000, 0, 7200));
+        assert_eq!("2018-06-01T12:30:00+02:00", dt.to_string());
+        assert_eq!("1969-12-31T23:00:00-01:00", DateTime::from_utc(0, 0, -3600).to_string());
+        assert_eq!(value, dt.to_structure());
+    }
+
+    #[test]
+    fn zoned_date_time_keeps_zone_id() {
+        let value = Value::Structure(DATE_TIME_WITH_ZONE_ID.into(), vec![
+            Value::from(1_527_856_200), Value::from(0), Value::from("Europe/Lisbon"),
+        ]);
+        let data = encode(&value).unwrap();
+
+        let read = Value::from_reader(&mut Cursor::new(data.clone())).unwrap();
+        let dt = ZonedDateTime::from_value(&read).unwrap();
+        assert_eq!("Europe/Lisbon", dt.zone_id());
+        assert_eq!("2018-06-01T12:30:00[Europe/Lisbon]", dt.to_string());
+        assert_eq!(data, encode(&dt.to_structure()).unwrap());
+    }
+
+    #[test]
+    fn either_flavour() {
+        let offset = DateTime::from_utc(0, 0, 3600);
+        let zoned = ZonedDateTime::new(LocalDateTime::new(0, 0), "UTC");
+
+        assert_eq!(Some(AnyDateTime::Offset(offset)), AnyDateTime::from_value(&offset.to_structure()));
+        assert_eq!(Some(AnyDateTime::Zoned(zoned.clone())), AnyDateTime::from_value(&zoned.to_structure()));
+        assert_eq!(None, AnyDateTime::from_value(&LocalDateTime::new(0, 0).to_structure()));
+    }
+
+    #[test]
+    fn malformed_structures_are_not_read() {
+        let wrong_count = Value::Structure(LOCAL_DATE_TIME.into(), vec![Value::from(0)]);
+        let bad_nanos = Value::Structure(LOCAL_DATE_TIME.into(), vec![Value::from(0), Value::from(NANOS_PER_SECOND)]);
+        let bad_offset = Value::Structure(DATE_TIME_WITH_OFFSET.into(), vec![Value::from(0), Value::from(0), Value::from(86_400)]);
+
+        assert_eq!(None, LocalDateTime::from_value(&wrong_count));
+        assert_eq!(None, LocalDateTime::from_value(&bad_nanos));
+        assert_eq!(None, DateTime::from_value(&bad_offset));
+        assert_eq!(None, ZonedDateTime::from_value(&Value::from("2018-06-01")));
+    }
+
+    #[test]
+    fn dates_before_epoch() {
+        assert_eq!("1969-12-31T23:59:59", LocalDateTime::new(-1, 0).to_string());
+        assert_eq!("2000-02-29T00:00:00", LocalDateTime::new(951_782_400, 0).to_string());
+    }
+
+    #[test]
+    fn duration_structure() {
+        let value = Value::Structure(DURATION.into(), vec![Value::from(1), Value::from(2), Value::from(3), Value::from(500_000_000)]);
+        let duration = Duration::from_value(&value).unwrap();
+
+        assert_eq!((1, 2, 3, 500_000_000), (duration.months(), duration.days(), duration.seconds(), duration.nanoseconds()));
+        assert_eq!("P1M2DT3.5S", duration.to_string());
+        assert_eq!(value, duration.to_structure());
+        assert_eq!(None, Duration::from_value(&LocalDateTime::new(0, 0).to_structure()));
+    }
+
+    #[test]
+    fn duration_nanoseconds_are_carried() {
+        let duration = Duration::new(0, 0, 1, -500_000_000).unwrap();
+        assert_eq!((0, 500_000_000), (duration.seconds(), duration.nanoseconds()));
+
+        let negative = Duration::new(0, 0, -1, -500_000_000).unwrap();
+        assert_eq!((-2, 500_000_000), (negative.seconds(), negative.nanoseconds()));
+        assert_eq!("P0M0DT-1.5S", negative.to_string());
+        assert_eq!("P0M0DT-0.5S", Duration::new(0, 0, 0, -500_000_000).unwrap().to_string());
+        assert_eq!(None, Duration::new(0, 0, i64::MAX, NANOS_PER_SECOND));
+    }
+
+    #[test]
+    fn duration_arithmetic_is_checked() {
+        let a = Duration::new(1, 1, 1, 600_000_000).unwrap();
+        let b = Duration::new(0, 2, 0, 600_000_000).unwrap();
+
+        assert_eq!(Duration::new(1, 3, 2, 200_000_000), a.checked_add(&b));
+        assert_eq!(Duration::new(1, -1, 1, 0), a.checked_sub(&b));
+        assert_eq!(Some(Duration::default()), a.checked_sub(&a));
+        assert_eq!(None, Duration::new(i64::MAX, 0, 0, 0).unwrap().checked_add(&a));
+        assert_eq!(None, Duration::new(i64::MIN, 0, 0, 0).unwrap().checked_neg());
+    }
+
+    #[test]
+    fn duration_to_std() {
+        let duration = Duration::new(0, 1, 30, 5).unwrap();
+        assert_eq!(Some(time::Duration::new(86_430, 5)), duration.to_std());
+        assert_eq!(None, Duration::new(1, 0, 0, 0).unwrap().to_std());
+        assert_eq!(None, Duration::new(0, 0, -1, 0).unwrap().to_std());
+
+        assert_eq!(Duration::new(0, 0, 86_430, 5), Duration::from_std(time::Duration::new(86_430, 5)));
+        assert_eq!(None, Duration::from_std(time::Duration::new(u64::MAX, 0)));
+    }
+}